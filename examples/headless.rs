@@ -0,0 +1,46 @@
+//! Plays a file on the default output device without any UI, printing what the player
+//! reports: `cargo run --example headless -- <file>`
+use anyhow::{anyhow, Result};
+use rhap_core::audio::Host;
+use rhap_core::config::Config;
+use rhap_core::musictrack::MusicTrack;
+use rhap_core::observer::PlayerEvent;
+use rhap_core::player::{Playback, Player};
+use rhap_core::tasks::TaskGroup;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or(anyhow!("Usage: headless <file>"))?;
+    let tasks = TaskGroup::default();
+    // Shared mode without DSP, unknown backends fall back to the platform default
+    let mut player = Player::new(
+        Host::new("default", false),
+        None,
+        false,
+        Config::default(),
+        true,
+        tasks.clone(),
+    )?;
+    player.play(Arc::new(MusicTrack::new(path)?)).await?;
+    let mut events = player.subscribe(Duration::from_millis(500));
+    while let Some(event) = events.recv().await {
+        match event {
+            PlayerEvent::State(Playback::Stopped) => break,
+            PlayerEvent::State(state) => println!("{:?}", state),
+            PlayerEvent::Position { elapsed, duration } => {
+                println!("{:>6.1}s / {:.1}s", elapsed, duration)
+            }
+            PlayerEvent::Spectrum { .. } => (),
+        }
+        if let Some((name, err)) = tasks.failures().pop() {
+            return Err(anyhow!("{} failed: {}", name, err));
+        }
+    }
+    player.stop().await?;
+    tasks.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use time::{OffsetDateTime, UtcOffset};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+
+use crate::config::AlarmConfig;
+use crate::library::Database;
+use crate::tasks::TaskGroup;
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const SECONDS_PER_DAY: f64 = 24.0 * 60.0 * 60.0;
+
+/// Attenuation the first track fades in from.
+pub const FADE_IN_DB: f64 = -40.0;
+
+/// Wake-up alarm, a saved playlist started at a time of day and playback stopped at another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlarmSettings {
+    pub enabled: bool,
+    /// Minutes after midnight, local time
+    pub start: u16,
+    pub stop: Option<u16>,
+    pub playlist: Option<String>,
+    pub fade_in_seconds: u32,
+}
+
+/// What the alarm asks of the playlist once due.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AlarmCommand {
+    Start {
+        playlist: String,
+        fade_in_seconds: u32,
+    },
+    Stop,
+}
+
+/// `HH:MM` as minutes after midnight.
+fn parse_time(time: &str) -> Result<u16> {
+    let (hours, minutes) = time
+        .split_once(':')
+        .ok_or(anyhow!("HH:MM expected: {}", time))?;
+    let (hours, minutes): (u16, u16) = (hours.trim().parse()?, minutes.trim().parse()?);
+    if hours >= 24 || minutes >= 60 {
+        return Err(anyhow!("Invalid time of day: {}", time));
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Minutes after midnight as `HH:MM`.
+pub fn format_time(minutes: u16) -> String {
+    format!("{:0>2}:{:0>2}", minutes / 60, minutes % 60)
+}
+
+/// Local time of day in seconds. The local offset cannot be read once other threads run on
+/// some platforms, UTC is used then.
+fn seconds_of_day() -> f64 {
+    let now = OffsetDateTime::now_utc();
+    let now = now.to_offset(UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC));
+    now.hour() as f64 * 3600.0
+        + now.minute() as f64 * 60.0
+        + now.second() as f64
+        + now.nanosecond() as f64 / 1e9
+}
+
+impl AlarmSettings {
+    /// Disabled until a valid start time and a playlist are given.
+    pub fn from_config(config: &AlarmConfig) -> Self {
+        let parse = |time: &Option<String>| {
+            time.as_deref().and_then(|time| {
+                parse_time(time)
+                    .inspect_err(|err| warn!("Alarm: {}", err))
+                    .ok()
+            })
+        };
+        let start = parse(&config.start);
+        Self {
+            enabled: start.is_some() && config.playlist.is_some(),
+            start: start.unwrap_or(7 * 60),
+            stop: parse(&config.stop),
+            playlist: config.playlist.clone(),
+            fade_in_seconds: config.fade_in_seconds,
+        }
+    }
+
+    /// Moves the start by `minutes`, around the clock.
+    pub fn shift_start(&mut self, minutes: i16) {
+        self.start = shift(self.start, minutes);
+    }
+
+    /// Moves the stop by `minutes`, the alarm stops at the start time once first set.
+    pub fn shift_stop(&mut self, minutes: i16) {
+        self.stop = Some(shift(self.stop.unwrap_or(self.start), minutes));
+    }
+
+    /// The next command due with the seconds left before it, `seconds` into the day.
+    fn next(&self, seconds: f64) -> Option<(f64, AlarmCommand)> {
+        if !self.enabled {
+            return None;
+        }
+        // A command due right now was just sent, the next one is a day later
+        let wait =
+            |minutes: u16| match (minutes as f64 * 60.0 - seconds).rem_euclid(SECONDS_PER_DAY) {
+                wait if wait < 1.0 => wait + SECONDS_PER_DAY,
+                wait => wait,
+            };
+        let start = self.playlist.clone().map(|playlist| {
+            let command = AlarmCommand::Start {
+                playlist,
+                fade_in_seconds: self.fade_in_seconds,
+            };
+            (wait(self.start), command)
+        });
+        let stop = self.stop.map(|stop| (wait(stop), AlarmCommand::Stop));
+        [start, stop]
+            .into_iter()
+            .flatten()
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+}
+
+fn shift(minutes: u16, by: i16) -> u16 {
+    (minutes as i32 + by as i32).rem_euclid(MINUTES_PER_DAY as i32) as u16
+}
+
+/// Timer sending the alarm commands as they fall due, the settings can change meanwhile.
+pub struct Alarm {
+    settings: watch::Sender<AlarmSettings>,
+    commands: UnboundedReceiver<AlarmCommand>,
+    library: Database,
+}
+
+impl Alarm {
+    /// Settings last changed in the app are used over the config.
+    pub fn start(config: &AlarmConfig, library: Database, tasks: &TaskGroup) -> Self {
+        let settings = match library.alarm() {
+            Ok(Some(settings)) => settings,
+            Ok(None) => AlarmSettings::from_config(config),
+            Err(err) => {
+                warn!("Cannot read the alarm settings: {}", err);
+                AlarmSettings::from_config(config)
+            }
+        };
+        if UtcOffset::current_local_offset().is_err() {
+            warn!("Cannot read the local time offset, alarm times are UTC");
+        }
+        let settings = watch::Sender::new(settings);
+        let (command_tx, command_rx) = unbounded_channel();
+        tasks.spawn(String::from("Alarm"), run(settings.subscribe(), command_tx));
+        Self {
+            settings,
+            commands: command_rx,
+            library,
+        }
+    }
+
+    pub fn settings(&self) -> AlarmSettings {
+        self.settings.borrow().clone()
+    }
+
+    /// Kept in the library, the timer follows right away.
+    pub fn update(&self, settings: AlarmSettings) {
+        if let Err(err) = self.library.save_alarm(&settings) {
+            warn!("Cannot save the alarm settings: {}", err);
+        }
+        self.settings.send_replace(settings);
+    }
+
+    /// Next command due, `None` until then.
+    pub fn command(&mut self) -> Option<AlarmCommand> {
+        self.commands.try_recv().ok()
+    }
+}
+
+async fn run(
+    mut settings: watch::Receiver<AlarmSettings>,
+    commands: UnboundedSender<AlarmCommand>,
+) -> Result<()> {
+    loop {
+        let next = settings.borrow_and_update().next(seconds_of_day());
+        let wait = next
+            .as_ref()
+            .map_or(Duration::MAX, |(wait, _)| Duration::from_secs_f64(*wait));
+        tokio::select! {
+            changed = settings.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+            }
+            _ = tokio::time::sleep(wait), if next.is_some() => {
+                if let Some((_, command)) = next {
+                    if commands.send(command).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alarm() -> AlarmSettings {
+        AlarmSettings {
+            enabled: true,
+            start: parse_time("07:30").unwrap(),
+            stop: Some(parse_time("08:00").unwrap()),
+            playlist: Some(String::from("Morning")),
+            fade_in_seconds: 30,
+        }
+    }
+
+    #[test]
+    fn the_closest_command_comes_next_around_midnight() {
+        let start = AlarmCommand::Start {
+            playlist: String::from("Morning"),
+            fade_in_seconds: 30,
+        };
+        assert_eq!(alarm().next(7.0 * 3600.0), Some((1800.0, start.clone())));
+        assert_eq!(
+            alarm().next(7.75 * 3600.0),
+            Some((900.0, AlarmCommand::Stop))
+        );
+        assert_eq!(
+            alarm().next(23.5 * 3600.0),
+            Some((8.0 * 3600.0, start.clone()))
+        );
+        // Just sent, the start is due again tomorrow
+        assert_eq!(
+            alarm().next(7.5 * 3600.0),
+            Some((1800.0, AlarmCommand::Stop))
+        );
+    }
+
+    #[test]
+    fn times_are_read_and_shifted_around_the_clock() {
+        assert_eq!(parse_time("7:05").unwrap(), 425);
+        assert!(parse_time("24:00").is_err());
+        let mut alarm = alarm();
+        alarm.shift_start(-8 * 60);
+        assert_eq!(format_time(alarm.start), "23:30");
+    }
+}
@@ -0,0 +1,299 @@
+use anyhow::{anyhow, Result};
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::errors::Error;
+
+use crate::musictrack::MusicTrack;
+
+/// Points of the stored waveform, whatever the track length.
+const WAVEFORM_POINTS: usize = 200;
+/// Length of the fingerprint frames in seconds, half overlapping. Frames span the same time
+/// at every sample rate.
+const FINGERPRINT_FRAME_SECONDS: f64 = 0.093;
+/// Fingerprints cover the start of the track, enough to tell recordings apart.
+const FINGERPRINT_SECONDS: usize = 120;
+/// Bands between 300Hz and 2kHz, their energy differences give 32 bits per frame.
+const FINGERPRINT_BANDS: usize = 33;
+
+/// Results of the analyses of one track, stored in the library.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Analysis {
+    /// Integrated loudness in LUFS, `None` for digital silence
+    pub loudness: Option<f64>,
+    /// Peak of each slice of the track, 255 being full scale
+    pub waveform: Vec<u8>,
+    pub fingerprint: Vec<u32>,
+}
+
+/// Direct form I biquad.
+#[derive(Clone, Copy, Default)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// K-weighting of ITU-R BS.1770 at any sample rate: a high shelf modelling the head followed
+/// by a high pass.
+fn k_weighting(sample_rate: f64) -> [Biquad; 2] {
+    let k = (PI * 1681.974450955533 / sample_rate).tan();
+    let q = 0.7071752369554196;
+    let vh = 10f64.powf(3.999843853973347 / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2.0 * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+    let k = (PI * 38.13547087602444 / sample_rate).tan();
+    let q = 0.5003270373238773;
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        ..Default::default()
+    };
+    [shelf, high_pass]
+}
+
+/// Gated integrated loudness from the mean square of consecutive 100ms slices, measured on
+/// 400ms blocks overlapping by 75%.
+fn integrated_loudness(slices: &[f64]) -> Option<f64> {
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let blocks: Vec<f64> = slices
+        .windows(4)
+        .map(|window| window.iter().sum::<f64>() / 4.0)
+        .filter(|power| loudness(*power) > -70.0)
+        .collect();
+    if blocks.is_empty() {
+        return None;
+    }
+    let relative_gate = loudness(blocks.iter().sum::<f64>() / blocks.len() as f64) - 10.0;
+    let gated: Vec<f64> = blocks
+        .into_iter()
+        .filter(|power| loudness(*power) > relative_gate)
+        .collect();
+    Some(loudness(gated.iter().sum::<f64>() / gated.len() as f64))
+}
+
+/// Reduces the peaks to `WAVEFORM_POINTS` points.
+fn waveform(peaks: &[f32]) -> Vec<u8> {
+    if peaks.is_empty() {
+        return Vec::new();
+    }
+    (0..WAVEFORM_POINTS)
+        .map(|point| {
+            let start = point * peaks.len() / WAVEFORM_POINTS;
+            let end = ((point + 1) * peaks.len() / WAVEFORM_POINTS).max(start + 1);
+            let peak = peaks[start..end.min(peaks.len())]
+                .iter()
+                .fold(0.0f32, |peak, value| peak.max(*value));
+            (peak.min(1.0) * 255.0).round() as u8
+        })
+        .collect()
+}
+
+/// Spectral fingerprint in the spirit of Haitsma and Kalker: each bit tells whether the energy
+/// difference between two adjacent bands grew since the previous frame. It survives
+/// transcoding and resampling, so copies of a recording share most bits.
+struct Fingerprinter {
+    frame: usize,
+    edges: Vec<usize>,
+    window: Vec<f64>,
+    fft: std::sync::Arc<dyn rustfft::Fft<f64>>,
+    samples: Vec<f64>,
+    previous: Option<Vec<f64>>,
+    fingerprint: Vec<u32>,
+    limit: usize,
+    consumed: usize,
+}
+
+impl Fingerprinter {
+    fn new(sample_rate: usize) -> Self {
+        let frame = (sample_rate as f64 * FINGERPRINT_FRAME_SECONDS) as usize & !1;
+        let bin = |frequency: f64| (frequency * frame as f64 / sample_rate as f64) as usize;
+        let edges = (0..=FINGERPRINT_BANDS)
+            .map(|band| {
+                bin(300.0 * (2000.0f64 / 300.0).powf(band as f64 / FINGERPRINT_BANDS as f64))
+            })
+            .collect();
+        let window = (0..frame)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / frame as f64).cos())
+            .collect();
+        Self {
+            frame,
+            edges,
+            window,
+            fft: FftPlanner::new().plan_fft_forward(frame),
+            samples: Vec::with_capacity(frame),
+            previous: None,
+            fingerprint: Vec::new(),
+            limit: sample_rate * FINGERPRINT_SECONDS,
+            consumed: 0,
+        }
+    }
+
+    fn push(&mut self, mono: f64) {
+        if self.consumed >= self.limit {
+            return;
+        }
+        self.consumed += 1;
+        self.samples.push(mono);
+        if self.samples.len() < self.frame {
+            return;
+        }
+        let mut spectrum: Vec<Complex<f64>> = self
+            .samples
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, window)| Complex::new(sample * window, 0.0))
+            .collect();
+        self.fft.process(&mut spectrum);
+        let energies: Vec<f64> = self
+            .edges
+            .windows(2)
+            .map(|edge| {
+                spectrum[edge[0]..edge[1].max(edge[0] + 1)]
+                    .iter()
+                    .map(|bin| bin.norm_sqr())
+                    .sum()
+            })
+            .collect();
+        if let Some(previous) = &self.previous {
+            let bits = (0..FINGERPRINT_BANDS - 1).fold(0u32, |bits, band| {
+                let difference =
+                    (energies[band] - energies[band + 1]) - (previous[band] - previous[band + 1]);
+                (bits << 1) | (difference > 0.0) as u32
+            });
+            self.fingerprint.push(bits);
+        }
+        self.previous = Some(energies);
+        self.samples.drain(..self.frame / 2);
+    }
+}
+
+/// Decodes the whole track once for every analysis, this takes seconds per track and is meant
+/// to run on the background workers.
+pub fn analyze(track: &MusicTrack) -> Result<Analysis> {
+    if track.dsd_rate.is_some() {
+        return Err(anyhow!("DSD tracks are not analyzed: {}", track.path));
+    }
+    let sample_rate = track.sample as usize;
+    let channels = track.channels;
+    let (mut format, mut decoder) = track.open()?;
+    let mut filters = vec![k_weighting(sample_rate as f64); channels];
+    let slice_frames = sample_rate / 10;
+    let mut slices = Vec::new();
+    let mut peaks = Vec::new();
+    let mut power = 0.0;
+    let mut peak = 0.0f32;
+    let mut frames = 0;
+    let mut fingerprinter = Fingerprinter::new(sample_rate);
+    let mut buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        let decoded = decoder.decode(&packet)?;
+        let buffer = buffer
+            .get_or_insert_with(|| SampleBuffer::new(decoded.capacity() as u64, *decoded.spec()));
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks_exact(channels) {
+            let mut mono = 0.0;
+            for (sample, filter) in frame.iter().zip(filters.iter_mut()) {
+                let shelved = filter[0].process(*sample as f64);
+                let weighted = filter[1].process(shelved);
+                power += weighted * weighted;
+                peak = peak.max(sample.abs());
+                mono += *sample as f64;
+            }
+            fingerprinter.push(mono / channels as f64);
+            frames += 1;
+            if frames == slice_frames {
+                slices.push(power / slice_frames as f64);
+                peaks.push(peak);
+                (power, peak, frames) = (0.0, 0.0, 0);
+            }
+        }
+    }
+    if frames > 0 {
+        peaks.push(peak);
+    }
+    Ok(Analysis {
+        loudness: integrated_loudness(&slices),
+        waveform: waveform(&peaks),
+        fingerprint: fingerprinter.fingerprint,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::flac::FlacWriter;
+
+    /// Writes a stereo 24 bits FLAC of a 1kHz sine to a temporary file and analyzes it.
+    fn analyze_sine(name: &str, sample_rate: usize, amplitude: f64) -> Analysis {
+        let path = std::env::temp_dir().join(format!("rhap-analysis-{}.flac", name));
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = FlacWriter::new(file, sample_rate as u32, 2, 24, &[], &[]).unwrap();
+        let samples: Vec<i32> = (0..sample_rate * 5 * 2)
+            .map(|i| {
+                let t = (i / 2) as f64 / sample_rate as f64;
+                ((2.0 * PI * 1000.0 * t).sin() * amplitude * 8388607.0) as i32
+            })
+            .collect();
+        writer.write(&samples).unwrap();
+        writer.finish().unwrap();
+        let track = MusicTrack::new(path.to_string_lossy().into_owned()).unwrap();
+        let analysis = analyze(&track).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        analysis
+    }
+
+    #[test]
+    fn stereo_sine_at_minus_20_dbfs_measures_minus_20_lufs() {
+        for sample_rate in [44100, 48000, 96000] {
+            let analysis = analyze_sine(&format!("sine{}", sample_rate), sample_rate, 0.1);
+            let loudness = analysis.loudness.unwrap();
+            assert!(
+                (loudness + 20.0).abs() < 0.1,
+                "{}Hz: {}",
+                sample_rate,
+                loudness
+            );
+            assert_eq!(analysis.waveform.len(), WAVEFORM_POINTS);
+            assert!(analysis
+                .waveform
+                .iter()
+                .all(|peak| (25..=26).contains(peak)));
+        }
+    }
+
+    #[test]
+    fn silence_has_no_loudness() {
+        let analysis = analyze_sine("silence", 44100, 0.0);
+        assert_eq!(analysis.loudness, None);
+        assert!(analysis.waveform.iter().all(|peak| *peak == 0));
+    }
+}
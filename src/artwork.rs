@@ -0,0 +1,161 @@
+use log::info;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use symphonia::core::meta::StandardVisualKey;
+
+use crate::audio::thread::unblock;
+use crate::musictrack::MusicTrack;
+use crate::tasks::TaskGroup;
+
+/// Images next to the tracks taken as album art when none is embedded, by file stem.
+const FOLDER_ART: &[&str] = &["cover", "folder", "front", "album"];
+const FOLDER_ART_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+/// Side of the thumbnail the colors are counted on
+const THUMBNAIL_SIZE: u32 = 64;
+/// Colors less saturated are greys, which make dull accents
+const MIN_SATURATION: f32 = 0.25;
+/// Darker colors are lost on the rows of the table
+const MIN_VALUE: u8 = 40;
+/// Brightest channel of an accent at least, to stand out on the dark rows
+const MIN_BRIGHTNESS: f32 = 200.0;
+
+pub type Rgb = (u8, u8, u8);
+
+/// Front cover embedded in the file of `song`, else an image of its folder such as
+/// `cover.jpg`. Any embedded picture does when none is marked as the front cover.
+pub fn album_art(song: &MusicTrack) -> Option<Vec<u8>> {
+    let embedded = song.metadata().ok().and_then(|metadata| {
+        let visuals = metadata.visuals();
+        visuals
+            .iter()
+            .find(|visual| visual.usage == Some(StandardVisualKey::FrontCover))
+            .or(visuals.first())
+            .map(|visual| visual.data.to_vec())
+    });
+    embedded.or_else(|| {
+        let dir = Path::new(&song.path).parent()?;
+        FOLDER_ART
+            .iter()
+            .flat_map(|stem| {
+                FOLDER_ART_EXTENSIONS
+                    .iter()
+                    .map(move |extension| dir.join(format!("{}.{}", stem, extension)))
+            })
+            .find_map(|path| std::fs::read(path).ok())
+    })
+}
+
+/// Most common saturated color of an image, brightened to stand out on the dark rows. `None`
+/// for greyscale art or data which is no PNG nor JPEG image.
+pub fn dominant_color(image: &[u8]) -> Option<Rgb> {
+    let thumbnail = image::load_from_memory(image)
+        .ok()?
+        .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+        .to_rgb8();
+    // Colors binned on their 4 high bits per channel: weight, channel sums and count
+    let mut bins: HashMap<u16, (f32, [u32; 3], u32)> = HashMap::new();
+    for pixel in thumbnail.pixels() {
+        let [r, g, b] = pixel.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        if max < MIN_VALUE {
+            continue;
+        }
+        let saturation = (max - min) as f32 / max as f32;
+        if saturation < MIN_SATURATION {
+            continue;
+        }
+        let key = ((r as u16 >> 4) << 8) | ((g as u16 >> 4) << 4) | (b as u16 >> 4);
+        let (weight, sums, count) = bins.entry(key).or_default();
+        // Vivid colors win over pale ones covering a little more of the art
+        *weight += saturation;
+        sums[0] += r as u32;
+        sums[1] += g as u32;
+        sums[2] += b as u32;
+        *count += 1;
+    }
+    let (_, sums, count) = bins.into_values().max_by(|a, b| a.0.total_cmp(&b.0))?;
+    let average = sums.map(|sum| sum as f32 / count as f32);
+    let brightest = average[0].max(average[1]).max(average[2]);
+    let scale = (MIN_BRIGHTNESS / brightest).max(1.0);
+    let [r, g, b] = average.map(|channel| (channel * scale).min(255.0).round() as u8);
+    Some((r, g, b))
+}
+
+/// Accent colors of the albums played, drawn from their art in the background and kept for
+/// the session. Albums are told apart by the folder of their files.
+#[derive(Default)]
+pub struct Accents {
+    /// `None` for albums without art or any color in it, missing while read
+    colors: Arc<Mutex<HashMap<PathBuf, Option<Rgb>>>>,
+}
+
+impl Accents {
+    /// Accent of the album of `song`, read on `tasks` the first time. `None` meanwhile.
+    pub fn get(&self, song: &MusicTrack, tasks: &TaskGroup) -> Option<Rgb> {
+        if song.is_stream() {
+            return None;
+        }
+        let album = Path::new(&song.path).parent()?.to_path_buf();
+        let mut colors = self.colors.lock().ok()?;
+        if let Some(color) = colors.get(&album) {
+            return *color;
+        }
+        // Marked as without color until read, not to be read twice
+        colors.insert(album.clone(), None);
+        let (colors, song) = (self.colors.clone(), song.clone());
+        tasks.spawn(format!("Accent of {}", album.display()), async move {
+            let color =
+                unblock(move || Ok(album_art(&song).and_then(|art| dominant_color(&art)))).await?;
+            info!("Accent of {}: {:?}", album.display(), color);
+            if let Ok(mut colors) = colors.lock() {
+                colors.insert(album, color);
+            }
+            Ok(())
+        });
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn png(image: RgbImage) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        image.write_to(&mut data, ImageFormat::Png).unwrap();
+        data.into_inner()
+    }
+
+    #[test]
+    fn greys_are_left_out() {
+        // A quarter of dark red on grey
+        let image = RgbImage::from_fn(64, 64, |x, y| match x < 32 && y < 32 {
+            true => image::Rgb([120, 20, 20]),
+            false => image::Rgb([128, 128, 128]),
+        });
+        let (r, g, b) = dominant_color(&png(image)).unwrap();
+        assert!(r as f32 >= MIN_BRIGHTNESS);
+        assert!(r > 4 * g && g == b);
+        let grey = RgbImage::from_pixel(64, 64, image::Rgb([90, 90, 90]));
+        assert_eq!(dominant_color(&png(grey)), None);
+    }
+
+    #[test]
+    fn vivid_colors_win() {
+        // More pale blue than vivid orange
+        let image = RgbImage::from_fn(64, 64, |x, _| match x < 28 {
+            true => image::Rgb([250, 130, 0]),
+            false => image::Rgb([150, 170, 210]),
+        });
+        assert_eq!(dominant_color(&png(image)), Some((250, 130, 0)));
+    }
+
+    #[test]
+    fn other_data_has_no_color() {
+        assert_eq!(dominant_color(b"not an image"), None);
+    }
+}
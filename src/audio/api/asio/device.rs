@@ -0,0 +1,402 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::ffi::c_void;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
+use tracing::trace_span;
+
+use super::driver::{BufferInfo, Callbacks, ComApartment, Driver, DriverInfo, SampleType};
+use crate::audio::ring::{self, RingReader, RingWriter};
+use crate::audio::{
+    BitsPerSample, Capabilities, DeviceTrait, FadeControl, Fader, StreamParams, StreamingData,
+};
+use crate::tools::cpu::CpuMeter;
+
+const ASIO_SELECTOR_SUPPORTED: i32 = 1;
+const ASIO_ENGINE_VERSION: i32 = 2;
+
+enum Command {
+    Stop,
+}
+
+/// Converts the interleaved stream into the driver half buffers on each buffer switch.
+struct Renderer {
+    data_rx: Option<RingReader>,
+    bits_per_sample: BitsPerSample,
+    sample_type: SampleType,
+    frames: usize,
+    buffers: Vec<[*mut c_void; 2]>,
+    pending: Vec<u8>,
+    fader: Fader,
+    cpu: Arc<CpuMeter>,
+}
+
+// The half buffers belong to the driver and stay valid until disposeBuffers,
+// which only happens after the renderer has been taken out of RENDERER.
+unsafe impl Send for Renderer {}
+
+// ASIO callbacks carry no user data, only one driver can run at a time anyway.
+static RENDERER: Mutex<Option<Renderer>> = Mutex::new(None);
+
+static CALLBACKS: Callbacks = Callbacks {
+    buffer_switch,
+    sample_rate_did_change,
+    asio_message,
+    buffer_switch_time_info,
+};
+
+extern "C" fn buffer_switch(index: i32, _direct_process: i32) {
+    if let Ok(mut renderer) = RENDERER.lock() {
+        if let Some(renderer) = renderer.as_mut() {
+            renderer.render(index as usize & 1);
+        }
+    }
+}
+
+extern "C" fn sample_rate_did_change(samplerate: f64) {
+    warn!("ASIO sample rate changed to {}Hz", samplerate);
+}
+
+extern "C" fn asio_message(
+    selector: i32,
+    value: i32,
+    _message: *mut c_void,
+    _opt: *mut f64,
+) -> i32 {
+    match selector {
+        ASIO_SELECTOR_SUPPORTED => (value == ASIO_ENGINE_VERSION) as i32,
+        ASIO_ENGINE_VERSION => 2,
+        _ => 0,
+    }
+}
+
+extern "C" fn buffer_switch_time_info(
+    params: *mut c_void,
+    index: i32,
+    direct_process: i32,
+) -> *mut c_void {
+    buffer_switch(index, direct_process);
+    params
+}
+
+enum Sample {
+    Int(i32),
+    Float(f32),
+}
+
+impl Sample {
+    /// Integer samples are left justified so that widening them stays bit perfect.
+    fn read(bytes: &[u8], bits_per_sample: BitsPerSample) -> Self {
+        match bits_per_sample {
+            BitsPerSample::Bits16 => Self::Int(i32::from_le_bytes([0, 0, bytes[0], bytes[1]])),
+            BitsPerSample::Bits24 => {
+                Self::Int(i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]))
+            }
+            BitsPerSample::Bits32 => {
+                Self::Float(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            }
+        }
+    }
+
+    fn to_i32(&self) -> i32 {
+        match self {
+            Self::Int(value) => *value,
+            Self::Float(value) => (value.clamp(-1.0, 1.0) as f64 * i32::MAX as f64) as i32,
+        }
+    }
+
+    fn to_f32(&self) -> f32 {
+        match self {
+            Self::Int(value) => *value as f32 / 2147483648.0,
+            Self::Float(value) => *value,
+        }
+    }
+
+    fn write(&self, sample_type: SampleType, output: &mut [u8]) {
+        match sample_type {
+            SampleType::Int16 => output.copy_from_slice(&self.to_i32().to_le_bytes()[2..]),
+            SampleType::Int24 => output.copy_from_slice(&self.to_i32().to_le_bytes()[1..]),
+            SampleType::Int32 => output.copy_from_slice(&self.to_i32().to_le_bytes()),
+            SampleType::Float32 => output.copy_from_slice(&self.to_f32().to_le_bytes()),
+        }
+    }
+}
+
+impl Renderer {
+    fn render(&mut self, half: usize) {
+        let _busy = self.cpu.busy();
+        let _span = trace_span!("device_write", half).entered();
+        let channels = self.buffers.len();
+        let input_sample_size = self.bits_per_sample as usize / 8;
+        let output_sample_size = self.sample_type.sample_size();
+        let frame_size = channels * input_sample_size;
+        let needed = self.frames * frame_size;
+        if self.fader.is_silent() {
+            for buffers in &self.buffers {
+                let output = unsafe {
+                    std::slice::from_raw_parts_mut(
+                        buffers[half] as *mut u8,
+                        self.frames * output_sample_size,
+                    )
+                };
+                output.fill(0);
+            }
+            return;
+        }
+        let mut finished = self.data_rx.is_none();
+        if let Some(data_rx) = self.data_rx.as_mut() {
+            let missing = needed.saturating_sub(self.pending.len());
+            data_rx.read_into(&mut self.pending, missing);
+            finished = data_rx.is_finished() || data_rx.is_abandoned();
+        }
+
+        // Only complete frames are played, the remainder waits for the next switch
+        let available = self.pending.len().min(needed);
+        let frames = available / frame_size;
+        self.fader.process(&mut self.pending[..frames * frame_size]);
+        for (channel, buffers) in self.buffers.iter().enumerate() {
+            let output = unsafe {
+                std::slice::from_raw_parts_mut(
+                    buffers[half] as *mut u8,
+                    self.frames * output_sample_size,
+                )
+            };
+            for (frame, output) in output
+                .chunks_exact_mut(output_sample_size)
+                .take(frames)
+                .enumerate()
+            {
+                let offset = frame * frame_size + channel * input_sample_size;
+                Sample::read(&self.pending[offset..], self.bits_per_sample)
+                    .write(self.sample_type, output);
+            }
+            output[frames * output_sample_size..].fill(0);
+        }
+        if frames < self.frames && !finished {
+            self.cpu.underrun();
+        }
+        self.pending.drain(..frames * frame_size);
+
+        // Closing the stream lets the player know the track has been played
+        if finished && self.pending.len() < frame_size {
+            self.data_rx = None;
+        }
+    }
+}
+
+/// Owns the driver on its own apartment thread until a stop command is received.
+fn run_stream(
+    info: DriverInfo,
+    params: StreamParams,
+    fader: Fader,
+    cpu: Arc<CpuMeter>,
+    commands: mpsc::Receiver<Command>,
+    started: mpsc::Sender<Result<RingWriter>>,
+) -> Result<()> {
+    let _apartment = ComApartment::new()?;
+    let driver = match open_stream(&info, &params, fader, cpu) {
+        Ok((driver, data_tx)) => {
+            let _ = started.send(Ok(data_tx));
+            driver
+        }
+        Err(err) => {
+            let _ = started.send(Err(err));
+            return Ok(());
+        }
+    };
+    // Pausing fades the output to silence while the driver keeps running
+    let _ = commands.recv();
+    let result = driver.stop();
+    if let Ok(mut renderer) = RENDERER.lock() {
+        *renderer = None;
+    }
+    driver.dispose_buffers()?;
+    result
+}
+
+fn open_stream(
+    info: &DriverInfo,
+    params: &StreamParams,
+    fader: Fader,
+    cpu: Arc<CpuMeter>,
+) -> Result<(Driver, RingWriter)> {
+    let driver = Driver::load(info)?;
+    driver.set_sample_rate(params.samplerate as usize as f64)?;
+    if driver.output_channels()? < params.channels as i32 {
+        return Err(anyhow!(
+            "{} has less than {} output channels",
+            info.name,
+            params.channels
+        ));
+    }
+    let sample_type = driver.output_sample_type(0)?;
+    // Keep at least 10ms per buffer switch to avoid underruns
+    let buffer_size = driver
+        .buffer_size()?
+        .negotiate(params.samplerate as i32 / 100);
+    // The stream is sized in buffer switches, known once the driver picked its buffer size
+    let (data_tx, data_rx) =
+        ring::channel(buffer_size as usize * params.frame_size() * ring::PERIODS);
+    let mut infos: Vec<BufferInfo> = (0..params.channels as i32)
+        .map(BufferInfo::output)
+        .collect();
+    driver.create_buffers(&mut infos, buffer_size, &CALLBACKS)?;
+    if let Ok(mut renderer) = RENDERER.lock() {
+        *renderer = Some(Renderer {
+            data_rx: Some(data_rx),
+            bits_per_sample: params.bits_per_sample,
+            sample_type,
+            frames: buffer_size as usize,
+            buffers: infos.iter().map(|info| info.buffers).collect(),
+            pending: Vec::new(),
+            fader,
+            cpu,
+        });
+    }
+    if let Err(err) = driver.start() {
+        if let Ok(mut renderer) = RENDERER.lock() {
+            *renderer = None;
+        }
+        let _ = driver.dispose_buffers();
+        return Err(err);
+    }
+    Ok((driver, data_tx))
+}
+
+pub struct Device {
+    info: DriverInfo,
+    is_default: bool,
+    capabilities: OnceLock<Capabilities>,
+    commands: Option<mpsc::Sender<Command>>,
+    stream_thread_handle: Option<JoinHandle<Result<()>>>,
+    fade: Arc<FadeControl>,
+    cpu: Arc<CpuMeter>,
+}
+
+impl Device {
+    pub(crate) fn new(info: DriverInfo, is_default: bool) -> Self {
+        Self {
+            info,
+            is_default,
+            capabilities: OnceLock::new(),
+            commands: None,
+            stream_thread_handle: None,
+            fade: Arc::new(FadeControl::default()),
+            cpu: Arc::new(CpuMeter::default()),
+        }
+    }
+
+    /// Loads the driver on a short lived apartment thread, the stream thread keeps its own instance.
+    fn query_capabilities(&self) -> Result<Capabilities> {
+        let info = self.info.clone();
+        std::thread::spawn(move || {
+            let _apartment = ComApartment::new()?;
+            let driver = Driver::load(&info)?;
+            let default_capabilities = Capabilities::default();
+            let sample_rates = default_capabilities
+                .sample_rates
+                .into_iter()
+                .filter(|samplerate| driver.can_sample_rate(*samplerate as usize as f64))
+                .collect();
+            let bits_per_samples = match driver.output_sample_type(0)? {
+                SampleType::Int16 => vec![BitsPerSample::Bits16],
+                SampleType::Int24 => vec![BitsPerSample::Bits16, BitsPerSample::Bits24],
+                SampleType::Int32 | SampleType::Float32 => default_capabilities.bits_per_samples,
+            };
+            // Outputs have no speaker positions, any layout fits in as many of them
+            let outputs = driver.output_channels()?;
+            let channels = default_capabilities
+                .channels
+                .into_iter()
+                .filter(|channels| *channels as i32 <= outputs)
+                .collect();
+            Ok(Capabilities {
+                sample_rates,
+                bits_per_samples,
+                channels,
+            })
+        })
+        .join()
+        .map_err(|_| anyhow!("ASIO driver thread panicked"))?
+    }
+}
+
+impl DeviceTrait for Device {
+    fn is_default(&self) -> Result<bool> {
+        Ok(self.is_default)
+    }
+
+    fn name(&self) -> Result<String> {
+        Ok(self.info.name.clone())
+    }
+
+    fn get_capabilities(&self) -> Result<Capabilities> {
+        // Drivers usually refuse a second instance while streaming, query them only once
+        if self.capabilities.get().is_none() {
+            let _ = self.capabilities.set(self.query_capabilities()?);
+        }
+        self.capabilities
+            .get()
+            .cloned()
+            .ok_or(anyhow!("No capabilities found"))
+    }
+
+    fn start(&mut self, params: &StreamParams) -> Result<RingWriter> {
+        self.stop()?;
+        let (command_tx, command_rx) = mpsc::channel();
+        let (started_tx, started_rx) = mpsc::channel();
+
+        let info = self.info.clone();
+        let params = *params;
+        self.fade.resume();
+        let fader = Fader::new(self.fade.clone(), &params);
+        let cpu = self.cpu.clone();
+        self.stream_thread_handle = Some(std::thread::spawn(move || {
+            run_stream(info, params, fader, cpu, command_rx, started_tx)
+        }));
+        self.commands = Some(command_tx);
+        started_rx
+            .recv()
+            .map_err(|_| anyhow!("ASIO stream thread exited"))?
+    }
+
+    fn start_capture(&mut self, _params: &StreamParams) -> Result<Receiver<StreamingData>> {
+        Err(anyhow!("Recording is not supported by the ASIO backend"))
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.fade.pause();
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.fade.resume();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        if let Some(commands) = self.commands.take() {
+            let _ = commands.send(Command::Stop);
+        }
+        if let Some(handle) = self.stream_thread_handle.take() {
+            handle
+                .join()
+                .map_err(|_| anyhow!("Stream thread panicked"))??;
+        }
+        Ok(())
+    }
+
+    fn cpu_usage(&self) -> f64 {
+        self.cpu.usage()
+    }
+
+    fn cpu_time(&self) -> Duration {
+        self.cpu.busy_time()
+    }
+
+    fn underruns(&self) -> u64 {
+        self.cpu.underruns()
+    }
+}
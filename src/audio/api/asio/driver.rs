@@ -0,0 +1,405 @@
+use anyhow::{anyhow, Result};
+use std::ffi::{c_char, c_void, CStr};
+use windows::core::{GUID, HRESULT, PCWSTR, PWSTR};
+use windows::Win32::{
+    Foundation::ERROR_SUCCESS,
+    System::{
+        Com::{
+            CLSIDFromString, CoInitializeEx, CoUninitialize, CLSCTX, CLSCTX_INPROC_SERVER,
+            COINIT_APARTMENTTHREADED,
+        },
+        Registry::{
+            RegCloseKey, RegEnumKeyExW, RegGetValueW, RegOpenKeyExW, HKEY, HKEY_LOCAL_MACHINE,
+            KEY_READ, RRF_RT_REG_SZ,
+        },
+    },
+};
+
+const ASE_OK: i32 = 0;
+const ASE_SUCCESS: i32 = 0x3f4847a0;
+
+const ASIO_ST_INT16_LSB: i32 = 16;
+const ASIO_ST_INT24_LSB: i32 = 17;
+const ASIO_ST_INT32_LSB: i32 = 18;
+const ASIO_ST_FLOAT32_LSB: i32 = 19;
+
+#[link(name = "ole32")]
+extern "system" {
+    // Declared by hand as ASIO drivers are queried with their CLSID as interface id.
+    fn CoCreateInstance(
+        rclsid: *const GUID,
+        outer: *mut c_void,
+        context: CLSCTX,
+        riid: *const GUID,
+        object: *mut *mut c_void,
+    ) -> HRESULT;
+}
+
+// IASIO methods use thiscall, which is the C calling convention on 64 bits targets only. The
+// backend is left out of other targets, see the api module.
+
+#[repr(C)]
+struct IAsioVtbl {
+    _query_interface: usize,
+    _add_ref: usize,
+    release: unsafe extern "system" fn(this: *mut c_void) -> u32,
+    init: unsafe extern "system" fn(this: *mut c_void, sys_handle: *mut c_void) -> i32,
+    _get_driver_name: usize,
+    _get_driver_version: usize,
+    get_error_message: unsafe extern "system" fn(this: *mut c_void, message: *mut c_char),
+    start: unsafe extern "system" fn(this: *mut c_void) -> i32,
+    stop: unsafe extern "system" fn(this: *mut c_void) -> i32,
+    get_channels:
+        unsafe extern "system" fn(this: *mut c_void, inputs: *mut i32, outputs: *mut i32) -> i32,
+    _get_latencies: usize,
+    get_buffer_size: unsafe extern "system" fn(
+        this: *mut c_void,
+        min: *mut i32,
+        max: *mut i32,
+        preferred: *mut i32,
+        granularity: *mut i32,
+    ) -> i32,
+    can_sample_rate: unsafe extern "system" fn(this: *mut c_void, samplerate: f64) -> i32,
+    _get_sample_rate: usize,
+    set_sample_rate: unsafe extern "system" fn(this: *mut c_void, samplerate: f64) -> i32,
+    _get_clock_sources: usize,
+    _set_clock_source: usize,
+    _get_sample_position: usize,
+    get_channel_info: unsafe extern "system" fn(this: *mut c_void, info: *mut ChannelInfo) -> i32,
+    create_buffers: unsafe extern "system" fn(
+        this: *mut c_void,
+        infos: *mut BufferInfo,
+        channels: i32,
+        buffer_size: i32,
+        callbacks: *const Callbacks,
+    ) -> i32,
+    dispose_buffers: unsafe extern "system" fn(this: *mut c_void) -> i32,
+    _control_panel: usize,
+    _future: usize,
+    _output_ready: usize,
+}
+
+#[repr(C)]
+struct ChannelInfo {
+    channel: i32,
+    is_input: i32,
+    is_active: i32,
+    channel_group: i32,
+    sample_type: i32,
+    name: [c_char; 32],
+}
+
+#[repr(C)]
+pub(crate) struct BufferInfo {
+    is_input: i32,
+    channel: i32,
+    pub buffers: [*mut c_void; 2],
+}
+
+impl BufferInfo {
+    pub fn output(channel: i32) -> Self {
+        Self {
+            is_input: 0,
+            channel,
+            buffers: [std::ptr::null_mut(); 2],
+        }
+    }
+}
+
+#[repr(C)]
+pub(crate) struct Callbacks {
+    pub buffer_switch: extern "C" fn(index: i32, direct_process: i32),
+    pub sample_rate_did_change: extern "C" fn(samplerate: f64),
+    pub asio_message:
+        extern "C" fn(selector: i32, value: i32, message: *mut c_void, opt: *mut f64) -> i32,
+    pub buffer_switch_time_info:
+        extern "C" fn(params: *mut c_void, index: i32, direct_process: i32) -> *mut c_void,
+}
+
+/// Native sample formats of a driver channel that can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum SampleType {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl SampleType {
+    fn from_asio(value: i32) -> Result<Self> {
+        match value {
+            ASIO_ST_INT16_LSB => Ok(Self::Int16),
+            ASIO_ST_INT24_LSB => Ok(Self::Int24),
+            ASIO_ST_INT32_LSB => Ok(Self::Int32),
+            ASIO_ST_FLOAT32_LSB => Ok(Self::Float32),
+            _ => Err(anyhow!("Unsupported ASIO sample type {}", value)),
+        }
+    }
+
+    pub fn sample_size(&self) -> usize {
+        match self {
+            Self::Int16 => 2,
+            Self::Int24 => 3,
+            Self::Int32 | Self::Float32 => 4,
+        }
+    }
+}
+
+pub(crate) struct BufferSize {
+    pub min: i32,
+    pub max: i32,
+    pub preferred: i32,
+    pub granularity: i32,
+}
+
+impl BufferSize {
+    /// Starts from the driver preferred size and grows it by the driver granularity
+    /// until it holds at least `min_frames`, a granularity of -1 means powers of two only.
+    pub fn negotiate(&self, min_frames: i32) -> i32 {
+        let mut size = self.preferred;
+        while size < min_frames && size < self.max {
+            size = match self.granularity {
+                -1 => size * 2,
+                granularity if granularity > 0 => size + granularity,
+                _ => break,
+            };
+        }
+        size.clamp(self.min, self.max)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct DriverInfo {
+    pub name: String,
+    pub clsid: GUID,
+}
+
+/// Lists the drivers registered under HKLM\SOFTWARE\ASIO.
+pub(crate) fn enumerate_drivers() -> Result<Vec<DriverInfo>> {
+    let mut key = HKEY::default();
+    let result = unsafe {
+        RegOpenKeyExW(
+            HKEY_LOCAL_MACHINE,
+            windows::core::w!("SOFTWARE\\ASIO"),
+            None,
+            KEY_READ,
+            &mut key,
+        )
+    };
+    if result != ERROR_SUCCESS {
+        return Ok(Vec::new());
+    }
+    let mut drivers = Vec::new();
+    let mut index = 0;
+    loop {
+        let mut name = [0u16; 256];
+        let mut name_length = name.len() as u32;
+        let result = unsafe {
+            RegEnumKeyExW(
+                key,
+                index,
+                Some(PWSTR(name.as_mut_ptr())),
+                &mut name_length,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+        if result != ERROR_SUCCESS {
+            break;
+        }
+        index += 1;
+
+        let mut clsid = [0u16; 64];
+        let mut clsid_size = std::mem::size_of_val(&clsid) as u32;
+        let result = unsafe {
+            RegGetValueW(
+                key,
+                PCWSTR(name.as_ptr()),
+                windows::core::w!("CLSID"),
+                RRF_RT_REG_SZ,
+                None,
+                Some(clsid.as_mut_ptr() as *mut c_void),
+                Some(&mut clsid_size),
+            )
+        };
+        if result != ERROR_SUCCESS {
+            continue;
+        }
+        if let Ok(clsid) = unsafe { CLSIDFromString(PCWSTR(clsid.as_ptr())) } {
+            drivers.push(DriverInfo {
+                name: String::from_utf16_lossy(&name[..name_length as usize]),
+                clsid,
+            });
+        }
+    }
+    unsafe {
+        let _ = RegCloseKey(key);
+    }
+    Ok(drivers)
+}
+
+/// Single threaded apartment required by most ASIO drivers, released on drop.
+pub(crate) struct ComApartment;
+
+impl ComApartment {
+    pub fn new() -> Result<Self> {
+        unsafe { CoInitializeEx(None, COINIT_APARTMENTTHREADED).ok()? };
+        Ok(Self)
+    }
+}
+
+impl Drop for ComApartment {
+    fn drop(&mut self) {
+        unsafe { CoUninitialize() }
+    }
+}
+
+/// Loaded and initialized IASIO driver instance.
+pub(crate) struct Driver {
+    this: *mut c_void,
+}
+
+impl Driver {
+    pub fn load(info: &DriverInfo) -> Result<Self> {
+        let mut this = std::ptr::null_mut();
+        unsafe {
+            CoCreateInstance(
+                &info.clsid,
+                std::ptr::null_mut(),
+                CLSCTX_INPROC_SERVER,
+                &info.clsid,
+                &mut this,
+            )
+            .ok()?
+        };
+        if this.is_null() {
+            return Err(anyhow!("Failed to load ASIO driver {}", info.name));
+        }
+        let driver = Self { this };
+        if unsafe { (driver.vtbl().init)(driver.this, std::ptr::null_mut()) } == 0 {
+            return Err(anyhow!(
+                "Failed to initialize ASIO driver {}: {}",
+                info.name,
+                driver.error_message()
+            ));
+        }
+        Ok(driver)
+    }
+
+    fn vtbl(&self) -> &IAsioVtbl {
+        unsafe { &**(self.this as *const *const IAsioVtbl) }
+    }
+
+    fn check(&self, result: i32, operation: &str) -> Result<()> {
+        match result {
+            ASE_OK | ASE_SUCCESS => Ok(()),
+            _ => Err(anyhow!(
+                "ASIO {} failed ({}): {}",
+                operation,
+                result,
+                self.error_message()
+            )),
+        }
+    }
+
+    fn error_message(&self) -> String {
+        let mut message = [0 as c_char; 128];
+        unsafe {
+            (self.vtbl().get_error_message)(self.this, message.as_mut_ptr());
+            CStr::from_ptr(message.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+
+    pub fn output_channels(&self) -> Result<i32> {
+        let (mut inputs, mut outputs) = (0, 0);
+        let result = unsafe { (self.vtbl().get_channels)(self.this, &mut inputs, &mut outputs) };
+        self.check(result, "getChannels")?;
+        Ok(outputs)
+    }
+
+    pub fn buffer_size(&self) -> Result<BufferSize> {
+        let mut size = BufferSize {
+            min: 0,
+            max: 0,
+            preferred: 0,
+            granularity: 0,
+        };
+        let result = unsafe {
+            (self.vtbl().get_buffer_size)(
+                self.this,
+                &mut size.min,
+                &mut size.max,
+                &mut size.preferred,
+                &mut size.granularity,
+            )
+        };
+        self.check(result, "getBufferSize")?;
+        Ok(size)
+    }
+
+    pub fn can_sample_rate(&self, samplerate: f64) -> bool {
+        unsafe { (self.vtbl().can_sample_rate)(self.this, samplerate) == ASE_OK }
+    }
+
+    pub fn set_sample_rate(&self, samplerate: f64) -> Result<()> {
+        let result = unsafe { (self.vtbl().set_sample_rate)(self.this, samplerate) };
+        self.check(result, "setSampleRate")
+    }
+
+    pub fn output_sample_type(&self, channel: i32) -> Result<SampleType> {
+        let mut info = ChannelInfo {
+            channel,
+            is_input: 0,
+            is_active: 0,
+            channel_group: 0,
+            sample_type: 0,
+            name: [0; 32],
+        };
+        let result = unsafe { (self.vtbl().get_channel_info)(self.this, &mut info) };
+        self.check(result, "getChannelInfo")?;
+        SampleType::from_asio(info.sample_type)
+    }
+
+    pub fn create_buffers(
+        &self,
+        infos: &mut [BufferInfo],
+        buffer_size: i32,
+        callbacks: &'static Callbacks,
+    ) -> Result<()> {
+        let result = unsafe {
+            (self.vtbl().create_buffers)(
+                self.this,
+                infos.as_mut_ptr(),
+                infos.len() as i32,
+                buffer_size,
+                callbacks,
+            )
+        };
+        self.check(result, "createBuffers")
+    }
+
+    pub fn dispose_buffers(&self) -> Result<()> {
+        let result = unsafe { (self.vtbl().dispose_buffers)(self.this) };
+        self.check(result, "disposeBuffers")
+    }
+
+    pub fn start(&self) -> Result<()> {
+        let result = unsafe { (self.vtbl().start)(self.this) };
+        self.check(result, "start")
+    }
+
+    pub fn stop(&self) -> Result<()> {
+        let result = unsafe { (self.vtbl().stop)(self.this) };
+        self.check(result, "stop")
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        unsafe { (self.vtbl().release)(self.this) };
+    }
+}
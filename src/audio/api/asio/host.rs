@@ -0,0 +1,49 @@
+use anyhow::{anyhow, Result};
+
+use super::device::Device;
+use super::driver::enumerate_drivers;
+use crate::audio::HostTrait;
+
+#[derive(Clone, Copy)]
+pub struct Host;
+
+impl Host {
+    pub(crate) fn new() -> Self {
+        Self
+    }
+
+    // ASIO has no notion of default device, the first registered driver is used
+    fn enumerate_endpoints(&self) -> Result<Vec<crate::audio::Device>> {
+        Ok(enumerate_drivers()?
+            .into_iter()
+            .enumerate()
+            .map(|(index, info)| crate::audio::Device::Asio(Device::new(info, index == 0)))
+            .collect())
+    }
+}
+
+impl HostTrait for Host {
+    fn create_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+        let index = id.unwrap_or(0);
+        self.enumerate_endpoints()?
+            .into_iter()
+            .nth(index as usize)
+            .ok_or(anyhow!("No ASIO driver found at index {}", index))
+    }
+
+    fn create_capture_device(&self, _id: Option<u32>) -> Result<crate::audio::Device> {
+        Err(anyhow!("Recording is not supported by the ASIO backend"))
+    }
+
+    fn get_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        self.enumerate_endpoints()
+    }
+
+    fn get_capture_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        Ok(Vec::new())
+    }
+
+    fn get_default_device(&self) -> Result<crate::audio::Device> {
+        self.create_device(None)
+    }
+}
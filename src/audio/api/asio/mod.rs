@@ -0,0 +1,3 @@
+pub(crate) mod device;
+mod driver;
+pub(crate) mod host;
@@ -0,0 +1,315 @@
+use ::cpal::traits::{DeviceTrait as _, StreamTrait};
+use ::cpal::{BufferSize, Data, SampleFormat, Stream, StreamConfig, SupportedStreamConfigRange};
+use anyhow::{anyhow, Result};
+use log::{error, warn};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Receiver};
+use tracing::trace_span;
+
+use crate::audio::ring::{self, RingReader, RingWriter};
+use crate::audio::{
+    BitsPerSample, Capabilities, DeviceTrait, Direction, FadeControl, Fader, StreamParams,
+    StreamingData,
+};
+use crate::tools::cpu::CpuMeter;
+
+/// cpal does not tell its period before the stream runs, callbacks are assumed to ask for up to
+/// this much of a second.
+const PERIODS_PER_SECOND: usize = 8;
+
+enum Command {
+    Stop,
+}
+
+pub struct Device {
+    inner_device: ::cpal::Device,
+    direction: Direction,
+    is_default: bool,
+    commands: Option<mpsc::Sender<Command>>,
+    stream_thread_handle: Option<JoinHandle<Result<()>>>,
+    fade: Arc<FadeControl>,
+    cpu: Arc<CpuMeter>,
+}
+
+// 24 bits samples are carried in the upper bytes of 32 bits integers as cpal has no packed 24 bits format.
+fn sample_format(bits_per_sample: BitsPerSample) -> SampleFormat {
+    match bits_per_sample {
+        BitsPerSample::Bits16 => SampleFormat::I16,
+        BitsPerSample::Bits24 => SampleFormat::I32,
+        BitsPerSample::Bits32 => SampleFormat::F32,
+    }
+}
+
+impl StreamParams {
+    fn create_stream_config(&self) -> StreamConfig {
+        StreamConfig {
+            channels: self.channels as u16,
+            sample_rate: ::cpal::SampleRate(self.samplerate as u32),
+            buffer_size: BufferSize::Default,
+        }
+    }
+}
+
+/// Keeps the stream alive until a stop command is received, the stream is released on return.
+/// Pausing fades the output to silence while the stream keeps running.
+fn run_stream(stream: Stream, commands: mpsc::Receiver<Command>) -> Result<()> {
+    stream.play()?;
+    let _ = commands.recv();
+    Ok(())
+}
+
+struct OutputFiller {
+    data_rx: RingReader,
+    bits_per_sample: BitsPerSample,
+    frame_size: usize,
+    pending: Vec<u8>,
+    finished: bool,
+    commands: mpsc::Sender<Command>,
+    fader: Fader,
+    cpu: Arc<CpuMeter>,
+}
+
+impl OutputFiller {
+    fn fill(&mut self, data: &mut Data) {
+        let _busy = self.cpu.busy();
+        let _span = trace_span!("device_write", bytes = data.bytes().len()).entered();
+        let input_sample_size = self.bits_per_sample as usize / 8;
+        let output_sample_size = data.sample_format().sample_size();
+        let output = data.bytes_mut();
+        if self.fader.is_silent() {
+            output.fill(0);
+            return;
+        }
+        let samples = output.len() / output_sample_size;
+        let needed = samples * input_sample_size;
+        let missing = needed.saturating_sub(self.pending.len());
+        self.data_rx.read_into(&mut self.pending, missing);
+        self.finished = self.data_rx.is_finished() || self.data_rx.is_abandoned();
+
+        // Only complete frames are played, the remainder waits for the next callback
+        let available = self.pending.len().min(needed);
+        let available = available - available % (self.frame_size * input_sample_size);
+        let written_samples = available / input_sample_size;
+        self.fader.process(&mut self.pending[..available]);
+        for (input, output) in self.pending[..available]
+            .chunks_exact(input_sample_size)
+            .zip(output.chunks_exact_mut(output_sample_size))
+        {
+            match self.bits_per_sample {
+                BitsPerSample::Bits24 => {
+                    output.copy_from_slice(&[0, input[0], input[1], input[2]]);
+                }
+                _ => output.copy_from_slice(input),
+            }
+        }
+        if written_samples < samples && !self.finished {
+            self.cpu.underrun();
+        }
+        output[written_samples * output_sample_size..].fill(0);
+        self.pending.drain(..available);
+
+        if self.finished && self.pending.len() < self.frame_size * input_sample_size {
+            let _ = self.commands.send(Command::Stop);
+        }
+    }
+}
+
+impl Device {
+    pub(crate) fn new(
+        inner_device: ::cpal::Device,
+        direction: Direction,
+        is_default: bool,
+    ) -> Self {
+        Self {
+            inner_device,
+            direction,
+            is_default,
+            commands: None,
+            stream_thread_handle: None,
+            fade: Arc::new(FadeControl::default()),
+            cpu: Arc::new(CpuMeter::default()),
+        }
+    }
+
+    fn supported_configs(&self) -> Result<Vec<SupportedStreamConfigRange>> {
+        Ok(match self.direction {
+            Direction::Render => self.inner_device.supported_output_configs()?.collect(),
+            Direction::Capture => self.inner_device.supported_input_configs()?.collect(),
+        })
+    }
+}
+
+impl DeviceTrait for Device {
+    fn is_default(&self) -> Result<bool> {
+        Ok(self.is_default)
+    }
+
+    fn name(&self) -> Result<String> {
+        Ok(self.inner_device.name()?)
+    }
+
+    fn get_capabilities(&self) -> Result<Capabilities> {
+        let configs = self.supported_configs()?;
+        let default_capabilities = Capabilities::default();
+        let sample_rates = default_capabilities
+            .sample_rates
+            .into_iter()
+            .filter(|samplerate| {
+                configs.iter().any(|config| {
+                    config.min_sample_rate().0 <= *samplerate as u32
+                        && config.max_sample_rate().0 >= *samplerate as u32
+                })
+            })
+            .collect();
+        let bits_per_samples = default_capabilities
+            .bits_per_samples
+            .into_iter()
+            .filter(|bits_per_sample| {
+                configs
+                    .iter()
+                    .any(|config| config.sample_format() == sample_format(*bits_per_sample))
+            })
+            .collect();
+        let channels = default_capabilities
+            .channels
+            .into_iter()
+            .filter(|channels| {
+                configs
+                    .iter()
+                    .any(|config| config.channels() == *channels as u16)
+            })
+            .collect();
+        Ok(Capabilities {
+            sample_rates,
+            bits_per_samples,
+            channels,
+        })
+    }
+
+    fn start(&mut self, params: &StreamParams) -> Result<RingWriter> {
+        self.stop()?;
+        let period = params.samplerate as usize / PERIODS_PER_SECOND * params.frame_size();
+        let (data_tx, data_rx) = ring::channel(period * ring::PERIODS);
+        let (command_tx, command_rx) = mpsc::channel();
+
+        let device = self.inner_device.clone();
+        let config = params.create_stream_config();
+        let format = sample_format(params.bits_per_sample);
+        let mut filler = OutputFiller {
+            data_rx,
+            bits_per_sample: params.bits_per_sample,
+            frame_size: params.channels as usize,
+            pending: Vec::new(),
+            finished: false,
+            commands: command_tx.clone(),
+            fader: Fader::new(self.fade.clone(), params),
+            cpu: self.cpu.clone(),
+        };
+        self.fade.resume();
+        self.stream_thread_handle = Some(std::thread::spawn(move || {
+            let stream = device.build_output_stream_raw(
+                &config,
+                format,
+                move |data: &mut Data, _| filler.fill(data),
+                |err| error!("Output stream error: {}", err),
+                None,
+            )?;
+            run_stream(stream, command_rx)
+        }));
+        self.commands = Some(command_tx);
+        Ok(data_tx)
+    }
+
+    fn start_capture(&mut self, params: &StreamParams) -> Result<Receiver<StreamingData>> {
+        self.stop()?;
+        let buffer = params.channels as usize
+            * ((params.bits_per_sample as usize * params.samplerate as usize) / 8);
+        let (data_tx, data_rx) = channel::<StreamingData>(buffer);
+        let (command_tx, command_rx) = mpsc::channel();
+
+        let device = self.inner_device.clone();
+        let config = params.create_stream_config();
+        let format = sample_format(params.bits_per_sample);
+        let bits_per_sample = params.bits_per_sample;
+        let closed = command_tx.clone();
+        self.stream_thread_handle = Some(std::thread::spawn(move || {
+            let stream = device.build_input_stream_raw(
+                &config,
+                format,
+                move |data: &Data, _| {
+                    let sample_size = data.sample_format().sample_size();
+                    let kept = match bits_per_sample {
+                        BitsPerSample::Bits24 => sample_size - 1,
+                        _ => sample_size,
+                    };
+                    // The whole buffer goes through or none of it, a byte lost would shift
+                    // every sample recorded afterwards
+                    let count = data.bytes().len() / sample_size * kept;
+                    if data_tx.capacity() < count {
+                        if data_tx.is_closed() {
+                            let _ = closed.send(Command::Stop);
+                        } else {
+                            warn!("Input overrun, {} bytes dropped", count);
+                        }
+                        return;
+                    }
+                    for sample in data.bytes().chunks_exact(sample_size) {
+                        let bytes = match bits_per_sample {
+                            BitsPerSample::Bits24 => &sample[1..],
+                            _ => sample,
+                        };
+                        for byte in bytes {
+                            if data_tx.try_send(StreamingData::Data(*byte)).is_err()
+                                && data_tx.is_closed()
+                            {
+                                let _ = closed.send(Command::Stop);
+                                return;
+                            }
+                        }
+                    }
+                },
+                |err| error!("Input stream error: {}", err),
+                None,
+            )?;
+            run_stream(stream, command_rx)
+        }));
+        self.commands = Some(command_tx);
+        Ok(data_rx)
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.fade.pause();
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.fade.resume();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        if let Some(commands) = self.commands.take() {
+            let _ = commands.send(Command::Stop);
+        }
+        if let Some(handle) = self.stream_thread_handle.take() {
+            handle
+                .join()
+                .map_err(|_| anyhow!("Stream thread panicked"))??;
+        }
+        Ok(())
+    }
+
+    fn cpu_usage(&self) -> f64 {
+        self.cpu.usage()
+    }
+
+    fn cpu_time(&self) -> Duration {
+        self.cpu.busy_time()
+    }
+
+    fn underruns(&self) -> u64 {
+        self.cpu.underruns()
+    }
+}
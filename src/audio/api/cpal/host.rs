@@ -0,0 +1,105 @@
+use ::cpal::traits::{DeviceTrait as _, HostTrait as _};
+use ::cpal::HostId;
+use anyhow::{anyhow, Result};
+
+use super::device::Device;
+use crate::audio::{Direction, HostTrait};
+
+#[derive(Clone, Copy)]
+pub struct Host {
+    id: HostId,
+}
+
+impl Host {
+    pub(crate) fn new() -> Self {
+        Self {
+            id: ::cpal::default_host().id(),
+        }
+    }
+
+    fn inner_host(&self) -> Result<::cpal::Host> {
+        Ok(::cpal::host_from_id(self.id)?)
+    }
+
+    fn default_endpoint_name(&self, direction: Direction) -> Result<String> {
+        let host = self.inner_host()?;
+        let device = match direction {
+            Direction::Render => host.default_output_device(),
+            Direction::Capture => host.default_input_device(),
+        };
+        match device {
+            Some(device) => Ok(device.name()?),
+            None => Ok(String::new()),
+        }
+    }
+
+    fn get_default_endpoint(&self, direction: Direction) -> Result<Device> {
+        let host = self.inner_host()?;
+        let device = match direction {
+            Direction::Render => host.default_output_device(),
+            Direction::Capture => host.default_input_device(),
+        };
+        Ok(Device::new(
+            device.ok_or(anyhow!("No default device found"))?,
+            direction,
+            true,
+        ))
+    }
+
+    fn enumerate_endpoints(&self, direction: Direction) -> Result<Vec<crate::audio::Device>> {
+        let host = self.inner_host()?;
+        let default_device_name = self.default_endpoint_name(direction)?;
+        let devices: Vec<::cpal::Device> = match direction {
+            Direction::Render => host.output_devices()?.collect(),
+            Direction::Capture => host.input_devices()?.collect(),
+        };
+        Ok(devices
+            .into_iter()
+            .map(|device| {
+                let is_default = device.name().unwrap_or_default() == default_device_name;
+                crate::audio::Device::Cpal(Device::new(device, direction, is_default))
+            })
+            .collect())
+    }
+
+    fn create_endpoint(
+        &self,
+        id: Option<u32>,
+        direction: Direction,
+    ) -> Result<crate::audio::Device> {
+        match id {
+            Some(index) => self
+                .enumerate_endpoints(direction)?
+                .into_iter()
+                .nth(index as usize)
+                .ok_or(anyhow!("No device found at index {}", index)),
+            None => Ok(crate::audio::Device::Cpal(
+                self.get_default_endpoint(direction)?,
+            )),
+        }
+    }
+}
+
+impl HostTrait for Host {
+    fn create_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+        self.create_endpoint(id, Direction::Render)
+    }
+
+    fn create_capture_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+        self.create_endpoint(id, Direction::Capture)
+    }
+
+    fn get_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        self.enumerate_endpoints(Direction::Render)
+    }
+
+    fn get_capture_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        self.enumerate_endpoints(Direction::Capture)
+    }
+
+    fn get_default_device(&self) -> Result<crate::audio::Device> {
+        Ok(crate::audio::Device::Cpal(
+            self.get_default_endpoint(Direction::Render)?,
+        ))
+    }
+}
@@ -1 +1,7 @@
+#[cfg(all(windows, target_pointer_width = "64"))]
+pub(crate) mod asio;
+pub(crate) mod cpal;
+#[cfg(all(target_os = "linux", feature = "pipewire"))]
+pub(crate) mod pipewire;
+#[cfg(windows)]
 pub(crate) mod wasapi;
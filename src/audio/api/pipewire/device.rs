@@ -0,0 +1,414 @@
+use ::pipewire as pw;
+use anyhow::{anyhow, Result};
+use log::warn;
+use pw::{
+    context::Context,
+    main_loop::MainLoop,
+    properties::properties,
+    spa::{
+        self,
+        param::audio::{AudioFormat, AudioInfoRaw},
+        pod::{serialize::PodSerializer, Object, Pod, Value},
+    },
+    stream::{Stream, StreamFlags, StreamListener},
+};
+use std::io::Cursor;
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tracing::trace_span;
+
+use super::host::NodeInfo;
+use crate::audio::ring::{self, RingReader, RingWriter};
+use crate::audio::{
+    channel_mask, BitsPerSample, Capabilities, DeviceTrait, Direction, FadeControl, Fader,
+    FormFactor, StreamParams, StreamingData,
+};
+use crate::tools::cpu::CpuMeter;
+
+enum Command {
+    Stop,
+}
+
+pub struct Device {
+    node: Option<NodeInfo>,
+    direction: Direction,
+    commands: Option<pw::channel::Sender<Command>>,
+    stream_thread_handle: Option<JoinHandle<Result<()>>>,
+    fade: Arc<FadeControl>,
+    cpu: Arc<CpuMeter>,
+}
+
+// S24LE is the packed 24 bits layout streamed by the player
+fn audio_format(bits_per_sample: BitsPerSample) -> AudioFormat {
+    match bits_per_sample {
+        BitsPerSample::Bits16 => AudioFormat::S16LE,
+        BitsPerSample::Bits24 => AudioFormat::S24LE,
+        BitsPerSample::Bits32 => AudioFormat::F32LE,
+    }
+}
+
+/// SPA positions of the speakers of the WAVE channel mask, in its bit order.
+const POSITIONS: [u32; 11] = [
+    spa::sys::SPA_AUDIO_CHANNEL_FL,
+    spa::sys::SPA_AUDIO_CHANNEL_FR,
+    spa::sys::SPA_AUDIO_CHANNEL_FC,
+    spa::sys::SPA_AUDIO_CHANNEL_LFE,
+    spa::sys::SPA_AUDIO_CHANNEL_RL,
+    spa::sys::SPA_AUDIO_CHANNEL_RR,
+    spa::sys::SPA_AUDIO_CHANNEL_FLC,
+    spa::sys::SPA_AUDIO_CHANNEL_FRC,
+    spa::sys::SPA_AUDIO_CHANNEL_RC,
+    spa::sys::SPA_AUDIO_CHANNEL_SL,
+    spa::sys::SPA_AUDIO_CHANNEL_SR,
+];
+
+/// Speaker of each channel, so the graph maps them to the sink instead of taking them for
+/// auxiliary ones.
+fn positions(channels: usize) -> [u32; spa::sys::SPA_AUDIO_MAX_CHANNELS as usize] {
+    let mut positions =
+        [spa::sys::SPA_AUDIO_CHANNEL_UNKNOWN; spa::sys::SPA_AUDIO_MAX_CHANNELS as usize];
+    let mask = channel_mask(channels);
+    let speakers = POSITIONS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| mask & (1 << bit) != 0)
+        .map(|(_, position)| *position);
+    for (position, speaker) in positions.iter_mut().zip(speakers) {
+        *position = speaker;
+    }
+    positions
+}
+
+impl StreamParams {
+    fn create_format_pod(&self) -> Result<Vec<u8>> {
+        let mut audio_info = AudioInfoRaw::new();
+        audio_info.set_format(audio_format(self.bits_per_sample));
+        audio_info.set_rate(self.samplerate as u32);
+        audio_info.set_channels(self.channels as u32);
+        audio_info.set_position(positions(self.channels as usize));
+        Ok(PodSerializer::serialize(
+            Cursor::new(Vec::new()),
+            &Value::Object(Object {
+                type_: spa::sys::SPA_TYPE_OBJECT_Format,
+                id: spa::sys::SPA_PARAM_EnumFormat,
+                properties: audio_info.into(),
+            }),
+        )
+        .map_err(|err| anyhow!("Failed to build stream format: {:?}", err))?
+        .0
+        .into_inner())
+    }
+
+    /// Frames per graph cycle, about 10ms at the stream rate.
+    fn quantum(&self) -> u32 {
+        (self.samplerate as u32 / 100).next_power_of_two()
+    }
+
+    /// Asks the graph for the stream quantum, smaller ones underrun, and for the stream rate
+    /// itself to avoid resampling.
+    fn create_properties(&self, direction: Direction) -> pw::properties::Properties {
+        let samplerate = self.samplerate as u32;
+        let quantum = self.quantum();
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_ROLE => "Music",
+            *pw::keys::MEDIA_CATEGORY => match direction {
+                Direction::Render => "Playback",
+                Direction::Capture => "Capture",
+            },
+            *pw::keys::NODE_LATENCY => format!("{}/{}", quantum, samplerate),
+            *pw::keys::NODE_RATE => format!("1/{}", samplerate),
+        }
+    }
+}
+
+struct OutputFiller {
+    data_rx: Option<RingReader>,
+    frame_size: usize,
+    pending: Vec<u8>,
+    fader: Fader,
+    cpu: Arc<CpuMeter>,
+}
+
+impl OutputFiller {
+    /// Fills whole frames and pads with silence, returns the number of bytes handed to the graph.
+    fn fill(&mut self, output: &mut [u8]) -> usize {
+        let _busy = self.cpu.busy();
+        let _span = trace_span!("device_write", bytes = output.len()).entered();
+        let needed = output.len() - output.len() % self.frame_size;
+        if self.fader.is_silent() {
+            output[..needed].fill(0);
+            return needed;
+        }
+        let mut finished = self.data_rx.is_none();
+        if let Some(data_rx) = self.data_rx.as_mut() {
+            let missing = needed.saturating_sub(self.pending.len());
+            data_rx.read_into(&mut self.pending, missing);
+            finished = data_rx.is_finished() || data_rx.is_abandoned();
+        }
+
+        let available = self.pending.len().min(needed);
+        let available = available - available % self.frame_size;
+        self.fader.process(&mut self.pending[..available]);
+        output[..available].copy_from_slice(&self.pending[..available]);
+        if available < needed && !finished {
+            self.cpu.underrun();
+        }
+        output[available..needed].fill(0);
+        self.pending.drain(..available);
+
+        // Closing the stream lets the player know the track has been played
+        if finished && self.pending.len() < self.frame_size {
+            self.data_rx = None;
+        }
+        needed
+    }
+}
+
+fn register_output(
+    stream: &Stream,
+    data_rx: RingReader,
+    frame_size: usize,
+    fader: Fader,
+    cpu: Arc<CpuMeter>,
+) -> Result<StreamListener<OutputFiller>> {
+    let filler = OutputFiller {
+        data_rx: Some(data_rx),
+        frame_size,
+        pending: Vec::new(),
+        fader,
+        cpu,
+    };
+    Ok(stream
+        .add_local_listener_with_user_data(filler)
+        .process(move |stream, filler| {
+            if let Some(mut buffer) = stream.dequeue_buffer() {
+                let data = &mut buffer.datas_mut()[0];
+                let size = data.data().map(|output| filler.fill(output)).unwrap_or(0);
+                let chunk = data.chunk_mut();
+                *chunk.offset_mut() = 0;
+                *chunk.stride_mut() = frame_size as i32;
+                *chunk.size_mut() = size as u32;
+            }
+        })
+        .register()?)
+}
+
+fn register_input(
+    stream: &Stream,
+    data_tx: Sender<StreamingData>,
+    mainloop: &MainLoop,
+) -> Result<StreamListener<Sender<StreamingData>>> {
+    let mainloop = mainloop.clone();
+    Ok(stream
+        .add_local_listener_with_user_data(data_tx)
+        .process(move |stream, data_tx| {
+            if let Some(mut buffer) = stream.dequeue_buffer() {
+                let data = &mut buffer.datas_mut()[0];
+                let offset = data.chunk().offset() as usize;
+                let size = data.chunk().size() as usize;
+                if let Some(input) = data.data() {
+                    // The whole buffer goes through or none of it, a byte lost would shift
+                    // every sample recorded afterwards
+                    if data_tx.capacity() < size {
+                        if data_tx.is_closed() {
+                            mainloop.quit();
+                        } else {
+                            warn!("Input overrun, {} bytes dropped", size);
+                        }
+                        return;
+                    }
+                    for byte in &input[offset..offset + size] {
+                        if data_tx.try_send(StreamingData::Data(*byte)).is_err()
+                            && data_tx.is_closed()
+                        {
+                            mainloop.quit();
+                            return;
+                        }
+                    }
+                }
+            }
+        })
+        .register()?)
+}
+
+/// Runs the PipeWire loop owning the stream until a stop command is received.
+fn run_stream(
+    node: Option<String>,
+    direction: Direction,
+    params: StreamParams,
+    data: StreamData,
+    commands: pw::channel::Receiver<Command>,
+    started: mpsc::Sender<Result<()>>,
+) -> Result<()> {
+    pw::init();
+    let mainloop = MainLoop::new(None)?;
+    let context = Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+    let mut properties = params.create_properties(direction);
+    if let Some(node) = &node {
+        properties.insert(*pw::keys::TARGET_OBJECT, node.as_str());
+    }
+    let stream = Stream::new(&core, "rhap", properties)?;
+    let frame_size = params.channels as usize * (params.bits_per_sample as usize / 8);
+
+    let (_output_listener, _input_listener) = match data {
+        StreamData::Output(data_rx, fader, cpu) => (
+            Some(register_output(&stream, data_rx, frame_size, fader, cpu)?),
+            None,
+        ),
+        StreamData::Input(data_tx) => (None, Some(register_input(&stream, data_tx, &mainloop)?)),
+    };
+    // Capture stays on the main loop thread so it can quit the loop once the recorder is gone
+    let flags = match direction {
+        Direction::Render => StreamFlags::RT_PROCESS,
+        Direction::Capture => StreamFlags::empty(),
+    };
+
+    let format = params.create_format_pod()?;
+    let mut format_params = [Pod::from_bytes(&format).ok_or(anyhow!("Invalid stream format"))?];
+    let connected = stream.connect(
+        match direction {
+            Direction::Render => spa::utils::Direction::Output,
+            Direction::Capture => spa::utils::Direction::Input,
+        },
+        None,
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS | flags,
+        &mut format_params,
+    );
+    if let Err(err) = connected {
+        let _ = started.send(Err(err.into()));
+        return Ok(());
+    }
+    let _ = started.send(Ok(()));
+
+    // Pausing fades the output to silence while the stream keeps running
+    let _commands = commands.attach(mainloop.loop_(), {
+        let mainloop = mainloop.clone();
+        move |command| match command {
+            Command::Stop => mainloop.quit(),
+        }
+    });
+    mainloop.run();
+    stream.disconnect()?;
+    Ok(())
+}
+
+enum StreamData {
+    Output(RingReader, Fader, Arc<CpuMeter>),
+    Input(Sender<StreamingData>),
+}
+
+impl Device {
+    pub(crate) fn new(node: Option<NodeInfo>, direction: Direction) -> Self {
+        Self {
+            node,
+            direction,
+            commands: None,
+            stream_thread_handle: None,
+            fade: Arc::new(FadeControl::default()),
+            cpu: Arc::new(CpuMeter::default()),
+        }
+    }
+
+    fn start_stream(&mut self, params: &StreamParams, data: StreamData) -> Result<()> {
+        self.stop()?;
+        let (command_tx, command_rx) = pw::channel::channel();
+        let (started_tx, started_rx) = mpsc::channel();
+        let node = self.node.as_ref().map(|node| node.name.clone());
+        let direction = self.direction;
+        let params = *params;
+        self.stream_thread_handle = Some(std::thread::spawn(move || {
+            run_stream(node, direction, params, data, command_rx, started_tx)
+        }));
+        self.commands = Some(command_tx);
+        started_rx
+            .recv()
+            .map_err(|_| anyhow!("PipeWire stream thread exited"))?
+    }
+}
+
+impl DeviceTrait for Device {
+    fn is_default(&self) -> Result<bool> {
+        Ok(self.node.is_none())
+    }
+
+    fn name(&self) -> Result<String> {
+        Ok(match &self.node {
+            Some(node) => node.description.clone(),
+            None => String::from("Default"),
+        })
+    }
+
+    fn id(&self) -> Result<String> {
+        Ok(match &self.node {
+            Some(node) => node.name.clone(),
+            None => String::from("default"),
+        })
+    }
+
+    fn form_factor(&self) -> Result<FormFactor> {
+        Ok(self
+            .node
+            .as_ref()
+            .map_or(FormFactor::Unknown, |node| node.form_factor))
+    }
+
+    // The graph adapts any format, the stream rate is requested through node.rate
+    fn get_capabilities(&self) -> Result<Capabilities> {
+        Ok(Capabilities::default())
+    }
+
+    fn start(&mut self, params: &StreamParams) -> Result<RingWriter> {
+        let period = params.quantum() as usize * params.frame_size();
+        let (data_tx, data_rx) = ring::channel(period * ring::PERIODS);
+        self.fade.resume();
+        let fader = Fader::new(self.fade.clone(), params);
+        self.start_stream(params, StreamData::Output(data_rx, fader, self.cpu.clone()))?;
+        Ok(data_tx)
+    }
+
+    fn start_capture(&mut self, params: &StreamParams) -> Result<Receiver<StreamingData>> {
+        let buffer = params.channels as usize
+            * ((params.bits_per_sample as usize * params.samplerate as usize) / 8);
+        let (data_tx, data_rx) = channel::<StreamingData>(buffer);
+        self.start_stream(params, StreamData::Input(data_tx))?;
+        Ok(data_rx)
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        self.fade.pause();
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.fade.resume();
+        Ok(())
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        if let Some(commands) = self.commands.take() {
+            let _ = commands.send(Command::Stop);
+        }
+        if let Some(handle) = self.stream_thread_handle.take() {
+            handle
+                .join()
+                .map_err(|_| anyhow!("Stream thread panicked"))??;
+        }
+        Ok(())
+    }
+
+    fn cpu_usage(&self) -> f64 {
+        self.cpu.usage()
+    }
+
+    fn cpu_time(&self) -> Duration {
+        self.cpu.busy_time()
+    }
+
+    fn underruns(&self) -> u64 {
+        self.cpu.underruns()
+    }
+}
@@ -0,0 +1,138 @@
+use ::pipewire as pw;
+use anyhow::{anyhow, Result};
+use pw::{context::Context, main_loop::MainLoop, types::ObjectType};
+use std::{cell::RefCell, rc::Rc};
+
+use super::device::Device;
+use crate::audio::{Direction, FormFactor, HostTrait};
+
+pub(crate) struct NodeInfo {
+    pub name: String,
+    pub description: String,
+    pub form_factor: FormFactor,
+}
+
+#[derive(Clone, Copy)]
+pub struct Host;
+
+fn media_class(direction: Direction) -> &'static str {
+    match direction {
+        Direction::Render => "Audio/Sink",
+        Direction::Capture => "Audio/Source",
+    }
+}
+
+impl Host {
+    pub(crate) fn new() -> Self {
+        Self
+    }
+
+    /// Collects the audio nodes announced by the registry until the core acknowledges the sync.
+    fn enumerate_nodes(&self, direction: Direction) -> Result<Vec<NodeInfo>> {
+        pw::init();
+        let mainloop = MainLoop::new(None)?;
+        let context = Context::new(&mainloop)?;
+        let core = context.connect(None)?;
+        let registry = core.get_registry()?;
+        let nodes = Rc::new(RefCell::new(Vec::new()));
+
+        let pending = core.sync(0)?;
+        let _core_listener = core
+            .add_listener_local()
+            .done({
+                let mainloop = mainloop.clone();
+                move |id, seq| {
+                    if id == pw::core::PW_ID_CORE && seq == pending {
+                        mainloop.quit();
+                    }
+                }
+            })
+            .register();
+        let _registry_listener = registry
+            .add_listener_local()
+            .global({
+                let nodes = nodes.clone();
+                move |global| {
+                    let Some(props) = global.props else {
+                        return;
+                    };
+                    if global.type_ != ObjectType::Node
+                        || props.get(*pw::keys::MEDIA_CLASS) != Some(media_class(direction))
+                    {
+                        return;
+                    }
+                    if let Some(name) = props.get(*pw::keys::NODE_NAME) {
+                        // Passed on by the device of ALSA nodes, HDMI and S/PDIF only show in
+                        // the name of their profile
+                        let form_factor = match props.get("device.form-factor") {
+                            _ if props.get("device.bus") == Some("usb") => FormFactor::Usb,
+                            Some("headphone" | "headset") => FormFactor::Headphones,
+                            Some("speaker") => FormFactor::Speakers,
+                            _ if name.contains("hdmi") => FormFactor::Hdmi,
+                            _ if name.contains("iec958") => FormFactor::Spdif,
+                            _ => FormFactor::Unknown,
+                        };
+                        nodes.borrow_mut().push(NodeInfo {
+                            name: name.to_string(),
+                            description: props
+                                .get(*pw::keys::NODE_DESCRIPTION)
+                                .unwrap_or(name)
+                                .to_string(),
+                            form_factor,
+                        });
+                    }
+                }
+            })
+            .register();
+        mainloop.run();
+        Ok(nodes.take())
+    }
+
+    // Index 0 follows the session manager default, nodes are listed after it
+    fn enumerate_endpoints(&self, direction: Direction) -> Result<Vec<crate::audio::Device>> {
+        let mut devices = vec![crate::audio::Device::PipeWire(Device::new(None, direction))];
+        devices.extend(
+            self.enumerate_nodes(direction)?
+                .into_iter()
+                .map(|node| crate::audio::Device::PipeWire(Device::new(Some(node), direction))),
+        );
+        Ok(devices)
+    }
+
+    fn create_endpoint(
+        &self,
+        id: Option<u32>,
+        direction: Direction,
+    ) -> Result<crate::audio::Device> {
+        match id {
+            Some(index) => self
+                .enumerate_endpoints(direction)?
+                .into_iter()
+                .nth(index as usize)
+                .ok_or(anyhow!("No device found at index {}", index)),
+            None => Ok(crate::audio::Device::PipeWire(Device::new(None, direction))),
+        }
+    }
+}
+
+impl HostTrait for Host {
+    fn create_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+        self.create_endpoint(id, Direction::Render)
+    }
+
+    fn create_capture_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+        self.create_endpoint(id, Direction::Capture)
+    }
+
+    fn get_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        self.enumerate_endpoints(Direction::Render)
+    }
+
+    fn get_capture_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        self.enumerate_endpoints(Direction::Capture)
+    }
+
+    fn get_default_device(&self) -> Result<crate::audio::Device> {
+        self.create_endpoint(None, Direction::Render)
+    }
+}
@@ -12,7 +12,7 @@ use windows::Win32::Media::Audio::AUDCLNT_E_DEVICE_IN_USE;
 use windows::Win32::Media::Audio::AUDCLNT_E_ENDPOINT_CREATE_FAILED;
 use windows::Win32::Media::Audio::AUDCLNT_E_EXCLUSIVE_MODE_NOT_ALLOWED;
 use windows::Win32::Media::Audio::AUDCLNT_E_UNSUPPORTED_FORMAT;
-use windows::Win32::System::Com::CLSCTX_ALL;
+use windows::Win32::System::Com::{CoTaskMemFree, CLSCTX_ALL};
 use windows::Win32::System::Threading::AvRevertMmThreadCharacteristics;
 use windows::Win32::System::Threading::AvSetMmThreadCharacteristicsW;
 use windows::Win32::System::Threading::GetCurrentProcess;
@@ -31,9 +31,11 @@ use windows::{
         Foundation::{HANDLE, RPC_E_CHANGED_MODE, WAIT_OBJECT_0},
         Media::{
             Audio::{
-                IAudioClient, IAudioRenderClient, AUDCLNT_SHAREMODE_EXCLUSIVE,
-                AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_EVENTCALLBACK, WAVEFORMATEX,
-                WAVEFORMATEXTENSIBLE, WAVEFORMATEXTENSIBLE_0,
+                IAudioCaptureClient, IAudioClient, IAudioRenderClient, AUDCLNT_BUFFERFLAGS_SILENT,
+                AUDCLNT_SHAREMODE_EXCLUSIVE, AUDCLNT_SHAREMODE_SHARED,
+                AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM, AUDCLNT_STREAMFLAGS_EVENTCALLBACK,
+                AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY, WAVEFORMATEX, WAVEFORMATEXTENSIBLE,
+                WAVEFORMATEXTENSIBLE_0,
             },
             KernelStreaming::{KSDATAFORMAT_SUBTYPE_PCM, WAVE_FORMAT_EXTENSIBLE},
             Multimedia::KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
@@ -45,7 +47,9 @@ use windows::{
     },
 };
 
-use crate::audio::{BitsPerSample, StreamParams};
+use crate::audio::{
+    channel_mask, render::RenderClient, BitsPerSample, Direction, ExclusiveRefused, StreamParams,
+};
 
 //const REFTIMES_PER_MILLISEC: u64 = 10000;
 //const REFTIMES_PER_SEC: u64 = 10000000;
@@ -94,7 +98,9 @@ pub enum ShareMode {
 pub struct AudioClient {
     inner_client: IAudioClient,
     format: WaveFormat,
+    direction: Direction,
     renderer: Option<AudioRenderClient>,
+    capturer: Option<AudioCaptureClient>,
     max_buffer_frames: usize,
     sharemode: ShareMode,
     pollmode: bool,
@@ -130,6 +136,13 @@ impl AudioClient {
         Ok(())
     }
 
+    pub(crate) fn read(&self, data: &mut Vec<u8>) -> Result<()> {
+        if let Some(capturer) = &self.capturer {
+            capturer.read(self.format.get_block_align() as usize, data)?;
+        }
+        Ok(())
+    }
+
     fn is_supported_exclusive(&self, format: WaveFormat) -> Result<WaveFormat> {
         let first_test = unsafe {
             self.inner_client
@@ -171,6 +184,16 @@ impl AudioClient {
         }
     }
 
+    /// Format the audio engine mixes shared streams in.
+    pub(crate) fn mix_format(&self) -> Result<WaveFormat> {
+        unsafe {
+            let format = self.inner_client.GetMixFormat()?;
+            let mix = WaveFormat::from_waveformatex(format.read());
+            CoTaskMemFree(Some(format as *const _));
+            mix
+        }
+    }
+
     pub fn get_default_and_min_periods(&self) -> Result<(i64, i64)> {
         let mut default_period = 0;
         let mut min_period = 0;
@@ -236,11 +259,14 @@ impl AudioClient {
                     AUDCLNT_STREAMFLAGS_EVENTCALLBACK
                 }
             }
+            // The audio engine converts the track to its mix format
             ShareMode::Shared => {
+                let convert =
+                    AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM | AUDCLNT_STREAMFLAGS_SRC_DEFAULT_QUALITY;
                 if self.pollmode {
-                    0
+                    convert
                 } else {
-                    AUDCLNT_STREAMFLAGS_EVENTCALLBACK
+                    convert | AUDCLNT_STREAMFLAGS_EVENTCALLBACK
                 }
             }
         };
@@ -254,6 +280,21 @@ impl AudioClient {
                 self.format.get_format(),
                 None,
             );
+            // Left to the caller, which can fall back to shared mode
+            if let (ShareMode::Exclusive, Err(e)) = (&self.sharemode, &result) {
+                match e.code() {
+                    AUDCLNT_E_DEVICE_IN_USE => {
+                        return Err(ExclusiveRefused(String::from("the device is in use")).into())
+                    }
+                    AUDCLNT_E_EXCLUSIVE_MODE_NOT_ALLOWED => {
+                        return Err(ExclusiveRefused(String::from(
+                            "exclusive mode is not allowed on the device",
+                        ))
+                        .into())
+                    }
+                    _ => (),
+                }
+            }
             self.max_buffer_frames = self.inner_client.GetBufferSize()? as usize;
             match result {
                 Ok(()) => debug!("IAudioClient::Initialize ok"),
@@ -318,7 +359,10 @@ impl AudioClient {
             };
         };
 
-        self.renderer = Some(self.get_renderer()?);
+        match self.direction {
+            Direction::Render => self.renderer = Some(self.get_renderer()?),
+            Direction::Capture => self.capturer = Some(self.get_capturer()?),
+        }
         if !self.pollmode {
             self.eventhandle = Some(self.set_get_eventhandle()?);
         }
@@ -332,6 +376,12 @@ impl AudioClient {
         }))
     }
 
+    fn get_capturer(&self) -> Result<AudioCaptureClient> {
+        Ok(AudioCaptureClient(unsafe {
+            self.inner_client.GetService::<IAudioCaptureClient>()?
+        }))
+    }
+
     pub(crate) fn stop(&mut self) -> Result<()> {
         Ok(unsafe {
             self.inner_client.Stop()?;
@@ -340,7 +390,7 @@ impl AudioClient {
     }
 
     fn get_available_buffer_frames(&self) -> Result<usize> {
-        if self.pollmode {
+        if self.pollmode && self.direction == Direction::Render {
             Ok(self.max_buffer_frames - unsafe { self.inner_client.GetCurrentPadding()? as usize })
         } else {
             Ok(self.max_buffer_frames)
@@ -357,6 +407,11 @@ impl AudioClient {
                 event.wait_for_event(1000)?;
             }
             return Ok(());
+        } else if self.direction == Direction::Capture {
+            // Captured packets are drained all at once, polling twice per period keeps up with the device
+            let (default_period, _) = self.get_default_and_min_periods()?;
+            std::thread::sleep(Duration::from_micros(default_period as u64 / 20));
+            Ok(())
         } else {
             loop {
                 let available_buffer_size = self.get_available_buffer_frames()?;
@@ -368,7 +423,11 @@ impl AudioClient {
         }
     }
 
-    pub(crate) fn new(device: &IMMDevice, params: &StreamParams) -> Result<AudioClient> {
+    pub(crate) fn new(
+        device: &IMMDevice,
+        params: &StreamParams,
+        direction: Direction,
+    ) -> Result<AudioClient> {
         com_initialize();
         let sharemode = match params.exclusive {
             true => ShareMode::Exclusive,
@@ -378,7 +437,9 @@ impl AudioClient {
         Ok(AudioClient {
             inner_client,
             format: WaveFormat::from(params),
+            direction,
             renderer: None,
+            capturer: None,
             sharemode,
             max_buffer_frames: 0,
             pollmode: params.pollmode,
@@ -398,6 +459,36 @@ impl AudioClient {
     }
 }
 
+impl RenderClient for AudioClient {
+    fn available_buffer_size(&self) -> Result<usize> {
+        self.get_available_buffer_size()
+    }
+
+    fn frame_size(&self) -> usize {
+        self.format.get_block_align() as usize
+    }
+
+    fn requires_full_period(&self) -> bool {
+        !self.pollmode
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        AudioClient::write(self, data)
+    }
+
+    fn start(&mut self) -> Result<()> {
+        AudioClient::start(self)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        AudioClient::stop(self)
+    }
+
+    fn wait_for_buffer(&self) -> Result<()> {
+        AudioClient::wait_for_buffer(self)
+    }
+}
+
 pub struct EventHandle(HANDLE);
 impl EventHandle {
     fn wait_for_event(&self, timeout: u32) -> Result<()> {
@@ -439,6 +530,32 @@ impl AudioRenderClient {
     }
 }
 
+pub struct AudioCaptureClient(IAudioCaptureClient);
+impl AudioCaptureClient {
+    fn read(&self, n_block_align: usize, data: &mut Vec<u8>) -> Result<()> {
+        loop {
+            let packet_frames = unsafe { self.0.GetNextPacketSize()? };
+            if packet_frames == 0 {
+                return Ok(());
+            }
+            let mut buffer_ptr: *mut u8 = std::ptr::null_mut();
+            let mut frames = 0;
+            let mut flags = 0;
+            unsafe {
+                self.0
+                    .GetBuffer(&mut buffer_ptr, &mut frames, &mut flags, None, None)?;
+                let nbr_bytes = frames as usize * n_block_align;
+                if flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 {
+                    data.resize(data.len() + nbr_bytes, 0);
+                } else {
+                    data.extend_from_slice(std::slice::from_raw_parts(buffer_ptr, nbr_bytes));
+                }
+                self.0.ReleaseBuffer(frames)?;
+            }
+        }
+    }
+}
+
 /// Struct wrapping a [WAVEFORMATEXTENSIBLE](https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible) format descriptor.
 #[derive(Clone)]
 pub struct WaveFormat(WAVEFORMATEXTENSIBLE);
@@ -472,13 +589,7 @@ impl WaveFormat {
             BitsPerSample::Bits32 => KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
         };
         // https://docs.microsoft.com/en-us/windows/win32/api/mmreg/ns-mmreg-waveformatextensible
-        let mask = match channels {
-            ch if ch <= 18 => {
-                // setting bit for each channel
-                (1 << ch) - 1
-            }
-            _ => 0,
-        };
+        let mask = channel_mask(channels);
         let wave_fmt = WAVEFORMATEXTENSIBLE {
             Format: wave_format,
             Samples: sample,
@@ -496,7 +607,11 @@ impl WaveFormat {
         Ok(WaveFormat::new(bits_per_sample, samplerate, channels))
     }
 
-    fn get_samples_per_sec(&self) -> u32 {
+    pub(crate) fn get_bits_per_sample(&self) -> u16 {
+        self.0.Format.wBitsPerSample
+    }
+
+    pub(crate) fn get_samples_per_sec(&self) -> u32 {
         self.0.Format.nSamplesPerSec
     }
 
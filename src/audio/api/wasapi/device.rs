@@ -1,19 +1,42 @@
-use anyhow::Result;
-use tokio::sync::mpsc::{channel, Sender};
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{channel, Receiver};
+use tokio::sync::Notify;
 use windows::Win32::{
-    Devices::FunctionDiscovery::PKEY_DeviceInterface_FriendlyName,
-    Media::Audio::IMMDevice,
-    System::Com::{StructuredStorage::PropVariantToStringAlloc, STGM_READ},
+    Devices::FunctionDiscovery::{
+        PKEY_DeviceInterface_FriendlyName, PKEY_Device_DriverVersion, PKEY_Device_EnumeratorName,
+        PKEY_Device_FriendlyName,
+    },
+    Media::Audio::{IMMDevice, PKEY_AudioEndpoint_FormFactor},
+    System::Com::{
+        StructuredStorage::{PropVariantToStringAlloc, PropVariantToUInt32},
+        STGM_READ,
+    },
 };
 
 use super::api::{com_initialize, AudioClient, ShareMode, ThreadPriority, WaveFormat};
-use crate::audio::{Capabilities, DeviceTrait, StreamParams, StreamingData};
+use crate::audio::ring::{self, RingWriter};
+use crate::audio::{
+    probe, render::render, thread::AudioThread, BitsPerSample, Capabilities, DeviceTrait,
+    Direction, FadeControl, FadeDurations, Fader, FormFactor, SampleRate, StreamParams,
+    StreamingData,
+};
+use crate::tools::cpu::CpuMeter;
+
+/// Time given to the render loop to release the client before its thread is left behind.
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub struct Device {
     default_device_id: String,
     inner_device: IMMDevice,
-    stream_thread_handle: Option<tokio::task::JoinHandle<Result<()>>>,
+    direction: Direction,
+    stream_thread: Option<AudioThread>,
     high_priority_mode: bool,
+    fade: Arc<FadeControl>,
+    cancel: Option<Arc<Notify>>,
+    cpu: Arc<CpuMeter>,
 }
 
 impl StreamParams {
@@ -29,14 +52,19 @@ impl StreamParams {
 impl Device {
     pub(crate) fn new(
         inner_device: IMMDevice,
+        direction: Direction,
         default_device_id: String,
         high_priority_mode: bool,
     ) -> Result<Self> {
         Ok(Self {
             inner_device,
+            direction,
             default_device_id,
-            stream_thread_handle: Option::None,
+            stream_thread: None,
             high_priority_mode,
+            fade: Arc::new(FadeControl::default()),
+            cancel: None,
+            cpu: Arc::new(CpuMeter::default()),
         })
     }
 
@@ -45,7 +73,7 @@ impl Device {
     }
 
     pub fn get_client(&self, params: &StreamParams) -> Result<AudioClient> {
-        AudioClient::new(&self.inner_device, params)
+        AudioClient::new(&self.inner_device, params, self.direction)
     }
 }
 
@@ -63,111 +91,190 @@ impl DeviceTrait for Device {
         Ok(unsafe { PropVariantToStringAlloc(&prop)?.to_string()? })
     }
 
-    fn get_capabilities(&self) -> Result<Capabilities> {
-        let mut sample_rates = Vec::new();
-        let mut bits_per_samples = Vec::new();
+    fn id(&self) -> Result<String> {
+        self.get_id()
+    }
+
+    fn friendly_name(&self) -> Result<String> {
+        let store = unsafe { self.inner_device.OpenPropertyStore(STGM_READ)? };
+        let prop = unsafe { store.GetValue(&PKEY_Device_FriendlyName)? };
+        Ok(unsafe { PropVariantToStringAlloc(&prop)?.to_string()? })
+    }
+
+    // USB adapters mostly report speakers, their bus tells them apart
+    fn form_factor(&self) -> Result<FormFactor> {
+        let store = unsafe { self.inner_device.OpenPropertyStore(STGM_READ)? };
+        let enumerator = unsafe { store.GetValue(&PKEY_Device_EnumeratorName) }
+            .ok()
+            .and_then(|prop| unsafe { PropVariantToStringAlloc(&prop) }.ok())
+            .and_then(|name| unsafe { name.to_string() }.ok());
+        if enumerator.is_some_and(|name| name.eq_ignore_ascii_case("USB")) {
+            return Ok(FormFactor::Usb);
+        }
+        let prop = unsafe { store.GetValue(&PKEY_AudioEndpoint_FormFactor)? };
+        // Values of EndpointFormFactor, headsets play like headphones
+        let form_factor = unsafe { PropVariantToUInt32(&prop) }.unwrap_or_default();
+        Ok(match form_factor {
+            1 => FormFactor::Speakers,
+            2 => FormFactor::LineLevel,
+            3 | 5 => FormFactor::Headphones,
+            8 => FormFactor::Spdif,
+            9 => FormFactor::Hdmi,
+            _ => FormFactor::Unknown,
+        })
+    }
 
-        let default_capabilities = Capabilities::default();
+    // Endpoints without the property, such as virtual ones, are probed once
+    fn driver_version(&self) -> Result<Option<String>> {
+        let store = unsafe { self.inner_device.OpenPropertyStore(STGM_READ)? };
+        let prop = unsafe { store.GetValue(&PKEY_Device_DriverVersion)? };
+        Ok(unsafe { PropVariantToStringAlloc(&prop) }
+            .ok()
+            .and_then(|version| unsafe { version.to_string() }.ok()))
+    }
 
+    fn get_capabilities(&self) -> Result<Capabilities> {
+        probe::all_formats(self)
+    }
+
+    // Activating a client takes a while on some drivers, a single one checks every format
+    fn supported_formats(&self, formats: &[(SampleRate, BitsPerSample, u8)]) -> Result<Vec<bool>> {
+        let Some((samplerate, bits_per_sample, channels)) = formats.first().copied() else {
+            return Ok(Vec::new());
+        };
         com_initialize();
-        for bits_per_sample in default_capabilities.bits_per_samples {
-            let default_capabilities = Capabilities::default();
-            for samplerate in default_capabilities.sample_rates {
+        let params = StreamParams {
+            samplerate,
+            bits_per_sample,
+            channels,
+            exclusive: true,
+            pollmode: false,
+            fade: FadeDurations::default(),
+        };
+        let client = self.get_client(&params)?;
+        Ok(formats
+            .iter()
+            .map(|(samplerate, bits_per_sample, channels)| {
                 let params = StreamParams {
-                    samplerate,
-                    bits_per_sample,
-                    channels: 2,
-                    exclusive: true,
-                    pollmode: false,
-                };
-                let client = self.get_client(&params)?;
-                let wave_format = params.create_wave_format();
-                let sharemode = match params.exclusive {
-                    true => ShareMode::Exclusive,
-                    false => ShareMode::Shared,
+                    samplerate: *samplerate,
+                    bits_per_sample: *bits_per_sample,
+                    channels: *channels,
+                    ..params
                 };
-                match sharemode {
-                    ShareMode::Exclusive => {
-                        if let Ok(_) = client.is_supported(wave_format, &sharemode) {
-                            if !bits_per_samples.contains(&bits_per_sample) {
-                                bits_per_samples.push(bits_per_sample);
-                            };
-                            if !sample_rates.contains(&samplerate) {
-                                sample_rates.push(samplerate);
-                            };
-                        }
-                    }
-                    ShareMode::Shared => match client.is_supported(wave_format, &sharemode) {
-                        Ok(_) => {
-                            if !bits_per_samples.contains(&bits_per_sample) {
-                                bits_per_samples.push(bits_per_sample);
-                            };
-                            if !sample_rates.contains(&samplerate) {
-                                sample_rates.push(samplerate);
-                            };
-                        }
-                        Err(_) => {}
-                    },
-                }
-            }
-        }
+                client
+                    .is_supported(params.create_wave_format(), &ShareMode::Exclusive)
+                    .is_ok()
+            })
+            .collect())
+    }
 
-        Ok(crate::audio::Capabilities {
-            sample_rates,
-            bits_per_samples,
+    // The engine takes any channel count, the track is resampled to the mix rate
+    fn shared_params(&self, params: &StreamParams) -> Result<StreamParams> {
+        com_initialize();
+        let mix = self.get_client(params)?.mix_format()?;
+        let samplerate = Capabilities::default()
+            .sample_rates
+            .into_iter()
+            .find(|samplerate| *samplerate as u32 == mix.get_samples_per_sec())
+            .ok_or(anyhow!(
+                "Unsupported mix rate {}Hz",
+                mix.get_samples_per_sec()
+            ))?;
+        Ok(StreamParams {
+            samplerate,
+            bits_per_sample: BitsPerSample::from(mix.get_bits_per_sample() as usize),
+            exclusive: false,
+            ..*params
         })
     }
 
-    fn start(&mut self, params: &StreamParams) -> Result<Sender<StreamingData>> {
+    fn start(&mut self, params: &StreamParams) -> Result<RingWriter> {
+        self.stop()?;
+        let mut client = self.get_client(params)?;
+        client.initialize()?;
+        // Nothing is queued yet, the whole device buffer is one period
+        let period = client.get_available_buffer_size()?;
+        let (data_tx, mut data_rx) = ring::channel(period * ring::PERIODS);
+        let high_priority_mode = self.high_priority_mode;
+        self.fade.resume();
+        let mut fader = Fader::new(self.fade.clone(), params);
+        let cancel = Arc::new(Notify::new());
+        self.cancel = Some(cancel.clone());
+        let cpu = self.cpu.clone();
+
+        self.stream_thread = Some(AudioThread::spawn("rhap-render", move || async move {
+            com_initialize();
+            let _thread_priority = ThreadPriority::new(high_priority_mode)?;
+            render(&mut client, &mut data_rx, &mut fader, &cancel, &cpu).await
+        })?);
+        Ok(data_tx)
+    }
+
+    fn start_capture(&mut self, params: &StreamParams) -> Result<Receiver<StreamingData>> {
         self.stop()?;
         let buffer = params.channels as usize
-            * ((params.bits_per_sample as usize * params.samplerate as usize) / 8 as usize);
-        let (data_tx, mut data_rx) = channel::<StreamingData>(buffer);
+            * ((params.bits_per_sample as usize * params.samplerate as usize) / 8);
+        let (data_tx, data_rx) = channel::<StreamingData>(buffer);
 
         let mut client = self.get_client(params)?;
         client.initialize()?;
         let high_priority_mode = self.high_priority_mode;
 
-        self.stream_thread_handle = Some(tokio::spawn(async move {
+        // Ends once the receiver is dropped
+        self.stream_thread = Some(AudioThread::spawn("rhap-capture", move || async move {
+            com_initialize();
             let _thread_priority = ThreadPriority::new(high_priority_mode)?;
-            let mut client_started = false;
             let mut buffer = vec![];
-            let mut available_buffer_size = client.get_available_buffer_size()?;
-            while let Some(streaming_data) = data_rx.recv().await {
-                match streaming_data {
-                    StreamingData::Data(data) => {
-                        buffer.push(data);
-                        if buffer.len() == available_buffer_size {
-                            client.write(buffer.as_slice())?;
-                            if !client_started {
-                                client.start()?;
-                                client_started = true;
-                            }
-                            client.wait_for_buffer()?;
-                            available_buffer_size = client.get_available_buffer_size()?;
-                            buffer.clear();
-                        }
+            client.start()?;
+            'capture: loop {
+                client.wait_for_buffer()?;
+                client.read(&mut buffer)?;
+                for data in buffer.drain(..) {
+                    if data_tx.send(StreamingData::Data(data)).await.is_err() {
+                        break 'capture;
                     }
-                    StreamingData::EndOfStream => break,
-                };
+                }
             }
             client.stop()
-        }));
-        Ok(data_tx)
+        })?);
+        Ok(data_rx)
     }
 
     fn pause(&mut self) -> Result<()> {
+        self.fade.pause();
         Ok(())
     }
 
     fn resume(&mut self) -> Result<()> {
+        self.fade.resume();
         Ok(())
     }
 
     fn stop(&mut self) -> Result<()> {
-        if let Some(handle) = self.stream_thread_handle.take() {
-            handle.abort();
+        let Some(thread) = self.stream_thread.take() else {
+            return Ok(());
+        };
+        // The render loop stops the client itself, capture stops with its receiver
+        if let Some(cancel) = self.cancel.take() {
+            cancel.notify_one();
+            match thread.join(STOP_TIMEOUT) {
+                Some(Err(err)) => warn!("Render stream failed: {}", err),
+                Some(Ok(())) => (),
+                None => warn!("Render stream did not stop in time, leaving it behind"),
+            }
         }
         Ok(())
     }
+
+    fn cpu_usage(&self) -> f64 {
+        self.cpu.usage()
+    }
+
+    fn cpu_time(&self) -> Duration {
+        self.cpu.busy_time()
+    }
+
+    fn underruns(&self) -> u64 {
+        self.cpu.underruns()
+    }
 }
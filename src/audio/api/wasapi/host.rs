@@ -1,9 +1,10 @@
-use super::{api::com_initialize, device::Device};
-use crate::audio::HostTrait;
+use super::{api::com_initialize, device::Device, notifications::Notifications};
+use crate::audio::{DeviceWatch, Direction, HostTrait};
 use anyhow::Result;
 use windows::Win32::{
     Media::Audio::{
-        eMultimedia, eRender, IMMDeviceEnumerator, MMDeviceEnumerator, DEVICE_STATE_ACTIVE,
+        eCapture, eMultimedia, eRender, EDataFlow, IMMDeviceEnumerator, MMDeviceEnumerator,
+        DEVICE_STATE_ACTIVE,
     },
     System::Com::{CoCreateInstance, CLSCTX_ALL},
 };
@@ -13,63 +14,112 @@ pub struct Host {
     high_priority_mode: bool,
 }
 
+fn data_flow(direction: Direction) -> EDataFlow {
+    match direction {
+        Direction::Render => eRender,
+        Direction::Capture => eCapture,
+    }
+}
+
 impl Host {
     pub(crate) fn new(high_priority_mode: bool) -> Self {
         Self { high_priority_mode }
     }
 
     pub fn get_default_device(&self) -> Result<Device> {
+        self.get_default_endpoint(Direction::Render)
+    }
+
+    fn get_default_endpoint(&self, direction: Direction) -> Result<Device> {
         com_initialize();
         let enumerator: IMMDeviceEnumerator =
             unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
-        let device = unsafe { enumerator.GetDefaultAudioEndpoint(eRender, eMultimedia)? };
+        let device =
+            unsafe { enumerator.GetDefaultAudioEndpoint(data_flow(direction), eMultimedia)? };
         let default_device_id = unsafe { device.GetId()?.to_string()? };
-        Ok(Device::new(device, default_device_id, self.high_priority_mode)?)
+        Device::new(
+            device,
+            direction,
+            default_device_id,
+            self.high_priority_mode,
+        )
     }
-}
 
-impl HostTrait for Host {
-    fn create_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+    fn create_endpoint(
+        &self,
+        id: Option<u32>,
+        direction: Direction,
+    ) -> Result<crate::audio::Device> {
         com_initialize();
         let enumerator: IMMDeviceEnumerator =
             unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
 
         let devices_collection =
-            unsafe { enumerator.EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)? };
+            unsafe { enumerator.EnumAudioEndpoints(data_flow(direction), DEVICE_STATE_ACTIVE)? };
 
-        let default_device = self.get_default_device()?;
+        let default_device = self.get_default_endpoint(direction)?;
         let default_device_id = default_device.get_id()?;
         let device = match id {
             Some(index) => Device::new(
                 unsafe { devices_collection.Item(index)? },
+                direction,
                 default_device_id,
-                self.high_priority_mode
+                self.high_priority_mode,
             )?,
             _ => default_device,
         };
         Ok(crate::audio::Device::Wasapi(device))
     }
 
-    fn get_devices(&self) -> Result<Vec<crate::audio::Device>> {
+    fn enumerate_endpoints(&self, direction: Direction) -> Result<Vec<crate::audio::Device>> {
         com_initialize();
         let enumerator: IMMDeviceEnumerator =
             unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
         let devices_collection =
-            unsafe { enumerator.EnumAudioEndpoints(eRender, DEVICE_STATE_ACTIVE)? };
-        let default_device = self.get_default_device()?;
+            unsafe { enumerator.EnumAudioEndpoints(data_flow(direction), DEVICE_STATE_ACTIVE)? };
+        let default_device = self.get_default_endpoint(direction)?;
         let default_device_id = default_device.get_id()?;
 
         let mut enumerated_devices: Vec<crate::audio::Device> = vec![];
 
         for i in 0..unsafe { devices_collection.GetCount()? } {
             let inner_device = unsafe { devices_collection.Item(i)? };
-            let device = Device::new(inner_device, default_device_id.clone(), self.high_priority_mode)?;
+            let device = Device::new(
+                inner_device,
+                direction,
+                default_device_id.clone(),
+                self.high_priority_mode,
+            )?;
             enumerated_devices.push(crate::audio::Device::Wasapi(device));
         }
         Ok(enumerated_devices)
     }
+}
+
+impl HostTrait for Host {
+    fn create_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+        self.create_endpoint(id, Direction::Render)
+    }
+
+    fn create_capture_device(&self, id: Option<u32>) -> Result<crate::audio::Device> {
+        self.create_endpoint(id, Direction::Capture)
+    }
+
+    fn get_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        self.enumerate_endpoints(Direction::Render)
+    }
+
+    fn get_capture_devices(&self) -> Result<Vec<crate::audio::Device>> {
+        self.enumerate_endpoints(Direction::Capture)
+    }
 
     fn get_default_device(&self) -> Result<crate::audio::Device> {
         Ok(crate::audio::Device::Wasapi(self.get_default_device()?))
     }
+
+    fn watch_devices(&self) -> Result<Option<DeviceWatch>> {
+        let (removed_tx, removed_rx) = std::sync::mpsc::channel();
+        let registration = Notifications::register(removed_tx)?;
+        Ok(Some(DeviceWatch::new(removed_rx, registration)))
+    }
 }
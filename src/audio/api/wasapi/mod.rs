@@ -1,3 +1,4 @@
 pub(crate) mod device;
 pub(crate) mod host;
+mod notifications;
 mod api;
@@ -0,0 +1,107 @@
+use super::{api::com_initialize, device::Device};
+use crate::audio::{DeviceTrait, Direction};
+use anyhow::Result;
+use log::warn;
+use std::sync::mpsc::Sender;
+use windows::core::{implement, Interface, PCWSTR};
+use windows::Win32::{
+    Foundation::PROPERTYKEY,
+    Media::Audio::{
+        eRender, EDataFlow, ERole, IMMDeviceEnumerator, IMMEndpoint, IMMNotificationClient,
+        IMMNotificationClient_Impl, MMDeviceEnumerator, DEVICE_STATE, DEVICE_STATE_ACTIVE,
+    },
+    System::Com::{CoCreateInstance, CLSCTX_ALL},
+};
+
+/// Reports the render endpoints leaving the active state. Unplugged endpoints stay known to
+/// Windows, only their state changes, so they can still be named.
+#[implement(IMMNotificationClient)]
+struct Client {
+    removed: Sender<String>,
+}
+
+impl Client {
+    /// `None` for capture endpoints.
+    fn render_device_name(id: &PCWSTR) -> Result<Option<String>> {
+        com_initialize();
+        let enumerator: IMMDeviceEnumerator =
+            unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+        let device = unsafe { enumerator.GetDevice(*id)? };
+        if unsafe { device.cast::<IMMEndpoint>()?.GetDataFlow()? } != eRender {
+            return Ok(None);
+        }
+        Ok(Some(
+            Device::new(device, Direction::Render, String::new(), false)?.name()?,
+        ))
+    }
+}
+
+impl IMMNotificationClient_Impl for Client_Impl {
+    fn OnDeviceStateChanged(&self, id: &PCWSTR, state: DEVICE_STATE) -> windows::core::Result<()> {
+        if state != DEVICE_STATE_ACTIVE {
+            match Client::render_device_name(id) {
+                Ok(Some(name)) => {
+                    let _ = self.removed.send(name);
+                }
+                Ok(None) => (),
+                Err(err) => warn!("Cannot name the device gone: {}", err),
+            }
+        }
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, _id: &PCWSTR) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, _id: &PCWSTR) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(
+        &self,
+        _flow: EDataFlow,
+        _role: ERole,
+        _id: &PCWSTR,
+    ) -> windows::core::Result<()> {
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(
+        &self,
+        _id: &PCWSTR,
+        _key: &PROPERTYKEY,
+    ) -> windows::core::Result<()> {
+        Ok(())
+    }
+}
+
+/// Registration of the client with the device enumerator, undone once dropped.
+pub(crate) struct Notifications {
+    enumerator: IMMDeviceEnumerator,
+    client: IMMNotificationClient,
+}
+
+impl Notifications {
+    pub(crate) fn register(removed: Sender<String>) -> Result<Self> {
+        com_initialize();
+        let enumerator: IMMDeviceEnumerator =
+            unsafe { CoCreateInstance(&MMDeviceEnumerator, None, CLSCTX_ALL)? };
+        let client: IMMNotificationClient = Client { removed }.into();
+        unsafe { enumerator.RegisterEndpointNotificationCallback(&client)? };
+        Ok(Self { enumerator, client })
+    }
+}
+
+unsafe impl Send for Notifications {}
+
+impl Drop for Notifications {
+    fn drop(&mut self) {
+        if let Err(err) = unsafe {
+            self.enumerator
+                .UnregisterEndpointNotificationCallback(&self.client)
+        } {
+            warn!("Cannot stop watching the devices: {}", err);
+        }
+    }
+}
@@ -1,27 +1,151 @@
-use super::{api, Capabilities, StreamParams, StreamingData};
+use super::ring::RingWriter;
+use super::{
+    api, BitsPerSample, Capabilities, FormFactor, SampleRate, StreamParams, StreamingData,
+};
 use anyhow::{anyhow, Result};
-use tokio::sync::mpsc::Sender;
+use log::warn;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
 
 pub trait DeviceTrait: Send + Sync {
     fn is_default(&self) -> Result<bool>;
     fn name(&self) -> Result<String>;
+    /// Identifies the device across runs, its name for backends without ids.
+    fn id(&self) -> Result<String> {
+        self.name()
+    }
+    /// Name of the endpoint as the system shows it, telling apart the outputs of one adapter.
+    fn friendly_name(&self) -> Result<String> {
+        self.name()
+    }
+    /// Kind of endpoint, unknown for backends which cannot tell.
+    fn form_factor(&self) -> Result<FormFactor> {
+        Ok(FormFactor::Unknown)
+    }
+    /// Version of the driver, probed capabilities are kept until it changes.
+    fn driver_version(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
     fn get_capabilities(&self) -> Result<Capabilities>;
-    fn start(&mut self, params: &StreamParams) -> Result<Sender<StreamingData>>;
+    /// Which of `formats` play, checked in one go so that backends probing formats one at a
+    /// time set up once for all of them.
+    fn supported_formats(&self, formats: &[(SampleRate, BitsPerSample, u8)]) -> Result<Vec<bool>> {
+        let capabilities = self.get_capabilities()?;
+        Ok(formats
+            .iter()
+            .map(|(samplerate, bits_per_sample, channels)| {
+                capabilities.sample_rates.contains(samplerate)
+                    && capabilities.bits_per_samples.contains(bits_per_sample)
+                    && capabilities.channels.contains(channels)
+            })
+            .collect())
+    }
+    /// Whether a single format plays.
+    fn supports(
+        &self,
+        samplerate: SampleRate,
+        bits_per_sample: BitsPerSample,
+        channels: u8,
+    ) -> Result<bool> {
+        let supported = self.supported_formats(&[(samplerate, bits_per_sample, channels)])?;
+        Ok(supported.contains(&true))
+    }
+    /// Format to play `params` in shared mode, the one of the system mixer for backends
+    /// having one.
+    fn shared_params(&self, params: &StreamParams) -> Result<StreamParams> {
+        Ok(StreamParams {
+            exclusive: false,
+            ..*params
+        })
+    }
+    fn start(&mut self, params: &StreamParams) -> Result<RingWriter>;
+    fn start_capture(&mut self, params: &StreamParams) -> Result<Receiver<StreamingData>>;
     fn pause(&mut self) -> Result<()>;
     fn resume(&mut self) -> Result<()>;
     fn stop(&mut self) -> Result<()>;
+    /// Share of one core used by the render thread.
+    fn cpu_usage(&self) -> f64;
+    /// CPU time used by the render thread since the device was opened.
+    fn cpu_time(&self) -> Duration;
+    /// Periods the render thread padded with silence for lack of data.
+    fn underruns(&self) -> u64;
 }
 
 pub enum Device {
     None,
+    #[cfg(windows)]
     Wasapi(api::wasapi::device::Device),
+    #[cfg(all(windows, target_pointer_width = "64"))]
+    Asio(api::asio::device::Device),
+    Cpal(api::cpal::device::Device),
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    PipeWire(api::pipewire::device::Device),
+}
+
+/// Format set in the config of a device, used whatever the device reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PinnedFormat {
+    pub samplerate: Option<SampleRate>,
+    pub bits_per_sample: Option<BitsPerSample>,
+    pub channels: Option<u8>,
 }
 
 impl Device {
-    pub fn adjust_stream_params(&self, params: &StreamParams) -> Result<StreamParams> {
-        let capabilities = self.get_capabilities()?;
-        let contains_sample_rates = capabilities.sample_rates.contains(&params.samplerate);
-        let contains_bits_per_samples = capabilities.bits_per_samples.contains(&params.bits_per_sample);
+    /// Backend the device belongs to, as set in the config.
+    pub fn backend(&self) -> &'static str {
+        match self {
+            #[cfg(windows)]
+            Self::Wasapi(_) => "wasapi",
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(_) => "asio",
+            Self::Cpal(_) => "cpal",
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(_) => "pipewire",
+            Self::None => "none",
+        }
+    }
+
+    /// The device is only probed for what is not pinned. Tracks get the fewest channels
+    /// holding their speakers when the device lacks their layout.
+    pub fn adjust_stream_params(
+        &self,
+        params: &StreamParams,
+        pinned: &PinnedFormat,
+    ) -> Result<StreamParams> {
+        // Pinning the format is meant to spare the probe, the channels are then kept as is
+        if let (Some(samplerate), Some(bits_per_sample)) =
+            (pinned.samplerate, pinned.bits_per_sample)
+        {
+            return Ok(StreamParams {
+                samplerate,
+                bits_per_sample,
+                channels: pinned.channels.unwrap_or(params.channels),
+                ..*params
+            });
+        }
+        let capabilities = super::probe::capabilities(self)?;
+        let channels = match pinned.channels {
+            Some(channels) => channels,
+            None => capabilities.layout_for(params.channels).unwrap_or_else(|| {
+                warn!(
+                    "No layout of the device holds {} channels, opening it with them anyway",
+                    params.channels
+                );
+                params.channels
+            }),
+        };
+        let params = &StreamParams {
+            samplerate: pinned.samplerate.unwrap_or(params.samplerate),
+            bits_per_sample: pinned.bits_per_sample.unwrap_or(params.bits_per_sample),
+            channels,
+            ..*params
+        };
+        let contains_sample_rates =
+            pinned.samplerate.is_some() || capabilities.sample_rates.contains(&params.samplerate);
+        let contains_bits_per_samples = pinned.bits_per_sample.is_some()
+            || capabilities
+                .bits_per_samples
+                .contains(&params.bits_per_sample);
         if !contains_sample_rates || !contains_bits_per_samples {
             let samplerate = if contains_sample_rates {
                 params.samplerate
@@ -33,11 +157,11 @@ impl Device {
             } else {
                 *capabilities.bits_per_samples.last().unwrap()
             };
-            return Ok(StreamParams {
+            Ok(StreamParams {
                 samplerate,
                 bits_per_sample,
                 ..*params
-            });
+            })
         } else {
             Ok(StreamParams {
                 ..*params
@@ -48,58 +172,240 @@ impl Device {
 
 impl DeviceTrait for Device {
     fn is_default(&self) -> Result<bool> {
-        let device = match self {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
             Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
             Self::None => return Ok(false),
         };
         device.is_default()
     }
 
     fn name(&self) -> Result<String> {
-        let device = match self {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
             Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
             Self::None => return Ok(String::from("none")),
         };
         device.name()
     }
 
+    fn id(&self) -> Result<String> {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Ok(String::from("none")),
+        };
+        device.id()
+    }
+
+    fn friendly_name(&self) -> Result<String> {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Ok(String::from("none")),
+        };
+        device.friendly_name()
+    }
+
+    fn form_factor(&self) -> Result<FormFactor> {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Ok(FormFactor::Unknown),
+        };
+        device.form_factor()
+    }
+
+    fn driver_version(&self) -> Result<Option<String>> {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Ok(None),
+        };
+        device.driver_version()
+    }
+
     fn get_capabilities(&self) -> Result<Capabilities> {
-        let device = match self {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
             Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
             Self::None => return Ok(Capabilities::default()),
         };
         device.get_capabilities()
     }
 
-    fn start(&mut self, params: &StreamParams) -> Result<Sender<StreamingData>> {
-        let device = match self {
+    fn supported_formats(&self, formats: &[(SampleRate, BitsPerSample, u8)]) -> Result<Vec<bool>> {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Ok(vec![true; formats.len()]),
+        };
+        device.supported_formats(formats)
+    }
+
+    fn shared_params(&self, params: &StreamParams) -> Result<StreamParams> {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Err(anyhow!("No host selected")),
+        };
+        device.shared_params(params)
+    }
+
+    fn start(&mut self, params: &StreamParams) -> Result<RingWriter> {
+        let device: &mut dyn DeviceTrait = match self {
+            #[cfg(windows)]
             Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
             Self::None => return Err(anyhow!("No host selected")),
         };
         device.start(params)
     }
 
+    fn start_capture(&mut self, params: &StreamParams) -> Result<Receiver<StreamingData>> {
+        let device: &mut dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Err(anyhow!("No host selected")),
+        };
+        device.start_capture(params)
+    }
+
     fn pause(&mut self) -> Result<()> {
-        let device = match self {
+        let device: &mut dyn DeviceTrait = match self {
+            #[cfg(windows)]
             Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
             Self::None => return Ok(()),
         };
         device.pause()
     }
 
     fn resume(&mut self) -> Result<()> {
-        let device = match self {
+        let device: &mut dyn DeviceTrait = match self {
+            #[cfg(windows)]
             Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
             Self::None => return Ok(()),
         };
         device.resume()
     }
 
     fn stop(&mut self) -> Result<()> {
-        let device = match self {
+        let device: &mut dyn DeviceTrait = match self {
+            #[cfg(windows)]
             Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
             Self::None => return Ok(()),
         };
         device.stop()
     }
+
+    fn cpu_usage(&self) -> f64 {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return 0.0,
+        };
+        device.cpu_usage()
+    }
+
+    fn cpu_time(&self) -> Duration {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return Duration::ZERO,
+        };
+        device.cpu_time()
+    }
+
+    fn underruns(&self) -> u64 {
+        let device: &dyn DeviceTrait = match self {
+            #[cfg(windows)]
+            Self::Wasapi(device) => device,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(device) => device,
+            Self::Cpal(device) => device,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(device) => device,
+            Self::None => return 0,
+        };
+        device.underruns()
+    }
 }
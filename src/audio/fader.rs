@@ -0,0 +1,126 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use super::{BitsPerSample, StreamParams};
+
+/// Length of the fades applied when pausing and resuming, zero switches abruptly.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct FadeDurations {
+    pub pause: Duration,
+    pub resume: Duration,
+}
+
+/// Pause state shared between a device and its streaming loop.
+#[derive(Default)]
+pub struct FadeControl {
+    paused: AtomicBool,
+    resumed: Notify,
+}
+
+impl FadeControl {
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+        self.resumed.notify_waiters();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub async fn wait_resumed(&self) {
+        while self.is_paused() {
+            let resumed = self.resumed.notified();
+            if !self.is_paused() {
+                break;
+            }
+            resumed.await;
+        }
+    }
+}
+
+/// Ramps the interleaved stream bytes towards silence while paused and back once resumed,
+/// samples are left untouched at unity gain so playback stays bit perfect.
+pub struct Fader {
+    control: Arc<FadeControl>,
+    bits_per_sample: BitsPerSample,
+    channels: usize,
+    gain: f64,
+    pause_step: f64,
+    resume_step: f64,
+}
+
+fn step(duration: Duration, samplerate: usize) -> f64 {
+    let frames = duration.as_secs_f64() * samplerate as f64;
+    if frames < 1.0 {
+        1.0
+    } else {
+        1.0 / frames
+    }
+}
+
+impl Fader {
+    pub fn new(control: Arc<FadeControl>, params: &StreamParams) -> Self {
+        let samplerate = params.samplerate as usize;
+        Self {
+            control,
+            bits_per_sample: params.bits_per_sample,
+            channels: params.channels as usize,
+            gain: 1.0,
+            pause_step: step(params.fade.pause, samplerate),
+            resume_step: step(params.fade.resume, samplerate),
+        }
+    }
+
+    /// True once a pause has faded out completely, nothing should be consumed until resumed.
+    pub fn is_silent(&self) -> bool {
+        self.gain == 0.0 && self.control.is_paused()
+    }
+
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub async fn wait_resumed(&self) {
+        self.control.wait_resumed().await
+    }
+
+    pub fn process(&mut self, bytes: &mut [u8]) {
+        let paused = self.control.is_paused();
+        if self.gain == 1.0 && !paused {
+            return;
+        }
+        let sample_size = self.bits_per_sample as usize / 8;
+        for frame in bytes.chunks_exact_mut(sample_size * self.channels) {
+            self.gain = if paused {
+                (self.gain - self.pause_step).max(0.0)
+            } else {
+                (self.gain + self.resume_step).min(1.0)
+            };
+            for sample in frame.chunks_exact_mut(sample_size) {
+                self.scale(sample);
+            }
+        }
+    }
+
+    fn scale(&self, sample: &mut [u8]) {
+        match self.bits_per_sample {
+            BitsPerSample::Bits16 => {
+                let value = i16::from_le_bytes([sample[0], sample[1]]) as f64 * self.gain;
+                sample.copy_from_slice(&(value as i16).to_le_bytes());
+            }
+            BitsPerSample::Bits24 => {
+                let value = i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8;
+                let value = (value as f64 * self.gain) as i32;
+                sample.copy_from_slice(&value.to_le_bytes()[..3]);
+            }
+            BitsPerSample::Bits32 => {
+                let value = f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+                sample.copy_from_slice(&((value as f64 * self.gain) as f32).to_le_bytes());
+            }
+        }
+    }
+}
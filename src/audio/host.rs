@@ -1,43 +1,131 @@
 use super::{Device, api};
 use anyhow::Result;
+use std::sync::mpsc::Receiver;
 
 
 pub trait HostTrait: Send + Sync {
     fn create_device(&self, id: Option<u32>) -> Result<Device>;
+    fn create_capture_device(&self, id: Option<u32>) -> Result<Device>;
     fn get_devices(&self) -> Result<Vec<Device>>;
+    fn get_capture_devices(&self) -> Result<Vec<Device>>;
     fn get_default_device(&self) -> Result<Device>;
+    /// Output devices going away, for the hosts which report them.
+    fn watch_devices(&self) -> Result<Option<DeviceWatch>> {
+        Ok(None)
+    }
+}
+
+/// Output devices unplugged or disabled, by name, as the host reports them.
+pub struct DeviceWatch {
+    removed: Receiver<String>,
+    /// Registration with the host, undone once dropped
+    _registration: Box<dyn Send>,
+}
+
+impl DeviceWatch {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    pub(crate) fn new(removed: Receiver<String>, registration: impl Send + 'static) -> Self {
+        Self {
+            removed,
+            _registration: Box::new(registration),
+        }
+    }
+
+    /// Name of a device gone since the last call.
+    pub fn removed(&self) -> Option<String> {
+        self.removed.try_recv().ok()
+    }
 }
 
 #[derive(Clone, Copy)]
 pub enum Host {
+    #[cfg(windows)]
     Wasapi(api::wasapi::host::Host),
+    #[cfg(all(windows, target_pointer_width = "64"))]
+    Asio(api::asio::host::Host),
+    Cpal(api::cpal::host::Host),
+    #[cfg(all(target_os = "linux", feature = "pipewire"))]
+    PipeWire(api::pipewire::host::Host),
 }
 
-impl HostTrait for Host {
-    fn get_devices(&self) -> Result<Vec<Device>> {
+impl Host {
+    fn inner(&self) -> &dyn HostTrait {
         match self {
-            Self::Wasapi(host) => host.get_devices(),
+            #[cfg(windows)]
+            Self::Wasapi(host) => host,
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            Self::Asio(host) => host,
+            Self::Cpal(host) => host,
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            Self::PipeWire(host) => host,
         }
     }
+}
+
+impl HostTrait for Host {
+    fn get_devices(&self) -> Result<Vec<Device>> {
+        self.inner().get_devices()
+    }
+
+    fn get_capture_devices(&self) -> Result<Vec<Device>> {
+        self.inner().get_capture_devices()
+    }
 
     fn create_device(&self, id: Option<u32>) -> Result<Device> {
-        match self {
-            Self::Wasapi(host) => host.create_device(id),
-        }
+        self.inner().create_device(id)
+    }
+
+    fn create_capture_device(&self, id: Option<u32>) -> Result<Device> {
+        self.inner().create_capture_device(id)
     }
 
     fn get_default_device(&self) -> Result<Device> {
-        match self {
-            Self::Wasapi(host) => Ok(super::device::Device::Wasapi(host.get_default_device()?)),
-        }
+        self.inner().get_default_device()
+    }
+
+    fn watch_devices(&self) -> Result<Option<DeviceWatch>> {
+        self.inner().watch_devices()
     }
 }
 
 impl Host {
-    pub(crate) fn new(name: &str, high_priority_mode: bool) -> Self {
+    pub fn new(name: &str, high_priority_mode: bool) -> Self {
         match name {
+            #[cfg(windows)]
             "wasapi" => Host::Wasapi(api::wasapi::host::Host::new(high_priority_mode)),
-            _ => Host::Wasapi(api::wasapi::host::Host::new(high_priority_mode)),
+            #[cfg(all(windows, target_pointer_width = "64"))]
+            "asio" => Host::Asio(api::asio::host::Host::new()),
+            "cpal" => Host::Cpal(api::cpal::host::Host::new()),
+            #[cfg(all(target_os = "linux", feature = "pipewire"))]
+            "pipewire" => Host::PipeWire(api::pipewire::host::Host::new()),
+            _ => Self::native(high_priority_mode),
         }
     }
+
+    /// Backends built in this binary, by their command line name.
+    pub(crate) fn backends() -> Vec<&'static str> {
+        let mut backends = Vec::new();
+        if cfg!(windows) {
+            backends.push("wasapi");
+        }
+        if cfg!(all(windows, target_pointer_width = "64")) {
+            backends.push("asio");
+        }
+        backends.push("cpal");
+        if cfg!(all(target_os = "linux", feature = "pipewire")) {
+            backends.push("pipewire");
+        }
+        backends
+    }
+
+    #[cfg(windows)]
+    fn native(high_priority_mode: bool) -> Self {
+        Host::Wasapi(api::wasapi::host::Host::new(high_priority_mode))
+    }
+
+    /// Falls back to cpal on platforms without a native backend
+    #[cfg(not(windows))]
+    fn native(_high_priority_mode: bool) -> Self {
+        Host::Cpal(api::cpal::host::Host::new())
+    }
 }
@@ -1,9 +1,18 @@
 pub(crate) mod api;
 pub(crate) mod host;
 pub(crate) mod device;
+pub(crate) mod fader;
+pub(crate) mod probe;
+pub(crate) mod render;
+pub mod ring;
+pub(crate) mod routing;
+pub(crate) mod thread;
 
-pub use host::{HostTrait, Host};
-pub use device::{DeviceTrait, Device};
+pub use device::{Device, DeviceTrait, PinnedFormat};
+pub use fader::{FadeControl, FadeDurations, Fader};
+pub use host::{DeviceWatch, Host, HostTrait};
+pub use ring::{RingReader, RingWriter};
+pub use routing::ChannelRouting;
 
 #[repr(usize)]
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -14,6 +23,7 @@ pub enum SampleRate {
     Rate96000Hz = 96000,
     Rate176400Hz = 176400,
     Rate192000Hz = 192000,
+    Rate352800Hz = 352800,
 }
 
 impl From<usize> for SampleRate {
@@ -25,6 +35,7 @@ impl From<usize> for SampleRate {
             96000 => SampleRate::Rate96000Hz,
             176400 => SampleRate::Rate176400Hz,
             192000 => SampleRate::Rate192000Hz,
+            352800 => SampleRate::Rate352800Hz,
             _ => panic!("Invalid sample rate"),
         }
     }
@@ -49,13 +60,41 @@ impl From<usize> for BitsPerSample {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Capabilities {
     pub sample_rates: Vec<SampleRate>,
     pub bits_per_samples: Vec<BitsPerSample>,
+    /// Channel counts the device opens with, in the layouts of `channel_mask`
+    pub channels: Vec<u8>,
 }
 
-impl Capabilities {
-    pub fn default() -> Self {
+/// Speaker positions of a channel count as a WAVE channel mask, in the order FLAC lays out
+/// its channels. Devices expect the channels of a stream in these positions.
+pub fn channel_mask(channels: usize) -> u32 {
+    match channels {
+        // Centre
+        1 => 0x4,
+        // Front left and right
+        2 => 0x3,
+        // Front left, right and centre
+        3 => 0x7,
+        // Front and back pairs
+        4 => 0x33,
+        // Front left, right and centre, back pair
+        5 => 0x37,
+        // 5.1, back pair
+        6 => 0x3f,
+        // 6.1, back centre and side pair
+        7 => 0x70f,
+        // 7.1, back and side pairs
+        8 => 0x63f,
+        ch if ch <= 18 => (1 << ch) - 1,
+        _ => 0,
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
         Self {
             sample_rates: vec![
                 SampleRate::Rate44100Hz,
@@ -64,23 +103,103 @@ impl Capabilities {
                 SampleRate::Rate96000Hz,
                 SampleRate::Rate176400Hz,
                 SampleRate::Rate192000Hz,
+                SampleRate::Rate352800Hz,
             ],
             bits_per_samples: vec![
                 BitsPerSample::Bits16,
                 BitsPerSample::Bits24,
                 BitsPerSample::Bits32,
             ],
+            channels: vec![1, 2, 3, 4, 5, 6, 7, 8],
         }
     }
 }
 
+impl Capabilities {
+    /// Channels to open the device with for tracks with `channels`, the fewest holding
+    /// every speaker of the track. `None` when no layout does.
+    pub fn layout_for(&self, channels: u8) -> Option<u8> {
+        if self.channels.contains(&channels) {
+            return Some(channels);
+        }
+        let mask = channel_mask(channels as usize);
+        self.channels
+            .iter()
+            .copied()
+            .filter(|candidate| {
+                *candidate > channels && mask & !channel_mask(*candidate as usize) == 0
+            })
+            .min()
+    }
+
+    /// Records a supported format, its rate and size are kept once each.
+    pub fn add(&mut self, samplerate: SampleRate, bits_per_sample: BitsPerSample) {
+        if !self.sample_rates.contains(&samplerate) {
+            self.sample_rates.push(samplerate);
+        }
+        if !self.bits_per_samples.contains(&bits_per_sample) {
+            self.bits_per_samples.push(bits_per_sample);
+        }
+    }
+
+    /// DoP frames are sent as 24 bits PCM at a sixteenth of the DSD rate.
+    pub fn supports_dop(&self, samplerate: SampleRate) -> bool {
+        self.sample_rates.contains(&samplerate)
+            && self.bits_per_samples.contains(&BitsPerSample::Bits24)
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct StreamParams {
     pub channels: u8,
     pub samplerate: SampleRate,
     pub bits_per_sample: BitsPerSample,
+    // Only honored by native backends, cpal streams are always shared
+    #[cfg_attr(not(windows), allow(dead_code))]
     pub exclusive: bool,
+    #[cfg_attr(not(windows), allow(dead_code))]
     pub pollmode: bool,
+    pub fade: FadeDurations,
+}
+
+impl StreamParams {
+    /// Bytes of one frame, a sample for each channel.
+    pub fn frame_size(&self) -> usize {
+        self.channels as usize * self.bits_per_sample as usize / 8
+    }
+}
+
+/// The device could not be opened in exclusive mode, another application holds it or its
+/// settings forbid it. It can still play in shared mode.
+#[derive(Debug)]
+pub struct ExclusiveRefused(pub String);
+
+impl std::fmt::Display for ExclusiveRefused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Exclusive mode refused: {}", self.0)
+    }
+}
+
+impl std::error::Error for ExclusiveRefused {}
+
+/// Kind of endpoint as the system reports it, telling apart devices with alike names. Devices
+/// are listed in this order.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FormFactor {
+    Speakers,
+    Headphones,
+    Usb,
+    Spdif,
+    Hdmi,
+    LineLevel,
+    #[default]
+    Unknown,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Render,
+    Capture,
 }
 
 #[derive(Copy, Clone)]
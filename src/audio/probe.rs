@@ -0,0 +1,310 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use super::{Capabilities, Device, DeviceTrait, Host, HostTrait};
+
+/// Capabilities of a device as stored, with the driver they were probed with.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Entry {
+    driver: Option<String>,
+    sample_rates: Vec<usize>,
+    bits_per_samples: Vec<usize>,
+    /// Missing from the capabilities stored before the channels were probed
+    #[serde(default)]
+    channels: Vec<u8>,
+}
+
+impl Entry {
+    fn new(driver: Option<String>, capabilities: &Capabilities) -> Self {
+        Self {
+            driver,
+            sample_rates: capabilities
+                .sample_rates
+                .iter()
+                .map(|samplerate| *samplerate as usize)
+                .collect(),
+            bits_per_samples: capabilities
+                .bits_per_samples
+                .iter()
+                .map(|bits_per_sample| *bits_per_sample as usize)
+                .collect(),
+            channels: capabilities.channels.clone(),
+        }
+    }
+
+    /// Formats unknown to this version, from an edited file, are left out.
+    fn capabilities(&self) -> Capabilities {
+        let all = Capabilities::default();
+        Capabilities {
+            sample_rates: all
+                .sample_rates
+                .into_iter()
+                .filter(|samplerate| self.sample_rates.contains(&(*samplerate as usize)))
+                .collect(),
+            bits_per_samples: all
+                .bits_per_samples
+                .into_iter()
+                .filter(|bits_per_sample| {
+                    self.bits_per_samples.contains(&(*bits_per_sample as usize))
+                })
+                .collect(),
+            channels: self.channels.clone(),
+        }
+    }
+}
+
+/// Probed capabilities by device, kept until its driver changes.
+#[derive(Default, Serialize, Deserialize)]
+struct Entries(HashMap<String, Entry>);
+
+impl Entries {
+    fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rhap").join("capabilities.json"))
+    }
+
+    /// Starts empty when nothing was stored or the file cannot be read.
+    fn load() -> Self {
+        let Some(path) = Self::path() else {
+            return Self::default();
+        };
+        match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|err| {
+                warn!("Cannot read {}: {}", path.display(), err);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(path) = Self::path() {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        }
+        Ok(())
+    }
+
+    /// Stored capabilities, unless the driver changed since they were probed.
+    fn get(&self, key: &str, driver: &Option<String>) -> Option<Capabilities> {
+        self.0
+            .get(key)
+            .filter(|entry| entry.driver == *driver && !entry.channels.is_empty())
+            .map(Entry::capabilities)
+    }
+}
+
+/// Loaded from disk on first use, saved each time a device is probed.
+fn cache() -> &'static Mutex<Entries> {
+    static CACHE: OnceLock<Mutex<Entries>> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(Entries::load()))
+}
+
+/// Devices of different backends may share an id.
+fn key(device: &Device) -> Result<String> {
+    Ok(format!("{}:{}", device.backend(), device.id()?))
+}
+
+fn driver(device: &Device) -> Option<String> {
+    device
+        .driver_version()
+        .inspect_err(|err| warn!("Cannot read the driver version: {}", err))
+        .ok()
+        .flatten()
+}
+
+fn cached(key: &str, driver: &Option<String>) -> Option<Capabilities> {
+    cache().lock().ok()?.get(key, driver)
+}
+
+fn store(key: String, driver: Option<String>, capabilities: &Capabilities) {
+    if let Ok(mut cache) = cache().lock() {
+        cache.0.insert(key, Entry::new(driver, capabilities));
+        if let Err(err) = cache.save() {
+            warn!("Cannot save the device capabilities: {}", err);
+        }
+    }
+}
+
+/// Every rate and depth in stereo, then the layouts in the first format found, checked in two
+/// batches whatever the backend.
+pub(crate) fn all_formats(device: &(impl DeviceTrait + ?Sized)) -> Result<Capabilities> {
+    let all = Capabilities::default();
+    let mut capabilities = Capabilities {
+        sample_rates: Vec::new(),
+        bits_per_samples: Vec::new(),
+        channels: Vec::new(),
+    };
+    let stereo: Vec<_> = all
+        .bits_per_samples
+        .iter()
+        .flat_map(|bits_per_sample| {
+            all.sample_rates
+                .iter()
+                .map(|samplerate| (*samplerate, *bits_per_sample, 2))
+        })
+        .collect();
+    let supported = device.supported_formats(&stereo)?;
+    let mut found = stereo
+        .into_iter()
+        .zip(supported)
+        .filter(|(_, supported)| *supported)
+        .map(|(format, _)| format)
+        .peekable();
+    // Layouts are tried in the first format found
+    let Some((samplerate, bits_per_sample, _)) = found.peek().copied() else {
+        return Ok(capabilities);
+    };
+    for (samplerate, bits_per_sample, _) in found {
+        capabilities.add(samplerate, bits_per_sample);
+    }
+    let layouts: Vec<_> = all
+        .channels
+        .iter()
+        .map(|channels| (samplerate, bits_per_sample, *channels))
+        .collect();
+    let supported = device.supported_formats(&layouts)?;
+    capabilities.channels = layouts
+        .into_iter()
+        .zip(supported)
+        .filter(|(_, supported)| *supported)
+        .map(|((_, _, channels), _)| channels)
+        .collect();
+    Ok(capabilities)
+}
+
+/// Capabilities of `device`, probed once per driver version.
+pub fn capabilities(device: &Device) -> Result<Capabilities> {
+    let (key, driver) = (key(device)?, driver(device));
+    if let Some(capabilities) = cached(&key, &driver) {
+        return Ok(capabilities);
+    }
+    let capabilities = device.get_capabilities()?;
+    store(key, driver, &capabilities);
+    Ok(capabilities)
+}
+
+/// Probes `device` again next time, once it refused a format it was found to support.
+pub fn forget(device: &Device) {
+    if let (Ok(key), Ok(mut cache)) = (key(device), cache().lock()) {
+        if cache.0.remove(&key).is_some() {
+            if let Err(err) = cache.save() {
+                warn!("Cannot save the device capabilities: {}", err);
+            }
+        }
+    }
+}
+
+/// What a background probe found so far.
+#[derive(Clone)]
+pub struct Probed {
+    pub capabilities: Capabilities,
+    pub done: bool,
+}
+
+/// Probes output devices in the background, each on its own thread so that the list is shown
+/// right away and a slow driver only holds back its own device.
+#[derive(Default)]
+pub struct CapabilityProbes {
+    probes: HashMap<String, Arc<Mutex<Probed>>>,
+}
+
+impl CapabilityProbes {
+    /// Starts probing `device` unless it is cached or already being probed.
+    pub fn get(&mut self, host: Host, device: &Device) -> Result<Probed> {
+        let (key, driver) = (key(device)?, driver(device));
+        if let Some(capabilities) = cached(&key, &driver) {
+            return Ok(Probed {
+                capabilities,
+                done: true,
+            });
+        }
+        let probe = self.probes.entry(key.clone()).or_insert_with(|| {
+            let probe = Arc::new(Mutex::new(Probed {
+                capabilities: Capabilities {
+                    sample_rates: Vec::new(),
+                    bits_per_samples: Vec::new(),
+                    channels: Vec::new(),
+                },
+                done: false,
+            }));
+            let found = probe.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = run(host, key.clone(), driver, &found) {
+                    warn!("Cannot probe {}: {}", key, err);
+                }
+                if let Ok(mut found) = found.lock() {
+                    found.done = true;
+                }
+            });
+            probe
+        });
+        Ok(match probe.lock() {
+            Ok(probed) => probed.clone(),
+            Err(err) => err.into_inner().clone(),
+        })
+    }
+}
+
+/// Devices are not shared across threads, the probe opens its own.
+fn run(host: Host, key: String, driver: Option<String>, found: &Mutex<Probed>) -> Result<()> {
+    let devices = host.get_devices()?;
+    let Some(device) = devices
+        .iter()
+        .find(|device| self::key(device).is_ok_and(|device| device == key))
+    else {
+        return Ok(());
+    };
+    let capabilities = all_formats(device)?;
+    store(key, driver, &capabilities);
+    if let Ok(mut found) = found.lock() {
+        found.capabilities = capabilities;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{BitsPerSample, SampleRate};
+
+    #[test]
+    fn capabilities_are_probed_again_once_the_driver_changed() {
+        let mut capabilities = Capabilities {
+            sample_rates: Vec::new(),
+            bits_per_samples: Vec::new(),
+            channels: vec![2, 6],
+        };
+        capabilities.add(SampleRate::Rate96000Hz, BitsPerSample::Bits24);
+        capabilities.add(SampleRate::Rate44100Hz, BitsPerSample::Bits16);
+        let driver = Some(String::from("10.0.1"));
+        let mut entries = Entries::default();
+        entries.0.insert(
+            String::from("wasapi:dac"),
+            Entry::new(driver.clone(), &capabilities),
+        );
+        let stored: Entries =
+            serde_json::from_slice(&serde_json::to_vec(&entries).unwrap()).unwrap();
+
+        let cached = stored.get("wasapi:dac", &driver).unwrap();
+        assert_eq!(
+            cached.sample_rates,
+            [SampleRate::Rate44100Hz, SampleRate::Rate96000Hz]
+        );
+        assert_eq!(
+            cached.bits_per_samples,
+            [BitsPerSample::Bits16, BitsPerSample::Bits24]
+        );
+        assert_eq!(cached.channels, [2, 6]);
+        assert_eq!(
+            stored.get("wasapi:dac", &Some(String::from("10.0.2"))),
+            None
+        );
+        assert_eq!(stored.get("asio:dac", &driver), None);
+    }
+}
@@ -0,0 +1,374 @@
+use anyhow::Result;
+use log::warn;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::trace_span;
+
+use super::ring::RingReader;
+use super::Fader;
+use crate::tools::cpu::CpuMeter;
+
+/// The device is released when the player writes nothing for this long, e.g. if its task died
+/// while still owning the writer.
+const WATCHDOG_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Received {
+    Data,
+    EndOfStream,
+    /// The stream was cancelled, closed or stalled, stop without playing out
+    Stop,
+}
+
+/// Device side of a stream written one period at a time.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub trait RenderClient: Send {
+    /// Bytes the device accepts for the next period.
+    fn available_buffer_size(&self) -> Result<usize>;
+    fn frame_size(&self) -> usize;
+    /// Exclusive event driven endpoints only accept whole periods.
+    fn requires_full_period(&self) -> bool;
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+    fn start(&mut self) -> Result<()>;
+    fn stop(&mut self) -> Result<()>;
+    /// Blocks until the device is ready for the next period.
+    fn wait_for_buffer(&self) -> Result<()>;
+}
+
+/// Pulls the data received so far, up to `size` bytes, waiting only until a whole frame is
+/// pending.
+async fn receive(
+    data_rx: &mut RingReader,
+    cancel: &Notify,
+    buffer: &mut Vec<u8>,
+    frame_size: usize,
+    size: usize,
+) -> Received {
+    if buffer.len() < frame_size {
+        tokio::select! {
+            waited = tokio::time::timeout(WATCHDOG_TIMEOUT, data_rx.wait(frame_size - buffer.len())) => {
+                if waited.is_err() {
+                    warn!("No data for {:?}, releasing the device", WATCHDOG_TIMEOUT);
+                    return Received::Stop;
+                }
+            }
+            _ = cancel.notified() => return Received::Stop,
+        }
+    }
+    if data_rx.is_abandoned() {
+        return Received::Stop;
+    }
+    data_rx.read_into(buffer, size.saturating_sub(buffer.len()));
+    if data_rx.is_finished() {
+        return Received::EndOfStream;
+    }
+    Received::Data
+}
+
+/// Streams the player data to the client, every period gets the complete frames received so
+/// far. At the end of stream the last period is played out before the client is stopped, the
+/// client is stopped as well on errors, cancellation or when the player went away.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub async fn render<C: RenderClient>(
+    client: &mut C,
+    data_rx: &mut RingReader,
+    fader: &mut Fader,
+    cancel: &Notify,
+    cpu: &CpuMeter,
+) -> Result<()> {
+    let result = stream(client, data_rx, fader, cancel, cpu).await;
+    let stopped = client.stop();
+    result.and(stopped)
+}
+
+async fn stream<C: RenderClient>(
+    client: &mut C,
+    data_rx: &mut RingReader,
+    fader: &mut Fader,
+    cancel: &Notify,
+    cpu: &CpuMeter,
+) -> Result<()> {
+    let frame_size = client.frame_size();
+    let mut client_started = false;
+    let mut buffer = vec![];
+    loop {
+        let size = client.available_buffer_size()?;
+        let received = receive(data_rx, cancel, &mut buffer, frame_size, size).await;
+        if let Received::Stop = received {
+            return Ok(());
+        }
+        let complete = buffer.len() - buffer.len() % frame_size;
+        if complete > 0 {
+            let mut period: Vec<u8> = buffer.drain(..complete).collect();
+            if client.requires_full_period() {
+                if client_started && complete < size && matches!(received, Received::Data) {
+                    cpu.underrun();
+                }
+                period.resize(size, 0);
+            }
+            {
+                let _busy = cpu.busy();
+                let _span = trace_span!("device_write", bytes = period.len()).entered();
+                fader.process(&mut period);
+                client.write(&period)?;
+            }
+            if !client_started {
+                client.start()?;
+                client_started = true;
+            }
+            client.wait_for_buffer()?;
+            // The client is stopped once faded out and restarted with the next buffer
+            if fader.is_silent() {
+                client.stop()?;
+                tokio::select! {
+                    _ = fader.wait_resumed() => {}
+                    _ = cancel.notified() => return Ok(()),
+                }
+                client_started = false;
+            }
+        }
+        if let Received::EndOfStream = received {
+            // Let the device consume the last period before stopping it
+            if client_started {
+                client.wait_for_buffer()?;
+            }
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::ring::channel;
+    use crate::audio::{BitsPerSample, FadeControl, FadeDurations, SampleRate, StreamParams};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Write(Vec<u8>),
+        Start,
+        Stop,
+        Wait,
+    }
+
+    /// Records every call so tests can assert what reached the device.
+    struct MockClient {
+        period: usize,
+        full_period: bool,
+        events: Arc<Mutex<Vec<Event>>>,
+    }
+
+    impl MockClient {
+        fn record(&self, event: Event) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    impl RenderClient for MockClient {
+        fn available_buffer_size(&self) -> Result<usize> {
+            Ok(self.period)
+        }
+
+        fn frame_size(&self) -> usize {
+            4
+        }
+
+        fn requires_full_period(&self) -> bool {
+            self.full_period
+        }
+
+        fn write(&mut self, data: &[u8]) -> Result<()> {
+            self.record(Event::Write(data.to_vec()));
+            Ok(())
+        }
+
+        fn start(&mut self) -> Result<()> {
+            self.record(Event::Start);
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<()> {
+            self.record(Event::Stop);
+            Ok(())
+        }
+
+        fn wait_for_buffer(&self) -> Result<()> {
+            self.record(Event::Wait);
+            Ok(())
+        }
+    }
+
+    fn fader() -> Fader {
+        let params = StreamParams {
+            channels: 2,
+            samplerate: SampleRate::Rate44100Hz,
+            bits_per_sample: BitsPerSample::Bits16,
+            exclusive: true,
+            pollmode: false,
+            fade: FadeDurations::default(),
+        };
+        Fader::new(Arc::new(FadeControl::default()), &params)
+    }
+
+    fn client(full_period: bool) -> (MockClient, Arc<Mutex<Vec<Event>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let client = MockClient {
+            period: 8,
+            full_period,
+            events: events.clone(),
+        };
+        (client, events)
+    }
+
+    /// Sends `data` up front, then `late` once the render loop is running.
+    async fn play(full_period: bool, data: &[u8], late: &[u8]) -> Vec<Event> {
+        let (mut client, events) = client(full_period);
+        let (data_tx, mut data_rx) = channel(data.len() + late.len() + 1);
+        data_tx.write(data).await.unwrap();
+        let late = late.to_vec();
+        let sender = tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            data_tx.write(&late).await.unwrap();
+            data_tx.end();
+        });
+        render(
+            &mut client,
+            &mut data_rx,
+            &mut fader(),
+            &Notify::new(),
+            &CpuMeter::default(),
+        )
+        .await
+        .unwrap();
+        sender.await.unwrap();
+        let events = std::mem::take(&mut *events.lock().unwrap());
+        events
+    }
+
+    #[tokio::test]
+    async fn end_of_stream_flushes_partial_period() {
+        let data: Vec<u8> = (1..=20).collect();
+        let events = play(true, &data, &[]).await;
+
+        let written: Vec<u8> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Write(bytes) => Some(bytes.clone()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(&written[..20], &data[..]);
+        assert_eq!(&written[20..], &[0; 4]);
+
+        // The padded period is waited on, then played out, before stopping
+        assert_eq!(
+            &events[events.len() - 4..],
+            &[
+                Event::Write([&data[16..], &[0; 4]].concat()),
+                Event::Wait,
+                Event::Wait,
+                Event::Stop,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn end_of_stream_on_period_boundary_plays_out_last_period() {
+        let data: Vec<u8> = (1..=16).collect();
+        let events = play(true, &data, &[]).await;
+
+        assert_eq!(
+            events,
+            vec![
+                Event::Write(data[..8].to_vec()),
+                Event::Start,
+                Event::Wait,
+                Event::Write(data[8..].to_vec()),
+                Event::Wait,
+                Event::Wait,
+                Event::Stop,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn complete_frames_are_written_without_waiting_for_a_full_period() {
+        let data: Vec<u8> = (1..=6).collect();
+        let late: Vec<u8> = (7..=12).collect();
+        let events = play(false, &data, &late).await;
+
+        // The trailing half frame waits for the rest of its frame
+        assert_eq!(
+            events,
+            vec![
+                Event::Write(data[..4].to_vec()),
+                Event::Start,
+                Event::Wait,
+                Event::Write((5..=12).collect()),
+                Event::Wait,
+                Event::Wait,
+                Event::Stop,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn partial_periods_are_padded_when_full_periods_are_required() {
+        let data: Vec<u8> = (1..=4).collect();
+        let late: Vec<u8> = (5..=8).collect();
+        let events = play(true, &data, &late).await;
+
+        assert_eq!(
+            events,
+            vec![
+                Event::Write(vec![1, 2, 3, 4, 0, 0, 0, 0]),
+                Event::Start,
+                Event::Wait,
+                Event::Write(vec![5, 6, 7, 8, 0, 0, 0, 0]),
+                Event::Wait,
+                Event::Wait,
+                Event::Stop,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_stream_releases_the_device() {
+        let (mut client, events) = client(false);
+        let (data_tx, mut data_rx) = channel(8);
+        data_tx.write(&[1, 2, 3, 4]).await.unwrap();
+        drop(data_tx);
+        render(
+            &mut client,
+            &mut data_rx,
+            &mut fader(),
+            &Notify::new(),
+            &CpuMeter::default(),
+        )
+        .await
+        .unwrap();
+
+        // Nothing is played out without an end of stream
+        assert_eq!(*events.lock().unwrap(), vec![Event::Stop]);
+    }
+
+    #[tokio::test]
+    async fn cancellation_releases_a_device_waiting_for_data() {
+        let (mut client, events) = client(false);
+        let (_data_tx, mut data_rx) = channel(8);
+        let cancel = Notify::new();
+        cancel.notify_one();
+        render(
+            &mut client,
+            &mut data_rx,
+            &mut fader(),
+            &cancel,
+            &CpuMeter::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(*events.lock().unwrap(), vec![Event::Stop]);
+    }
+}
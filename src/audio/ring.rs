@@ -0,0 +1,278 @@
+use anyhow::{anyhow, Result};
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+/// Device periods buffered between the player and the render thread.
+pub const PERIODS: usize = 8;
+
+/// Bytes streamed from the player to the render thread. Each side only moves its own position,
+/// neither takes a lock to read or write.
+struct Ring {
+    /// Written between `read` and `written` by the writer, read by the reader up to `written`
+    slots: Box<UnsafeCell<[u8]>>,
+    capacity: usize,
+    /// Bytes read since the start, moved by the reader only
+    read: AtomicUsize,
+    /// Bytes written since the start, moved by the writer only
+    written: AtomicUsize,
+    end_of_stream: AtomicBool,
+    reader_closed: AtomicBool,
+    writers: AtomicUsize,
+    /// Woken once bytes are written, the stream ends or the writers are gone
+    readable: Notify,
+    /// Woken once bytes are read or the reader is gone, while a writer waits for it
+    writable: Notify,
+    writer_waiting: AtomicBool,
+    /// Held while a writer copies, the clones of a writer may live on other threads
+    copying: AtomicBool,
+}
+
+// The reader and the writer touch separate bytes of the slots, as ordered by `read` and
+// `written`, and writers copy one at a time.
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn available(&self) -> usize {
+        self.written
+            .load(Ordering::SeqCst)
+            .wrapping_sub(self.read.load(Ordering::SeqCst))
+    }
+
+    fn free(&self) -> usize {
+        self.capacity - self.available()
+    }
+
+    /// The slots from `position` on, in at most two parts as they wrap around.
+    ///
+    /// # Safety
+    /// The `count` bytes from `position` must be left alone by the other side of the stream.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn parts(&self, position: usize, count: usize) -> (&mut [u8], &mut [u8]) {
+        let slots = self.slots.get() as *mut u8;
+        let start = position % self.capacity;
+        let first = count.min(self.capacity - start);
+        (
+            std::slice::from_raw_parts_mut(slots.add(start), first),
+            std::slice::from_raw_parts_mut(slots, count - first),
+        )
+    }
+
+    fn has_ended(&self) -> bool {
+        self.end_of_stream.load(Ordering::SeqCst) || self.writers.load(Ordering::SeqCst) == 0
+    }
+
+    /// Waits until `ready` holds, checked again each time the reader moves on or goes away.
+    async fn wait_writable(&self, ready: impl Fn(&Self) -> bool) {
+        loop {
+            let notified = self.writable.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            self.writer_waiting.store(true, Ordering::SeqCst);
+            if ready(self) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Player side of a device stream. Clones share the stream and must not write at the same
+/// time, the crossfade hands it over from one track to the next.
+pub struct RingWriter {
+    ring: Arc<Ring>,
+}
+
+/// Render side of a device stream.
+pub struct RingReader {
+    ring: Arc<Ring>,
+}
+
+/// Stream holding up to `capacity` bytes, usually a few device periods.
+pub fn channel(capacity: usize) -> (RingWriter, RingReader) {
+    let capacity = capacity.max(1);
+    let slots: Box<[u8]> = vec![0; capacity].into_boxed_slice();
+    let ring = Arc::new(Ring {
+        // UnsafeCell<[u8]> has the layout of [u8]
+        slots: unsafe { Box::from_raw(Box::into_raw(slots) as *mut UnsafeCell<[u8]>) },
+        capacity,
+        read: AtomicUsize::new(0),
+        written: AtomicUsize::new(0),
+        end_of_stream: AtomicBool::new(false),
+        reader_closed: AtomicBool::new(false),
+        writers: AtomicUsize::new(1),
+        readable: Notify::new(),
+        writable: Notify::new(),
+        writer_waiting: AtomicBool::new(false),
+        copying: AtomicBool::new(false),
+    });
+    (RingWriter { ring: ring.clone() }, RingReader { ring })
+}
+
+impl RingWriter {
+    /// Writes all of `data`, waiting for the render thread to make room. Fails once the device
+    /// is stopped.
+    pub async fn write(&self, mut data: &[u8]) -> Result<()> {
+        let ring = &self.ring;
+        while !data.is_empty() {
+            ring.wait_writable(|ring| ring.free() > 0 || ring.reader_closed.load(Ordering::SeqCst))
+                .await;
+            if self.is_closed() {
+                return Err(anyhow!("The device stream is closed"));
+            }
+            while ring.copying.swap(true, Ordering::Acquire) {
+                std::hint::spin_loop();
+            }
+            let written = ring.written.load(Ordering::SeqCst);
+            let count = ring.free().min(data.len());
+            // The free bytes are only read once `written` moves past them
+            let (first, second) = unsafe { ring.parts(written, count) };
+            let (head, tail) = data[..count].split_at(first.len());
+            first.copy_from_slice(head);
+            second.copy_from_slice(tail);
+            ring.written
+                .store(written.wrapping_add(count), Ordering::SeqCst);
+            ring.copying.store(false, Ordering::Release);
+            ring.readable.notify_one();
+            data = &data[count..];
+        }
+        Ok(())
+    }
+
+    /// The bytes written so far are played out, then the device stops.
+    pub fn end(&self) {
+        self.ring.end_of_stream.store(true, Ordering::SeqCst);
+        self.ring.readable.notify_one();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.ring.reader_closed.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the device to stop reading the stream.
+    pub async fn closed(&self) {
+        self.ring
+            .wait_writable(|ring| ring.reader_closed.load(Ordering::SeqCst))
+            .await;
+    }
+}
+
+impl Clone for RingWriter {
+    fn clone(&self) -> Self {
+        self.ring.writers.fetch_add(1, Ordering::SeqCst);
+        Self {
+            ring: self.ring.clone(),
+        }
+    }
+}
+
+/// The stream stops without playing out once every writer is gone.
+impl Drop for RingWriter {
+    fn drop(&mut self) {
+        if self.ring.writers.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.ring.readable.notify_one();
+        }
+    }
+}
+
+impl RingReader {
+    /// Bytes written and not read yet.
+    pub fn available(&self) -> usize {
+        self.ring.available()
+    }
+
+    /// Whether the writers are all gone without ending the stream.
+    pub fn is_abandoned(&self) -> bool {
+        !self.ring.end_of_stream.load(Ordering::SeqCst)
+            && self.ring.writers.load(Ordering::SeqCst) == 0
+    }
+
+    /// Whether the stream was ended and every byte of it read.
+    pub fn is_finished(&self) -> bool {
+        self.ring.end_of_stream.load(Ordering::SeqCst) && self.available() == 0
+    }
+
+    /// Appends up to `max` bytes to `buffer` without waiting, returns how many.
+    pub fn read_into(&mut self, buffer: &mut Vec<u8>, max: usize) -> usize {
+        let ring = &self.ring;
+        let read = ring.read.load(Ordering::SeqCst);
+        let count = ring.available().min(max);
+        // The available bytes are only written again once `read` moves past them
+        let (first, second) = unsafe { ring.parts(read, count) };
+        buffer.extend_from_slice(first);
+        buffer.extend_from_slice(second);
+        ring.read.store(read.wrapping_add(count), Ordering::SeqCst);
+        if ring.writer_waiting.swap(false, Ordering::SeqCst) {
+            ring.writable.notify_waiters();
+        }
+        count
+    }
+
+    /// Waits until `count` bytes are available, or the stream ended or was abandoned.
+    pub async fn wait(&self, count: usize) {
+        while self.available() < count && !self.ring.has_ended() {
+            self.ring.readable.notified().await;
+        }
+    }
+}
+
+impl Drop for RingReader {
+    fn drop(&mut self) {
+        self.ring.reader_closed.store(true, Ordering::SeqCst);
+        self.ring.writable.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn bytes_wrap_around_in_order() {
+        let (writer, mut reader) = channel(8);
+        let data: Vec<u8> = (0..100).collect();
+        let sending = data.clone();
+        let writing = tokio::spawn(async move {
+            writer.write(&sending).await.unwrap();
+            writer.end();
+        });
+        let mut received = Vec::new();
+        while !reader.is_finished() {
+            reader.wait(1).await;
+            reader.read_into(&mut received, 3);
+        }
+        writing.await.unwrap();
+
+        assert_eq!(received, data);
+    }
+
+    #[tokio::test]
+    async fn writers_see_the_device_stop() {
+        let (writer, reader) = channel(4);
+        let closed = writer.clone();
+        let writing = tokio::spawn(async move { writer.write(&[0; 16]).await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(reader);
+
+        assert!(writing.await.unwrap().is_err());
+        tokio::time::timeout(Duration::from_secs(1), closed.closed())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn dropped_writers_abandon_the_stream() {
+        let (writer, reader) = channel(4);
+        let other = writer.clone();
+        drop(writer);
+        assert!(!reader.is_abandoned());
+        drop(other);
+
+        tokio::time::timeout(Duration::from_secs(1), reader.wait(1))
+            .await
+            .unwrap();
+        assert!(reader.is_abandoned());
+    }
+}
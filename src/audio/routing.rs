@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+
+use super::channel_mask;
+
+/// Output channel of each channel of the track, set per device. Device channels nothing is
+/// routed to stay silent, e.g. `[3, 4]` plays a stereo track on the third and fourth channels
+/// of a four channel interface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelRouting {
+    /// Output channel of each input channel, from 0
+    outputs: Vec<usize>,
+    channels: usize,
+}
+
+impl ChannelRouting {
+    /// Reads the output channels as written in the config, from 1. `None` when empty.
+    pub fn new(routing: &[u8]) -> Result<Option<Self>> {
+        if routing.is_empty() {
+            return Ok(None);
+        }
+        let mut outputs = Vec::with_capacity(routing.len());
+        for output in routing {
+            let output = (*output as usize)
+                .checked_sub(1)
+                .ok_or(anyhow!("Channels are numbered from 1"))?;
+            if outputs.contains(&output) {
+                return Err(anyhow!("Channel {} is routed twice", output + 1));
+            }
+            outputs.push(output);
+        }
+        let channels = outputs.iter().max().map_or(0, |last| last + 1);
+        Ok(Some(Self { outputs, channels }))
+    }
+
+    /// Each channel to the same output channel.
+    pub fn identity(channels: usize) -> Self {
+        Self {
+            outputs: (0..channels).collect(),
+            channels,
+        }
+    }
+
+    /// Each speaker of a layout with `from` channels to the same speaker of one with `to`,
+    /// layouts as set by `channel_mask`. `None` when `to` lacks some of them.
+    pub fn between(from: usize, to: usize) -> Option<Self> {
+        let (from_mask, to_mask) = (channel_mask(from), channel_mask(to));
+        if from_mask == 0 || from_mask & !to_mask != 0 {
+            return None;
+        }
+        let outputs = (0..u32::BITS)
+            .map(|position| 1u32 << position)
+            .filter(|speaker| from_mask & speaker != 0)
+            .map(|speaker| (to_mask & (speaker - 1)).count_ones() as usize)
+            .collect();
+        Some(Self {
+            outputs,
+            channels: to,
+        })
+    }
+
+    /// Routed to a device opened with `channels`, the channels left over stay silent.
+    pub fn padded(self, channels: usize) -> Result<Self> {
+        if channels < self.channels {
+            return Err(anyhow!(
+                "{} channels are routed to a device opened with {}",
+                self.channels,
+                channels
+            ));
+        }
+        Ok(Self { channels, ..self })
+    }
+
+    /// Channels of the tracks it applies to.
+    pub fn inputs(&self) -> usize {
+        self.outputs.len()
+    }
+
+    /// Channels the device is opened with.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Interleaved frames rearranged for the device, samples are moved as is whatever their
+    /// format.
+    pub fn route(&self, data: &[u8], sample_size: usize) -> Vec<u8> {
+        let input_frame = self.inputs() * sample_size;
+        let output_frame = self.channels * sample_size;
+        let frames = data.len() / input_frame;
+        let mut routed = vec![0; frames * output_frame];
+        for (input, output) in data
+            .chunks_exact(input_frame)
+            .zip(routed.chunks_exact_mut(output_frame))
+        {
+            for (channel, target) in self.outputs.iter().enumerate() {
+                output[target * sample_size..(target + 1) * sample_size]
+                    .copy_from_slice(&input[channel * sample_size..(channel + 1) * sample_size]);
+            }
+        }
+        routed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stereo_is_swapped_or_moved_to_other_channels() {
+        let frames = [1, 1, 2, 2, 3, 3, 4, 4];
+        let swapped = ChannelRouting::new(&[2, 1]).unwrap().unwrap();
+        assert_eq!(swapped.channels(), 2);
+        assert_eq!(swapped.route(&frames, 2), [2, 2, 1, 1, 4, 4, 3, 3]);
+        let moved = ChannelRouting::new(&[3, 4]).unwrap().unwrap();
+        assert_eq!(moved.channels(), 4);
+        assert_eq!(
+            moved.route(&frames, 2),
+            [0, 0, 0, 0, 1, 1, 2, 2, 0, 0, 0, 0, 3, 3, 4, 4]
+        );
+    }
+
+    #[test]
+    fn channels_left_over_stay_silent() {
+        let padded = ChannelRouting::identity(2).padded(4).unwrap();
+        assert_eq!(padded.route(&[1, 2, 3, 4], 1), [1, 2, 0, 0, 3, 4, 0, 0]);
+        assert!(ChannelRouting::identity(6).padded(2).is_err());
+    }
+
+    #[test]
+    fn speakers_keep_their_place_in_a_larger_layout() {
+        let five = ChannelRouting::between(5, 6).unwrap();
+        assert_eq!(five.route(&[1, 2, 3, 4, 5], 1), [1, 2, 3, 0, 4, 5]);
+        assert_eq!(
+            ChannelRouting::between(6, 8),
+            Some(ChannelRouting::identity(6).padded(8).unwrap())
+        );
+        assert_eq!(ChannelRouting::between(6, 4), None);
+    }
+
+    #[test]
+    fn invalid_routings_are_refused() {
+        assert_eq!(ChannelRouting::new(&[]).unwrap(), None);
+        assert!(ChannelRouting::new(&[0, 1]).is_err());
+        assert!(ChannelRouting::new(&[2, 2]).is_err());
+    }
+}
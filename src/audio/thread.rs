@@ -0,0 +1,84 @@
+use anyhow::{anyhow, Result};
+use std::future::Future;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// Runs a blocking driver call, such as opening or stopping a device, on the blocking pool so
+/// the tasks of the app runtime keep going meanwhile.
+pub async fn unblock<T: Send + 'static>(
+    call: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(call).await?
+}
+
+/// Stream loop running on its own thread and single threaded runtime. Its waits on the driver
+/// block that thread only, never a worker of the app runtime, and it may raise the priority of
+/// its thread.
+// Only the WASAPI backend drives its streams from async loops
+#[cfg_attr(not(windows), allow(dead_code))]
+pub struct AudioThread {
+    done: Receiver<Result<()>>,
+}
+
+#[cfg_attr(not(windows), allow(dead_code))]
+impl AudioThread {
+    /// The loop is created on the new thread, it does not need to be `Send`.
+    pub fn spawn<F, Fut>(name: &str, stream: F) -> Result<Self>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>>,
+    {
+        let (sender, done) = mpsc::channel();
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let result = tokio::runtime::Builder::new_current_thread()
+                    .enable_time()
+                    .build()
+                    .map_err(anyhow::Error::from)
+                    .and_then(|runtime| runtime.block_on(stream()));
+                let _ = sender.send(result);
+            })?;
+        Ok(Self { done })
+    }
+
+    /// Waits up to `timeout` for the loop to return, `None` once timed out. The thread is then
+    /// left to finish on its own.
+    pub fn join(self, timeout: Duration) -> Option<Result<()>> {
+        match self.done.recv_timeout(timeout) {
+            Ok(result) => Some(result),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => Some(Err(anyhow!("Audio thread panicked"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_or_times_out() {
+        let thread = AudioThread::spawn("test", || async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Err(anyhow!("failed"))
+        })
+        .unwrap();
+        assert!(thread
+            .join(Duration::from_secs(5))
+            .is_some_and(|result| result.is_err()));
+
+        let thread = AudioThread::spawn("test", || async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        })
+        .unwrap();
+        assert!(thread.join(Duration::from_millis(10)).is_none());
+
+        let thread = AudioThread::spawn("test", || async { panic!("render") }).unwrap();
+        assert!(thread
+            .join(Duration::from_secs(5))
+            .is_some_and(|result| result.is_err()));
+    }
+}
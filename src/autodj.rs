@@ -0,0 +1,96 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::musictrack::MusicTrack;
+
+/// Tracks played this recently are left out of the picks while others remain.
+const RECENT: usize = 50;
+
+/// Next track once the playlist ran out: one by the artist of `last` or by an artist
+/// `similar` to it, avoiding the tracks of `history`, most recent first. Any track will do
+/// when no related one is left. Radios never end, they are not picked.
+pub fn pick(
+    songs: &[Arc<MusicTrack>],
+    last: Option<&MusicTrack>,
+    similar: &[String],
+    history: impl Iterator<Item = usize>,
+    rng: &mut impl Rng,
+) -> Option<usize> {
+    let recent: HashSet<usize> = history.take(RECENT).collect();
+    let playable: Vec<usize> = (0..songs.len())
+        .filter(|index| !songs[*index].is_stream())
+        .collect();
+    let fresh: Vec<usize> = playable
+        .iter()
+        .copied()
+        .filter(|index| !recent.contains(index))
+        .collect();
+    let related: Vec<usize> = fresh
+        .iter()
+        .copied()
+        .filter(|index| {
+            let artist = &songs[*index].artist;
+            last.is_some_and(|last| &last.artist == artist)
+                || similar
+                    .iter()
+                    .any(|similar| similar.eq_ignore_ascii_case(artist))
+        })
+        .collect();
+    [related, fresh, playable]
+        .into_iter()
+        .find(|candidates| !candidates.is_empty())?
+        .choose(rng)
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn songs(artists: &[&str]) -> Vec<Arc<MusicTrack>> {
+        let base = MusicTrack::new("tests/assets/tagged.flac".to_string()).unwrap();
+        artists
+            .iter()
+            .enumerate()
+            .map(|(index, artist)| {
+                Arc::new(MusicTrack {
+                    path: format!("/music/{}.flac", index),
+                    artist: artist.to_string(),
+                    ..base.clone()
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn picks_the_artist_or_similar_ones_not_played_lately() {
+        let songs = songs(&["Muse", "Radiohead", "Abba", "Radiohead", "Thom Yorke"]);
+        let similar = vec!["thom yorke".to_string()];
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..20 {
+            let picked = pick(
+                &songs,
+                Some(&songs[3]),
+                &similar,
+                [3, 1].into_iter(),
+                &mut rng,
+            );
+            assert_eq!(picked, Some(4));
+        }
+    }
+
+    #[test]
+    fn falls_back_to_any_track() {
+        let songs = songs(&["Muse", "Abba"]);
+        let mut rng = StdRng::seed_from_u64(3);
+        let picked = pick(&songs, Some(&songs[1]), &[], [1].into_iter(), &mut rng);
+        assert_eq!(picked, Some(0));
+        let picked = pick(&songs, None, &[], [0, 1].into_iter(), &mut rng);
+        assert!(picked.is_some());
+        assert_eq!(pick(&[], None, &[], std::iter::empty(), &mut rng), None);
+    }
+}
@@ -0,0 +1,655 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::audio::{Capabilities, FadeDurations, PinnedFormat};
+use crate::cue::Pregap;
+use crate::dsp::{DownmixMode, UpmixConfig};
+use crate::tools::resampler::{ResamplerEngine, ResamplerQuality, ResamplerSettings};
+
+const SPEED_OF_SOUND: f64 = 343.0;
+const MAX_CROSSFADE_SECONDS: f64 = 10.0;
+
+/// Delay applied to one output channel, given either directly or as the extra
+/// distance between the speaker and the listening position.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelDelay {
+    Ms(f64),
+    Meters(f64),
+}
+
+impl ChannelDelay {
+    pub fn as_seconds(&self) -> f64 {
+        match self {
+            Self::Ms(ms) => ms / 1000.0,
+            Self::Meters(meters) => meters / SPEED_OF_SOUND,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct DeviceConfig {
+    #[serde(default)]
+    pub delays: Vec<ChannelDelay>,
+    /// Highest volume in dB reachable from the keyboard, e.g. -20 for sensitive IEMs
+    #[serde(default)]
+    pub max_volume_db: i8,
+    /// Attenuation in dB always applied on top of the volume
+    #[serde(default)]
+    pub headroom_db: u8,
+    /// Output channel, from 1, of each channel of the tracks, e.g. `[2, 1]` swaps left and
+    /// right. Tracks with another channel count play as is.
+    #[serde(default)]
+    pub routing: Vec<u8>,
+    /// Sample rate the device is always opened at, tracks are resampled to it
+    #[serde(default)]
+    pub sample_rate: Option<usize>,
+    #[serde(default)]
+    pub bits_per_sample: Option<usize>,
+    /// Channels the device is always opened with, the extra ones stay silent
+    #[serde(default)]
+    pub channels: Option<u8>,
+    /// Spreads stereo tracks over the speakers of a multichannel device
+    #[serde(default)]
+    pub upmix: Option<UpmixConfig>,
+}
+
+impl DeviceConfig {
+    /// Sample rate and bit depth pinned for the device, failing on formats no device plays.
+    pub fn pinned_format(&self) -> Result<PinnedFormat> {
+        let supported = Capabilities::default();
+        let samplerate = match self.sample_rate {
+            Some(rate) => Some(
+                supported
+                    .sample_rates
+                    .into_iter()
+                    .find(|supported| *supported as usize == rate)
+                    .ok_or(anyhow!("Unsupported sample rate {}Hz", rate))?,
+            ),
+            None => None,
+        };
+        let bits_per_sample = match self.bits_per_sample {
+            Some(bits) => Some(
+                supported
+                    .bits_per_samples
+                    .into_iter()
+                    .find(|supported| *supported as usize == bits)
+                    .ok_or(anyhow!("Unsupported {} bits samples", bits))?,
+            ),
+            None => None,
+        };
+        Ok(PinnedFormat {
+            samplerate,
+            bits_per_sample,
+            channels: self.channels,
+        })
+    }
+}
+
+/// Gain ramp applied when a track is much louder than the previous one.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SmartVolumeConfig {
+    pub enabled: bool,
+    /// Loudness increase in dB above which the ramp kicks in
+    pub threshold_db: f64,
+    pub ramp_seconds: f64,
+}
+
+impl Default for SmartVolumeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            threshold_db: 6.0,
+            ramp_seconds: 3.0,
+        }
+    }
+}
+
+/// Fades applied by the output device when pausing and resuming playback.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct FadeConfig {
+    pub pause_ms: u64,
+    pub resume_ms: u64,
+}
+
+impl Default for FadeConfig {
+    fn default() -> Self {
+        Self {
+            pause_ms: 200,
+            resume_ms: 200,
+        }
+    }
+}
+
+impl FadeConfig {
+    pub fn durations(&self) -> FadeDurations {
+        FadeDurations {
+            pause: Duration::from_millis(self.pause_ms),
+            resume: Duration::from_millis(self.resume_ms),
+        }
+    }
+}
+
+/// Overlap of consecutive tracks, only between tracks of the same format and never within
+/// an album, which stays gapless.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CrossfadeConfig {
+    /// From 0, disabled, to 10
+    pub seconds: f64,
+}
+
+impl CrossfadeConfig {
+    pub fn seconds(&self) -> Option<f64> {
+        let seconds = self.seconds.clamp(0.0, MAX_CROSSFADE_SECONDS);
+        (seconds > 0.0).then_some(seconds)
+    }
+}
+
+/// Where `rhap-queue.m3u8` is written, its paths are relative to that directory so the
+/// playlist can be copied along with the music.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    /// Defaults to the music directory
+    pub root: Option<PathBuf>,
+}
+
+/// Background analyses of loudness, waveform and fingerprint.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AnalysisConfig {
+    /// Worker threads, 0 disables the analyses
+    pub workers: usize,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        Self { workers: 1 }
+    }
+}
+
+/// Tracks of CUE sheets.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CueConfig {
+    pub pregap: Pregap,
+}
+
+/// Format badges of the playlist. Colors are keyed by badge label, as color names or
+/// `#rrggbb`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BadgesConfig {
+    /// Adds a badges column to the playlist, the playing track always shows its badges
+    pub column: bool,
+    pub colors: HashMap<String, String>,
+}
+
+/// Online album info, off until providers are listed. They are asked in order, each one
+/// filling what the previous ones did not find.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MetadataConfig {
+    /// `musicbrainz`, `lastfm` or `discogs`
+    pub providers: Vec<String>,
+    pub lastfm_api_key: Option<String>,
+    pub discogs_token: Option<String>,
+}
+
+/// Wake-up alarm, set once a start time and a saved playlist are given. Times are local,
+/// `HH:MM`. Changes made in the alarm settings of the app are kept in the library and take
+/// over these.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AlarmConfig {
+    pub start: Option<String>,
+    pub stop: Option<String>,
+    pub playlist: Option<String>,
+    /// Length of the fade in of the first track
+    pub fade_in_seconds: u32,
+}
+
+impl Default for AlarmConfig {
+    fn default() -> Self {
+        Self {
+            start: None,
+            stop: None,
+            playlist: None,
+            fade_in_seconds: 60,
+        }
+    }
+}
+
+/// MPD protocol server, on by default on the local machine as `rhap add` goes through it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MpdConfig {
+    pub enabled: bool,
+    /// Listening address, other machines need e.g. `0.0.0.0`
+    pub address: String,
+    pub port: u16,
+}
+
+impl Default for MpdConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            address: "127.0.0.1".to_string(),
+            port: 6600,
+        }
+    }
+}
+
+/// Event stream for WebSocket and JSON lines clients, started when a port is set.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct EventsConfig {
+    /// Listening address, other machines need e.g. `0.0.0.0`
+    pub address: String,
+    pub port: Option<u16>,
+}
+
+impl Default for EventsConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".to_string(),
+            port: None,
+        }
+    }
+}
+
+/// Address the commands talking to the running player connect to for a server listening on
+/// `address`. Servers listening on every interface are reached locally.
+pub fn local_address(address: &str) -> &str {
+    match address {
+        "0.0.0.0" => "127.0.0.1",
+        "::" => "::1",
+        address => address,
+    }
+}
+
+/// When the rows of the playlist link to the folder of their track, for terminals which open
+/// OSC 8 hyperlinks on ctrl+click.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HyperlinkMode {
+    /// In the terminals known to support them
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Redraws of the terminal. The screen is drawn on input, when a track ends and on each tick,
+/// ticking at the frame rate cap while the spectrum or meters animate.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    pub tick_ms: u64,
+    pub max_fps: u32,
+    pub hyperlinks: HyperlinkMode,
+    /// Time the playing track takes to scroll by one character when too long to fit, as
+    /// redrawn on ticks. 0 leaves it cut.
+    pub marquee_ms: u64,
+    /// Highlights in the dominant color of the album art of the playing track rather than
+    /// amber, from the embedded front cover or a `cover.jpg` next to the files.
+    pub album_accent: bool,
+}
+
+impl Default for UiConfig {
+    fn default() -> Self {
+        Self {
+            tick_ms: 200,
+            max_fps: 30,
+            hyperlinks: HyperlinkMode::Auto,
+            marquee_ms: 250,
+            album_accent: false,
+        }
+    }
+}
+
+impl UiConfig {
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms.max(1))
+    }
+
+    /// Shortest time between two frames.
+    pub fn frame(&self) -> Duration {
+        Duration::from_secs(1) / self.max_fps.max(1)
+    }
+}
+
+/// What follows the last track of the playlist and the queue, with repeat off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndOfQueue {
+    #[default]
+    Stop,
+    /// From the first track again
+    Repeat,
+    /// Keeps queueing tracks of the last artist or of similar ones, until a track is played
+    /// from the app
+    #[serde(rename = "autodj")]
+    AutoDj,
+    /// Quits rhap, for scripted sessions
+    Quit,
+}
+
+/// Playback through the playlist and the queue.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub end: EndOfQueue,
+}
+
+/// Pre-listening of the selected track on a second output device, e.g. headphones, while the
+/// playlist keeps playing on the main one.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PreviewConfig {
+    /// Name of the device, preview is off until one is set
+    pub device: Option<String>,
+    /// Volume of the preview, from full scale
+    pub volume_db: i8,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            device: None,
+            volume_db: -12,
+        }
+    }
+}
+
+/// Where the session summary goes on quit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryOutput {
+    #[default]
+    Off,
+    Print,
+    Log,
+}
+
+/// How the output device is opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// Plays through the system mixer, resampled to its rate, so other applications can play
+    /// meanwhile. DoP tracks still need exclusive mode.
+    pub shared: bool,
+    /// Folds tracks of more than two channels down to stereo: `auto` when the device cannot
+    /// play them, `always` or `never`.
+    pub downmix: DownmixMode,
+}
+
+/// Statistics of the session, tracks played, listening time, underruns and render CPU.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SessionConfig {
+    pub summary: SummaryOutput,
+}
+
+/// One chord or a list of chords bound to a keyboard event, see `KeyboardManager`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum KeyChords {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl KeyChords {
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        match self {
+            Self::One(chord) => std::slice::from_ref(chord),
+            Self::Many(chords) => chords.as_slice(),
+        }
+        .iter()
+        .map(String::as_str)
+    }
+}
+
+/// User settings read from `rhap/config.toml` in the platform config directory, and read
+/// again when it changes. Output device settings are keyed by device name:
+///
+/// ```toml
+/// [output]
+/// shared = false
+/// downmix = "auto"
+///
+/// [smart_volume]
+/// threshold_db = 6.0
+///
+/// [fade]
+/// pause_ms = 200
+///
+/// [crossfade]
+/// seconds = 4.0
+///
+/// [resampler]
+/// engine = "sinc"
+/// quality = "medium"
+///
+/// [export]
+/// root = "E:/"
+///
+/// [analysis]
+/// workers = 2
+///
+/// [cue]
+/// pregap = "skip"
+///
+/// [queue]
+/// end = "autodj"
+///
+/// [preview]
+/// device = "Headphones (USB)"
+/// volume_db = -12
+///
+/// [badges]
+/// column = true
+/// colors = { "HI-RES" = "#ffbf00", FLAC = "lightblue" }
+///
+/// [ui]
+/// tick_ms = 200
+/// max_fps = 30
+/// hyperlinks = "auto"
+/// marquee_ms = 250
+/// album_accent = true
+///
+/// [session]
+/// summary = "print"
+///
+/// [metadata]
+/// providers = ["musicbrainz", "lastfm"]
+/// lastfm_api_key = "0123456789abcdef"
+///
+/// [alarm]
+/// start = "07:30"
+/// stop = "08:15"
+/// playlist = "Morning"
+///
+/// [mpd]
+/// address = "0.0.0.0"
+///
+/// [events]
+/// port = 6680
+///
+/// [keys]
+/// next = ["N", "ctrl+right"]
+///
+/// [devices."Speakers (USB DAC)"]
+/// delays = [{ ms = 0.0 }, { meters = 0.35 }]
+///
+/// [devices."Headphones (IEM)"]
+/// max_volume_db = -20
+/// headroom_db = 3
+///
+/// [devices."Line 3/4 (Audio Interface)"]
+/// routing = [3, 4]
+///
+/// [devices."Speakers (DAC)"]
+/// sample_rate = 96000
+/// bits_per_sample = 24
+///
+/// [devices."Living room (HDMI)"]
+/// upmix = { layout = "5.1", decoding = "surround" }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub smart_volume: SmartVolumeConfig,
+    #[serde(default)]
+    pub fade: FadeConfig,
+    #[serde(default)]
+    pub crossfade: CrossfadeConfig,
+    #[serde(default)]
+    pub resampler: ResamplerSettings,
+    #[serde(default)]
+    pub export: ExportConfig,
+    #[serde(default)]
+    pub analysis: AnalysisConfig,
+    #[serde(default)]
+    pub cue: CueConfig,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    #[serde(default)]
+    pub preview: PreviewConfig,
+    #[serde(default)]
+    pub badges: BadgesConfig,
+    #[serde(default)]
+    pub ui: UiConfig,
+    #[serde(default)]
+    pub session: SessionConfig,
+    #[serde(default)]
+    pub metadata: MetadataConfig,
+    #[serde(default)]
+    pub alarm: AlarmConfig,
+    #[serde(default)]
+    pub mpd: MpdConfig,
+    #[serde(default)]
+    pub events: EventsConfig,
+    #[serde(default)]
+    pub keys: HashMap<String, KeyChords>,
+    #[serde(default)]
+    pub devices: HashMap<String, DeviceConfig>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rhap").join("config.toml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => Ok(toml::from_str(&std::fs::read_to_string(path)?)?),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn device(&self, name: &str) -> Option<&DeviceConfig> {
+        self.devices.get(name)
+    }
+
+    /// Sections which differ in `new`, by whether the running app takes them.
+    pub fn changes(&self, new: &Config) -> ConfigChanges {
+        let sections = [
+            (
+                "output.shared",
+                self.output.shared != new.output.shared,
+                false,
+            ),
+            (
+                "output.downmix",
+                self.output.downmix != new.output.downmix,
+                true,
+            ),
+            ("smart_volume", self.smart_volume != new.smart_volume, true),
+            ("fade", self.fade != new.fade, true),
+            ("crossfade", self.crossfade != new.crossfade, true),
+            ("resampler", self.resampler != new.resampler, true),
+            ("export", self.export != new.export, true),
+            ("analysis", self.analysis != new.analysis, false),
+            ("cue", self.cue != new.cue, false),
+            ("queue", self.queue != new.queue, true),
+            ("preview", self.preview != new.preview, true),
+            ("badges", self.badges != new.badges, true),
+            ("ui", self.ui != new.ui, true),
+            ("session", self.session != new.session, false),
+            ("metadata", self.metadata != new.metadata, false),
+            ("alarm", self.alarm != new.alarm, false),
+            ("mpd", self.mpd != new.mpd, false),
+            ("events", self.events != new.events, false),
+            ("keys", self.keys != new.keys, true),
+            ("devices", self.devices != new.devices, true),
+        ];
+        let mut changes = ConfigChanges::default();
+        for (name, _, live) in sections.into_iter().filter(|(_, changed, _)| *changed) {
+            match live {
+                true => changes.applied.push(name),
+                false => changes.restart.push(name),
+            }
+        }
+        changes
+    }
+}
+
+/// Sections of the config changed in the file while running.
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigChanges {
+    /// Taken at once, from the next track for the audio settings
+    pub applied: Vec<&'static str>,
+    /// Only read on startup
+    pub restart: Vec<&'static str>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.restart.is_empty()
+    }
+}
+
+impl fmt::Display for ConfigChanges {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Config reloaded")?;
+        if !self.applied.is_empty() {
+            write!(f, ", {} applied", self.applied.join(" "))?;
+        }
+        if !self.restart.is_empty() {
+            write!(f, ", {} on restart", self.restart.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Settings given on the command line, taking over the config file each time it is read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Overrides {
+    pub resampler_engine: Option<ResamplerEngine>,
+    pub resampler_quality: Option<ResamplerQuality>,
+    pub shared: bool,
+}
+
+impl Overrides {
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(engine) = self.resampler_engine {
+            config.resampler.engine = engine;
+        }
+        if let Some(quality) = self.resampler_quality {
+            config.resampler.quality = quality;
+        }
+        if self.shared {
+            config.output.shared = true;
+        }
+    }
+}
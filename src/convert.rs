@@ -0,0 +1,490 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rand::rngs::ThreadRng;
+use rand::Rng;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::errors::Error;
+use symphonia::core::meta::{MetadataRevision, StandardTagKey, StandardVisualKey};
+
+use crate::audio::BitsPerSample;
+use crate::musictrack::MusicTrack;
+use crate::scanner::Scanner;
+use crate::tools::flac::{FlacWriter, Picture};
+#[cfg(feature = "mp3")]
+use crate::tools::mp3::Mp3Writer;
+#[cfg(feature = "opus")]
+use crate::tools::opus::OpusWriter;
+use crate::tools::resampler::{ResamplerSettings, RubatoResampler};
+
+/// Output format of converted files, WAV files carry no tags. Opus and MP3 need the
+/// encoders of the `opus` and `mp3` features.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    Flac,
+    Wav,
+    Opus,
+    Mp3,
+}
+
+/// Rates of MPEG-1 layer III, the only ones written to MP3 files.
+const MP3_RATES: [usize; 3] = [32000, 44100, 48000];
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Flac => "flac",
+            Format::Wav => "wav",
+            Format::Opus => "opus",
+            Format::Mp3 => "mp3",
+        }
+    }
+
+    fn is_lossy(&self) -> bool {
+        matches!(self, Format::Opus | Format::Mp3)
+    }
+
+    /// Fails when the encoder of the format was left out of this build.
+    pub fn check_available(&self) -> Result<()> {
+        match self {
+            Format::Opus if cfg!(not(feature = "opus")) => Err(anyhow!(
+                "Opus files are written by rhap built with the opus feature, which needs libopus"
+            )),
+            Format::Mp3 if cfg!(not(feature = "mp3")) => Err(anyhow!(
+                "MP3 files are written by rhap built with the mp3 feature, which needs libmp3lame"
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rate written for a source at `rate` when none is asked for, the closest one the
+    /// format holds.
+    fn default_rate(&self, rate: usize) -> usize {
+        match self {
+            Format::Opus => 48000,
+            Format::Mp3 if MP3_RATES.contains(&rate) => rate,
+            Format::Mp3 if rate.is_multiple_of(11025) => 44100,
+            Format::Mp3 => 48000,
+            Format::Flac | Format::Wav => rate,
+        }
+    }
+
+    fn check_channels(&self, channels: usize) -> Result<()> {
+        match self {
+            Format::Opus | Format::Mp3 if channels > 2 => Err(anyhow!(
+                "{} files are written in mono or stereo only",
+                self.extension().to_uppercase()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_rate(&self, rate: usize) -> Result<()> {
+        match self {
+            Format::Opus if rate != 48000 => Err(anyhow!("Opus files are always 48000 Hz")),
+            Format::Mp3 if !MP3_RATES.contains(&rate) => {
+                Err(anyhow!("MP3 files are written at 32000, 44100 or 48000 Hz"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Output of a conversion, missing values keep those of the source.
+#[derive(Debug, Clone, Copy)]
+pub struct Target {
+    pub format: Format,
+    pub sample_rate: Option<usize>,
+    pub bits: Option<u32>,
+}
+
+/// Vorbis comment names of the tags worth carrying over.
+const TAG_NAMES: &[(StandardTagKey, &str)] = &[
+    (StandardTagKey::TrackTitle, "TITLE"),
+    (StandardTagKey::Artist, "ARTIST"),
+    (StandardTagKey::AlbumArtist, "ALBUMARTIST"),
+    (StandardTagKey::Album, "ALBUM"),
+    (StandardTagKey::TrackNumber, "TRACKNUMBER"),
+    (StandardTagKey::TrackTotal, "TRACKTOTAL"),
+    (StandardTagKey::DiscNumber, "DISCNUMBER"),
+    (StandardTagKey::DiscTotal, "DISCTOTAL"),
+    (StandardTagKey::Date, "DATE"),
+    (StandardTagKey::Genre, "GENRE"),
+    (StandardTagKey::Composer, "COMPOSER"),
+    (StandardTagKey::Conductor, "CONDUCTOR"),
+    (StandardTagKey::Performer, "PERFORMER"),
+    (StandardTagKey::Label, "LABEL"),
+    (StandardTagKey::Comment, "COMMENT"),
+    (StandardTagKey::Lyrics, "LYRICS"),
+    (StandardTagKey::IdentIsrc, "ISRC"),
+    (StandardTagKey::ReplayGainTrackGain, "REPLAYGAIN_TRACK_GAIN"),
+    (StandardTagKey::ReplayGainTrackPeak, "REPLAYGAIN_TRACK_PEAK"),
+    (StandardTagKey::ReplayGainAlbumGain, "REPLAYGAIN_ALBUM_GAIN"),
+    (StandardTagKey::ReplayGainAlbumPeak, "REPLAYGAIN_ALBUM_PEAK"),
+    (
+        StandardTagKey::MusicBrainzRecordingId,
+        "MUSICBRAINZ_TRACKID",
+    ),
+    (StandardTagKey::MusicBrainzAlbumId, "MUSICBRAINZ_ALBUMID"),
+    (StandardTagKey::MusicBrainzArtistId, "MUSICBRAINZ_ARTISTID"),
+];
+
+/// Known tags under their Vorbis comment name. Unknown tags such as `R128_TRACK_GAIN` are only
+/// kept from Vorbis comment sources, raw ID3 and MP4 keys would mean nothing to readers.
+fn vorbis_comments(metadata: &MetadataRevision, keep_unknown: bool) -> Vec<(String, String)> {
+    metadata
+        .tags()
+        .iter()
+        .filter_map(|tag| {
+            let name = match tag.std_key {
+                Some(key) => TAG_NAMES
+                    .iter()
+                    .find(|(known, _)| *known == key)
+                    .map(|(_, name)| name.to_string())?,
+                None if keep_unknown => tag.key.to_uppercase(),
+                None => return None,
+            };
+            let value = tag.value.to_string().trim_end_matches('\0').to_string();
+            Some((name, value))
+        })
+        .collect()
+}
+
+fn pictures(metadata: &MetadataRevision) -> Vec<Picture> {
+    metadata
+        .visuals()
+        .iter()
+        .map(|visual| Picture {
+            kind: match visual.usage {
+                Some(StandardVisualKey::FrontCover) => 3,
+                Some(StandardVisualKey::BackCover) => 4,
+                Some(StandardVisualKey::Leaflet) => 5,
+                Some(StandardVisualKey::Media) => 6,
+                _ => 0,
+            },
+            media_type: visual.media_type.clone(),
+            width: visual.dimensions.map_or(0, |size| size.width),
+            height: visual.dimensions.map_or(0, |size| size.height),
+            depth: visual.bits_per_pixel.map_or(0, |depth| depth.get()),
+            data: visual.data.clone(),
+        })
+        .collect()
+}
+
+/// Scales to integers of `bits` bits, with triangular dither when precision is lost to hide
+/// the truncation distortion under a constant noise floor.
+fn quantize(sample: f64, bits: u32, rng: Option<&mut impl Rng>) -> i32 {
+    let scale = (1i64 << (bits - 1)) as f64;
+    let noise = rng.map_or(0.0, |rng| rng.gen::<f64>() - rng.gen::<f64>());
+    (sample * scale + noise).round().clamp(-scale, scale - 1.0) as i32
+}
+
+fn quantize_all(
+    samples: &[f64],
+    bits: u32,
+    mut rng: Option<&mut ThreadRng>,
+    output: &mut Vec<i32>,
+) {
+    output.clear();
+    output.extend(
+        samples
+            .iter()
+            .map(|sample| quantize(*sample, bits, rng.as_deref_mut())),
+    );
+}
+
+enum Encoder {
+    Flac(FlacWriter<BufWriter<File>>),
+    Wav(WavWriter<BufWriter<File>>),
+    #[cfg(feature = "opus")]
+    Opus(OpusWriter<BufWriter<File>>),
+    #[cfg(feature = "mp3")]
+    Mp3(Mp3Writer<BufWriter<File>>),
+}
+
+/// Encoder of a converted file. The lossless formats get samples quantized to `bits`, the
+/// lossy ones take them as they are.
+struct Output {
+    encoder: Encoder,
+    bits: u32,
+    /// Set when precision is lost
+    dither: Option<ThreadRng>,
+    quantized: Vec<i32>,
+}
+
+impl Output {
+    fn write(&mut self, samples: &[f64]) -> Result<()> {
+        match &mut self.encoder {
+            Encoder::Flac(writer) => {
+                quantize_all(
+                    samples,
+                    self.bits,
+                    self.dither.as_mut(),
+                    &mut self.quantized,
+                );
+                writer.write(&self.quantized)?;
+            }
+            Encoder::Wav(writer) => {
+                quantize_all(
+                    samples,
+                    self.bits,
+                    self.dither.as_mut(),
+                    &mut self.quantized,
+                );
+                for sample in &self.quantized {
+                    writer.write_sample(*sample)?;
+                }
+            }
+            #[cfg(feature = "opus")]
+            Encoder::Opus(writer) => writer.write(samples)?,
+            #[cfg(feature = "mp3")]
+            Encoder::Mp3(writer) => writer.write(samples)?,
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self.encoder {
+            Encoder::Flac(writer) => {
+                writer.finish()?;
+            }
+            Encoder::Wav(writer) => writer.finalize()?,
+            #[cfg(feature = "opus")]
+            Encoder::Opus(writer) => {
+                writer.finish()?;
+            }
+            #[cfg(feature = "mp3")]
+            Encoder::Mp3(writer) => {
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes `track`, resamples and dithers it as `target` asks and encodes it to
+/// `destination`, keeping tags and artwork in all but WAV output.
+pub fn convert(
+    track: &MusicTrack,
+    destination: &Path,
+    target: &Target,
+    resampler: ResamplerSettings,
+) -> Result<()> {
+    if track.dsd_rate.is_some() {
+        return Err(anyhow!("DSD tracks cannot be converted: {}", track.path));
+    }
+    target.format.check_available()?;
+    let sample_rate = track.sample as usize;
+    let target_rate = target
+        .sample_rate
+        .unwrap_or_else(|| target.format.default_rate(sample_rate));
+    target.format.check_rate(target_rate)?;
+    target.format.check_channels(track.channels)?;
+    // Float sources are stored as 24 bits integers
+    let source_bits = (track.bits_per_sample as u32).min(24);
+    // Lossy encoders take floats, 24 bits only sets the resampler precision
+    let bits = match (target.format.is_lossy(), target.bits) {
+        (true, Some(_)) => return Err(anyhow!("The bit depth of lossy files cannot be set")),
+        (true, None) => 24,
+        (false, bits) => bits.unwrap_or(source_bits),
+    };
+    if bits != 16 && bits != 24 {
+        return Err(anyhow!("Unsupported bit depth {}, use 16 or 24", bits));
+    }
+    let dither = sample_rate != target_rate || bits < track.bits_per_sample as u32;
+
+    let metadata = track.metadata()?;
+    let extension = Path::new(&track.path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let is_vorbis = matches!(extension.as_deref(), Some("flac") | Some("ogg"));
+    let file = BufWriter::new(File::create(destination)?);
+    let encoder = match target.format {
+        Format::Flac => Encoder::Flac(FlacWriter::new(
+            file,
+            target_rate as u32,
+            track.channels,
+            bits,
+            &vorbis_comments(&metadata, is_vorbis),
+            &pictures(&metadata),
+        )?),
+        Format::Wav => {
+            let spec = WavSpec {
+                channels: track.channels as u16,
+                sample_rate: target_rate as u32,
+                bits_per_sample: bits as u16,
+                sample_format: SampleFormat::Int,
+            };
+            Encoder::Wav(WavWriter::new(file, spec)?)
+        }
+        #[cfg(feature = "opus")]
+        Format::Opus => Encoder::Opus(OpusWriter::new(
+            file,
+            sample_rate as u32,
+            track.channels,
+            &vorbis_comments(&metadata, is_vorbis),
+            &pictures(&metadata),
+        )?),
+        #[cfg(feature = "mp3")]
+        Format::Mp3 => Encoder::Mp3(Mp3Writer::new(
+            file,
+            target_rate,
+            track.channels,
+            &vorbis_comments(&metadata, is_vorbis),
+            &pictures(&metadata),
+        )?),
+        #[cfg(not(all(feature = "opus", feature = "mp3")))]
+        format => unreachable!("{:?} is checked above", format),
+    };
+    let mut output = Output {
+        encoder,
+        bits,
+        dither: dither.then(rand::thread_rng),
+        quantized: Vec::new(),
+    };
+
+    let (mut format, mut decoder) = track.open()?;
+    let mut resampled: Option<RubatoResampler<f64>> = None;
+    let mut buffer: Option<SampleBuffer<f64>> = None;
+    // The resampler delay is dropped from the start and flushed out at the end, keeping the
+    // converted file aligned with its source
+    let mut skipped = 0;
+    let mut input_frames = 0;
+    let mut written = 0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err.into()),
+        };
+        let decoded = decoder.decode(&packet)?;
+        // The first Vorbis packet only primes the decoder, an empty chunk would stall the
+        // resampler
+        if decoded.frames() == 0 {
+            continue;
+        }
+        input_frames += decoded.frames();
+        let samples = if sample_rate != target_rate {
+            let resampler = match &mut resampled {
+                Some(resampler) => resampler,
+                None => resampled.insert(RubatoResampler::new(
+                    sample_rate,
+                    target_rate,
+                    track.bits_per_sample,
+                    BitsPerSample::from(bits as usize),
+                    decoded.frames(),
+                    track.channels,
+                    resampler,
+                )?),
+            };
+            let delay = resampler.output_delay() * track.channels;
+            let samples = resampler.resample(&decoded)?;
+            let skip = (delay - skipped).min(samples.len());
+            skipped += skip;
+            &samples[skip..]
+        } else {
+            let buffer = buffer.get_or_insert_with(|| {
+                SampleBuffer::new(decoded.capacity() as u64, *decoded.spec())
+            });
+            buffer.copy_interleaved_ref(decoded);
+            buffer.samples()
+        };
+        output.write(samples)?;
+        written += samples.len();
+    }
+    if let Some(resampler) = &mut resampled {
+        let expected = (input_frames * target_rate).div_ceil(sample_rate) * track.channels;
+        let delay = resampler.output_delay() * track.channels;
+        while written < expected {
+            let samples = resampler.flush()?;
+            if samples.is_empty() {
+                break;
+            }
+            let skip = (delay - skipped).min(samples.len());
+            skipped += skip;
+            let samples = &samples[skip..(skip + expected - written).min(samples.len())];
+            output.write(samples)?;
+            written += samples.len();
+        }
+    }
+    output.finish()
+}
+
+/// Files to convert with their destination, folders are walked and keep their layout under
+/// `output`. Without `output` files are written next to their source.
+fn destinations(
+    paths: &[PathBuf],
+    output: Option<&Path>,
+    format: Format,
+) -> Vec<(String, PathBuf)> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            for file in Scanner::new(path).files(path) {
+                let relative = Path::new(&file)
+                    .strip_prefix(path)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default();
+                let destination = match output {
+                    Some(output) => output.join(relative),
+                    None => PathBuf::from(&file),
+                };
+                files.push((file, destination));
+            }
+        } else {
+            let destination = match (output, path.file_name()) {
+                (Some(output), Some(name)) => output.join(name),
+                _ => path.clone(),
+            };
+            files.push((path.to_string_lossy().into_owned(), destination));
+        }
+    }
+    for (_, destination) in files.iter_mut() {
+        destination.set_extension(format.extension());
+    }
+    files
+}
+
+/// Converts files and folders one at a time, reporting progress on the console. Failed files
+/// are reported and skipped.
+pub fn convert_files(
+    paths: &[PathBuf],
+    output: Option<&Path>,
+    target: &Target,
+    resampler: ResamplerSettings,
+) -> Result<()> {
+    target.format.check_available()?;
+    let files = destinations(paths, output, target.format);
+    let total = files.len();
+    let mut failed = 0;
+    for (index, (source, destination)) in files.into_iter().enumerate() {
+        println!(
+            "[{}/{}] {} -> {}",
+            index + 1,
+            total,
+            source,
+            destination.display()
+        );
+        let result = if Path::new(&source) == destination {
+            Err(anyhow!("Would overwrite the source, use --output"))
+        } else {
+            destination
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .map_err(Into::into)
+                .and_then(|_| MusicTrack::new(source))
+                .and_then(|track| convert(&track, &destination, target, resampler))
+        };
+        if let Err(err) = result {
+            eprintln!("    {}", err);
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow!("{} of {} files failed", failed, total)),
+    }
+}
@@ -0,0 +1,336 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::hash_map::{Entry, HashMap};
+use std::path::{Path, PathBuf};
+use symphonia::core::units::Time;
+
+use crate::library::Database;
+use crate::musictrack::MusicTrack;
+
+/// Positions in CUE sheets are `mm:ss:ff`, in CD frames.
+const CD_FRAMES_PER_SECOND: u64 = 75;
+
+/// How the gap between INDEX 00 and INDEX 01 of a track is played, the hidden track before the
+/// first track included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pregap {
+    /// Starts the track, its elapsed time counting up from minus the gap length
+    #[default]
+    Show,
+    /// Ends the previous track so live albums stay gapless, the hidden track is not played
+    Skip,
+}
+
+/// Part of a file played as a track of its own. Positions are in frames of the file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Segment {
+    pub start: u64,
+    /// End of the file when `None`
+    pub end: Option<u64>,
+    /// Frames of gap at the start of the segment, before the track proper
+    pub pregap: u64,
+}
+
+impl Segment {
+    /// Frames to drop from the start and from the end of a packet of `frames` frames starting
+    /// at `ts`, `None` once the packet is past the end of the segment.
+    pub fn trim(&self, ts: u64, frames: usize) -> Option<(usize, usize)> {
+        let end = self.end.unwrap_or(u64::MAX);
+        if ts >= end {
+            return None;
+        }
+        let packet_end = ts + frames as u64;
+        let start = self.start.saturating_sub(ts).min(frames as u64) as usize;
+        let tail = packet_end.saturating_sub(end) as usize;
+        Some((start, tail.min(frames - start)))
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Index {
+    /// Position of the file in `Sheet::files`
+    file: usize,
+    /// In CD frames from the start of the file
+    position: u64,
+}
+
+#[derive(Default)]
+struct SheetTrack {
+    number: u32,
+    title: Option<String>,
+    performer: Option<String>,
+    pregap: Option<Index>,
+    start: Option<Index>,
+}
+
+#[derive(Default)]
+struct Sheet {
+    title: Option<String>,
+    performer: Option<String>,
+    files: Vec<PathBuf>,
+    tracks: Vec<SheetTrack>,
+}
+
+/// Sheets written by older rippers are often in a Windows code page rather than UTF-8, their
+/// bytes are read as Latin-1 then.
+fn read_text(path: &Path) -> Result<String> {
+    let bytes = std::fs::read(path)?;
+    let text = match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(err) => err.into_bytes().iter().map(|byte| *byte as char).collect(),
+    };
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Quoted values may hold spaces, unquoted ones end at the first space.
+fn value(rest: &str) -> String {
+    let rest = rest.trim();
+    match rest.strip_prefix('"') {
+        Some(quoted) => quoted.split('"').next().unwrap_or_default().to_string(),
+        None => rest
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+    }
+}
+
+fn position(text: &str) -> Option<u64> {
+    let mut parts = text.split(':').map(|part| part.parse::<u64>().ok());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Some(minutes)), Some(Some(seconds)), Some(Some(frames)), None) => {
+            Some((minutes * 60 + seconds) * CD_FRAMES_PER_SECOND + frames)
+        }
+        _ => None,
+    }
+}
+
+/// Keeps what playback needs, commands such as FLAGS, ISRC or REM are ignored.
+fn parse(text: &str, dir: &Path) -> Result<Sheet> {
+    let mut sheet = Sheet::default();
+    for line in text.lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let track = sheet.tracks.last_mut();
+        match (command.to_uppercase().as_str(), track) {
+            ("FILE", _) => {
+                // The file type follows the name
+                let name = value(rest);
+                sheet.files.push(dir.join(name));
+            }
+            ("TRACK", _) => {
+                let number = rest
+                    .split_whitespace()
+                    .next()
+                    .and_then(|number| number.parse().ok())
+                    .ok_or(anyhow!("Invalid track number: {}", line))?;
+                sheet.tracks.push(SheetTrack {
+                    number,
+                    ..Default::default()
+                });
+            }
+            ("TITLE", Some(track)) => track.title = Some(value(rest)),
+            ("TITLE", None) => sheet.title = Some(value(rest)),
+            ("PERFORMER", Some(track)) => track.performer = Some(value(rest)),
+            ("PERFORMER", None) => sheet.performer = Some(value(rest)),
+            ("INDEX", Some(track)) => {
+                let mut fields = rest.split_whitespace();
+                let number = fields.next().and_then(|number| number.parse::<u32>().ok());
+                let position = fields.next().and_then(position);
+                let (Some(number), Some(position)) = (number, position) else {
+                    return Err(anyhow!("Invalid index: {}", line));
+                };
+                let file = sheet
+                    .files
+                    .len()
+                    .checked_sub(1)
+                    .ok_or(anyhow!("Index before any file: {}", line))?;
+                let index = Some(Index { file, position });
+                match number {
+                    0 => track.pregap = index,
+                    1 => track.start = index,
+                    // Sub-indexes only mark positions within the track
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(sheet)
+}
+
+/// Audio files referenced by a sheet, left out of the playlist in favour of its tracks.
+pub fn files(sheet: &Path) -> Result<Vec<PathBuf>> {
+    let dir = sheet.parent().unwrap_or(Path::new(""));
+    Ok(parse(&read_text(sheet)?, dir)?.files)
+}
+
+impl Sheet {
+    /// Where the track starts playing. Gaps stored at the end of the previous file, as some
+    /// rips do, stay there.
+    fn start(&self, track: &SheetTrack, pregap: Pregap) -> Option<Index> {
+        let start = track.start?;
+        Some(match (pregap, track.pregap) {
+            (Pregap::Show, Some(index)) if index.file == start.file => index,
+            _ => start,
+        })
+    }
+}
+
+fn time(frames: u64, rate: u64) -> Time {
+    Time::new(frames / rate, (frames % rate) as f64 / rate as f64)
+}
+
+/// Tracks of a CUE sheet, each playing its part of the referenced files. The files are probed
+/// through the library.
+pub fn tracks(sheet_path: &Path, pregap: Pregap, library: &Database) -> Result<Vec<MusicTrack>> {
+    let dir = sheet_path.parent().unwrap_or(Path::new(""));
+    let sheet = parse(&read_text(sheet_path)?, dir)?;
+    let mut probed: HashMap<usize, MusicTrack> = HashMap::new();
+    let mut tracks = Vec::new();
+    for (position, track) in sheet.tracks.iter().enumerate() {
+        let (Some(start), Some(index)) = (sheet.start(track, pregap), track.start) else {
+            continue;
+        };
+        let file = match probed.entry(start.file) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = sheet.files[start.file].to_string_lossy().into_owned();
+                let file = library.track(path)?;
+                if file.dsd_rate.is_some() {
+                    return Err(anyhow!(
+                        "CUE sheets of DSD files are not supported: {}",
+                        file.path
+                    ));
+                }
+                entry.insert(file)
+            }
+        };
+        let rate = file.sample as u64;
+        let frames = |position: u64| position * rate / CD_FRAMES_PER_SECOND;
+        let end = sheet.tracks[position + 1..]
+            .iter()
+            .find_map(|next| sheet.start(next, pregap))
+            .filter(|next| next.file == start.file)
+            .map(|next| frames(next.position));
+        let segment = Segment {
+            start: frames(start.position),
+            end,
+            pregap: frames(index.position) - frames(start.position),
+        };
+        let length = match end {
+            Some(end) => end.saturating_sub(segment.start),
+            None => {
+                let duration =
+                    file.duration.seconds * rate + (file.duration.frac * rate as f64) as u64;
+                duration.saturating_sub(segment.start)
+            }
+        };
+        tracks.push(MusicTrack {
+            title: track
+                .title
+                .clone()
+                .unwrap_or_else(|| format!("Track {:0>2}", track.number)),
+            artist: track
+                .performer
+                .clone()
+                .or_else(|| sheet.performer.clone())
+                .unwrap_or_else(|| file.artist.clone()),
+            album: sheet.title.clone().unwrap_or_else(|| file.album.clone()),
+            duration: time(length, rate),
+            segment: Some(segment),
+            ..file.clone()
+        });
+    }
+    Ok(tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Live album with a hidden track before the first one and a gap before the second.
+    const SHEET: &str = r#"REM GENRE Rock
+PERFORMER "The Band"
+TITLE "Live"
+FILE "live.flac" WAVE
+  TRACK 01 AUDIO
+    TITLE "Intro"
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+  TRACK 02 AUDIO
+    TITLE "Song"
+    PERFORMER "Guest"
+    INDEX 00 03:00:00
+    INDEX 01 03:01:37
+  TRACK 03 AUDIO
+    INDEX 01 05:00:00
+"#;
+
+    fn sheet() -> Sheet {
+        parse(SHEET, Path::new("/music")).unwrap()
+    }
+
+    #[test]
+    fn positions_are_in_cd_frames() {
+        assert_eq!(position("00:00:00"), Some(0));
+        assert_eq!(position("03:01:37"), Some((181) * 75 + 37));
+        assert_eq!(position("1:2"), None);
+        assert_eq!(position("aa:00:00"), None);
+    }
+
+    #[test]
+    fn sheet_commands() {
+        let sheet = sheet();
+        assert_eq!(sheet.title.as_deref(), Some("Live"));
+        assert_eq!(sheet.performer.as_deref(), Some("The Band"));
+        assert_eq!(sheet.files, vec![Path::new("/music").join("live.flac")]);
+        assert_eq!(sheet.tracks.len(), 3);
+        assert_eq!(sheet.tracks[1].performer.as_deref(), Some("Guest"));
+        assert_eq!(sheet.tracks[2].number, 3);
+        assert!(sheet.tracks[2].pregap.is_none());
+    }
+
+    #[test]
+    fn shown_pregaps_start_the_track() {
+        let sheet = sheet();
+        let starts: Vec<u64> = sheet
+            .tracks
+            .iter()
+            .filter_map(|track| sheet.start(track, Pregap::Show))
+            .map(|index| index.position)
+            .collect();
+        assert_eq!(starts, vec![0, 180 * 75, 300 * 75]);
+    }
+
+    #[test]
+    fn skipped_pregaps_end_the_previous_track() {
+        let sheet = sheet();
+        let starts: Vec<u64> = sheet
+            .tracks
+            .iter()
+            .filter_map(|track| sheet.start(track, Pregap::Skip))
+            .map(|index| index.position)
+            .collect();
+        assert_eq!(starts, vec![2 * 75, 181 * 75 + 37, 300 * 75]);
+    }
+
+    #[test]
+    fn packets_are_trimmed_to_the_segment() {
+        let segment = Segment {
+            start: 1000,
+            end: Some(5000),
+            pregap: 0,
+        };
+        assert_eq!(segment.trim(0, 4096), Some((1000, 0)));
+        assert_eq!(segment.trim(4096, 4096), Some((0, 3192)));
+        assert_eq!(segment.trim(2000, 1000), Some((0, 0)));
+        assert_eq!(segment.trim(5000, 4096), None);
+        let open = Segment {
+            end: None,
+            ..segment
+        };
+        assert_eq!(open.trim(8192, 4096), Some((0, 0)));
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use std::io::IsTerminal;
+use std::path::Path;
+
+use crate::audio::{BitsPerSample, Device, DeviceTrait, Host, HostTrait, SampleRate};
+use crate::config::Config;
+use crate::library::Database;
+
+/// Prints one check on its own line, the status first.
+fn check(ok: bool, label: &str, detail: impl AsRef<str>) {
+    let status = if ok { " ok " } else { "FAIL" };
+    println!("[{}] {}: {}", status, label, detail.as_ref());
+}
+
+fn env(name: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| "unset".to_string())
+}
+
+fn terminal() {
+    println!("Terminal");
+    let stdout = std::io::stdout();
+    check(
+        stdout.is_terminal(),
+        "Interactive",
+        if stdout.is_terminal() {
+            "yes"
+        } else {
+            "output is redirected"
+        },
+    );
+    if let Ok((columns, rows)) = crossterm::terminal::size() {
+        check(
+            columns >= 80 && rows >= 24,
+            "Size",
+            format!("{}x{}", columns, rows),
+        );
+    }
+    // Windows Terminal renders truecolor and the box drawing and braille glyphs
+    let windows_terminal = std::env::var_os("WT_SESSION").is_some();
+    let colorterm = env("COLORTERM");
+    check(
+        windows_terminal || colorterm == "truecolor" || colorterm == "24bit",
+        "Truecolor",
+        format!("COLORTERM={}", colorterm),
+    );
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_default();
+    let utf8 = locale.to_lowercase().replace('-', "").contains("utf8");
+    check(
+        windows_terminal || utf8,
+        "Glyphs",
+        format!("TERM={}, locale {}", env("TERM"), locale),
+    );
+}
+
+fn audio(backend: &str, high_priority_mode: bool) {
+    println!("Audio");
+    let built = Host::backends();
+    let selected = if built.contains(&backend) {
+        backend.to_string()
+    } else {
+        format!("{} is not built, the platform default is used", backend)
+    };
+    check(
+        true,
+        "Backend",
+        format!("{} (built with {})", selected, built.join(", ")),
+    );
+    for name in built {
+        match Host::new(name, high_priority_mode).get_devices() {
+            Ok(devices) => check(
+                !devices.is_empty(),
+                name,
+                format!("{} devices", devices.len()),
+            ),
+            Err(err) => check(false, name, err.to_string()),
+        }
+    }
+    let host = Host::new(backend, high_priority_mode);
+    let device = match host.get_default_device() {
+        Ok(device) => device,
+        Err(err) => return check(false, "Default device", err.to_string()),
+    };
+    check(
+        true,
+        "Default device",
+        device.name().unwrap_or_else(|err| err.to_string()),
+    );
+    // WASAPI probes formats in exclusive mode, all of them fail when the device refuses it
+    if !cfg!(windows) || backend != "wasapi" {
+        return formats(&device);
+    }
+    let exclusive = device.supported_formats(&[
+        (SampleRate::Rate44100Hz, BitsPerSample::Bits16, 2),
+        (SampleRate::Rate48000Hz, BitsPerSample::Bits16, 2),
+    ]);
+    match exclusive {
+        Ok(supported) if supported.contains(&true) => check(true, "Exclusive mode", "allowed"),
+        Ok(_) => check(
+            false,
+            "Exclusive mode",
+            "refused, allow applications to take exclusive control in the device properties",
+        ),
+        Err(err) => check(false, "Exclusive mode", err.to_string()),
+    }
+    formats(&device);
+}
+
+fn formats(device: &Device) {
+    match device.get_capabilities() {
+        Ok(capabilities) => check(
+            !capabilities.sample_rates.is_empty(),
+            "Formats",
+            format!(
+                "{:?} Hz, {:?} bits, {:?} channels",
+                capabilities
+                    .sample_rates
+                    .iter()
+                    .map(|rate| *rate as usize)
+                    .collect::<Vec<_>>(),
+                capabilities
+                    .bits_per_samples
+                    .iter()
+                    .map(|bits| *bits as usize)
+                    .collect::<Vec<_>>(),
+                capabilities.channels
+            ),
+        ),
+        Err(err) => check(false, "Formats", err.to_string()),
+    }
+}
+
+fn files(music: Option<&Path>) {
+    println!("Files");
+    match (Config::path(), Config::load()) {
+        (Some(path), Ok(_)) if path.exists() => check(true, "Config", path.display().to_string()),
+        (Some(path), Ok(_)) => check(true, "Config", format!("{} (defaults)", path.display())),
+        (None, Ok(_)) => check(true, "Config", "no config directory, defaults"),
+        (_, Err(err)) => check(false, "Config", err.to_string()),
+    }
+    match Database::check() {
+        Ok(path) => check(true, "Library", path.display().to_string()),
+        Err(err) => check(false, "Library", err.to_string()),
+    }
+    if let Some(music) = music {
+        match std::fs::read_dir(music) {
+            Ok(entries) => check(
+                true,
+                "Music",
+                format!("{} ({} entries)", music.display(), entries.count()),
+            ),
+            Err(err) => check(false, "Music", format!("{}: {}", music.display(), err)),
+        }
+    }
+}
+
+/// Checks the terminal, the audio backends and the files rhap relies on, printing a report
+/// meant to be pasted into issues.
+pub fn run(backend: &str, high_priority_mode: bool, music: Option<&Path>) -> Result<()> {
+    println!(
+        "rhap {} on {} {}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    terminal();
+    audio(backend, high_priority_mode);
+    files(music);
+    Ok(())
+}
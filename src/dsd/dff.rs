@@ -0,0 +1,142 @@
+use std::io::{Seek, SeekFrom};
+
+use symphonia::core::{
+    errors::{decode_error, unsupported_error, Result},
+    io::{MediaSourceStream, ReadBytes},
+};
+
+use super::{DsdLayout, Interleaving};
+
+struct Properties {
+    rate: u32,
+    channels: usize,
+    compression: [u8; 4],
+}
+
+/// Reads the sound property chunk, DST compressed streams are rejected.
+fn read_properties(source: &mut MediaSourceStream, end: u64) -> Result<Properties> {
+    if &source.read_quad_bytes()? != b"SND " {
+        return decode_error("dff: invalid property chunk");
+    }
+    let mut properties = Properties {
+        rate: 0,
+        channels: 0,
+        compression: *b"DSD ",
+    };
+    while source.pos() < end {
+        let id = source.read_quad_bytes()?;
+        let size = source.read_be_u64()?;
+        let next = source.pos() + size + (size & 1);
+        match &id {
+            b"FS  " => properties.rate = source.read_be_u32()?,
+            b"CHNL" => properties.channels = source.read_be_u16()? as usize,
+            b"CMPR" => properties.compression = source.read_quad_bytes()?,
+            _ => (),
+        }
+        source.seek(SeekFrom::Start(next))?;
+    }
+    if &properties.compression != b"DSD " {
+        return unsupported_error("dff: only uncompressed DSD is supported");
+    }
+    Ok(properties)
+}
+
+/// Reads the DSDIFF chunks following the `FRM8` magic, all fields are big endian.
+pub(crate) fn read_layout(source: &mut MediaSourceStream) -> Result<DsdLayout> {
+    let form_size = source.read_be_u64()?;
+    let end = source.pos() + form_size;
+    if &source.read_quad_bytes()? != b"DSD " {
+        return unsupported_error("dff: not a DSD form");
+    }
+
+    let mut properties = None;
+    let mut data = None;
+    let mut metadata_offset = None;
+    while source.pos() < end {
+        let id = source.read_quad_bytes()?;
+        let size = source.read_be_u64()?;
+        let start = source.pos();
+        match &id {
+            b"PROP" => properties = Some(read_properties(source, start + size)?),
+            b"DSD " => data = Some((start, size)),
+            b"DST " => return unsupported_error("dff: only uncompressed DSD is supported"),
+            b"ID3 " => metadata_offset = Some(start),
+            _ => (),
+        }
+        // The sound data may be large, seek over it rather than reading it
+        source.seek(SeekFrom::Start(start + size + (size & 1)))?;
+    }
+
+    let (Some(properties), Some((data_offset, data_size))) = (properties, data) else {
+        return decode_error("dff: missing property or sound data chunk");
+    };
+    if properties.channels == 0 {
+        return decode_error("dff: invalid channel count");
+    }
+    Ok(DsdLayout {
+        rate: properties.rate,
+        channels: properties.channels,
+        data_offset,
+        channel_bytes: data_size / properties.channels as u64,
+        interleaving: Interleaving::Byte,
+        metadata_offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsd::{DsdReader, DSD64_RATE};
+    use std::io::Cursor;
+    use symphonia::core::errors::{Error, Result};
+    use symphonia::core::formats::{FormatOptions, FormatReader};
+    use symphonia::core::io::MediaSourceStream;
+
+    fn chunk(id: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut chunk = id.to_vec();
+        chunk.extend((body.len() as u64).to_be_bytes());
+        chunk.extend(body);
+        if body.len() % 2 == 1 {
+            chunk.push(0);
+        }
+        chunk
+    }
+
+    /// Stereo DSDIFF file holding `data`, already interleaved byte by byte.
+    fn dff(compression: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut properties = b"SND ".to_vec();
+        properties.extend(chunk(b"FS  ", &DSD64_RATE.to_be_bytes()));
+        properties.extend(chunk(b"CHNL", b"\x00\x02SLFTSRGT"));
+        // Compression type then a Pascal string name
+        let mut cmpr = compression.to_vec();
+        cmpr.extend(b"\x00");
+        properties.extend(chunk(b"CMPR", &cmpr));
+        let mut form = b"DSD ".to_vec();
+        form.extend(chunk(b"FVER", &0x0105_0000u32.to_be_bytes()));
+        form.extend(chunk(b"PROP", &properties));
+        form.extend(chunk(b"DSD ", data));
+        chunk(b"FRM8", &form)
+    }
+
+    fn reader(file: Vec<u8>) -> Result<DsdReader> {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(file)), Default::default());
+        DsdReader::try_new(source, &FormatOptions::default())
+    }
+
+    #[test]
+    fn bytes_are_kept_msb_first_and_interleaved() {
+        let data = [0x80, 0xC0, 0x01, 0x03, 0xF0, 0x48, 0x96, 0xFF];
+        let mut reader = reader(dff(b"DSD ", &data)).unwrap();
+        let params = &reader.tracks()[0].codec_params;
+        assert_eq!(params.sample_rate, Some(DSD64_RATE / 16));
+        assert_eq!(params.n_frames, Some(2));
+        assert_eq!(*reader.next_packet().unwrap().data, data);
+    }
+
+    #[test]
+    fn dst_compressed_streams_are_rejected() {
+        assert!(matches!(
+            reader(dff(b"DST ", &[0; 8])),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
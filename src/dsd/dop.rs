@@ -0,0 +1,162 @@
+use symphonia::core::{
+    audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Signal, SignalSpec},
+    codecs::{CodecDescriptor, CodecParameters, Decoder, DecoderOptions, FinalizeResult},
+    errors::{decode_error, unsupported_error, Result},
+    formats::Packet,
+    sample::i24,
+    support_codec,
+};
+
+use super::CODEC_TYPE_DSD;
+
+/// DoP markers alternate on every frame so the DAC can tell DSD from PCM.
+const MARKERS: [u32; 2] = [0x05, 0xFA];
+
+/// Packs DSD into 24 bits PCM frames following the DoP 1.1 specification, each sample carries
+/// a marker byte followed by 16 DSD bits of its channel. The samples must reach the DAC
+/// untouched, any volume or resampling stage would destroy the stream.
+pub struct DopDecoder {
+    params: CodecParameters,
+    buffer: AudioBuffer<i24>,
+    marker: usize,
+}
+
+impl Decoder for DopDecoder {
+    fn try_new(params: &CodecParameters, _options: &DecoderOptions) -> Result<Self> {
+        if params.codec != CODEC_TYPE_DSD {
+            return unsupported_error("dop: invalid codec type");
+        }
+        let (Some(rate), Some(channels), Some(max_frames)) = (
+            params.sample_rate,
+            params.channels,
+            params.max_frames_per_packet,
+        ) else {
+            return unsupported_error("dop: incomplete codec parameters");
+        };
+        Ok(Self {
+            params: params.clone(),
+            buffer: AudioBuffer::new(max_frames, SignalSpec::new(rate, channels)),
+            marker: 0,
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[support_codec!(CODEC_TYPE_DSD, "dop", "DSD over PCM")]
+    }
+
+    fn reset(&mut self) {
+        self.marker = 0;
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        let channels = self.buffer.spec().channels.count();
+        let frames = packet.data.len() / (channels * 2);
+        if frames as u64 > self.buffer.capacity() as u64 {
+            return decode_error("dop: packet exceeds the maximum frame count");
+        }
+        self.buffer.clear();
+        self.buffer.render_reserved(Some(frames));
+        let start = self.marker;
+        for channel in 0..channels {
+            for (frame, sample) in self.buffer.chan_mut(channel).iter_mut().enumerate() {
+                let first = packet.data[frame * 2 * channels + channel] as u32;
+                let second = packet.data[(frame * 2 + 1) * channels + channel] as u32;
+                let word = MARKERS[(start + frame) % 2] << 16 | first << 8 | second;
+                // Sign extend the 24 bits word
+                *sample = i24(((word << 8) as i32) >> 8);
+            }
+        }
+        self.marker = (start + frames) % 2;
+        Ok(self.buffer.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        FinalizeResult::default()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.buffer.as_audio_buffer_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::audio::Channels;
+
+    fn decoder(channels: usize) -> DopDecoder {
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_DSD)
+            .with_sample_rate(176_400)
+            .with_max_frames_per_packet(8)
+            .with_channels(Channels::from_bits_truncate((1 << channels) - 1));
+        DopDecoder::try_new(&params, &DecoderOptions::default()).unwrap()
+    }
+
+    /// 24 bits words of each channel, the marker in the top byte.
+    fn words(decoder: &mut DopDecoder, data: &[u8]) -> Vec<Vec<u32>> {
+        let packet = Packet::new_from_slice(0, 0, 0, data);
+        let AudioBufferRef::S24(buffer) = decoder.decode(&packet).unwrap() else {
+            panic!("DoP frames are 24 bits");
+        };
+        (0..buffer.spec().channels.count())
+            .map(|channel| {
+                buffer
+                    .chan(channel)
+                    .iter()
+                    .map(|sample| sample.inner() as u32 & 0xFF_FFFF)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn words_hold_the_marker_then_two_dsd_bytes_of_the_channel() {
+        let mut decoder = decoder(2);
+        // Byte interleaved, oldest byte first: L0 R0 L1 R1
+        let words = words(&mut decoder, &[0x12, 0xAB, 0x34, 0xCD]);
+        assert_eq!(words, vec![vec![0x05_1234], vec![0x05_ABCD]]);
+    }
+
+    #[test]
+    fn negative_words_are_sign_extended() {
+        let mut decoder = decoder(1);
+        let packet = Packet::new_from_slice(0, 0, 0, &[0x00, 0x00, 0xFF, 0xFF]);
+        let AudioBufferRef::S24(buffer) = decoder.decode(&packet).unwrap() else {
+            panic!("DoP frames are 24 bits");
+        };
+        assert_eq!(buffer.chan(0)[0].inner(), 0x05_0000);
+        // The 0xFA marker sets the sign bit of the word
+        assert_eq!(buffer.chan(0)[1].inner(), 0xFA_FFFF - (1 << 24));
+    }
+
+    #[test]
+    fn markers_alternate_across_frames_and_packets() {
+        let mut decoder = decoder(1);
+        let markers =
+            |words: Vec<Vec<u32>>| -> Vec<u32> { words[0].iter().map(|word| word >> 16).collect() };
+        assert_eq!(
+            markers(words(&mut decoder, &[0; 6])),
+            vec![0x05, 0xFA, 0x05]
+        );
+        // An odd frame count leaves the next packet starting on the other marker
+        assert_eq!(markers(words(&mut decoder, &[0; 4])), vec![0xFA, 0x05]);
+        decoder.reset();
+        assert_eq!(markers(words(&mut decoder, &[0; 2])), vec![0x05]);
+    }
+
+    #[test]
+    fn markers_are_shared_by_the_channels_of_a_frame() {
+        let mut decoder = decoder(2);
+        let words = words(&mut decoder, &[0; 8]);
+        for channel in words {
+            assert_eq!(channel[0] >> 16, 0x05);
+            assert_eq!(channel[1] >> 16, 0xFA);
+        }
+    }
+}
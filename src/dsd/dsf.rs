@@ -0,0 +1,122 @@
+use symphonia::core::{
+    errors::{decode_error, unsupported_error, Result},
+    io::{MediaSourceStream, ReadBytes},
+};
+
+use super::{DsdLayout, Interleaving};
+
+/// Size of the fmt chunk defined by the DSF specification.
+const FMT_CHUNK_SIZE: u64 = 52;
+
+/// Reads the DSF header following the `DSD ` magic, all fields are little endian.
+pub(crate) fn read_layout(source: &mut MediaSourceStream) -> Result<DsdLayout> {
+    let _chunk_size = source.read_u64()?;
+    let _file_size = source.read_u64()?;
+    let metadata_offset = source.read_u64()?;
+
+    if &source.read_quad_bytes()? != b"fmt " {
+        return decode_error("dsf: missing fmt chunk");
+    }
+    let fmt_size = source.read_u64()?;
+    let _version = source.read_u32()?;
+    if source.read_u32()? != 0 {
+        return unsupported_error("dsf: only raw DSD is supported");
+    }
+    let _channel_type = source.read_u32()?;
+    let channels = source.read_u32()? as usize;
+    let rate = source.read_u32()?;
+    let lsb_first = match source.read_u32()? {
+        1 => true,
+        8 => false,
+        _ => return decode_error("dsf: invalid bits per sample"),
+    };
+    let sample_count = source.read_u64()?;
+    let block_size = source.read_u32()? as usize;
+    let _reserved = source.read_u32()?;
+    source.ignore_bytes(fmt_size.saturating_sub(FMT_CHUNK_SIZE))?;
+    if block_size == 0 {
+        return decode_error("dsf: invalid block size");
+    }
+
+    if &source.read_quad_bytes()? != b"data" {
+        return decode_error("dsf: missing data chunk");
+    }
+    let _data_size = source.read_u64()?;
+
+    Ok(DsdLayout {
+        rate,
+        channels,
+        data_offset: source.pos(),
+        channel_bytes: sample_count.div_ceil(8),
+        interleaving: Interleaving::Block {
+            size: block_size,
+            lsb_first,
+        },
+        metadata_offset: (metadata_offset != 0).then_some(metadata_offset),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dsd::{DsdReader, DSD64_RATE};
+    use std::io::Cursor;
+    use symphonia::core::formats::{FormatOptions, FormatReader};
+    use symphonia::core::io::MediaSourceStream;
+
+    const BLOCK_SIZE: usize = 4;
+
+    /// Stereo DSF file with one block per channel, `bits` 1 for LSB first and 8 for MSB first.
+    fn dsf(bits: u32, left: [u8; BLOCK_SIZE], right: [u8; BLOCK_SIZE]) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.extend(b"DSD ");
+        file.extend(28u64.to_le_bytes());
+        file.extend(((28 + 52 + 12 + 2 * BLOCK_SIZE) as u64).to_le_bytes());
+        file.extend(0u64.to_le_bytes());
+        file.extend(b"fmt ");
+        file.extend(52u64.to_le_bytes());
+        // Version, raw DSD, stereo channel type, channels and rate
+        for field in [1, 0, 2, 2, DSD64_RATE, bits] {
+            file.extend(field.to_le_bytes());
+        }
+        file.extend((BLOCK_SIZE as u64 * 8).to_le_bytes());
+        file.extend((BLOCK_SIZE as u32).to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(b"data");
+        file.extend(((12 + 2 * BLOCK_SIZE) as u64).to_le_bytes());
+        file.extend(left);
+        file.extend(right);
+        file
+    }
+
+    fn first_packet(file: Vec<u8>) -> Vec<u8> {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(file)), Default::default());
+        let mut reader = DsdReader::try_new(source, &FormatOptions::default()).unwrap();
+        reader.next_packet().unwrap().data.to_vec()
+    }
+
+    #[test]
+    fn blocks_are_interleaved_byte_by_byte() {
+        let data = first_packet(dsf(8, [0x01, 0x02, 0x03, 0x04], [0x11, 0x12, 0x13, 0x14]));
+        assert_eq!(data, [0x01, 0x11, 0x02, 0x12, 0x03, 0x13, 0x04, 0x14]);
+    }
+
+    #[test]
+    fn lsb_first_bytes_are_reversed_to_msb_first() {
+        let data = first_packet(dsf(1, [0x01, 0x80, 0x0F, 0x69], [0x03, 0xC0, 0x12, 0xFF]));
+        assert_eq!(data, [0x80, 0xC0, 0x01, 0x03, 0xF0, 0x48, 0x96, 0xFF]);
+    }
+
+    #[test]
+    fn layout_reports_the_stream_shape() {
+        let source = MediaSourceStream::new(
+            Box::new(Cursor::new(dsf(1, [0; BLOCK_SIZE], [0; BLOCK_SIZE]))),
+            Default::default(),
+        );
+        let reader = DsdReader::try_new(source, &FormatOptions::default()).unwrap();
+        let params = &reader.tracks()[0].codec_params;
+        // 16 DSD bits per DoP frame
+        assert_eq!(params.sample_rate, Some(DSD64_RATE / 16));
+        assert_eq!(params.n_frames, Some(BLOCK_SIZE as u64 / 2));
+        assert_eq!(params.channels.map(|channels| channels.count()), Some(2));
+    }
+}
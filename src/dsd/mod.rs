@@ -0,0 +1,207 @@
+pub(crate) mod dff;
+pub(crate) mod dop;
+pub(crate) mod dsf;
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use symphonia::core::{
+    audio::Channels,
+    codecs::{decl_codec_type, CodecParameters, CodecType},
+    errors::{seek_error, unsupported_error, Error, Result, SeekErrorKind},
+    formats::{Cue, FormatOptions, FormatReader, Packet, SeekMode, SeekTo, SeekedTo, Track},
+    io::{MediaSourceStream, ReadBytes},
+    meta::{Metadata, MetadataBuilder, MetadataLog},
+    units::TimeBase,
+};
+
+/// Raw one bit DSD, packets carry channel interleaved bytes with the oldest bit first.
+pub const CODEC_TYPE_DSD: CodecType = decl_codec_type(b"dsd");
+
+/// Sample rate of DSD64, higher rates are multiples of it.
+pub const DSD64_RATE: u32 = 2_822_400;
+
+/// Bytes read per channel for each packet of byte interleaved streams.
+const PACKET_BYTES: usize = 4096;
+
+/// Location and shape of the DSD stream inside its container.
+pub(crate) struct DsdLayout {
+    pub rate: u32,
+    pub channels: usize,
+    pub data_offset: u64,
+    /// Length of the stream in bytes for a single channel
+    pub channel_bytes: u64,
+    pub interleaving: Interleaving,
+    /// Offset of an ID3v2 tag, if any
+    pub metadata_offset: Option<u64>,
+}
+
+pub(crate) enum Interleaving {
+    /// DSF stores fixed size blocks per channel, bits may be stored least significant first
+    Block { size: usize, lsb_first: bool },
+    /// DSDIFF interleaves channels byte by byte, most significant bit first
+    Byte,
+}
+
+impl DsdLayout {
+    fn packet_bytes(&self) -> usize {
+        match self.interleaving {
+            Interleaving::Block { size, .. } => size,
+            Interleaving::Byte => PACKET_BYTES,
+        }
+    }
+}
+
+pub fn is_dsd(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| {
+            extension.eq_ignore_ascii_case("dsf") || extension.eq_ignore_ascii_case("dff")
+        })
+        .unwrap_or(false)
+}
+
+/// Demuxes DSF and DSDIFF files, timestamps are counted in DoP frames of 16 DSD bits.
+pub struct DsdReader {
+    source: MediaSourceStream,
+    layout: DsdLayout,
+    tracks: Vec<Track>,
+    metadata: MetadataLog,
+    /// Bytes per channel already read
+    position: u64,
+}
+
+impl DsdReader {
+    fn read_metadata(source: &mut MediaSourceStream, offset: u64) -> Result<MetadataLog> {
+        source.seek(SeekFrom::Start(offset))?;
+        let mut builder = MetadataBuilder::new();
+        symphonia_metadata::id3v2::read_id3v2(source, &mut builder)?;
+        let mut metadata = MetadataLog::default();
+        metadata.push(builder.metadata());
+        Ok(metadata)
+    }
+}
+
+impl FormatReader for DsdReader {
+    fn try_new(mut source: MediaSourceStream, _options: &FormatOptions) -> Result<Self> {
+        let layout = match &source.read_quad_bytes()? {
+            b"DSD " => dsf::read_layout(&mut source)?,
+            b"FRM8" => dff::read_layout(&mut source)?,
+            _ => return unsupported_error("dsd: not a DSF or DSDIFF file"),
+        };
+        if layout.channels == 0 || layout.rate % DSD64_RATE != 0 {
+            return unsupported_error("dsd: unsupported stream");
+        }
+
+        // Tags are optional, a broken one should not prevent playback
+        let metadata = layout
+            .metadata_offset
+            .and_then(|offset| Self::read_metadata(&mut source, offset).ok())
+            .unwrap_or_default();
+
+        let frame_rate = layout.rate / 16;
+        let mut codec_params = CodecParameters::new();
+        codec_params
+            .for_codec(CODEC_TYPE_DSD)
+            .with_sample_rate(frame_rate)
+            .with_time_base(TimeBase::new(1, frame_rate))
+            .with_n_frames(layout.channel_bytes / 2)
+            .with_bits_per_sample(24)
+            .with_max_frames_per_packet(layout.packet_bytes() as u64 / 2)
+            .with_channels(Channels::from_bits_truncate((1 << layout.channels) - 1));
+
+        source.seek(SeekFrom::Start(layout.data_offset))?;
+        Ok(Self {
+            source,
+            layout,
+            tracks: vec![Track::new(0, codec_params)],
+            metadata,
+            position: 0,
+        })
+    }
+
+    fn cues(&self) -> &[Cue] {
+        &[]
+    }
+
+    fn metadata(&mut self) -> Metadata<'_> {
+        self.metadata.metadata()
+    }
+
+    fn seek(&mut self, _mode: SeekMode, to: SeekTo) -> Result<SeekedTo> {
+        let required_ts = match to {
+            SeekTo::TimeStamp { ts, .. } => ts,
+            SeekTo::Time { time, .. } => {
+                TimeBase::new(1, self.layout.rate / 16).calc_timestamp(time)
+            }
+        };
+        let byte = required_ts * 2;
+        if byte > 0 && byte >= self.layout.channel_bytes {
+            return seek_error(SeekErrorKind::OutOfRange);
+        }
+        // Seeking lands on the start of a packet so blocks and frames stay aligned
+        let packet_bytes = self.layout.packet_bytes() as u64;
+        let position = byte - byte % packet_bytes;
+        let channels = self.layout.channels as u64;
+        self.source.seek(SeekFrom::Start(
+            self.layout.data_offset + position * channels,
+        ))?;
+        self.position = position;
+        Ok(SeekedTo {
+            track_id: 0,
+            required_ts,
+            actual_ts: position / 2,
+        })
+    }
+
+    fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    fn next_packet(&mut self) -> Result<Packet> {
+        let remaining = self.layout.channel_bytes.saturating_sub(self.position);
+        if remaining == 0 {
+            return Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+        let channels = self.layout.channels;
+        let packet_bytes = self.layout.packet_bytes();
+        // DoP frames carry two bytes per channel, a trailing odd byte is dropped
+        let valid = (remaining.min(packet_bytes as u64) as usize) & !1;
+
+        let data = match self.layout.interleaving {
+            Interleaving::Block { size, lsb_first } => {
+                // The last block is zero padded up to the full block size
+                let mut blocks = vec![0u8; size * channels];
+                self.source.read_exact(&mut blocks)?;
+                let mut data = Vec::with_capacity(valid * channels);
+                for index in 0..valid {
+                    for channel in 0..channels {
+                        let byte = blocks[channel * size + index];
+                        data.push(if lsb_first { byte.reverse_bits() } else { byte });
+                    }
+                }
+                data
+            }
+            Interleaving::Byte => {
+                let length = remaining.min(packet_bytes as u64) as usize * channels;
+                let mut data = vec![0u8; length];
+                self.source.read_exact(&mut data)?;
+                data.truncate(valid * channels);
+                data
+            }
+        };
+
+        let ts = self.position / 2;
+        self.position += packet_bytes.min(remaining as usize) as u64;
+        Ok(Packet::new_from_boxed_slice(
+            0,
+            ts,
+            valid as u64 / 2,
+            data.into_boxed_slice(),
+        ))
+    }
+
+    fn into_inner(self: Box<Self>) -> MediaSourceStream {
+        self.source
+    }
+}
@@ -0,0 +1,113 @@
+use std::f64::consts::PI;
+
+/// Second order IIR section using the RBJ audio EQ cookbook formulas.
+#[derive(Clone, Copy)]
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    pub fn low_pass(samplerate: f64, frequency: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * frequency / samplerate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        Self::new(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn high_pass(samplerate: f64, frequency: f64, q: f64) -> Self {
+        let w0 = 2.0 * PI * frequency / samplerate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        Self::new(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn low_shelf(samplerate: f64, frequency: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / samplerate;
+        // Shelf slope of 1, the steepest one without overshoot
+        let alpha = w0.sin() / 2.0 * std::f64::consts::SQRT_2;
+        let cos = w0.cos();
+        let sqrt_alpha = 2.0 * a.sqrt() * alpha;
+        Self::new(
+            a * ((a + 1.0) - (a - 1.0) * cos + sqrt_alpha),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - sqrt_alpha),
+            (a + 1.0) + (a - 1.0) * cos + sqrt_alpha,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - sqrt_alpha,
+        )
+    }
+
+    pub fn high_shelf(samplerate: f64, frequency: f64, gain_db: f64) -> Self {
+        let a = 10f64.powf(gain_db / 40.0);
+        let w0 = 2.0 * PI * frequency / samplerate;
+        let alpha = w0.sin() / 2.0 * std::f64::consts::SQRT_2;
+        let cos = w0.cos();
+        let sqrt_alpha = 2.0 * a.sqrt() * alpha;
+        Self::new(
+            a * ((a + 1.0) + (a - 1.0) * cos + sqrt_alpha),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - sqrt_alpha),
+            (a + 1.0) - (a - 1.0) * cos + sqrt_alpha,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - sqrt_alpha,
+        )
+    }
+
+    /// Takes over the history of `previous` so retuning a running filter doesn't click.
+    pub fn continue_from(mut self, previous: &Biquad) -> Self {
+        self.x1 = previous.x1;
+        self.x2 = previous.x2;
+        self.y1 = previous.y1;
+        self.y2 = previous.y2;
+        self
+    }
+
+    #[inline(always)]
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
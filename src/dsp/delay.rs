@@ -0,0 +1,50 @@
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+use super::Filter;
+
+/// Per channel delay lines compensating speakers placed at different distances.
+pub struct SpeakerDelay {
+    lines: Vec<DelayLine>,
+}
+
+struct DelayLine {
+    samples: Vec<f64>,
+    position: usize,
+}
+
+impl DelayLine {
+    fn process(&mut self, sample: f64) -> f64 {
+        if self.samples.is_empty() {
+            return sample;
+        }
+        let delayed = std::mem::replace(&mut self.samples[self.position], sample);
+        self.position = (self.position + 1) % self.samples.len();
+        delayed
+    }
+}
+
+impl SpeakerDelay {
+    /// Delays are given in seconds per channel, missing channels are not delayed.
+    pub fn new(samplerate: u32, delays: &[f64]) -> Self {
+        Self {
+            lines: delays
+                .iter()
+                .map(|delay| DelayLine {
+                    samples: vec![0.0; (delay.max(0.0) * samplerate as f64).round() as usize],
+                    position: 0,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Filter for SpeakerDelay {
+    fn process(&mut self, buffer: &mut AudioBuffer<f64>) {
+        let channels = buffer.spec().channels.count();
+        for (channel, line) in self.lines.iter_mut().take(channels).enumerate() {
+            for sample in buffer.chan_mut(channel) {
+                *sample = line.process(*sample);
+            }
+        }
+    }
+}
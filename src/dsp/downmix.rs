@@ -0,0 +1,167 @@
+use serde::Deserialize;
+use std::f64::consts::FRAC_1_SQRT_2;
+use symphonia::core::audio::{AudioBuffer, Channels, Signal, SignalSpec};
+
+/// When tracks of more than two channels are folded down to stereo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DownmixMode {
+    /// Only when the device has no layout holding the channels of the track
+    #[default]
+    Auto,
+    Always,
+    /// The device is opened with the channels of the track whatever it supports
+    Never,
+}
+
+/// Share of a channel sent to the left and right outputs, following ITU-R BS.775: the centre
+/// and the surrounds 3dB lower, the LFE left out.
+fn coefficients(channel: Channels) -> (f64, f64) {
+    match channel {
+        Channels::FRONT_LEFT => (1.0, 0.0),
+        Channels::FRONT_RIGHT => (0.0, 1.0),
+        Channels::LFE1 | Channels::LFE2 => (0.0, 0.0),
+        Channels::FRONT_LEFT_CENTRE
+        | Channels::REAR_LEFT
+        | Channels::SIDE_LEFT
+        | Channels::FRONT_LEFT_WIDE
+        | Channels::FRONT_LEFT_HIGH
+        | Channels::REAR_LEFT_CENTRE
+        | Channels::TOP_FRONT_LEFT
+        | Channels::TOP_REAR_LEFT => (FRAC_1_SQRT_2, 0.0),
+        Channels::FRONT_RIGHT_CENTRE
+        | Channels::REAR_RIGHT
+        | Channels::SIDE_RIGHT
+        | Channels::FRONT_RIGHT_WIDE
+        | Channels::FRONT_RIGHT_HIGH
+        | Channels::REAR_RIGHT_CENTRE
+        | Channels::TOP_FRONT_RIGHT
+        | Channels::TOP_REAR_RIGHT => (0.0, FRAC_1_SQRT_2),
+        _ => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+    }
+}
+
+/// Folds multichannel tracks down to stereo. Each output is scaled by the sum of what feeds
+/// it so that full scale on every channel does not clip. This departs from BS.775 on purpose:
+/// the mix keeps the balance between channels but plays quieter, 7.7dB lower for 5.1, where
+/// the standard matrix would clip loud passages.
+pub struct Downmix {
+    /// Left and right share of each input channel, in the order of the buffer
+    matrix: Vec<(f64, f64)>,
+    buffer: Option<AudioBuffer<f64>>,
+}
+
+impl Downmix {
+    pub fn new() -> Self {
+        Self {
+            matrix: Vec::new(),
+            buffer: None,
+        }
+    }
+
+    fn matrix(channels: Channels) -> Vec<(f64, f64)> {
+        let matrix: Vec<(f64, f64)> = channels.iter().map(coefficients).collect();
+        let left: f64 = matrix.iter().map(|(left, _)| left).sum();
+        let right: f64 = matrix.iter().map(|(_, right)| right).sum();
+        matrix
+            .into_iter()
+            .map(|(l, r)| (l / left.max(1.0), r / right.max(1.0)))
+            .collect()
+    }
+
+    /// `input` must have more than two channels.
+    pub fn process(&mut self, input: &AudioBuffer<f64>) -> &mut AudioBuffer<f64> {
+        let frames = input.frames();
+        let channels = input.spec().channels;
+        let spec = SignalSpec::new(
+            input.spec().rate,
+            Channels::FRONT_LEFT | Channels::FRONT_RIGHT,
+        );
+        let reusable = matches!(&self.buffer, Some(buffer)
+            if buffer.capacity() >= frames && *buffer.spec() == spec);
+        if !reusable {
+            self.buffer = None;
+        }
+        if self.matrix.len() != channels.count() {
+            self.matrix = Self::matrix(channels);
+        }
+        let output = self
+            .buffer
+            .get_or_insert_with(|| AudioBuffer::new(frames as u64, spec));
+        output.clear();
+        output.render_reserved(Some(frames));
+
+        let (left, right) = output.chan_pair_mut(0, 1);
+        left.fill(0.0);
+        right.fill(0.0);
+        for (channel, (to_left, to_right)) in self.matrix.iter().enumerate() {
+            let samples = input.chan(channel);
+            for ((left, right), sample) in left.iter_mut().zip(right.iter_mut()).zip(samples) {
+                *left += to_left * sample;
+                *right += to_right * sample;
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn surround_is_folded_with_normalised_bs775_shares() {
+        let layout = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        let sum = 1.0 + 2.0 * FRAC_1_SQRT_2;
+        let front = 1.0 / sum;
+        let other = FRAC_1_SQRT_2 / sum;
+        let expected = [
+            (front, 0.0),
+            (0.0, front),
+            (other, other),
+            (0.0, 0.0),
+            (other, 0.0),
+            (0.0, other),
+        ];
+
+        let matrix = Downmix::matrix(layout);
+        assert_eq!(matrix.len(), expected.len());
+        for ((left, right), (expected_left, expected_right)) in matrix.iter().zip(expected) {
+            assert!((left - expected_left).abs() < 1e-12);
+            assert!((right - expected_right).abs() < 1e-12);
+        }
+        let (left, right) = matrix
+            .iter()
+            .fold((0.0, 0.0), |(l, r), (left, right)| (l + left, r + right));
+        assert!((left - 1.0).abs() < 1e-12 && (right - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn full_scale_on_every_channel_does_not_clip() {
+        let layout = Channels::FRONT_LEFT
+            | Channels::FRONT_RIGHT
+            | Channels::FRONT_CENTRE
+            | Channels::LFE1
+            | Channels::REAR_LEFT
+            | Channels::REAR_RIGHT;
+        let mut input = AudioBuffer::<f64>::new(4, SignalSpec::new(48000, layout));
+        input.render_reserved(Some(4));
+        for channel in 0..6 {
+            input.chan_mut(channel).fill(1.0);
+        }
+
+        let mut downmix = Downmix::new();
+        let output = downmix.process(&input);
+        for channel in 0..2 {
+            assert!(output
+                .chan(channel)
+                .iter()
+                .all(|sample| (sample - 1.0).abs() < 1e-12));
+        }
+    }
+}
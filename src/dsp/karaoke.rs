@@ -0,0 +1,41 @@
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+use super::{biquad::Biquad, Filter};
+
+const VOCAL_LOW_FREQUENCY: f64 = 200.0;
+const VOCAL_HIGH_FREQUENCY: f64 = 4000.0;
+const BUTTERWORTH_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// Attenuates the center of a stereo image within the vocal range using mid/side processing,
+/// bass and treble of the mid channel are kept so drums and bass lines survive.
+pub struct VocalRemover {
+    high_pass: Biquad,
+    low_pass: Biquad,
+}
+
+impl VocalRemover {
+    pub fn new(samplerate: u32) -> Self {
+        let samplerate = samplerate as f64;
+        Self {
+            high_pass: Biquad::high_pass(samplerate, VOCAL_LOW_FREQUENCY, BUTTERWORTH_Q),
+            low_pass: Biquad::low_pass(samplerate, VOCAL_HIGH_FREQUENCY, BUTTERWORTH_Q),
+        }
+    }
+}
+
+impl Filter for VocalRemover {
+    fn process(&mut self, buffer: &mut AudioBuffer<f64>) {
+        if buffer.spec().channels.count() != 2 {
+            return;
+        }
+        let (left, right) = buffer.chan_pair_mut(0, 1);
+        for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let mid = (*l + *r) / 2.0;
+            let side = (*l - *r) / 2.0;
+            let vocals = self.low_pass.process(self.high_pass.process(mid));
+            let mid = mid - vocals;
+            *l = mid + side;
+            *r = mid - side;
+        }
+    }
+}
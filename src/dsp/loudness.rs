@@ -0,0 +1,109 @@
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+use super::{biquad::Biquad, Filter};
+
+/// Listening level assumed at full volume, the usual mixing room calibration.
+const REFERENCE_PHON: f64 = 83.0;
+/// Lowest level covered by the ISO 226 contours.
+const MIN_PHON: f64 = 20.0;
+const BASS_SHELF_FREQUENCY: f64 = 100.0;
+const TREBLE_SHELF_FREQUENCY: f64 = 8000.0;
+
+/// ISO 226:2003 equal-loudness contour parameters at a single frequency.
+struct ContourPoint {
+    af: f64,
+    lu: f64,
+    tf: f64,
+}
+
+const CONTOUR_50HZ: ContourPoint = ContourPoint {
+    af: 0.432,
+    lu: -15.9,
+    tf: 44.0,
+};
+const CONTOUR_1KHZ: ContourPoint = ContourPoint {
+    af: 0.250,
+    lu: 0.0,
+    tf: 2.4,
+};
+const CONTOUR_12_5KHZ: ContourPoint = ContourPoint {
+    af: 0.301,
+    lu: -3.1,
+    tf: 12.3,
+};
+
+impl ContourPoint {
+    /// Sound pressure level in dB needed at this frequency to be perceived at `phon`.
+    fn spl(&self, phon: f64) -> f64 {
+        let af = 4.47e-3 * (10f64.powf(0.025 * phon) - 1.15)
+            + (0.4 * 10f64.powf((self.tf + self.lu) / 10.0 - 9.0)).powf(self.af);
+        10.0 / self.af * af.log10() - self.lu + 94.0
+    }
+
+    /// Boost keeping this frequency balanced against 1kHz once the level is lowered by `volume_db`.
+    fn compensation(&self, volume_db: f64) -> f64 {
+        let listening = (REFERENCE_PHON + volume_db).max(MIN_PHON);
+        let relative = |phon: f64| self.spl(phon) - CONTOUR_1KHZ.spl(phon);
+        relative(listening) - relative(REFERENCE_PHON)
+    }
+}
+
+/// Bass and treble shelves following the equal-loudness contours for the current volume.
+pub struct LoudnessCompensation {
+    samplerate: f64,
+    volume_db: f64,
+    shelves: Vec<(Biquad, Biquad)>,
+}
+
+impl LoudnessCompensation {
+    pub fn new(samplerate: u32) -> Self {
+        Self {
+            samplerate: samplerate as f64,
+            volume_db: 0.0,
+            shelves: Vec::new(),
+        }
+    }
+
+    fn create_shelves(&self) -> (Biquad, Biquad) {
+        (
+            Biquad::low_shelf(
+                self.samplerate,
+                BASS_SHELF_FREQUENCY,
+                CONTOUR_50HZ.compensation(self.volume_db),
+            ),
+            Biquad::high_shelf(
+                self.samplerate,
+                TREBLE_SHELF_FREQUENCY,
+                CONTOUR_12_5KHZ.compensation(self.volume_db),
+            ),
+        )
+    }
+
+    pub fn set_volume(&mut self, volume_db: f64) {
+        if volume_db == self.volume_db {
+            return;
+        }
+        self.volume_db = volume_db;
+        let (bass, treble) = self.create_shelves();
+        for shelves in self.shelves.iter_mut() {
+            *shelves = (
+                bass.continue_from(&shelves.0),
+                treble.continue_from(&shelves.1),
+            );
+        }
+    }
+}
+
+impl Filter for LoudnessCompensation {
+    fn process(&mut self, buffer: &mut AudioBuffer<f64>) {
+        let channels = buffer.spec().channels.count();
+        if self.shelves.len() != channels {
+            self.shelves = vec![self.create_shelves(); channels];
+        }
+        for (channel, (bass, treble)) in self.shelves.iter_mut().enumerate() {
+            for sample in buffer.chan_mut(channel) {
+                *sample = treble.process(bass.process(*sample));
+            }
+        }
+    }
+}
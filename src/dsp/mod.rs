@@ -0,0 +1,244 @@
+pub(crate) mod biquad;
+pub(crate) mod delay;
+pub(crate) mod downmix;
+pub(crate) mod karaoke;
+pub(crate) mod loudness;
+pub(crate) mod ramp;
+pub(crate) mod upmix;
+
+use std::sync::atomic::{AtomicBool, AtomicI8, Ordering};
+use std::sync::{Arc, Mutex};
+
+use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, AudioBufferRef, Signal};
+use tracing::trace_span;
+
+use self::delay::SpeakerDelay;
+use self::downmix::Downmix;
+pub use self::downmix::DownmixMode;
+use self::karaoke::VocalRemover;
+use self::loudness::LoudnessCompensation;
+use self::ramp::GainRamp;
+use self::upmix::Upmix;
+pub use self::upmix::{UpmixConfig, UpmixDecoding, UpmixLayout};
+
+pub const MIN_VOLUME_DB: i8 = -60;
+
+pub trait Filter: Send {
+    fn process(&mut self, buffer: &mut AudioBuffer<f64>);
+}
+
+/// Toggles shared between the UI and the streaming task, read on every decoded packet.
+pub struct DspSettings {
+    karaoke: AtomicBool,
+    speaker_delays: Mutex<Vec<f64>>,
+    upmix: Mutex<Option<UpmixConfig>>,
+    downmix: AtomicBool,
+    volume: AtomicI8,
+    max_volume: AtomicI8,
+    headroom: AtomicI8,
+    loudness: AtomicBool,
+}
+
+impl Default for DspSettings {
+    fn default() -> Self {
+        Self {
+            karaoke: AtomicBool::new(false),
+            speaker_delays: Mutex::new(Vec::new()),
+            upmix: Mutex::new(None),
+            downmix: AtomicBool::new(false),
+            volume: AtomicI8::new(0),
+            max_volume: AtomicI8::new(0),
+            headroom: AtomicI8::new(0),
+            loudness: AtomicBool::new(false),
+        }
+    }
+}
+
+impl DspSettings {
+    pub fn karaoke(&self) -> bool {
+        self.karaoke.load(Ordering::Relaxed)
+    }
+
+    pub fn set_karaoke(&self, enabled: bool) {
+        self.karaoke.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Software volume in dB, 0 leaves the samples untouched.
+    pub fn volume(&self) -> i8 {
+        self.volume.load(Ordering::Relaxed)
+    }
+
+    pub fn set_volume(&self, volume: i8) {
+        let max_volume = self.max_volume.load(Ordering::Relaxed);
+        self.volume
+            .store(volume.clamp(MIN_VOLUME_DB, max_volume), Ordering::Relaxed);
+    }
+
+    /// Output limits of the current device, the volume is lowered right away when above them.
+    pub fn set_limits(&self, max_volume: i8, headroom: u8) {
+        self.max_volume
+            .store(max_volume.clamp(MIN_VOLUME_DB, 0), Ordering::Relaxed);
+        self.headroom.store(
+            headroom.min(MIN_VOLUME_DB.unsigned_abs()) as i8,
+            Ordering::Relaxed,
+        );
+        self.set_volume(self.volume());
+    }
+
+    /// Gain in dB applied to the samples, the volume lowered by the device headroom.
+    pub fn gain(&self) -> f64 {
+        self.volume() as f64 - self.headroom.load(Ordering::Relaxed) as f64
+    }
+
+    pub fn loudness(&self) -> bool {
+        self.loudness.load(Ordering::Relaxed)
+    }
+
+    pub fn set_loudness(&self, enabled: bool) {
+        self.loudness.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn speaker_delays(&self) -> Vec<f64> {
+        self.speaker_delays
+            .lock()
+            .map(|delays| delays.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn upmix(&self) -> Option<UpmixConfig> {
+        self.upmix.lock().ok().and_then(|upmix| *upmix)
+    }
+
+    /// Upmix of the stereo tracks for the current device.
+    pub fn set_upmix(&self, upmix: Option<UpmixConfig>) {
+        if let Ok(mut current) = self.upmix.lock() {
+            *current = upmix;
+        }
+    }
+
+    pub fn downmix(&self) -> bool {
+        self.downmix.load(Ordering::Relaxed)
+    }
+
+    /// Folds the tracks started from now on down to stereo.
+    pub fn set_downmix(&self, enabled: bool) {
+        self.downmix.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Delays in seconds per output channel, a chain without delays is kept inactive.
+    pub fn set_speaker_delays(&self, delays: Vec<f64>) {
+        if let Ok(mut speaker_delays) = self.speaker_delays.lock() {
+            *speaker_delays = if delays.iter().any(|delay| *delay > 0.0) {
+                delays
+            } else {
+                Vec::new()
+            };
+        }
+    }
+}
+
+/// Processing stages applied between the decoder and the device, the decoded samples are left
+/// untouched while no stage is enabled so playback stays bit perfect.
+pub struct DspChain {
+    settings: Arc<DspSettings>,
+    buffer: Option<AudioBuffer<f64>>,
+    karaoke: Option<VocalRemover>,
+    loudness: Option<LoudnessCompensation>,
+    speaker_delays: Vec<f64>,
+    delay: Option<SpeakerDelay>,
+    ramp: Option<GainRamp>,
+    upmix: Option<Upmix>,
+    downmix: Option<Downmix>,
+}
+
+impl DspChain {
+    /// Speaker delays, the upmix and the downmix are read once as the chain lives for a
+    /// single track.
+    pub fn new(settings: Arc<DspSettings>) -> Self {
+        let speaker_delays = settings.speaker_delays();
+        let upmix = settings.upmix().map(Upmix::new);
+        let downmix = settings.downmix().then(Downmix::new);
+        Self {
+            settings,
+            buffer: None,
+            karaoke: None,
+            loudness: None,
+            speaker_delays,
+            delay: None,
+            ramp: None,
+            upmix,
+            downmix,
+        }
+    }
+
+    /// Starts the track attenuated by `gain_db` and fades back to unity over `seconds`.
+    pub fn start_gain_ramp(&mut self, gain_db: f64, seconds: f64) {
+        self.ramp = Some(GainRamp::new(gain_db, seconds));
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.settings.karaoke()
+            || self.settings.gain() < 0.0
+            || !self.speaker_delays.is_empty()
+            || self.ramp.is_some()
+            || self.upmix.is_some()
+            || self.downmix.is_some()
+    }
+
+    pub fn process<'a>(&'a mut self, input: &AudioBufferRef<'_>) -> AudioBufferRef<'a> {
+        let _span = trace_span!("dsp", frames = input.frames()).entered();
+        let reusable = matches!(&self.buffer, Some(buffer)
+            if buffer.capacity() >= input.capacity() && buffer.spec() == input.spec());
+        if !reusable {
+            self.buffer = None;
+        }
+        let buffer = self
+            .buffer
+            .get_or_insert_with(|| input.make_equivalent::<f64>());
+        input.convert(buffer);
+        // Everything after sees the channels of the device
+        let buffer = match self.downmix.as_mut() {
+            Some(downmix) if buffer.spec().channels.count() > 2 => downmix.process(buffer),
+            _ => buffer,
+        };
+
+        if self.settings.karaoke() {
+            let samplerate = buffer.spec().rate;
+            self.karaoke
+                .get_or_insert_with(|| VocalRemover::new(samplerate))
+                .process(buffer);
+        }
+        let volume = self.settings.gain();
+        if self.settings.loudness() {
+            let samplerate = buffer.spec().rate;
+            let loudness = self
+                .loudness
+                .get_or_insert_with(|| LoudnessCompensation::new(samplerate));
+            loudness.set_volume(volume);
+            loudness.process(buffer);
+        }
+        if volume < 0.0 {
+            let gain = 10f64.powf(volume / 20.0);
+            buffer.transform(|sample| sample * gain);
+        }
+        if let Some(ramp) = self.ramp.as_mut() {
+            ramp.process(buffer);
+            if ramp.is_finished() {
+                self.ramp = None;
+            }
+        }
+        // Speaker delays apply to the channels of the device
+        let buffer = match self.upmix.as_mut() {
+            Some(upmix) if buffer.spec().channels.count() == 2 => upmix.process(buffer),
+            _ => buffer,
+        };
+        if !self.speaker_delays.is_empty() {
+            let samplerate = buffer.spec().rate;
+            let speaker_delays = &self.speaker_delays;
+            self.delay
+                .get_or_insert_with(|| SpeakerDelay::new(samplerate, speaker_delays))
+                .process(buffer);
+        }
+        buffer.as_audio_buffer_ref()
+    }
+}
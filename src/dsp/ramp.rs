@@ -0,0 +1,48 @@
+use symphonia::core::audio::{AudioBuffer, Signal};
+
+use super::Filter;
+
+/// Fades from an initial attenuation back to unity gain.
+pub struct GainRamp {
+    gain_db: f64,
+    seconds: f64,
+    position: usize,
+    frames: Option<usize>,
+}
+
+impl GainRamp {
+    pub fn new(gain_db: f64, seconds: f64) -> Self {
+        Self {
+            gain_db,
+            seconds,
+            position: 0,
+            frames: None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self.frames, Some(frames) if self.position >= frames)
+    }
+}
+
+impl Filter for GainRamp {
+    fn process(&mut self, buffer: &mut AudioBuffer<f64>) {
+        let samplerate = buffer.spec().rate;
+        let frames = *self
+            .frames
+            .get_or_insert((self.seconds * samplerate as f64) as usize);
+        let start = self.position;
+        let length = buffer.frames();
+        for channel in 0..buffer.spec().channels.count() {
+            for (index, sample) in buffer.chan_mut(channel).iter_mut().enumerate() {
+                let position = start + index;
+                if position >= frames {
+                    break;
+                }
+                let progress = position as f64 / frames as f64;
+                *sample *= 10f64.powf(self.gain_db * (1.0 - progress) / 20.0);
+            }
+        }
+        self.position += length;
+    }
+}
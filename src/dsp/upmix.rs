@@ -0,0 +1,135 @@
+use serde::Deserialize;
+use std::f64::consts::FRAC_1_SQRT_2;
+use symphonia::core::audio::{AudioBuffer, Channels, Signal, SignalSpec};
+
+use super::delay::SpeakerDelay;
+use super::Filter;
+
+/// Delay of the surround channels, the ear then locates sounds in front.
+const SURROUND_DELAY: f64 = 0.015;
+
+/// Speaker layout stereo tracks are spread over, channels in WAVE order.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum UpmixLayout {
+    #[serde(rename = "4.0")]
+    Quad,
+    #[serde(rename = "5.1")]
+    Surround51,
+}
+
+impl UpmixLayout {
+    fn channels(&self) -> Channels {
+        let rears = Channels::REAR_LEFT | Channels::REAR_RIGHT;
+        match self {
+            Self::Quad => Channels::FRONT_LEFT | Channels::FRONT_RIGHT | rears,
+            Self::Surround51 => {
+                Channels::FRONT_LEFT
+                    | Channels::FRONT_RIGHT
+                    | Channels::FRONT_CENTRE
+                    | Channels::LFE1
+                    | rears
+            }
+        }
+    }
+}
+
+/// How the extra channels are derived from left and right.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpmixDecoding {
+    /// Rears repeat the fronts 3dB lower
+    #[default]
+    Matrix,
+    /// Passive surround decoding, rears get the difference of left and right, delayed
+    Surround,
+}
+
+/// Upmix of stereo tracks for a multichannel device, set per device.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct UpmixConfig {
+    pub layout: UpmixLayout,
+    #[serde(default)]
+    pub decoding: UpmixDecoding,
+}
+
+impl UpmixConfig {
+    /// Channels the device is opened with.
+    pub fn channels(&self) -> usize {
+        self.layout.channels().count()
+    }
+}
+
+/// Spreads stereo over more speakers. The centre gets the sum of left and right, the LFE is
+/// left silent for the receiver bass management.
+pub struct Upmix {
+    config: UpmixConfig,
+    buffer: Option<AudioBuffer<f64>>,
+    surround_delay: Option<SpeakerDelay>,
+}
+
+impl Upmix {
+    pub fn new(config: UpmixConfig) -> Self {
+        Self {
+            config,
+            buffer: None,
+            surround_delay: None,
+        }
+    }
+
+    /// `input` must be stereo.
+    pub fn process(&mut self, input: &AudioBuffer<f64>) -> &mut AudioBuffer<f64> {
+        let frames = input.frames();
+        let spec = SignalSpec::new(input.spec().rate, self.config.layout.channels());
+        let reusable = matches!(&self.buffer, Some(buffer)
+            if buffer.capacity() >= frames && *buffer.spec() == spec);
+        if !reusable {
+            self.buffer = None;
+        }
+        let output = self
+            .buffer
+            .get_or_insert_with(|| AudioBuffer::new(frames as u64, spec));
+        output.clear();
+        output.render_reserved(Some(frames));
+
+        let (left, right) = (input.chan(0), input.chan(1));
+        output.chan_mut(0).copy_from_slice(left);
+        output.chan_mut(1).copy_from_slice(right);
+        let rears = match self.config.layout {
+            UpmixLayout::Quad => 2,
+            UpmixLayout::Surround51 => {
+                for (centre, (left, right)) in
+                    output.chan_mut(2).iter_mut().zip(left.iter().zip(right))
+                {
+                    *centre = 0.5 * (left + right);
+                }
+                output.chan_mut(3).fill(0.0);
+                4
+            }
+        };
+        let (rear_left, rear_right) = output.chan_pair_mut(rears, rears + 1);
+        let samples = rear_left
+            .iter_mut()
+            .zip(rear_right.iter_mut())
+            .zip(left.iter().zip(right));
+        match self.config.decoding {
+            UpmixDecoding::Matrix => {
+                for ((rear_left, rear_right), (left, right)) in samples {
+                    *rear_left = FRAC_1_SQRT_2 * left;
+                    *rear_right = FRAC_1_SQRT_2 * right;
+                }
+            }
+            UpmixDecoding::Surround => {
+                for ((rear_left, rear_right), (left, right)) in samples {
+                    *rear_left = 0.5 * (left - right);
+                    *rear_right = *rear_left;
+                }
+                let mut delays = vec![0.0; rears];
+                delays.extend([SURROUND_DELAY, SURROUND_DELAY]);
+                self.surround_delay
+                    .get_or_insert_with(|| SpeakerDelay::new(spec.rate, &delays))
+                    .process(output);
+            }
+        }
+        output
+    }
+}
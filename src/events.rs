@@ -0,0 +1,366 @@
+use anyhow::{anyhow, Result};
+use futures_util::SinkExt;
+use log::{info, warn};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::config::local_address;
+use crate::library::Database;
+use crate::musictrack::MusicTrack;
+use crate::player::{Playback, PlayerSnapshot};
+use crate::tasks::TaskGroup;
+
+/// Events kept for clients slower than the stream, older ones are dropped.
+const BACKLOG: usize = 64;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+/// First line of the clients which get the events as JSON lines rather than over WebSocket
+const SUBSCRIBE: &str = "subscribe";
+
+/// What the clients receive, as JSON objects tagged by `event`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TrackStarted {
+        index: usize,
+        title: String,
+        artist: String,
+        album: String,
+        path: String,
+        duration: f64,
+    },
+    Paused,
+    Resumed,
+    Stopped,
+    /// Seconds into the playing track, sent every second while playing
+    Progress {
+        elapsed: f64,
+        duration: f64,
+    },
+    DeviceChanged {
+        name: String,
+    },
+    /// Seconds a long track resumes from, `None` once it starts over. The bookmarks are all
+    /// sent on connecting.
+    Bookmark {
+        path: String,
+        position: Option<f64>,
+    },
+}
+
+/// What the app reports, compared on each update to find the events to send.
+#[derive(Clone, Default)]
+struct Snapshot {
+    playback: Playback,
+    track: Option<(usize, Arc<MusicTrack>)>,
+    device: Option<String>,
+}
+
+/// Events leading from `previous` to `now`, a client connecting from scratch gets the whole
+/// state.
+fn events(previous: &Snapshot, now: &Snapshot) -> Vec<Event> {
+    let mut events = Vec::new();
+    if let (Some(name), true) = (&now.device, now.device != previous.device) {
+        events.push(Event::DeviceChanged { name: name.clone() });
+    }
+    let same_track = match (&previous.track, &now.track) {
+        (Some((was, previous)), Some((index, song))) => was == index && Arc::ptr_eq(previous, song),
+        (None, None) => true,
+        _ => false,
+    };
+    if let (Some((index, song)), false) = (&now.track, same_track) {
+        events.push(Event::TrackStarted {
+            index: *index,
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            path: song.path.clone(),
+            duration: song.duration_seconds(),
+        });
+    }
+    match (previous.playback, now.playback) {
+        (previous, Playback::Paused) if previous != Playback::Paused => events.push(Event::Paused),
+        (Playback::Paused, Playback::Playing) => events.push(Event::Resumed),
+        (previous, Playback::Stopped) if previous != Playback::Stopped => {
+            events.push(Event::Stopped)
+        }
+        _ => (),
+    }
+    events
+}
+
+fn broadcast(sender: &broadcast::Sender<String>, event: &Event) {
+    match serde_json::to_string(event) {
+        // No receiver only means no client is connected
+        Ok(event) => {
+            let _ = sender.send(event);
+        }
+        Err(err) => warn!("Cannot serialize {:?}: {}", event, err),
+    }
+}
+
+/// Events bringing a new client up to date: the playing track, the playback state, the device
+/// and the bookmarks.
+fn current(state: &Mutex<Snapshot>, database: &Database) -> Vec<Event> {
+    let mut current = state
+        .lock()
+        .map(|state| events(&Snapshot::default(), &state))
+        .unwrap_or_default();
+    let bookmarks = database.bookmarks().unwrap_or_else(|err| {
+        warn!("Cannot read the bookmarks: {}", err);
+        Vec::new()
+    });
+    current.extend(
+        bookmarks
+            .into_iter()
+            .map(|(path, position)| Event::Bookmark {
+                path,
+                position: Some(position),
+            }),
+    );
+    current
+}
+
+/// Serves WebSocket clients, or JSON lines to those sending `subscribe` first, told apart by
+/// the request line of the handshake.
+async fn serve(
+    stream: TcpStream,
+    state: Arc<Mutex<Snapshot>>,
+    database: Database,
+    mut receiver: broadcast::Receiver<String>,
+) -> Result<()> {
+    let mut stream = BufReader::new(stream);
+    if stream.fill_buf().await?.starts_with(b"GET ") {
+        let mut socket = tokio_tungstenite::accept_async(stream).await?;
+        for event in current(&state, &database) {
+            socket
+                .send(Message::text(serde_json::to_string(&event)?))
+                .await?;
+        }
+        loop {
+            match receiver.recv().await {
+                Ok(event) => socket.send(Message::text(event)).await?,
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Event client lagging, {} skipped", skipped)
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            }
+        }
+    }
+    let mut command = String::new();
+    stream.read_line(&mut command).await?;
+    if command.trim() != SUBSCRIBE {
+        stream
+            .write_all(b"{\"error\":\"unknown command\"}\n")
+            .await?;
+        return Err(anyhow!("Unknown command {:?}", command.trim()));
+    }
+    for event in current(&state, &database) {
+        let line = format!("{}\n", serde_json::to_string(&event)?);
+        stream.write_all(line.as_bytes()).await?;
+    }
+    loop {
+        match receiver.recv().await {
+            Ok(event) => stream.write_all(format!("{}\n", event).as_bytes()).await?,
+            Err(RecvError::Lagged(skipped)) => warn!("Event client lagging, {} skipped", skipped),
+            Err(RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+/// Prints the events of the player running the event stream at `address` and `port`, one JSON
+/// object per line, until it quits.
+pub async fn subscribe(address: &str, port: u16) -> Result<()> {
+    let address = local_address(address);
+    let mut stream = TcpStream::connect((address, port))
+        .await
+        .map_err(|err| anyhow!("No player listening on {}:{}: {}", address, port, err))?;
+    stream
+        .write_all(format!("{}\n", SUBSCRIBE).as_bytes())
+        .await?;
+    let mut lines = BufReader::new(stream).lines();
+    let mut stdout = tokio::io::stdout();
+    while let Some(line) = lines.next_line().await? {
+        // Status bars read line by line, nothing waits in the buffer
+        stdout.write_all(format!("{}\n", line).as_bytes()).await?;
+        stdout.flush().await?;
+    }
+    Ok(())
+}
+
+/// Server pushing track changes, pauses, progress, device changes and bookmarks as JSON, for
+/// dashboards, stream overlays and remotes. WebSocket clients get one message per event, their
+/// messages are ignored; plain TCP clients sending `subscribe` get one line per event.
+pub struct EventStream {
+    state: Arc<Mutex<Snapshot>>,
+    sender: broadcast::Sender<String>,
+    last_progress: Instant,
+}
+
+impl EventStream {
+    pub async fn start(
+        address: &str,
+        port: u16,
+        database: &Database,
+        tasks: &TaskGroup,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind((address, port)).await?;
+        info!("Event stream listening on ws://{}", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(Snapshot::default()));
+        let (sender, _) = broadcast::channel(BACKLOG);
+        let shared = state.clone();
+        let subscriber = sender.clone();
+        let group = tasks.clone();
+        let mut bookmarks = database.watch_bookmarks();
+        let bookmarked = sender.clone();
+        tasks.spawn("Event bookmarks".to_string(), async move {
+            // Bookmarks are set by the app as well as by MPD clients
+            while let Some((path, position)) = bookmarks.next().await {
+                broadcast(&bookmarked, &Event::Bookmark { path, position });
+            }
+            Ok(())
+        });
+        let database = database.clone();
+        tasks.spawn("Event stream".to_string(), async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        warn!("Cannot accept an event client: {}", err);
+                        continue;
+                    }
+                };
+                let state = shared.clone();
+                let database = database.clone();
+                let receiver = subscriber.subscribe();
+                group.spawn(format!("Event client {}", peer), async move {
+                    if let Err(err) = serve(stream, state, database, receiver).await {
+                        info!("Event client {} disconnected: {}", peer, err);
+                    }
+                    Ok(())
+                });
+            }
+        });
+        Ok(Self {
+            state,
+            sender,
+            last_progress: Instant::now(),
+        })
+    }
+
+    fn send(&self, event: &Event) {
+        broadcast(&self.sender, event);
+    }
+
+    /// Reports the player and the output device, sending the events since the last update.
+    pub fn update(&mut self, player: &PlayerSnapshot, device: Option<String>) {
+        let now = Snapshot {
+            playback: player.playback,
+            track: player.track.clone(),
+            device,
+        };
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let changes = events(&state, &now);
+        *state = now;
+        drop(state);
+        for event in &changes {
+            self.send(event);
+        }
+        if let (Playback::Playing, Some(_)) = (player.playback, &player.track) {
+            if self.last_progress.elapsed() >= PROGRESS_INTERVAL {
+                self.last_progress = Instant::now();
+                self.send(&Event::Progress {
+                    elapsed: player.elapsed.max(0.0),
+                    duration: player.duration,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track() -> Arc<MusicTrack> {
+        Arc::new(MusicTrack::new("tests/assets/tagged.flac".to_string()).unwrap())
+    }
+
+    #[test]
+    fn reports_changes_only() {
+        let song = track();
+        let playing = Snapshot {
+            playback: Playback::Playing,
+            track: Some((3, song.clone())),
+            device: Some("Speakers".to_string()),
+        };
+        let started = events(&Snapshot::default(), &playing);
+        assert_eq!(started.len(), 2);
+        assert_eq!(
+            started[0],
+            Event::DeviceChanged {
+                name: "Speakers".to_string()
+            }
+        );
+        assert!(matches!(started[1], Event::TrackStarted { index: 3, .. }));
+        assert!(events(&playing, &playing).is_empty());
+
+        let paused = Snapshot {
+            playback: Playback::Paused,
+            ..playing.clone()
+        };
+        assert_eq!(events(&playing, &paused), vec![Event::Paused]);
+        assert_eq!(events(&paused, &playing), vec![Event::Resumed]);
+        assert_eq!(events(&playing, &Snapshot::default()), vec![Event::Stopped]);
+    }
+
+    #[tokio::test]
+    async fn subscribers_get_json_lines() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(Snapshot {
+            playback: Playback::Paused,
+            track: None,
+            device: Some("Speakers".to_string()),
+        }));
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let database = Database::with_db(db).unwrap();
+        let (sender, receiver) = broadcast::channel(BACKLOG);
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            serve(stream, state, database, receiver).await
+        });
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        stream.write_all(b"subscribe\n").await.unwrap();
+        let mut lines = BufReader::new(stream).lines();
+        let device = lines.next_line().await.unwrap();
+        assert_eq!(
+            device.as_deref(),
+            Some(r#"{"event":"device_changed","name":"Speakers"}"#)
+        );
+        let paused = lines.next_line().await.unwrap();
+        assert_eq!(paused.as_deref(), Some(r#"{"event":"paused"}"#));
+        broadcast(&sender, &Event::Stopped);
+        let stopped = lines.next_line().await.unwrap();
+        assert_eq!(stopped.as_deref(), Some(r#"{"event":"stopped"}"#));
+    }
+
+    #[test]
+    fn events_are_tagged() {
+        let json = serde_json::to_string(&Event::Progress {
+            elapsed: 1.5,
+            duration: 200.0,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            r#"{"event":"progress","elapsed":1.5,"duration":200.0}"#
+        );
+    }
+}
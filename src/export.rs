@@ -0,0 +1,60 @@
+use anyhow::Result;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use crate::musictrack::MusicTrack;
+
+/// `path` relative to `root`, going up with `..` when it is not under it. Paths on another
+/// drive stay absolute.
+fn relative_to(path: &Path, root: &Path) -> PathBuf {
+    let (Ok(path), Ok(root)) = (std::path::absolute(path), std::path::absolute(root)) else {
+        return path.to_path_buf();
+    };
+    let mut path_components = path.components().peekable();
+    let mut root_components = root.components().peekable();
+    if let (Some(Component::Prefix(a)), Some(Component::Prefix(b))) =
+        (path_components.peek(), root_components.peek())
+    {
+        if a != b {
+            return path;
+        }
+    }
+    while let (Some(a), Some(b)) = (path_components.peek(), root_components.peek()) {
+        if a != b {
+            break;
+        }
+        path_components.next();
+        root_components.next();
+    }
+    root_components
+        .map(|_| Component::ParentDir)
+        .chain(path_components)
+        .collect()
+}
+
+/// Writes an extended M3U playlist with paths relative to `root`, separated by `/` as most
+/// portable players and head units expect.
+pub fn write_m3u(file: &Path, root: &Path, tracks: &[Arc<MusicTrack>]) -> Result<()> {
+    let mut output = std::io::BufWriter::new(std::fs::File::create(file)?);
+    writeln!(output, "#EXTM3U")?;
+    for track in tracks {
+        let path = relative_to(Path::new(&track.path), root);
+        let path = if path.is_absolute() {
+            path.to_string_lossy().into_owned()
+        } else {
+            path.components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        };
+        writeln!(
+            output,
+            "#EXTINF:{},{} - {}",
+            track.duration.seconds, track.artist, track.title
+        )?;
+        writeln!(output, "{}", path)?;
+    }
+    output.flush()?;
+    Ok(())
+}
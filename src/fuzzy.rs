@@ -0,0 +1,170 @@
+//! Fuzzy matching scored the way skim and fzf do: the characters of the pattern must appear in
+//! order, matches on word starts and runs of consecutive characters rank higher, gaps lower.
+
+const SCORE_MATCH: i32 = 16;
+const GAP_START: i32 = -3;
+const GAP_EXTENSION: i32 = -1;
+/// After a space or at the start of the text
+const BONUS_BOUNDARY_WHITE: i32 = 10;
+/// After a delimiter such as `-`, `/` or `(`
+const BONUS_BOUNDARY: i32 = 8;
+/// On a capital after a lowercase letter, or a digit after a letter
+const BONUS_CAMEL: i32 = 7;
+const BONUS_CONSECUTIVE: i32 = 4;
+/// Weight of the bonus of the first character, leading gaps are free
+const FIRST_CHAR_MULTIPLIER: i32 = 2;
+
+/// How a pattern matched a text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub score: i32,
+    /// Char indexes of the text matched by each character of the pattern
+    pub positions: Vec<usize>,
+}
+
+fn bonus(previous: Option<char>, current: char) -> i32 {
+    match previous {
+        None => BONUS_BOUNDARY_WHITE,
+        Some(previous) if previous.is_whitespace() => BONUS_BOUNDARY_WHITE,
+        Some(previous) if !previous.is_alphanumeric() && current.is_alphanumeric() => {
+            BONUS_BOUNDARY
+        }
+        Some(previous) if previous.is_lowercase() && current.is_uppercase() => BONUS_CAMEL,
+        Some(previous) if previous.is_alphabetic() && current.is_numeric() => BONUS_CAMEL,
+        _ => 0,
+    }
+}
+
+/// Best match of `pattern` in `text`, `None` unless every character of the pattern appears in
+/// order. Case is ignored unless the pattern has capitals.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<Match> {
+    let smart_case = pattern.chars().any(char::is_uppercase);
+    let fold = |c: char| match smart_case {
+        true => c,
+        false => c.to_lowercase().next().unwrap_or(c),
+    };
+    let pattern: Vec<char> = pattern.chars().map(fold).collect();
+    let original: Vec<char> = text.chars().collect();
+    let text: Vec<char> = original.iter().copied().map(fold).collect();
+    if pattern.is_empty() {
+        return None;
+    }
+    // Cheap rejection before the full table
+    let mut rest = text.iter();
+    if !pattern.iter().all(|c| rest.any(|t| t == c)) {
+        return None;
+    }
+
+    let (rows, columns) = (pattern.len(), text.len());
+    let bonuses: Vec<i32> = (0..columns)
+        .map(|j| bonus(j.checked_sub(1).map(|k| original[k]), original[j]))
+        .collect();
+    // Score with pattern[i] matched on text[j], and whether the match before was at j - 1
+    let mut matched = vec![None::<(i32, bool)>; rows * columns];
+    // Best score of pattern[..=i] ending with a match at or before j, and where that match is
+    let mut best = vec![None::<(i32, usize)>; rows * columns];
+    for i in 0..rows {
+        for j in 0..columns {
+            let here = if text[j] != pattern[i] {
+                None
+            } else if i == 0 {
+                Some((SCORE_MATCH + bonuses[j] * FIRST_CHAR_MULTIPLIER, false))
+            } else if j == 0 {
+                None
+            } else {
+                let consecutive = matched[(i - 1) * columns + j - 1]
+                    .map(|(score, _)| score + SCORE_MATCH + bonuses[j].max(BONUS_CONSECUTIVE));
+                let after_gap = best[(i - 1) * columns + j - 1]
+                    .filter(|(_, at)| *at < j - 1)
+                    .map(|(score, _)| score + SCORE_MATCH + bonuses[j]);
+                match (consecutive, after_gap) {
+                    (Some(run), Some(gap)) if gap > run => Some((gap, false)),
+                    (Some(run), _) => Some((run, true)),
+                    (None, gap) => gap.map(|gap| (gap, false)),
+                }
+            };
+            matched[i * columns + j] = here;
+            // The gap after the last match counts once a later character matches
+            let carried = match j {
+                0 => None,
+                _ => best[i * columns + j - 1].map(|(score, at)| {
+                    let penalty = if at == j - 1 {
+                        GAP_START
+                    } else {
+                        GAP_EXTENSION
+                    };
+                    (score + penalty, at)
+                }),
+            };
+            best[i * columns + j] = match (here, carried) {
+                (Some((score, _)), Some(carried)) if carried.0 > score => Some(carried),
+                (Some((score, _)), _) => Some((score, j)),
+                (None, carried) => carried,
+            };
+        }
+    }
+
+    // Trailing gaps are free, the best match of the last character wins
+    let last = (rows - 1) * columns;
+    let (mut j, score) = (0..columns)
+        .filter_map(|j| matched[last + j].map(|(score, _)| (j, score)))
+        .max_by_key(|(j, score)| (*score, std::cmp::Reverse(*j)))?;
+    let mut positions = vec![j];
+    for i in (0..rows - 1).rev() {
+        let (_, consecutive) = matched[(i + 1) * columns + j]?;
+        j = match consecutive {
+            true => j - 1,
+            false => best[i * columns + j - 1]?.1,
+        };
+        positions.push(j);
+    }
+    positions.reverse();
+    Some(Match { score, positions })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters_must_appear_in_order() {
+        assert!(fuzzy_match("abc", "a big cat").is_some());
+        assert!(fuzzy_match("cba", "a big cat").is_none());
+        assert!(fuzzy_match("", "anything").is_none());
+    }
+
+    #[test]
+    fn case_is_ignored_unless_asked_for() {
+        assert!(fuzzy_match("beatles", "The Beatles").is_some());
+        assert!(fuzzy_match("Beatles", "the beatles").is_none());
+    }
+
+    #[test]
+    fn word_starts_are_preferred() {
+        let found = fuzzy_match("dsm", "Dark Side of the Moon").unwrap();
+        assert_eq!(found.positions, vec![0, 5, 17]);
+        let words = fuzzy_match("tm", "The Moon").unwrap();
+        let inside = fuzzy_match("tm", "Atmosphere").unwrap();
+        assert!(words.score > inside.score);
+    }
+
+    #[test]
+    fn consecutive_runs_beat_scattered_characters() {
+        let run = fuzzy_match("love", "Lovely Day").unwrap();
+        let scattered = fuzzy_match("love", "Loud Voices").unwrap();
+        assert_eq!(run.positions, vec![0, 1, 2, 3]);
+        assert!(run.score > scattered.score);
+    }
+
+    #[test]
+    fn positions_are_char_indexes() {
+        assert_eq!(
+            fuzzy_match("bjg", "Björk - Jóga").unwrap().positions,
+            vec![0, 8, 10]
+        );
+        assert_eq!(
+            fuzzy_match("jo", "Björk - Joga").unwrap().positions,
+            vec![8, 9]
+        );
+    }
+}
@@ -0,0 +1,51 @@
+/// Played tracks are kept for the session, older ones are forgotten past this length.
+const HISTORY_LENGTH: usize = 500;
+
+/// Tracks in the order they were played, stored as playlist indexes, the current track last.
+#[derive(Default)]
+pub struct History {
+    played: Vec<usize>,
+}
+
+impl History {
+    /// Replaying the current track, as repeat one does, is not recorded again.
+    pub fn push(&mut self, index: usize) {
+        if self.played.last() == Some(&index) {
+            return;
+        }
+        if self.played.len() == HISTORY_LENGTH {
+            self.played.remove(0);
+        }
+        self.played.push(index);
+    }
+
+    /// Forgets the current track and returns the one played before it, which becomes current.
+    pub fn back(&mut self) -> Option<usize> {
+        if self.played.len() < 2 {
+            return None;
+        }
+        self.played.pop();
+        self.played.last().copied()
+    }
+
+    /// Drops a track removed from the playlist and shifts the indexes following it.
+    pub fn remove(&mut self, index: usize) {
+        self.played.retain(|entry| *entry != index);
+        self.played.dedup();
+        for entry in self.played.iter_mut().filter(|entry| **entry > index) {
+            *entry -= 1;
+        }
+    }
+
+    /// Follows tracks moved or inserted in the playlist, `moved` giving the new index of each.
+    pub fn remap(&mut self, mut moved: impl FnMut(usize) -> usize) {
+        for entry in self.played.iter_mut() {
+            *entry = moved(*entry);
+        }
+    }
+
+    /// Iterates from the current track back to the oldest one.
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.played.iter().rev().copied()
+    }
+}
@@ -0,0 +1,443 @@
+use anyhow::{anyhow, Result};
+use csv::ReaderBuilder;
+use log::warn;
+use percent_encoding::percent_decode_str;
+use plist::{Dictionary, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::library::{Database, Stats};
+
+/// What an import brought into the library.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub tracks: usize,
+    pub playlists: usize,
+    /// Entries whose file does not exist anymore, they are skipped
+    pub missing: usize,
+}
+
+/// Exported files refer to tracks by absolute path or relative to the export itself.
+fn resolve(export: &Path, entry: &str) -> PathBuf {
+    let path = PathBuf::from(entry.trim());
+    match export.parent() {
+        Some(dir) if path.is_relative() => dir.join(path),
+        _ => path,
+    }
+}
+
+/// Column names differ between players and export templates, e.g. `%play_count%` or
+/// `Plays`.
+fn column(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Accepts stars, a 1 to 5 scale or a 0 to 100 scale, 0 meaning unrated.
+fn parse_rating(value: &str) -> Option<u8> {
+    let value = value.trim();
+    let stars = value.chars().filter(|c| *c == '★').count();
+    if stars > 0 {
+        return Some(stars.min(5) as u8);
+    }
+    let rating = value.parse::<f64>().ok()?;
+    let rating = if rating > 5.0 { rating / 20.0 } else { rating };
+    (rating >= 1.0).then(|| rating.round().min(5.0) as u8)
+}
+
+fn delimiter(header: &str) -> u8 {
+    // The last of equally frequent candidates wins, commas when there is a single column
+    [b'\t', b';', b',']
+        .into_iter()
+        .max_by_key(|delimiter| header.bytes().filter(|c| c == delimiter).count())
+        .unwrap_or(b',')
+}
+
+/// Ratings and play counts exported as CSV, e.g. by foobar2000 or MusicBee, with a header
+/// row naming a path column and a rating and/or play count column.
+fn import_csv(path: &Path, library: &Database) -> Result<Summary> {
+    let content = std::fs::read_to_string(path)?;
+    let header = content.lines().next().unwrap_or_default();
+    let mut reader = ReaderBuilder::new()
+        .delimiter(delimiter(header))
+        .flexible(true)
+        .from_reader(content.as_bytes());
+    let columns: Vec<String> = reader.headers()?.iter().map(column).collect();
+    let find = |names: &[&str]| columns.iter().position(|c| names.contains(&c.as_str()));
+    let path_column = find(&["path", "filepath", "location", "file", "filename"])
+        .ok_or(anyhow!("No path column in {}", path.display()))?;
+    let rating_column = find(&["rating"]);
+    let play_count_column = find(&["playcount", "plays", "timesplayed"]);
+    let mut summary = Summary::default();
+    for record in reader.records() {
+        let record = record?;
+        let Some(entry) = record.get(path_column) else {
+            continue;
+        };
+        let file = resolve(path, entry);
+        if !file.is_file() {
+            summary.missing += 1;
+            continue;
+        }
+        let stats = Stats {
+            rating: rating_column
+                .and_then(|column| record.get(column))
+                .and_then(parse_rating),
+            play_count: play_count_column
+                .and_then(|column| record.get(column))
+                .and_then(|value| value.trim().parse().ok())
+                .unwrap_or_default(),
+        };
+        library.merge_stats(&file, stats)?;
+        summary.tracks += 1;
+    }
+    Ok(summary)
+}
+
+/// Playlists exported as M3U, named after the file.
+fn import_m3u(path: &Path, library: &Database) -> Result<Summary> {
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut summary = Summary::default();
+    let mut files = Vec::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let file = resolve(path, line);
+        if file.is_file() {
+            files.push(file);
+        } else {
+            summary.missing += 1;
+        }
+    }
+    library.save_playlist(&name, &files)?;
+    summary.tracks = files.len();
+    summary.playlists = 1;
+    Ok(summary)
+}
+
+/// iTunes locations are `file://localhost/C:/Music/...` on Windows and `file:///Users/...`
+/// on macOS, with every special character percent-encoded.
+fn location_path(location: &str) -> Option<PathBuf> {
+    let path = location.strip_prefix("file://")?;
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    // Drive letters come after the root slash, other hosts are network shares
+    let path = match path.as_bytes() {
+        [b'/', _, b':', ..] => path[1..].to_string(),
+        [b'/', ..] => path.into_owned(),
+        _ => format!("//{}", path),
+    };
+    Some(PathBuf::from(path))
+}
+
+/// Built-in playlists of the whole library or of a media kind are not worth importing.
+fn is_user_playlist(playlist: &Dictionary) -> bool {
+    let flag = |key: &str| {
+        playlist
+            .get(key)
+            .and_then(Value::as_boolean)
+            .unwrap_or_default()
+    };
+    !flag("Master")
+        && !flag("Folder")
+        && playlist.get("Visible").and_then(Value::as_boolean) != Some(false)
+        && !playlist.contains_key("Distinguished Kind")
+}
+
+/// `iTunes Library.xml` as exported by iTunes and Apple Music, MusicBee writes the same
+/// format. Tracks bring their rating and play count, playlists refer to tracks by ID.
+fn import_itunes(path: &Path, library: &Database) -> Result<Summary> {
+    let root = Value::from_file(path)?;
+    let root = root
+        .as_dictionary()
+        .ok_or(anyhow!("{} is not an iTunes library", path.display()))?;
+    let mut summary = Summary::default();
+    let mut files = HashMap::new();
+    let tracks = root.get("Tracks").and_then(Value::as_dictionary);
+    for (id, track) in tracks.into_iter().flatten() {
+        let Some(track) = track.as_dictionary() else {
+            continue;
+        };
+        // Streamed tracks have no location
+        let Some(file) = track
+            .get("Location")
+            .and_then(Value::as_string)
+            .and_then(location_path)
+        else {
+            continue;
+        };
+        if !file.is_file() {
+            summary.missing += 1;
+            continue;
+        }
+        let integer = |key: &str| {
+            track
+                .get(key)
+                .and_then(Value::as_unsigned_integer)
+                .unwrap_or_default()
+        };
+        // Computed ratings are inherited from the album rating
+        let computed = track
+            .get("Rating Computed")
+            .and_then(Value::as_boolean)
+            .unwrap_or_default();
+        let stats = Stats {
+            rating: (!computed)
+                .then(|| integer("Rating"))
+                .filter(|rating| *rating >= 20)
+                .map(|rating| (rating / 20).min(5) as u8),
+            play_count: integer("Play Count") as u32,
+        };
+        library.merge_stats(&file, stats)?;
+        summary.tracks += 1;
+        files.insert(id.clone(), file);
+    }
+    let playlists = root.get("Playlists").and_then(Value::as_array);
+    for playlist in playlists.into_iter().flatten() {
+        let Some(playlist) = playlist.as_dictionary().filter(|p| is_user_playlist(p)) else {
+            continue;
+        };
+        let Some(name) = playlist.get("Name").and_then(Value::as_string) else {
+            continue;
+        };
+        let items = playlist.get("Playlist Items").and_then(Value::as_array);
+        let paths: Vec<PathBuf> = items
+            .into_iter()
+            .flatten()
+            .filter_map(|item| item.as_dictionary()?.get("Track ID")?.as_unsigned_integer())
+            .filter_map(|id| files.get(&id.to_string()).cloned())
+            .collect();
+        library.save_playlist(name, &paths)?;
+        summary.playlists += 1;
+    }
+    Ok(summary)
+}
+
+/// Merges an export of another player into the library, picked by file extension.
+pub fn import(path: &Path, library: &Database) -> Result<Summary> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let summary = match extension.as_deref() {
+        Some("csv") => import_csv(path, library)?,
+        Some("m3u") | Some("m3u8") => import_m3u(path, library)?,
+        Some("xml") => import_itunes(path, library)?,
+        _ => return Err(anyhow!("Unsupported export {}", path.display())),
+    };
+    if summary.missing > 0 {
+        warn!(
+            "{} entries of {} refer to missing files",
+            summary.missing,
+            path.display()
+        );
+    }
+    library.flush()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+    use std::fs;
+
+    const CONTROLS_AND_SPACE: &AsciiSet = &CONTROLS.add(b' ');
+
+    fn database() -> Database {
+        Database::with_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    /// Fresh temporary directory holding the tracks the exports refer to.
+    fn music(name: &str, tracks: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rhap-import-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for track in tracks {
+            fs::write(dir.join(track), "").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn ratings() {
+        for (value, rating) in [
+            ("★★★", Some(3)),
+            ("★★★★★★", Some(5)),
+            ("4", Some(4)),
+            (" 2.6 ", Some(3)),
+            ("0", None),
+            ("0.4", None),
+            ("100", Some(5)),
+            ("60", Some(3)),
+            ("10", None),
+            ("", None),
+            ("good", None),
+        ] {
+            assert_eq!(parse_rating(value), rating, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn delimiters() {
+        for (header, delimiter_byte) in [
+            ("path,rating,plays", b','),
+            ("%path%;%rating%;%play_count%", b';'),
+            ("Location\tRating\tPlays", b'\t'),
+            // Commas in names do not outweigh the real separator
+            ("File, name;Rating;Plays", b';'),
+            ("path", b','),
+        ] {
+            assert_eq!(delimiter(header), delimiter_byte, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn csv_exports() {
+        for (name, export) in [
+            (
+                "semicolon",
+                "%path%;%rating%;%play_count%\na.flac;★★★★;12\nmissing.flac;5;1\nb.flac;;3\n",
+            ),
+            (
+                "tab",
+                "Location\tRating\tPlays\na.flac\t80\t12\nmissing.flac\t5\t1\nb.flac\t0\t3\n",
+            ),
+        ] {
+            let dir = music(name, &["a.flac", "b.flac"]);
+            let path = dir.join("export.csv");
+            fs::write(&path, export).unwrap();
+            let library = database();
+            let summary = import_csv(&path, &library).unwrap();
+            assert_eq!((summary.tracks, summary.missing), (2, 1), "{}", name);
+            let stats = |track: &str| library.stats(&dir.join(track)).unwrap();
+            assert_eq!(stats("a.flac").rating, Some(4), "{}", name);
+            assert_eq!(stats("a.flac").play_count, 12, "{}", name);
+            assert_eq!(stats("b.flac").rating, None, "{}", name);
+            assert_eq!(stats("b.flac").play_count, 3, "{}", name);
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn itunes_locations() {
+        for (location, path) in [
+            (
+                "file://localhost/C:/Music/Artist/Song.flac",
+                Some("C:/Music/Artist/Song.flac"),
+            ),
+            (
+                "file:///Users/me/Music/My%20Song.m4a",
+                Some("/Users/me/Music/My Song.m4a"),
+            ),
+            (
+                "file://localhost/Users/me/Caf%C3%A9%20%26%20Mix%2B.mp3",
+                Some("/Users/me/Café & Mix+.mp3"),
+            ),
+            (
+                "file://server/share/Song.mp3",
+                Some("//server/share/Song.mp3"),
+            ),
+            ("file:///Users/me/%FF.mp3", None),
+            ("http://example.com/stream.mp3", None),
+            ("/Users/me/Song.mp3", None),
+        ] {
+            assert_eq!(
+                location_path(location),
+                path.map(PathBuf::from),
+                "{}",
+                location
+            );
+        }
+    }
+
+    #[test]
+    fn itunes_libraries() {
+        let dir = music("itunes", &["My Song.flac", "Café.flac"]);
+        let location = |track: &str| {
+            let path = dir.join(track);
+            let encoded = utf8_percent_encode(path.to_str().unwrap(), CONTROLS_AND_SPACE);
+            format!("file://localhost{}", encoded)
+        };
+        let export = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+    <key>Tracks</key>
+    <dict>
+        <key>1</key>
+        <dict>
+            <key>Location</key><string>{}</string>
+            <key>Rating</key><integer>80</integer>
+            <key>Play Count</key><integer>7</integer>
+        </dict>
+        <key>2</key>
+        <dict>
+            <key>Location</key><string>{}</string>
+            <key>Rating</key><integer>100</integer>
+            <key>Rating Computed</key><true/>
+        </dict>
+        <key>3</key>
+        <dict>
+            <key>Location</key><string>{}</string>
+        </dict>
+    </dict>
+    <key>Playlists</key>
+    <array>
+        <dict>
+            <key>Name</key><string>Library</string>
+            <key>Master</key><true/>
+        </dict>
+        <dict>
+            <key>Name</key><string>Favorites</string>
+            <key>Playlist Items</key>
+            <array>
+                <dict><key>Track ID</key><integer>2</integer></dict>
+                <dict><key>Track ID</key><integer>3</integer></dict>
+                <dict><key>Track ID</key><integer>1</integer></dict>
+            </array>
+        </dict>
+    </array>
+</dict>
+</plist>"#,
+            location("My Song.flac"),
+            location("Café.flac"),
+            location("Missing.flac"),
+        );
+        let path = dir.join("iTunes Library.xml");
+        fs::write(&path, export).unwrap();
+        let library = database();
+        let summary = import_itunes(&path, &library).unwrap();
+        assert_eq!(
+            (summary.tracks, summary.playlists, summary.missing),
+            (2, 1, 1)
+        );
+        let stats = library.stats(&dir.join("My Song.flac")).unwrap();
+        assert_eq!((stats.rating, stats.play_count), (Some(4), 7));
+        // Album ratings are not the track's own
+        let stats = library.stats(&dir.join("Café.flac")).unwrap();
+        assert_eq!(stats.rating, None);
+        let playlists = library.playlists().unwrap();
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(playlists[0].0, "Favorites");
+        // The missing track is left out
+        assert_eq!(playlists[0].1.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn csv_exports_need_a_path_column() {
+        let dir = music("no-path", &[]);
+        let path = dir.join("export.csv");
+        fs::write(&path, "title,rating\nSong,5\n").unwrap();
+        assert!(import_csv(&path, &database()).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
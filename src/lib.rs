@@ -0,0 +1,43 @@
+//! Playback engine of rhap, for embedding: the `Player` plays tracks on an output device,
+//! `Player::observe` and `Player::subscribe` report its position, spectrum and state.
+
+pub mod alarm;
+pub mod analysis;
+pub mod artwork;
+pub mod audio;
+pub mod autodj;
+pub mod config;
+pub mod convert;
+pub mod cue;
+pub mod doctor;
+pub mod dsd;
+pub mod dsp;
+pub mod events;
+pub mod export;
+pub mod fuzzy;
+pub mod history;
+pub mod import;
+pub mod library;
+pub mod loader;
+pub mod logger;
+pub mod lyrics;
+pub mod metadata;
+pub mod mpd;
+#[cfg(all(target_os = "linux", feature = "mpris"))]
+pub mod mpris;
+pub mod musictrack;
+pub mod observer;
+pub mod player;
+pub mod preview;
+pub mod queue;
+pub mod radio;
+pub mod recorder;
+pub mod scanner;
+pub mod session;
+#[cfg(windows)]
+pub mod smtc;
+pub mod sync;
+pub mod tasks;
+pub mod tools;
+pub mod ui;
+pub mod watcher;
@@ -0,0 +1,530 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use symphonia::core::units::Time;
+
+use crate::alarm::AlarmSettings;
+use crate::analysis::Analysis;
+use crate::audio::{BitsPerSample, SampleRate};
+use crate::metadata::{AlbumInfo, ArtistInfo};
+use crate::musictrack::MusicTrack;
+
+/// What is known of a file after probing it, valid as long as its modification time matches.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    modified: u128,
+    title: String,
+    artist: String,
+    album: String,
+    seconds: u64,
+    frac: f64,
+    samplerate: usize,
+    channels: usize,
+    bits_per_sample: usize,
+    loudness: Option<f64>,
+    dsd_rate: Option<u32>,
+}
+
+impl Entry {
+    fn new(track: &MusicTrack, modified: u128) -> Self {
+        Self {
+            modified,
+            title: track.title.clone(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+            seconds: track.duration.seconds,
+            frac: track.duration.frac,
+            samplerate: track.sample as usize,
+            channels: track.channels,
+            bits_per_sample: track.bits_per_sample as usize,
+            loudness: track.loudness,
+            dsd_rate: track.dsd_rate,
+        }
+    }
+
+    // Only probed tracks are stored, their formats are known to convert
+    fn into_track(self, path: String) -> MusicTrack {
+        MusicTrack {
+            path,
+            sample: SampleRate::from(self.samplerate),
+            channels: self.channels,
+            bits_per_sample: BitsPerSample::from(self.bits_per_sample),
+            title: self.title,
+            artist: self.artist,
+            album: self.album,
+            duration: Time::new(self.seconds, self.frac),
+            loudness: self.loudness,
+            dsd_rate: self.dsd_rate,
+            segment: None,
+        }
+    }
+}
+
+/// Listening history of a track, kept apart from the probed metadata so it survives re-probing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    /// From 1 to 5 stars
+    pub rating: Option<u8>,
+    pub play_count: u32,
+}
+
+impl Stats {
+    /// Keeps the local rating and the highest play count, importing twice changes nothing.
+    fn merge(self, imported: Stats) -> Self {
+        Self {
+            rating: self.rating.or(imported.rating),
+            play_count: self.play_count.max(imported.play_count),
+        }
+    }
+}
+
+/// Stats and playlists refer to files by absolute path, they may come from other players.
+fn absolute_key(path: &Path) -> Result<Vec<u8>> {
+    Ok(std::path::absolute(path)?
+        .to_string_lossy()
+        .into_owned()
+        .into_bytes())
+}
+
+/// Track of a saved playlist. The tags find the file again once it has been moved or renamed,
+/// they are empty for files never probed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlaylistEntry {
+    pub path: String,
+    pub artist: String,
+    pub album: String,
+    pub title: String,
+}
+
+impl PlaylistEntry {
+    pub fn new(track: &MusicTrack) -> Result<Self> {
+        Ok(Self {
+            path: std::path::absolute(&track.path)?
+                .to_string_lossy()
+                .into_owned(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+            title: track.title.clone(),
+        })
+    }
+
+    /// Whether `track` carries the same tags, untagged entries match nothing.
+    pub fn matches(&self, track: &MusicTrack) -> bool {
+        !self.title.is_empty()
+            && self.title == track.title
+            && self.artist == track.artist
+            && self.album == track.album
+    }
+}
+
+/// Bookmarks set or removed from now on, by the app or by a remote client.
+pub struct BookmarkChanges(sled::Subscriber);
+
+impl BookmarkChanges {
+    /// Path of the track with its new position, `None` once removed. Ends with the library.
+    pub async fn next(&mut self) -> Option<(String, Option<f64>)> {
+        loop {
+            let event = (&mut self.0).await?;
+            let path = String::from_utf8_lossy(event.key()).into_owned();
+            match event {
+                sled::Event::Insert { value, .. } => match bincode::deserialize(&value) {
+                    Ok(position) => return Some((path, Some(position))),
+                    Err(err) => warn!("Dropping unreadable bookmark of {}: {}", path, err),
+                },
+                sled::Event::Remove { .. } => return Some((path, None)),
+            }
+        }
+    }
+}
+
+/// Analysis results, valid as long as the modification time of the file matches.
+#[derive(Serialize, Deserialize)]
+struct AnalysisEntry {
+    modified: u128,
+    analysis: Analysis,
+}
+
+/// Tags are compared as written, NUL cannot appear in either of them.
+fn album_key(artist: &str, album: &str) -> Vec<u8> {
+    format!("{}\0{}", artist, album).into_bytes()
+}
+
+fn modified(path: &str) -> Result<u128> {
+    Ok(std::fs::metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_nanos())
+}
+
+/// Scanned tracks persisted in `rhap/library` in the platform data directory, keyed by path,
+/// so only new or modified files are probed on startup.
+#[derive(Clone)]
+pub struct Database {
+    db: sled::Db,
+    stats: sled::Tree,
+    playlists: sled::Tree,
+    analyses: sled::Tree,
+    /// Answers of the metadata providers, keyed by artist and album
+    albums: sled::Tree,
+    /// Answers of the metadata providers, keyed by artist
+    artists: sled::Tree,
+    /// Settings changed in the app, by name
+    settings: sled::Tree,
+    /// Albums never crossfaded, keyed by artist and album
+    strict_gapless: sled::Tree,
+    /// Seconds to resume long tracks from, keyed by absolute path, shared with the remote
+    /// clients
+    bookmarks: sled::Tree,
+}
+
+impl Database {
+    fn path() -> Option<PathBuf> {
+        dirs::data_dir().map(|dir| dir.join("rhap").join("library"))
+    }
+
+    /// Falls back to an in memory database when the library cannot be opened, e.g. while
+    /// another instance holds it.
+    pub fn open() -> Result<Self> {
+        if let Some(path) = Self::path() {
+            match sled::open(&path) {
+                Ok(db) => return Self::with_db(db),
+                Err(err) => warn!("Cannot open the library at {}: {}", path.display(), err),
+            }
+        }
+        Self::with_db(sled::Config::new().temporary(true).open()?)
+    }
+
+    /// Opens the library on disk only, failing instead of falling back to memory. Returns
+    /// where it is stored.
+    pub fn check() -> Result<PathBuf> {
+        let path = Self::path().ok_or(anyhow!("No data directory"))?;
+        sled::open(&path).map_err(|err| anyhow!("{}: {}", path.display(), err))?;
+        Ok(path)
+    }
+
+    pub(crate) fn with_db(db: sled::Db) -> Result<Self> {
+        Ok(Self {
+            stats: db.open_tree("stats")?,
+            playlists: db.open_tree("playlists")?,
+            analyses: db.open_tree("analyses")?,
+            albums: db.open_tree("albums")?,
+            artists: db.open_tree("artists")?,
+            settings: db.open_tree("settings")?,
+            strict_gapless: db.open_tree("strict_gapless")?,
+            bookmarks: db.open_tree("bookmarks")?,
+            db,
+        })
+    }
+
+    /// Returns the stored track, probing the file again when it changed since it was stored.
+    pub fn track(&self, path: String) -> Result<MusicTrack> {
+        let modified = modified(&path)?;
+        if let Some(value) = self.db.get(path.as_bytes())? {
+            match bincode::deserialize::<Entry>(&value) {
+                Ok(entry) if entry.modified == modified => return Ok(entry.into_track(path)),
+                Ok(_) => (),
+                Err(err) => warn!("Dropping unreadable library entry for {}: {}", path, err),
+            }
+        }
+        let track = MusicTrack::new(path)?;
+        self.db.insert(
+            track.path.as_bytes(),
+            bincode::serialize(&Entry::new(&track, modified))?,
+        )?;
+        Ok(track)
+    }
+
+    /// The stored analysis, unless the file changed since.
+    pub fn analysis(&self, path: &str) -> Option<Analysis> {
+        let value = self.analyses.get(path.as_bytes()).ok()??;
+        let entry = bincode::deserialize::<AnalysisEntry>(&value).ok()?;
+        (modified(path).ok()? == entry.modified).then_some(entry.analysis)
+    }
+
+    pub fn save_analysis(&self, path: &str, analysis: Analysis) -> Result<()> {
+        let entry = AnalysisEntry {
+            modified: modified(path)?,
+            analysis,
+        };
+        self.analyses
+            .insert(path.as_bytes(), bincode::serialize(&entry)?)?;
+        Ok(())
+    }
+
+    pub fn album_info(&self, artist: &str, album: &str) -> Result<Option<AlbumInfo>> {
+        Ok(match self.albums.get(album_key(artist, album))? {
+            Some(value) => Some(bincode::deserialize(&value)?),
+            None => None,
+        })
+    }
+
+    pub fn save_album_info(&self, artist: &str, album: &str, info: &AlbumInfo) -> Result<()> {
+        self.albums
+            .insert(album_key(artist, album), bincode::serialize(info)?)?;
+        Ok(())
+    }
+
+    pub fn artist_info(&self, artist: &str) -> Result<Option<ArtistInfo>> {
+        Ok(match self.artists.get(artist.as_bytes())? {
+            Some(value) => Some(bincode::deserialize(&value)?),
+            None => None,
+        })
+    }
+
+    pub fn save_artist_info(&self, artist: &str, info: &ArtistInfo) -> Result<()> {
+        self.artists
+            .insert(artist.as_bytes(), bincode::serialize(info)?)?;
+        Ok(())
+    }
+
+    /// Artist and album of the albums played strictly gapless.
+    pub fn strict_gapless_albums(&self) -> Result<Vec<(String, String)>> {
+        let mut albums = Vec::new();
+        for key in self.strict_gapless.iter().keys() {
+            let key = String::from_utf8(key?.to_vec())?;
+            if let Some((artist, album)) = key.split_once('\0') {
+                albums.push((artist.to_string(), album.to_string()));
+            }
+        }
+        Ok(albums)
+    }
+
+    pub fn set_strict_gapless(&self, artist: &str, album: &str, strict: bool) -> Result<()> {
+        match strict {
+            true => self.strict_gapless.insert(album_key(artist, album), &[])?,
+            false => self.strict_gapless.remove(album_key(artist, album))?,
+        };
+        Ok(())
+    }
+
+    pub fn bookmark(&self, path: &Path) -> Result<Option<f64>> {
+        Ok(match self.bookmarks.get(absolute_key(path)?)? {
+            Some(value) => Some(bincode::deserialize(&value)?),
+            None => None,
+        })
+    }
+
+    /// Removes the bookmark without `position`.
+    pub fn set_bookmark(&self, path: &Path, position: Option<f64>) -> Result<()> {
+        let key = absolute_key(path)?;
+        match position {
+            Some(position) => self.bookmarks.insert(key, bincode::serialize(&position)?)?,
+            None => self.bookmarks.remove(key)?,
+        };
+        Ok(())
+    }
+
+    /// Bookmarked tracks by absolute path, with their position.
+    pub fn bookmarks(&self) -> Result<Vec<(String, f64)>> {
+        self.bookmarks
+            .iter()
+            .map(|entry| {
+                let (path, value) = entry?;
+                let path = String::from_utf8_lossy(&path).into_owned();
+                Ok((path, bincode::deserialize(&value)?))
+            })
+            .collect()
+    }
+
+    pub fn watch_bookmarks(&self) -> BookmarkChanges {
+        BookmarkChanges(self.bookmarks.watch_prefix(""))
+    }
+
+    /// Alarm as last set in the app, `None` until then.
+    pub fn alarm(&self) -> Result<Option<AlarmSettings>> {
+        Ok(match self.settings.get("alarm")? {
+            Some(value) => Some(bincode::deserialize(&value)?),
+            None => None,
+        })
+    }
+
+    pub fn save_alarm(&self, alarm: &AlarmSettings) -> Result<()> {
+        self.settings.insert("alarm", bincode::serialize(alarm)?)?;
+        Ok(())
+    }
+
+    pub fn stats(&self, path: &Path) -> Result<Stats> {
+        Ok(match self.stats.get(absolute_key(path)?)? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => Stats::default(),
+        })
+    }
+
+    pub fn merge_stats(&self, path: &Path, imported: Stats) -> Result<()> {
+        let stats = self.stats(path)?.merge(imported);
+        self.stats
+            .insert(absolute_key(path)?, bincode::serialize(&stats)?)?;
+        Ok(())
+    }
+
+    pub fn add_play(&self, path: &Path) -> Result<()> {
+        let mut stats = self.stats(path)?;
+        stats.play_count += 1;
+        self.stats
+            .insert(absolute_key(path)?, bincode::serialize(&stats)?)?;
+        Ok(())
+    }
+
+    /// Replaces the playlist of the same name, with the tags of the files already in the
+    /// library. Nothing is probed, imports may list thousands of files.
+    pub fn save_playlist(&self, name: &str, paths: &[PathBuf]) -> Result<()> {
+        let entries = paths
+            .iter()
+            .map(|path| {
+                let path = std::path::absolute(path)?.to_string_lossy().into_owned();
+                let stored = self
+                    .db
+                    .get(path.as_bytes())?
+                    .and_then(|value| bincode::deserialize::<Entry>(&value).ok());
+                Ok(match stored {
+                    Some(entry) => PlaylistEntry {
+                        path,
+                        artist: entry.artist,
+                        album: entry.album,
+                        title: entry.title,
+                    },
+                    None => PlaylistEntry {
+                        path,
+                        ..Default::default()
+                    },
+                })
+            })
+            .collect::<Result<Vec<PlaylistEntry>>>()?;
+        self.save_playlist_entries(name, &entries)
+    }
+
+    /// Replaces the playlist of the same name, keeping the order of `entries`.
+    pub fn save_playlist_entries(&self, name: &str, entries: &[PlaylistEntry]) -> Result<()> {
+        self.playlists
+            .insert(name.as_bytes(), bincode::serialize(entries)?)?;
+        Ok(())
+    }
+
+    /// Saved playlists by name, in their saved order. Playlists saved before tags were stored
+    /// are plain lists of paths.
+    pub fn playlists(&self) -> Result<Vec<(String, Vec<PlaylistEntry>)>> {
+        self.playlists
+            .iter()
+            .map(|entry| {
+                let (name, value) = entry?;
+                let entries = match bincode::deserialize::<Vec<PlaylistEntry>>(&value) {
+                    Ok(entries) => entries,
+                    Err(_) => bincode::deserialize::<Vec<String>>(&value)?
+                        .into_iter()
+                        .map(|path| PlaylistEntry {
+                            path,
+                            ..Default::default()
+                        })
+                        .collect(),
+                };
+                Ok((String::from_utf8_lossy(&name).into_owned(), entries))
+            })
+            .collect()
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// Drops the stored entry so the file is probed again, for editors preserving the
+    /// modification time.
+    pub fn forget(&self, path: &str) -> Result<()> {
+        self.db.remove(path.as_bytes())?;
+        Ok(())
+    }
+
+    /// Forgets the files under `dir` that are not part of `paths` anymore.
+    pub fn retain(&self, dir: &Path, paths: &[String]) -> Result<()> {
+        let prefix = dir.to_string_lossy();
+        let paths: HashSet<&[u8]> = paths.iter().map(|path| path.as_bytes()).collect();
+        for key in self.db.scan_prefix(prefix.as_bytes()).keys() {
+            let key = key?;
+            if !paths.contains(key.as_ref()) {
+                self.db.remove(key)?;
+            }
+        }
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database() -> Database {
+        Database::with_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn playlists_saved_as_paths_are_still_read() {
+        let database = database();
+        let paths = vec![String::from("/music/a.flac"), String::from("/music/b.flac")];
+        database
+            .playlists
+            .insert("old", bincode::serialize(&paths).unwrap())
+            .unwrap();
+        let playlists = database.playlists().unwrap();
+        assert_eq!(playlists.len(), 1);
+        let (name, entries) = &playlists[0];
+        assert_eq!(name, "old");
+        let read: Vec<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(read, paths);
+        assert!(entries.iter().all(|entry| entry.title.is_empty()));
+    }
+
+    #[test]
+    fn playlist_order_is_kept() {
+        let database = database();
+        let entries: Vec<PlaylistEntry> = ["c", "a", "b"]
+            .iter()
+            .map(|title| PlaylistEntry {
+                path: format!("/music/{}.flac", title),
+                artist: String::from("Artist"),
+                album: String::from("Album"),
+                title: title.to_string(),
+            })
+            .collect();
+        database.save_playlist_entries("new", &entries).unwrap();
+        assert_eq!(
+            database.playlists().unwrap(),
+            vec![(String::from("new"), entries)]
+        );
+    }
+
+    #[tokio::test]
+    async fn bookmarks_are_shared_with_watchers() {
+        let database = database();
+        let mut changes = database.watch_bookmarks();
+        let path = Path::new("/music/mix.flac");
+        database.set_bookmark(path, Some(1834.5)).unwrap();
+        database.set_bookmark(path, None).unwrap();
+        assert_eq!(database.bookmark(path).unwrap(), None);
+        let changes = vec![changes.next().await, changes.next().await];
+        let path = String::from("/music/mix.flac");
+        assert_eq!(
+            changes,
+            vec![Some((path.clone(), Some(1834.5))), Some((path, None))]
+        );
+    }
+
+    #[test]
+    fn strict_gapless_albums_are_kept_until_unset() {
+        let database = database();
+        database.set_strict_gapless("Artist", "Live", true).unwrap();
+        database
+            .set_strict_gapless("Artist", "Studio", true)
+            .unwrap();
+        database
+            .set_strict_gapless("Artist", "Studio", false)
+            .unwrap();
+        assert_eq!(
+            database.strict_gapless_albums().unwrap(),
+            vec![(String::from("Artist"), String::from("Live"))]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use log::{info, warn};
+use rand::{seq::SliceRandom, thread_rng};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::cue::{self, Pregap};
+use crate::library::Database;
+use crate::musictrack::MusicTrack;
+use crate::scanner::Scanner;
+
+/// Adds the tracks of a CUE sheet and remembers its files. Invalid sheets are skipped, their
+/// files are then played whole.
+pub fn add_cue_sheet(
+    sheet: &Path,
+    pregap: Pregap,
+    library: &Database,
+    songs: &mut Vec<Arc<MusicTrack>>,
+    cue_files: &mut HashSet<String>,
+) {
+    let tracks = cue::files(sheet).and_then(|files| {
+        let tracks = cue::tracks(sheet, pregap, library)?;
+        cue_files.extend(files.iter().map(|file| file.to_string_lossy().into_owned()));
+        Ok(tracks)
+    });
+    match tracks {
+        Ok(tracks) => songs.extend(tracks.into_iter().map(Arc::new)),
+        Err(err) => warn!("Cannot read the CUE sheet {}: {}", sheet.display(), err),
+    }
+}
+
+pub enum Loaded {
+    /// Sent once the directory is listed, before any track
+    Listed {
+        /// Tracks to come, CUE sheet ones included
+        total: usize,
+        /// Files played through the tracks of a CUE sheet rather than whole
+        cue_files: HashSet<String>,
+    },
+    Track(Arc<MusicTrack>),
+    /// Unreadable file, counted in the progress all the same
+    Skipped,
+}
+
+/// Lists and probes the files of the music directory on a blocking task, the tracks come in
+/// shuffled as they are read so the playlist is usable before the end on large libraries.
+/// The task stops once the loader is dropped.
+pub struct Loader {
+    receiver: UnboundedReceiver<Loaded>,
+    /// Tracks announced by the listing, `None` until it is done
+    total: Option<usize>,
+    done: usize,
+    /// Files added by the playlist on its own meanwhile, not to be added twice
+    added: HashSet<String>,
+    /// Files and directories removed meanwhile
+    removed: Vec<PathBuf>,
+    finished: bool,
+}
+
+impl Loader {
+    pub fn new(dir: PathBuf, pregap: Pregap, library: Database) -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::task::spawn_blocking(move || scan(&dir, pregap, &library, sender));
+        Self {
+            receiver,
+            total: None,
+            done: 0,
+            added: HashSet::new(),
+            removed: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Next message of the task if one is ready. Tracks of files added or removed since are
+    /// left out, they still count as done.
+    pub fn try_next(&mut self) -> Option<Loaded> {
+        let loaded = match self.receiver.try_recv() {
+            Ok(loaded) => loaded,
+            Err(mpsc::error::TryRecvError::Empty) => return None,
+            Err(mpsc::error::TryRecvError::Disconnected) => {
+                self.finished = true;
+                return None;
+            }
+        };
+        Some(match loaded {
+            Loaded::Listed { total, cue_files } => {
+                self.total = Some(total);
+                Loaded::Listed { total, cue_files }
+            }
+            Loaded::Track(track) => {
+                self.done += 1;
+                let path = Path::new(&track.path);
+                if self.added.contains(&track.path)
+                    || self.removed.iter().any(|removed| path.starts_with(removed))
+                {
+                    Loaded::Skipped
+                } else {
+                    Loaded::Track(track)
+                }
+            }
+            Loaded::Skipped => {
+                self.done += 1;
+                Loaded::Skipped
+            }
+        })
+    }
+
+    /// Keeps the track of `file`, once read, out of the playlist which has its own.
+    pub fn added(&mut self, file: &str) {
+        self.added.insert(file.to_string());
+    }
+
+    /// Keeps the tracks at or under `path` out of the playlist.
+    pub fn removed(&mut self, path: &Path) {
+        self.removed.push(path.to_path_buf());
+    }
+
+    /// Tracks read out of those to come, `None` while the directory is listed.
+    pub fn progress(&self) -> (usize, Option<usize>) {
+        (self.done, self.total)
+    }
+
+    /// Whether the task ended and every track was taken.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+fn scan(dir: &Path, pregap: Pregap, library: &Database, sender: UnboundedSender<Loaded>) {
+    let started = Instant::now();
+    let mut scanner = Scanner::new(dir);
+    let files = scanner.files(dir);
+    if let Err(err) = library.retain(dir, &files) {
+        warn!("Cannot prune the library under {}: {}", dir.display(), err);
+    }
+    let mut songs = vec![];
+    let mut cue_files = HashSet::new();
+    for sheet in scanner.cue_sheets(dir) {
+        add_cue_sheet(
+            Path::new(&sheet),
+            pregap,
+            library,
+            &mut songs,
+            &mut cue_files,
+        );
+    }
+    songs.shuffle(&mut thread_rng());
+    let mut files: Vec<String> = files
+        .into_iter()
+        .filter(|file| !cue_files.contains(file))
+        .collect();
+    files.shuffle(&mut thread_rng());
+    info!(
+        "Listed {} files and {} CUE sheet tracks in {:?}",
+        files.len(),
+        songs.len(),
+        started.elapsed()
+    );
+    let listed = Loaded::Listed {
+        total: songs.len() + files.len(),
+        cue_files,
+    };
+    if sender.send(listed).is_err() {
+        return;
+    }
+    let tracks = songs
+        .into_iter()
+        .map(Ok)
+        .chain(files.into_iter().map(|file| {
+            library
+                .track(file)
+                .map(Arc::new)
+                .inspect_err(|err| warn!("Skipping unreadable file: {}", err))
+        }));
+    for track in tracks {
+        let loaded = match track {
+            Ok(track) => Loaded::Track(track),
+            Err(_) => Loaded::Skipped,
+        };
+        // The playlist is gone
+        if sender.send(loaded).is_err() {
+            return;
+        }
+    }
+    info!(
+        "Read the files of {} in {:?}",
+        dir.display(),
+        started.elapsed()
+    );
+}
@@ -0,0 +1,74 @@
+use anyhow::{anyhow, Result};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::prelude::*;
+
+/// Writes every record to a file, the terminal belongs to the UI.
+struct FileLogger {
+    file: Mutex<File>,
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(
+                file,
+                "{}.{:03} {:<5} {}: {}",
+                time.as_secs(),
+                time.subsec_millis(),
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Log file of the diagnostic start, replaced on each run.
+fn path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("rhap").join("safe-mode.log"))
+}
+
+/// Logs every level to the file at `path()`.
+pub fn init() -> Result<PathBuf> {
+    let path = path().ok_or(anyhow!("No data directory"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let logger = FileLogger {
+        file: Mutex::new(File::create(&path)?),
+    };
+    log::set_logger(Box::leak(Box::new(logger))).map_err(|err| anyhow!("{}", err))?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(path)
+}
+
+/// Records the spans of the audio path, decoding, resampling, DSP and device writes, with
+/// the underruns, to `path` in the Chrome trace format read by Perfetto or chrome://tracing.
+/// The file is complete once the guard is dropped.
+pub fn trace(path: &Path) -> Result<FlushGuard> {
+    let (layer, guard) = ChromeLayerBuilder::new()
+        .writer(BufWriter::new(File::create(path)?))
+        .include_args(true)
+        .build();
+    tracing::subscriber::set_global_default(tracing_subscriber::registry().with(layer))?;
+    Ok(guard)
+}
@@ -0,0 +1,185 @@
+use anyhow::Result;
+use std::path::Path;
+use symphonia::core::meta::StandardTagKey;
+
+use crate::musictrack::MusicTrack;
+
+struct Line {
+    /// Seconds from the start of the track, 0 for unsynchronized lyrics
+    time: f64,
+    text: String,
+}
+
+/// Lyrics of a track, in the LRC format when synchronized.
+pub struct Lyrics {
+    lines: Vec<Line>,
+    synced: bool,
+}
+
+/// Parses `mm:ss`, `mm:ss.xx` or `mm:ss:xx` into seconds.
+fn timestamp(text: &str) -> Option<f64> {
+    let (minutes, seconds) = text.split_once(':')?;
+    let minutes = minutes.trim().parse::<u64>().ok()?;
+    let seconds = seconds.trim().replacen(':', ".", 1).parse::<f64>().ok()?;
+    Some(minutes as f64 * 60.0 + seconds)
+}
+
+/// Drops the word timings of enhanced LRC, `<mm:ss.xx>` ahead of each word.
+fn strip_word_timings(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        match rest[open..].find('>') {
+            Some(close) if timestamp(&rest[open + 1..open + close]).is_some() => {
+                stripped.push_str(&rest[..open]);
+                rest = &rest[open + close + 1..];
+            }
+            _ => {
+                stripped.push_str(&rest[..=open]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped.trim().to_string()
+}
+
+impl Lyrics {
+    /// Lines may carry several timestamps when repeated, such as choruses. Without any
+    /// timestamp the text is kept as plain lyrics.
+    pub fn parse(text: &str) -> Self {
+        let mut timed = Vec::new();
+        let mut plain = Vec::new();
+        // In milliseconds, positive values show the lines sooner
+        let mut offset = 0.0;
+        for line in text.trim_start_matches('\u{feff}').lines() {
+            let mut rest = line.trim();
+            let mut times = Vec::new();
+            let mut tagged = false;
+            while let Some((tag, after)) =
+                rest.strip_prefix('[').and_then(|tag| tag.split_once(']'))
+            {
+                match timestamp(tag) {
+                    Some(time) => times.push(time),
+                    None => {
+                        // ID tags such as [ar:...] or [ti:...] fill their own line
+                        if let Some(value) = tag.strip_prefix("offset:") {
+                            offset = value.trim().parse().unwrap_or(0.0);
+                        }
+                        tagged = true;
+                    }
+                }
+                rest = after.trim_start();
+            }
+            let text = strip_word_timings(rest);
+            if !times.is_empty() {
+                timed.extend(times.into_iter().map(|time| (time, text.clone())));
+            } else if !tagged {
+                plain.push(text);
+            }
+        }
+        if timed.is_empty() {
+            // Blank lines around plain lyrics come from the tag or file layout
+            while plain.last().is_some_and(String::is_empty) {
+                plain.pop();
+            }
+            let start = plain
+                .iter()
+                .position(|line| !line.is_empty())
+                .unwrap_or(plain.len());
+            return Self {
+                lines: plain
+                    .drain(start..)
+                    .map(|text| Line { time: 0.0, text })
+                    .collect(),
+                synced: false,
+            };
+        }
+        timed.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            lines: timed
+                .into_iter()
+                .map(|(time, text)| Line {
+                    time: (time - offset / 1000.0).max(0.0),
+                    text,
+                })
+                .collect(),
+            synced: true,
+        }
+    }
+
+    /// Reads the `.lrc` file next to the track, or the lyrics tag of the file.
+    pub fn load(track: &MusicTrack) -> Result<Option<Self>> {
+        let path = Path::new(&track.path);
+        for extension in ["lrc", "LRC"] {
+            let sidecar = path.with_extension(extension);
+            if sidecar.is_file() {
+                let bytes = std::fs::read(sidecar)?;
+                return Ok(Some(Self::parse(&String::from_utf8_lossy(&bytes))));
+            }
+        }
+        let metadata = track.metadata()?;
+        Ok(metadata
+            .tags()
+            .iter()
+            .find(|tag| tag.std_key == Some(StandardTagKey::Lyrics))
+            .map(|tag| Self::parse(&tag.value.to_string()))
+            .filter(|lyrics| !lyrics.lines.is_empty()))
+    }
+
+    pub fn lines(&self) -> Vec<&str> {
+        self.lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    /// Line sung at `elapsed` seconds, none before the first one or for plain lyrics.
+    pub fn current(&self, elapsed: f64) -> Option<usize> {
+        if !self.synced {
+            return None;
+        }
+        self.lines
+            .partition_point(|line| line.time <= elapsed)
+            .checked_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LRC: &str = "[ti:Song]
+[ar:Artist]
+[offset:+500]
+[00:12.00]First line
+[00:17.20][01:02.50]Chorus
+[00:21.10]<00:21.10>Word <00:21.60>by <00:22.00>word
+";
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(timestamp("00:12.00"), Some(12.0));
+        assert_eq!(timestamp("01:02.5"), Some(62.5));
+        assert_eq!(timestamp("02:03:50"), Some(123.5));
+        assert_eq!(timestamp("ar:Artist"), None);
+    }
+
+    #[test]
+    fn synced_lines_are_sorted_and_offset() {
+        let lyrics = Lyrics::parse(LRC);
+        assert_eq!(
+            lyrics.lines(),
+            vec!["First line", "Chorus", "Word by word", "Chorus"]
+        );
+        assert_eq!(lyrics.lines[0].time, 11.5);
+        assert_eq!(lyrics.current(0.0), None);
+        assert_eq!(lyrics.current(11.5), Some(0));
+        assert_eq!(lyrics.current(30.0), Some(2));
+        assert_eq!(lyrics.current(600.0), Some(3));
+    }
+
+    #[test]
+    fn plain_lyrics_are_not_synced() {
+        let lyrics = Lyrics::parse("\nFirst line\n\nSecond line\n\n");
+        assert_eq!(lyrics.lines(), vec!["First line", "", "Second line"]);
+        assert_eq!(lyrics.current(10.0), None);
+    }
+}
@@ -1,73 +1,362 @@
-use anyhow::Result;
-use audio::Host;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use audio::{Device, Host};
+use clap::{Parser, Subcommand};
+use config::{Config, Overrides, SummaryOutput};
+use library::Database;
+use log::{error, info};
+use mpd::AddMode;
 use player::Player;
+use recorder::Recorder;
+use rhap_core::{
+    audio, config, convert, doctor, events, import, library, logger, mpd, player, recorder, sync,
+    tasks, tools, ui,
+};
 use std::path::PathBuf;
-use ui::App;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use sync::{Action, DeviceSync, Transcode};
+use tasks::TaskGroup;
+use ui::{
+    screens::{RecorderScreen, SyncScreen},
+    App,
+};
 
-mod audio;
-mod musictrack;
-mod player;
-mod tools;
-mod ui;
-
-use crate::audio::{DeviceTrait, HostTrait};
+use audio::{DeviceTrait, HostTrait};
+use tools::resampler::{ResamplerEngine, ResamplerQuality};
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     #[clap(short, long)]
     list: bool,
+    #[clap(long)]
+    list_inputs: bool,
     #[clap(short, long, default_value_t = false)]
     high_priority_mode: bool,
-    #[clap(short, long, required = true)]
-    path: PathBuf,
+    #[clap(short, long, required_unless_present_any = ["list", "list_inputs", "record", "import"])]
+    path: Option<PathBuf>,
     #[clap(short, long)]
     device: Option<u32>,
     #[clap(long, default_value_t = false)]
     pollmode: bool,
+    /// Audio backend, unavailable backends fall back to the platform default
+    #[clap(long, default_value = "wasapi", value_parser = ["wasapi", "asio", "cpal", "pipewire"])]
+    backend: String,
+    /// Record the input device to the given WAV or FLAC file
+    #[clap(short, long)]
+    record: Option<PathBuf>,
+    /// Index of the input device used for recording, see --list-inputs
+    #[clap(short, long)]
+    input: Option<u32>,
+    /// Merge ratings, play counts and playlists exported as CSV, M3U or iTunes XML into the
+    /// library
+    #[clap(long)]
+    import: Option<PathBuf>,
+    /// Resampler used when the device does not support the track sample rate, overrides the config
+    #[clap(long, value_enum)]
+    resampler: Option<ResamplerEngine>,
+    /// Resampler filter length preset, overrides the config
+    #[clap(long, value_enum)]
+    resampler_quality: Option<ResamplerQuality>,
+    /// Mirror the music directory, or the playlists given with --sync-playlist, to a device
+    /// folder
+    #[clap(long)]
+    sync: Option<PathBuf>,
+    /// Saved playlist to mirror with --sync, can be repeated
+    #[clap(long, requires = "sync")]
+    sync_playlist: Vec<String>,
+    /// Convert while syncing, hi-res tracks to 16 bits FLAC at 44.1kHz at most with flac, the
+    /// default, or lossless tracks to Opus with opus
+    #[clap(
+        long,
+        value_enum,
+        requires = "sync",
+        num_args = 0..=1,
+        default_missing_value = "flac"
+    )]
+    transcode: Option<Transcode>,
+    /// Print what --sync would copy and transcode without writing anything
+    #[clap(long, requires = "sync")]
+    dry_run: bool,
+    /// Play through the system mixer so other applications can play meanwhile, overrides the
+    /// config
+    #[clap(long)]
+    shared: bool,
+    /// Diagnostic start in shared mode on the default device, without DSP nor config, logging
+    /// everything to a file
+    #[clap(long)]
+    safe_mode: bool,
+    /// Record where time goes while playing, decoding, resampling, DSP and device writes, to
+    /// this file in the Chrome trace format, to attach to reports of glitches. Open it with
+    /// https://ui.perfetto.dev
+    #[clap(long)]
+    trace: Option<PathBuf>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert files, or every track of folders, to another format, sample rate or bit depth
+    Convert {
+        /// Output format, opus and mp3 need rhap built with the opus and mp3 features
+        #[clap(long, value_enum, default_value = "flac")]
+        to: convert::Format,
+        /// Sample rate of the converted files, defaults to the source rate or the closest one
+        /// Opus and MP3 files hold
+        #[clap(long)]
+        rate: Option<usize>,
+        /// 16 or 24, defaults to the source bit depth, lossless formats only
+        #[clap(long)]
+        bits: Option<u32>,
+        /// Folder receiving the converted files, defaults to the source folder
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Check the terminal, audio backends, default device, config and library, printing a
+    /// report to paste into issues. The music directory is checked when given with --path
+    Doctor,
+    /// Add a file or directory to the running player, through its MPD server. Queued after
+    /// the tracks already queued unless told otherwise
+    Add {
+        path: PathBuf,
+        /// Play it after the playing track
+        #[clap(long, conflicts_with_all = ["end", "replace"])]
+        next: bool,
+        /// Queue it after the tracks already queued
+        #[clap(long, conflicts_with = "replace")]
+        end: bool,
+        /// Play it right away in place of the playing track
+        #[clap(long)]
+        replace: bool,
+    },
+    /// Print the events of the running player as JSON, one per line, through its event stream:
+    /// track changes, progress every second, pauses, stops and device changes
+    Subscribe,
+}
 
-    let args = Args::parse();
-    if args.list {
-        let host = Host::new("wasapi", args.high_priority_mode);
-        let devices = host.get_devices()?;
-        let mut index = 0;
-        for device in devices {
-            let capabilities = device.get_capabilities()?;
-            println!(
-                "{} [{}]: {}",
-                if device.is_default()? { "->" } else { "  " },
-                index,
-                device.name()?
-            );
-            if let Some(bitrate) = capabilities.bits_per_samples.last() {
-                println!("    Max bits per sample: {}bits", *bitrate as usize);
-            }
-            if let Some(rate) = capabilities.sample_rates.last() {
-                println!("    Max sample rate: {}Hz", *rate as usize);
-            }
-            index = index + 1;
+fn print_devices(devices: Vec<Device>) -> Result<()> {
+    for (index, device) in devices.into_iter().enumerate() {
+        let capabilities = device.get_capabilities()?;
+        println!(
+            "{} [{}]: {}",
+            if device.is_default()? { "->" } else { "  " },
+            index,
+            device.name()?
+        );
+        if let Some(bitrate) = capabilities.bits_per_samples.last() {
+            println!("    Max bits per sample: {}bits", *bitrate as usize);
         }
-        return Ok(());
+        if let Some(rate) = capabilities.sample_rates.last() {
+            println!("    Max sample rate: {}Hz", *rate as usize);
+        }
+        if let Some(channels) = capabilities.channels.last() {
+            println!("    Max channels: {}", channels);
+        }
+    }
+    Ok(())
+}
+
+fn print_plan(plan: &sync::Plan) {
+    for job in &plan.jobs {
+        let action = match job.action {
+            Action::Copy => "copy",
+            Action::Transcode => "transcode",
+            Action::UpToDate => continue,
+        };
+        println!(
+            "{:<9} {} -> {}",
+            action,
+            job.track.path,
+            job.destination.display()
+        );
+    }
+    for (name, tracks) in &plan.playlists {
+        println!("playlist  {} ({} tracks)", name, tracks.len());
     }
+    println!(
+        "{} to copy, {} to transcode, {} up to date",
+        plan.count(Action::Copy),
+        plan.count(Action::Transcode),
+        plan.count(Action::UpToDate)
+    );
+}
 
+/// Ctrl+C only raises a flag, the screens stop their streams before the terminal is restored.
+fn listen_for_shutdown() -> Arc<AtomicBool> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let requested = shutdown.clone();
     tokio::spawn(async move {
         tokio::signal::ctrl_c()
             .await
             .expect("failed to listen for CTRL+C signal");
-        std::process::exit(0);
+        requested.store(true, Ordering::Relaxed);
     });
+    shutdown
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
 
+    let args = Args::parse();
+    if args.list {
+        let host = Host::new(&args.backend, args.high_priority_mode);
+        print_devices(host.get_devices()?)?;
+        return Ok(());
+    }
+
+    if args.list_inputs {
+        let host = Host::new(&args.backend, args.high_priority_mode);
+        print_devices(host.get_capture_devices()?)?;
+        return Ok(());
+    }
+
+    if let Some(Command::Doctor) = args.command {
+        return doctor::run(&args.backend, args.high_priority_mode, args.path.as_deref());
+    }
+
+    if let Some(export) = args.import {
+        let summary = import::import(&export, &Database::open()?)?;
+        println!(
+            "Imported {} tracks and {} playlists, {} missing files skipped",
+            summary.tracks, summary.playlists, summary.missing
+        );
+        return Ok(());
+    }
+
+    // Written until the end of main
+    let _trace = args.trace.as_deref().map(logger::trace).transpose()?;
+
+    let log_path = if args.safe_mode {
+        let path = logger::init()?;
+        info!("Safe mode: shared mode, default device, no DSP, default config");
+        info!("{:?}", args);
+        Some(path)
+    } else {
+        None
+    };
+
+    let mut config = if args.safe_mode {
+        Config::default()
+    } else {
+        Config::load()?
+    };
+    let overrides = Overrides {
+        resampler_engine: args.resampler,
+        resampler_quality: args.resampler_quality,
+        shared: args.shared,
+    };
+    overrides.apply(&mut config);
+
+    if let Some(Command::Add {
+        path,
+        next,
+        replace,
+        ..
+    }) = &args.command
+    {
+        if !config.mpd.enabled {
+            return Err(anyhow!(
+                "The running player is reached through MPD, turned off by [mpd] enabled"
+            ));
+        }
+        let mode = match (next, replace) {
+            (true, _) => AddMode::Next,
+            (_, true) => AddMode::Replace,
+            _ => AddMode::End,
+        };
+        return mpd::add(&config.mpd.address, config.mpd.port, path, mode).await;
+    }
+
+    if let Some(Command::Subscribe) = args.command {
+        let port = config.events.port.ok_or(anyhow!(
+            "The running player is reached through its event stream, set [events] port"
+        ))?;
+        return events::subscribe(&config.events.address, port).await;
+    }
+
+    if let Some(Command::Convert {
+        to,
+        rate,
+        bits,
+        output,
+        paths,
+    }) = args.command
+    {
+        let target = convert::Target {
+            format: to,
+            sample_rate: rate,
+            bits,
+        };
+        return convert::convert_files(&paths, output.as_deref(), &target, config.resampler);
+    }
+
+    let shutdown = listen_for_shutdown();
+
+    let host = Host::new(&args.backend, args.high_priority_mode);
+    if let Some(record) = args.record {
+        let device = host.create_capture_device(args.input)?;
+        let recorder = Recorder::new(device, args.pollmode)?;
+        let mut terminal = ratatui::init();
+        let result = RecorderScreen::new(recorder, record)
+            .run(&mut terminal, &shutdown)
+            .await;
+        ratatui::restore();
+        return result;
+    }
+
+    let path = args.path.ok_or(anyhow!("No path given"))?;
+
+    if let Some(destination) = args.sync {
+        let plan = sync::plan(
+            &Database::open()?,
+            &path,
+            &destination,
+            &args.sync_playlist,
+            args.transcode,
+        )?;
+        if args.dry_run {
+            print_plan(&plan);
+            return Ok(());
+        }
+        let mut terminal = ratatui::init();
+        let result = SyncScreen::new(DeviceSync::new(plan, config.resampler))
+            .run(&mut terminal, &shutdown)
+            .await;
+        ratatui::restore();
+        return result;
+    }
+    let summary = config.session.summary;
+    let device = if args.safe_mode { None } else { args.device };
+    let background = TaskGroup::default();
+    let player = Player::new(
+        host,
+        device,
+        args.pollmode,
+        config,
+        args.safe_mode,
+        background.clone(),
+    )?;
+    let library = Database::open()?;
+    // Safe mode sticks to the default config
+    let reload = (!args.safe_mode).then_some(overrides);
+    let mut app = App::new(host, player, path, &library, background, reload)?;
     let mut terminal = ratatui::init();
-    let host = Host::new("wasapi", args.high_priority_mode);
-    let player = Player::new(host, args.device, args.pollmode)?;
-    let mut app = App::new(host, player, args.path)?;
-    app.run(&mut terminal).await?;
+    // The app stops playback and its tasks before the terminal is given back
+    let result = app.run(&mut terminal, &shutdown).await;
     ratatui::restore();
-
-    Ok(())
+    if let Err(err) = &result {
+        error!("{:?}", err);
+    }
+    match summary {
+        SummaryOutput::Print => println!("{}", app.session()),
+        SummaryOutput::Log => info!("Session: {}", app.session()),
+        SummaryOutput::Off => (),
+    }
+    if let Some(log_path) = log_path {
+        println!("Safe mode log written to {}", log_path.display());
+    }
+    result
 }
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use serde_json::Value;
+
+use super::{agent, AlbumInfo, MetadataProvider};
+
+/// Discogs release database, needs a personal access token. The year and genre are those of
+/// the master release.
+pub struct Discogs {
+    token: String,
+}
+
+impl Discogs {
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: token.to_string(),
+        }
+    }
+}
+
+impl MetadataProvider for Discogs {
+    fn name(&self) -> &'static str {
+        "Discogs"
+    }
+
+    fn album(&self, artist: &str, album: &str) -> Result<AlbumInfo> {
+        let response = agent()
+            .get("https://api.discogs.com/database/search")
+            .set("Authorization", &format!("Discogs token={}", self.token))
+            .query("type", "master")
+            .query("artist", artist)
+            .query("release_title", album)
+            .query("per_page", "1")
+            .call()?;
+        let json: Value = serde_json::from_reader(response.into_reader())?;
+        let master = &json["results"][0];
+        Ok(AlbumInfo {
+            year: master["year"].as_str().and_then(|year| year.parse().ok()),
+            genre: master["genre"][0].as_str().map(str::to_string),
+            ..Default::default()
+        })
+    }
+}
@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use super::{agent, AlbumInfo, ArtistInfo, MetadataProvider};
+
+/// Album not found, or artist, which is an answer rather than a failure.
+const NOT_FOUND: i64 = 6;
+
+/// Last.fm tags and artist biographies, needs an API key.
+pub struct LastFm {
+    api_key: String,
+}
+
+/// Plain text of a biography, without its markup nor the link back to Last.fm.
+fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => (),
+        }
+    }
+    text.trim()
+        .trim_end_matches("Read more on Last.fm")
+        .trim()
+        .to_string()
+}
+
+fn parse_artist(json: &Value) -> ArtistInfo {
+    let artist = &json["artist"];
+    ArtistInfo {
+        bio: artist["bio"]["summary"]
+            .as_str()
+            .map(plain_text)
+            .filter(|bio| !bio.is_empty()),
+        similar: artist["similar"]["artist"]
+            .as_array()
+            .map(|similar| {
+                similar
+                    .iter()
+                    .filter_map(|artist| artist["name"].as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+impl LastFm {
+    pub fn new(api_key: &str) -> Self {
+        Self {
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// `None` when Last.fm does not know the artist or album.
+    fn call(&self, method: &str, params: &[(&str, &str)]) -> Result<Option<Value>> {
+        let mut request = agent()
+            .get("https://ws.audioscrobbler.com/2.0/")
+            .query("method", method)
+            .query("api_key", &self.api_key)
+            .query("format", "json")
+            .query("autocorrect", "1");
+        for (param, value) in params {
+            request = request.query(param, value);
+        }
+        // Errors are reported in the body, with an error status or not
+        let response = match request.call() {
+            Ok(response) | Err(ureq::Error::Status(_, response)) => response,
+            Err(err) => return Err(err.into()),
+        };
+        let json: Value = serde_json::from_reader(response.into_reader())?;
+        match json["error"].as_i64() {
+            None => Ok(Some(json)),
+            Some(NOT_FOUND) => Ok(None),
+            Some(_) => Err(anyhow!(
+                "{}",
+                json["message"].as_str().unwrap_or("Unknown error")
+            )),
+        }
+    }
+}
+
+impl MetadataProvider for LastFm {
+    fn name(&self) -> &'static str {
+        "Last.fm"
+    }
+
+    fn album(&self, artist: &str, album: &str) -> Result<AlbumInfo> {
+        let genre = self
+            .call("album.getinfo", &[("artist", artist), ("album", album)])?
+            .and_then(|json| {
+                json["album"]["tags"]["tag"][0]["name"]
+                    .as_str()
+                    .map(str::to_string)
+            });
+        let artist_bio = self
+            .call("artist.getinfo", &[("artist", artist)])?
+            .and_then(|json| parse_artist(&json).bio);
+        Ok(AlbumInfo {
+            genre,
+            artist_bio,
+            ..Default::default()
+        })
+    }
+
+    fn artist(&self, artist: &str) -> Result<ArtistInfo> {
+        let Some(json) = self.call("artist.getinfo", &[("artist", artist)])? else {
+            return Ok(ArtistInfo::default());
+        };
+        Ok(parse_artist(&json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn biographies_lose_their_markup_and_link() {
+        let summary = "Radiohead are an English rock band. \
+            <a href=\"https://www.last.fm/music/Radiohead\">Read more on Last.fm</a>";
+        assert_eq!(plain_text(summary), "Radiohead are an English rock band.");
+        assert_eq!(plain_text(" <a href=\"\">Read more on Last.fm</a>"), "");
+    }
+
+    #[test]
+    fn similar_artists_are_read_from_the_artist_info() {
+        let json = serde_json::json!({
+            "artist": {
+                "name": "Radiohead",
+                "similar": {"artist": [{"name": "Thom Yorke"}, {"name": "Muse"}]},
+                "bio": {"summary": "An English rock band."}
+            }
+        });
+        let info = parse_artist(&json);
+        assert_eq!(info.bio.as_deref(), Some("An English rock band."));
+        assert_eq!(info.similar, ["Thom Yorke", "Muse"]);
+    }
+}
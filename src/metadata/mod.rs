@@ -0,0 +1,381 @@
+pub(crate) mod discogs;
+pub(crate) mod lastfm;
+pub(crate) mod musicbrainz;
+
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::audio::thread::unblock;
+use crate::config::MetadataConfig;
+use crate::library::Database;
+use crate::tasks::TaskGroup;
+
+/// Online lookups give up after this long, the popup then shows what was found so far.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the providers know of an album, each field from the first provider which had it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AlbumInfo {
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub artist_bio: Option<String>,
+    /// Providers which answered, in the order they were asked
+    pub sources: Vec<String>,
+}
+
+impl AlbumInfo {
+    fn merge(&mut self, found: AlbumInfo, source: &str) {
+        self.year = self.year.or(found.year);
+        self.genre = self.genre.take().or(found.genre);
+        self.artist_bio = self.artist_bio.take().or(found.artist_bio);
+        self.sources.push(source.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.year.is_none() && self.genre.is_none() && self.artist_bio.is_none()
+    }
+}
+
+/// What the providers know of an artist, the biography of the first provider which had one
+/// and the similar artists of all of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArtistInfo {
+    pub bio: Option<String>,
+    pub similar: Vec<String>,
+    /// Providers which had something, in the order they were asked
+    pub sources: Vec<String>,
+}
+
+impl ArtistInfo {
+    fn merge(&mut self, found: ArtistInfo, source: &str) {
+        if found.is_empty() {
+            return;
+        }
+        self.bio = self.bio.take().or(found.bio);
+        for artist in found.similar {
+            if !self
+                .similar
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(&artist))
+            {
+                self.similar.push(artist);
+            }
+        }
+        self.sources.push(source.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bio.is_none() && self.similar.is_empty()
+    }
+}
+
+/// Online source of album details. Lookups block on the network, they run on the blocking
+/// pool.
+pub trait MetadataProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn album(&self, artist: &str, album: &str) -> Result<AlbumInfo>;
+
+    /// Providers without artist details find nothing.
+    fn artist(&self, _artist: &str) -> Result<ArtistInfo> {
+        Ok(ArtistInfo::default())
+    }
+}
+
+/// Agent shared by the providers, some services reject requests without a user agent.
+pub(crate) fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .user_agent(concat!(
+            "rhap/",
+            env!("CARGO_PKG_VERSION"),
+            " (https://github.com/PapyKahan/rhap)"
+        ))
+        .build()
+}
+
+/// The providers listed in the `[metadata]` config, asked in order. Answers are kept in the
+/// library so each album is looked up once.
+pub struct MetadataProviders {
+    providers: Vec<Box<dyn MetadataProvider>>,
+    library: Database,
+}
+
+impl MetadataProviders {
+    /// Unknown providers and providers missing their key are reported and left out.
+    pub fn new(config: &MetadataConfig, library: Database) -> Self {
+        let mut providers: Vec<Box<dyn MetadataProvider>> = Vec::new();
+        for name in &config.providers {
+            match name.to_lowercase().as_str() {
+                "musicbrainz" => providers.push(Box::new(musicbrainz::MusicBrainz)),
+                "lastfm" => match &config.lastfm_api_key {
+                    Some(key) => providers.push(Box::new(lastfm::LastFm::new(key))),
+                    None => warn!("The lastfm provider needs a lastfm_api_key"),
+                },
+                "discogs" => match &config.discogs_token {
+                    Some(token) => providers.push(Box::new(discogs::Discogs::new(token))),
+                    None => warn!("The discogs provider needs a discogs_token"),
+                },
+                _ => warn!("Unknown metadata provider {}", name),
+            }
+        }
+        Self::with_providers(providers, library)
+    }
+
+    fn with_providers(providers: Vec<Box<dyn MetadataProvider>>, library: Database) -> Self {
+        Self { providers, library }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.providers.is_empty()
+    }
+
+    /// The stored info, else what the providers find. A provider failing, e.g. offline, is
+    /// skipped, nothing is stored when none of them answered so the album is looked up again
+    /// next time.
+    pub fn album(&self, artist: &str, album: &str) -> Result<AlbumInfo> {
+        if let Some(info) = self.library.album_info(artist, album)? {
+            return Ok(info);
+        }
+        let mut info = AlbumInfo::default();
+        for provider in &self.providers {
+            match provider.album(artist, album) {
+                Ok(found) => info.merge(found, provider.name()),
+                Err(err) => warn!(
+                    "{} lookup of {} - {} failed: {}",
+                    provider.name(),
+                    artist,
+                    album,
+                    err
+                ),
+            }
+        }
+        if !info.sources.is_empty() {
+            self.library.save_album_info(artist, album, &info)?;
+        }
+        Ok(info)
+    }
+
+    /// The stored info, else what the providers find, stored the same way as albums.
+    pub fn artist(&self, artist: &str) -> Result<ArtistInfo> {
+        if let Some(info) = self.library.artist_info(artist)? {
+            return Ok(info);
+        }
+        let mut info = ArtistInfo::default();
+        let mut answered = false;
+        for provider in &self.providers {
+            match provider.artist(artist) {
+                Ok(found) => {
+                    info.merge(found, provider.name());
+                    answered = true;
+                }
+                Err(err) => warn!("{} lookup of {} failed: {}", provider.name(), artist, err),
+            }
+        }
+        if answered {
+            self.library.save_artist_info(artist, &info)?;
+        }
+        Ok(info)
+    }
+}
+
+/// Album looked up in the background for the album info popup.
+pub struct AlbumLookup {
+    pub artist: String,
+    pub album: String,
+    found: Arc<OnceLock<AlbumInfo>>,
+}
+
+impl AlbumLookup {
+    pub fn start(
+        providers: &Arc<MetadataProviders>,
+        tasks: &TaskGroup,
+        artist: &str,
+        album: &str,
+    ) -> Self {
+        let found = Arc::new(OnceLock::new());
+        if providers.is_enabled() {
+            let (providers, result) = (providers.clone(), found.clone());
+            let (artist, album) = (artist.to_string(), album.to_string());
+            tasks.spawn(format!("Album info {} - {}", artist, album), async move {
+                let info = unblock(move || providers.album(&artist, &album)).await?;
+                let _ = result.set(info);
+                Ok(())
+            });
+        }
+        Self {
+            artist: artist.to_string(),
+            album: album.to_string(),
+            found,
+        }
+    }
+
+    /// `None` while the providers are asked.
+    pub fn found(&self) -> Option<&AlbumInfo> {
+        self.found.get()
+    }
+}
+
+/// Artist looked up in the background for the artist pane.
+pub struct ArtistLookup {
+    pub artist: String,
+    found: Arc<OnceLock<ArtistInfo>>,
+}
+
+impl ArtistLookup {
+    pub fn start(providers: &Arc<MetadataProviders>, tasks: &TaskGroup, artist: &str) -> Self {
+        let found = Arc::new(OnceLock::new());
+        if providers.is_enabled() {
+            let (providers, result) = (providers.clone(), found.clone());
+            let name = artist.to_string();
+            tasks.spawn(format!("Artist info {}", artist), async move {
+                let info = unblock(move || providers.artist(&name)).await?;
+                let _ = result.set(info);
+                Ok(())
+            });
+        }
+        Self {
+            artist: artist.to_string(),
+            found,
+        }
+    }
+
+    /// `None` while the providers are asked.
+    pub fn found(&self) -> Option<&ArtistInfo> {
+        self.found.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Fake {
+        name: &'static str,
+        found: Option<AlbumInfo>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl MetadataProvider for Fake {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn album(&self, _artist: &str, _album: &str) -> Result<AlbumInfo> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            self.found.clone().ok_or(anyhow!("offline"))
+        }
+    }
+
+    struct Similar(&'static str, &'static [&'static str]);
+
+    impl MetadataProvider for Similar {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn album(&self, _artist: &str, _album: &str) -> Result<AlbumInfo> {
+            Ok(AlbumInfo::default())
+        }
+
+        fn artist(&self, _artist: &str) -> Result<ArtistInfo> {
+            Ok(ArtistInfo {
+                similar: self.1.iter().map(|artist| artist.to_string()).collect(),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn library() -> Database {
+        Database::with_db(sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn providers_fill_in_what_the_previous_ones_missed() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let providers = MetadataProviders::with_providers(
+            vec![
+                Box::new(Fake {
+                    name: "Offline",
+                    found: None,
+                    calls: calls.clone(),
+                }),
+                Box::new(Fake {
+                    name: "First",
+                    found: Some(AlbumInfo {
+                        year: Some(1997),
+                        ..Default::default()
+                    }),
+                    calls: calls.clone(),
+                }),
+                Box::new(Fake {
+                    name: "Second",
+                    found: Some(AlbumInfo {
+                        year: Some(2001),
+                        genre: Some("Rock".to_string()),
+                        ..Default::default()
+                    }),
+                    calls: calls.clone(),
+                }),
+            ],
+            library(),
+        );
+
+        let expected = AlbumInfo {
+            year: Some(1997),
+            genre: Some("Rock".to_string()),
+            artist_bio: None,
+            sources: vec!["First".to_string(), "Second".to_string()],
+        };
+        assert_eq!(providers.album("Artist", "Album").unwrap(), expected);
+        // Stored, the providers are not asked again
+        assert_eq!(providers.album("Artist", "Album").unwrap(), expected);
+        assert_eq!(calls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn nothing_is_stored_while_offline() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let providers = MetadataProviders::with_providers(
+            vec![Box::new(Fake {
+                name: "Offline",
+                found: None,
+                calls: calls.clone(),
+            })],
+            library(),
+        );
+
+        assert!(providers.album("Artist", "Album").unwrap().is_empty());
+        assert!(providers.album("Artist", "Album").unwrap().is_empty());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn similar_artists_are_listed_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let providers = MetadataProviders::with_providers(
+            vec![
+                Box::new(Fake {
+                    name: "No artists",
+                    found: Some(AlbumInfo::default()),
+                    calls,
+                }),
+                Box::new(Similar("First", &["Blur", "Pulp"])),
+                Box::new(Similar("Second", &["pulp", "Suede"])),
+            ],
+            library(),
+        );
+
+        let expected = ArtistInfo {
+            bio: None,
+            similar: vec!["Blur".to_string(), "Pulp".to_string(), "Suede".to_string()],
+            sources: vec!["First".to_string(), "Second".to_string()],
+        };
+        assert_eq!(providers.artist("Oasis").unwrap(), expected);
+        assert_eq!(providers.artist("Oasis").unwrap(), expected);
+    }
+}
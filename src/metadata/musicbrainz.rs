@@ -0,0 +1,76 @@
+use anyhow::Result;
+use serde_json::Value;
+
+use super::{agent, AlbumInfo, MetadataProvider};
+
+/// Open music encyclopedia, no key needed. Release years come from the first release of the
+/// album and the genre from its most voted tag.
+pub struct MusicBrainz;
+
+/// Quotes a term of a search query.
+fn phrase(term: &str) -> String {
+    format!("\"{}\"", term.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn parse(json: &Value) -> AlbumInfo {
+    let Some(group) = json["release-groups"].get(0) else {
+        return AlbumInfo::default();
+    };
+    AlbumInfo {
+        year: group["first-release-date"]
+            .as_str()
+            .and_then(|date| date.get(..4)?.parse().ok()),
+        genre: group["tags"].as_array().and_then(|tags| {
+            tags.iter()
+                .max_by_key(|tag| tag["count"].as_i64().unwrap_or(0))
+                .and_then(|tag| tag["name"].as_str())
+                .map(str::to_string)
+        }),
+        ..Default::default()
+    }
+}
+
+impl MetadataProvider for MusicBrainz {
+    fn name(&self) -> &'static str {
+        "MusicBrainz"
+    }
+
+    fn album(&self, artist: &str, album: &str) -> Result<AlbumInfo> {
+        let query = format!(
+            "releasegroup:{} AND artist:{}",
+            phrase(album),
+            phrase(artist)
+        );
+        let response = agent()
+            .get("https://musicbrainz.org/ws/2/release-group/")
+            .query("query", &query)
+            .query("fmt", "json")
+            .query("limit", "1")
+            .call()?;
+        let json: Value = serde_json::from_reader(response.into_reader())?;
+        Ok(parse(&json))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn year_and_most_voted_tag() {
+        let json = serde_json::json!({
+            "release-groups": [{
+                "first-release-date": "1997-05-21",
+                "tags": [{ "count": 2, "name": "art rock" }, { "count": 5, "name": "alternative rock" }]
+            }]
+        });
+        let info = parse(&json);
+
+        assert_eq!(info.year, Some(1997));
+        assert_eq!(info.genre.as_deref(), Some("alternative rock"));
+        assert_eq!(
+            parse(&serde_json::json!({ "release-groups": [] })),
+            AlbumInfo::default()
+        );
+    }
+}
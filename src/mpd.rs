@@ -0,0 +1,851 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+
+use crate::config::local_address;
+use crate::library::Database;
+use crate::musictrack::MusicTrack;
+use crate::player::{CurrentTrackInfo, Playback};
+use crate::queue::Placement;
+use crate::tasks::TaskGroup;
+
+/// Version of the protocol announced to clients, the commands below behave as in MPD 0.23.
+const VERSION: &str = "0.23.0";
+
+const COMMANDS: &[&str] = &[
+    "add",
+    "close",
+    "command_list_begin",
+    "command_list_end",
+    "command_list_ok_begin",
+    "commands",
+    "currentsong",
+    "idle",
+    "next",
+    "noidle",
+    "notcommands",
+    "pause",
+    "ping",
+    "play",
+    "playid",
+    "playlistinfo",
+    "plchanges",
+    "previous",
+    "seek",
+    "seekcur",
+    "seekid",
+    "status",
+    "sticker",
+    "stop",
+];
+
+/// Requests of the clients, run by the app against the playlist.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Plays the track at the given position, or resumes
+    Play(Option<usize>),
+    /// Pauses or resumes, toggles without argument
+    Pause(Option<bool>),
+    Stop,
+    Next,
+    Previous,
+    /// Seconds into the track at the given position, the playing one otherwise
+    Seek(Option<usize>, f64),
+    /// Seconds relative to the current position
+    SeekBy(f64),
+    /// File or directory to add to the playlist
+    Add(String, Placement),
+}
+
+/// Counters bumped on each change, waited on by idle clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Changes {
+    player: u64,
+    playlist: u64,
+    sticker: u64,
+    options: u64,
+}
+
+/// Playback modes of the playlist, in MPD terms where repeating one track is repeat with single.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Modes {
+    pub repeat: bool,
+    pub single: bool,
+    pub consume: bool,
+}
+
+#[derive(Default)]
+struct State {
+    songs: Vec<Arc<MusicTrack>>,
+    playback: Playback,
+    current: Option<(usize, CurrentTrackInfo)>,
+    modes: Modes,
+}
+
+/// Error sent back to a client, with the code of the MPD protocol.
+struct Ack {
+    code: u32,
+    message: String,
+}
+
+const ACK_ERROR_ARG: u32 = 2;
+const ACK_ERROR_UNKNOWN: u32 = 5;
+const ACK_ERROR_NO_EXIST: u32 = 50;
+const ACK_ERROR_SYSTEM: u32 = 52;
+
+/// The only sticker, the bookmarked position of songs in seconds.
+const RESUME_STICKER: &str = "resume";
+
+impl Ack {
+    fn arg(message: impl Into<String>) -> Self {
+        Self {
+            code: ACK_ERROR_ARG,
+            message: message.into(),
+        }
+    }
+
+    fn no_sticker() -> Self {
+        Self {
+            code: ACK_ERROR_NO_EXIST,
+            message: "no such sticker".to_string(),
+        }
+    }
+
+    fn system(err: anyhow::Error) -> Self {
+        Self {
+            code: ACK_ERROR_SYSTEM,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Splits a command line into its words, arguments may be double quoted with backslash
+/// escapes.
+fn split(line: &str) -> Result<Vec<String>, Ack> {
+    let mut words = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => continue,
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err(Ack::arg("Missing closing '\"'")),
+                    }
+                }
+                words.push(word);
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+                words.push(word);
+            }
+        }
+    }
+    Ok(words)
+}
+
+fn integer<T: std::str::FromStr>(arg: &str) -> Result<T, Ack> {
+    arg.parse()
+        .map_err(|_| Ack::arg(format!("Integer expected: {}", arg)))
+}
+
+fn position(arg: Option<&String>) -> Result<Option<usize>, Ack> {
+    arg.map(|arg| integer(arg)).transpose()
+}
+
+fn seconds(arg: Option<&String>) -> Result<f64, Ack> {
+    let arg = arg.ok_or(Ack::arg("Missing argument"))?;
+    arg.parse()
+        .map_err(|_| Ack::arg(format!("Number expected: {}", arg)))
+}
+
+fn song(response: &mut String, position: usize, song: &MusicTrack) {
+    let duration = song.duration_seconds();
+    let _ = write!(
+        response,
+        "file: {}\nTitle: {}\nArtist: {}\nAlbum: {}\nTime: {}\nduration: {:.3}\nPos: {}\nId: {}\n",
+        song.path,
+        song.title,
+        song.artist,
+        song.album,
+        duration.round() as u64,
+        duration,
+        position,
+        position
+    );
+}
+
+/// Runs the sticker commands against the bookmarks of the library, the ones the app resumes
+/// tracks from.
+fn sticker(database: &Database, args: &[String]) -> Result<String, Ack> {
+    let [action, kind, uri, rest @ ..] = args else {
+        return Err(Ack::arg("Missing argument"));
+    };
+    if kind != "song" {
+        return Err(Ack::arg(format!("Unknown sticker type: {}", kind)));
+    }
+    let name = rest.first().map(String::as_str);
+    let path = Path::new(uri);
+    let mut response = String::new();
+    match action.as_str() {
+        "get" => {
+            let name = name.ok_or(Ack::arg("Missing argument"))?;
+            let position = database.bookmark(path).map_err(Ack::system)?;
+            match position.filter(|_| name == RESUME_STICKER) {
+                Some(position) => {
+                    let _ = writeln!(response, "sticker: {}={:.3}", RESUME_STICKER, position);
+                }
+                None => return Err(Ack::no_sticker()),
+            }
+        }
+        "set" => {
+            if name.ok_or(Ack::arg("Missing argument"))? != RESUME_STICKER {
+                return Err(Ack::arg(format!(
+                    "Only the {} sticker is stored",
+                    RESUME_STICKER
+                )));
+            }
+            let position = seconds(rest.get(1))?;
+            database
+                .set_bookmark(path, Some(position.max(0.0)))
+                .map_err(Ack::system)?;
+        }
+        "delete" => {
+            let position = database.bookmark(path).map_err(Ack::system)?;
+            if position.is_none() || name.is_some_and(|name| name != RESUME_STICKER) {
+                return Err(Ack::no_sticker());
+            }
+            database.set_bookmark(path, None).map_err(Ack::system)?;
+        }
+        "list" => {
+            if let Some(position) = database.bookmark(path).map_err(Ack::system)? {
+                let _ = writeln!(response, "sticker: {}={:.3}", RESUME_STICKER, position);
+            }
+        }
+        "find" => {
+            if name.ok_or(Ack::arg("Missing argument"))? == RESUME_STICKER {
+                let bookmarks = database.bookmarks().map_err(Ack::system)?;
+                for (file, position) in bookmarks {
+                    if Path::new(&file).starts_with(path) {
+                        let _ = write!(
+                            response,
+                            "file: {}\nsticker: {}={:.3}\n",
+                            file, RESUME_STICKER, position
+                        );
+                    }
+                }
+            }
+        }
+        action => return Err(Ack::arg(format!("Unknown sticker command: {}", action))),
+    }
+    Ok(response)
+}
+
+/// Subsystems changed between `seen` and `now`, among the `wanted` ones or all of them.
+fn changed(seen: Changes, now: Changes, wanted: &[String]) -> Vec<&'static str> {
+    let wants = |name: &str| wanted.is_empty() || wanted.iter().any(|wanted| wanted == name);
+    let mut changed = Vec::new();
+    if now.playlist != seen.playlist && wants("playlist") {
+        changed.push("playlist");
+    }
+    if now.player != seen.player && wants("player") {
+        changed.push("player");
+    }
+    if now.sticker != seen.sticker && wants("sticker") {
+        changed.push("sticker");
+    }
+    if now.options != seen.options && wants("options") {
+        changed.push("options");
+    }
+    changed
+}
+
+/// One client, served until it closes the connection.
+struct Client {
+    state: Arc<Mutex<State>>,
+    changes: watch::Receiver<Changes>,
+    commands: UnboundedSender<Command>,
+    database: Database,
+}
+
+impl Client {
+    fn send(&self, command: Command) {
+        // The receiver lives as long as the app
+        let _ = self.commands.send(command);
+    }
+
+    fn length(&self) -> usize {
+        self.state.lock().map_or(0, |state| state.songs.len())
+    }
+
+    fn in_range(&self, position: Option<usize>) -> Result<Option<usize>, Ack> {
+        match position {
+            Some(position) if position >= self.length() => {
+                Err(Ack::arg(format!("Bad song index: {}", position)))
+            }
+            position => Ok(position),
+        }
+    }
+
+    /// Runs a command other than idle and the command lists, returning its response.
+    fn execute(&self, name: &str, args: &[String]) -> Result<String, Ack> {
+        let mut response = String::new();
+        match name {
+            "ping" => (),
+            "commands" => {
+                for command in COMMANDS {
+                    let _ = writeln!(response, "command: {}", command);
+                }
+            }
+            "notcommands" => (),
+            "status" => {
+                let state = self.state.lock().map_err(|_| Ack::arg("Unavailable"))?;
+                let playback = match state.playback {
+                    Playback::Playing => "play",
+                    Playback::Paused => "pause",
+                    Playback::Stopped => "stop",
+                };
+                let _ = write!(
+                    response,
+                    "repeat: {}\nrandom: 0\nsingle: {}\nconsume: {}\nplaylist: {}\nplaylistlength: {}\nstate: {}\n",
+                    state.modes.repeat as u8,
+                    state.modes.single as u8,
+                    state.modes.consume as u8,
+                    self.changes.borrow().playlist,
+                    state.songs.len(),
+                    playback
+                );
+                if let Some((index, info)) = &state.current {
+                    let elapsed = info.elapsed_seconds().max(0.0);
+                    let duration = info.duration_seconds();
+                    let _ = write!(
+                        response,
+                        "song: {}\nsongid: {}\ntime: {}:{}\nelapsed: {:.3}\nduration: {:.3}\n",
+                        index,
+                        index,
+                        elapsed as u64,
+                        duration.round() as u64,
+                        elapsed,
+                        duration
+                    );
+                }
+            }
+            "currentsong" => {
+                let state = self.state.lock().map_err(|_| Ack::arg("Unavailable"))?;
+                if let Some((index, _)) = &state.current {
+                    if let Some(track) = state.songs.get(*index) {
+                        song(&mut response, *index, track);
+                    }
+                }
+            }
+            "playlistinfo" | "plchanges" => {
+                let state = self.state.lock().map_err(|_| Ack::arg("Unavailable"))?;
+                let range = match (name, args.first()) {
+                    // Changes are not tracked per song, any change lists them all
+                    ("plchanges", Some(version)) => {
+                        if integer::<u64>(version)? == self.changes.borrow().playlist {
+                            0..0
+                        } else {
+                            0..state.songs.len()
+                        }
+                    }
+                    ("plchanges", None) => return Err(Ack::arg("Missing argument")),
+                    (_, Some(range)) => {
+                        let start;
+                        let end = match range.split_once(':') {
+                            Some((first, "")) => {
+                                start = integer(first)?;
+                                state.songs.len()
+                            }
+                            Some((first, last)) => {
+                                start = integer(first)?;
+                                integer(last)?
+                            }
+                            None => {
+                                start = integer(range)?;
+                                start + 1
+                            }
+                        };
+                        if start >= state.songs.len() {
+                            return Err(Ack::arg(format!("Bad song index: {}", start)));
+                        }
+                        start..end.min(state.songs.len())
+                    }
+                    (_, None) => 0..state.songs.len(),
+                };
+                for index in range {
+                    song(&mut response, index, &state.songs[index]);
+                }
+            }
+            // Only the relative position right after the playing track is known, played next
+            "add" => {
+                let uri = args.first().ok_or(Ack::arg("Missing argument"))?;
+                if !Path::new(uri).exists() {
+                    return Err(Ack {
+                        code: ACK_ERROR_NO_EXIST,
+                        message: format!("No such file or directory: {}", uri),
+                    });
+                }
+                let placement = match args.get(1).map(String::as_str) {
+                    None => Placement::End,
+                    Some("+0") => Placement::Next,
+                    Some(position) => match integer(position)? {
+                        position if position > self.length() => {
+                            return Err(Ack::arg(format!("Bad song index: {}", position)))
+                        }
+                        position => Placement::At(position),
+                    },
+                };
+                self.send(Command::Add(uri.clone(), placement));
+            }
+            "play" | "playid" => {
+                let position = self.in_range(position(args.first())?)?;
+                self.send(Command::Play(position));
+            }
+            "pause" => {
+                let pause = match args.first().map(String::as_str) {
+                    None => None,
+                    Some("1") => Some(true),
+                    Some("0") => Some(false),
+                    Some(arg) => return Err(Ack::arg(format!("Boolean expected: {}", arg))),
+                };
+                self.send(Command::Pause(pause));
+            }
+            "sticker" => response = sticker(&self.database, args)?,
+            "stop" => self.send(Command::Stop),
+            "next" => self.send(Command::Next),
+            "previous" => self.send(Command::Previous),
+            "seek" | "seekid" => {
+                let position = self.in_range(position(args.first())?)?;
+                self.send(Command::Seek(position, seconds(args.get(1))?));
+            }
+            "seekcur" => {
+                let time = seconds(args.first())?;
+                let relative = args
+                    .first()
+                    .is_some_and(|arg| arg.starts_with('+') || arg.starts_with('-'));
+                self.send(if relative {
+                    Command::SeekBy(time)
+                } else {
+                    Command::Seek(None, time)
+                });
+            }
+            name => {
+                return Err(Ack {
+                    code: ACK_ERROR_UNKNOWN,
+                    message: format!("unknown command \"{}\"", name),
+                })
+            }
+        }
+        Ok(response)
+    }
+
+    async fn serve(mut self, stream: TcpStream) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer
+            .write_all(format!("OK MPD {}\n", VERSION).as_bytes())
+            .await?;
+        let mut seen = *self.changes.borrow_and_update();
+        // Commands of the list being received, and whether each one is acknowledged
+        let mut list: Option<(Vec<String>, bool)> = None;
+        while let Some(line) = lines.next_line().await? {
+            let words = match split(&line) {
+                Ok(words) => words,
+                Err(ack) => {
+                    let reply = format!("ACK [{}@0] {{}} {}\n", ack.code, ack.message);
+                    writer.write_all(reply.as_bytes()).await?;
+                    continue;
+                }
+            };
+            let Some((name, args)) = words.split_first() else {
+                continue;
+            };
+            let name = name.to_lowercase();
+            if let Some((commands, _)) = list.as_mut().filter(|_| name != "command_list_end") {
+                commands.push(line);
+                continue;
+            }
+            let commands = match (list.take(), name.as_str()) {
+                (Some((commands, acknowledge)), _) => Some((commands, acknowledge, true)),
+                (None, "command_list_begin") => {
+                    list = Some((Vec::new(), false));
+                    continue;
+                }
+                (None, "command_list_ok_begin") => {
+                    list = Some((Vec::new(), true));
+                    continue;
+                }
+                (None, "close") => return Ok(()),
+                (None, "idle") => {
+                    seen = match self.idle(seen, args, &mut lines, &mut writer).await? {
+                        Some(seen) => seen,
+                        None => return Ok(()),
+                    };
+                    continue;
+                }
+                (None, "noidle") => continue,
+                (None, _) => Some((vec![line], false, false)),
+            };
+            let Some((commands, acknowledge, is_list)) = commands else {
+                continue;
+            };
+            let mut reply = String::new();
+            let mut failed = false;
+            for (index, line) in commands.iter().enumerate() {
+                let words = split(line).unwrap_or_default();
+                let Some((name, args)) = words.split_first() else {
+                    continue;
+                };
+                match self.execute(&name.to_lowercase(), args) {
+                    Ok(response) => {
+                        reply.push_str(&response);
+                        if acknowledge {
+                            reply.push_str("list_OK\n");
+                        }
+                    }
+                    Err(ack) => {
+                        let _ = writeln!(
+                            reply,
+                            "ACK [{}@{}] {{{}}} {}",
+                            ack.code,
+                            if is_list { index } else { 0 },
+                            name,
+                            ack.message
+                        );
+                        failed = true;
+                        break;
+                    }
+                }
+            }
+            if !failed {
+                reply.push_str("OK\n");
+            }
+            writer.write_all(reply.as_bytes()).await?;
+        }
+        Ok(())
+    }
+
+    /// Waits for a change of the `wanted` subsystems, or noidle. Returns the changes reported,
+    /// none once the client is gone.
+    async fn idle(
+        &mut self,
+        mut seen: Changes,
+        wanted: &[String],
+        lines: &mut tokio::io::Lines<BufReader<tokio::net::tcp::OwnedReadHalf>>,
+        writer: &mut tokio::net::tcp::OwnedWriteHalf,
+    ) -> Result<Option<Changes>> {
+        loop {
+            let now = *self.changes.borrow_and_update();
+            let changed = changed(seen, now, wanted);
+            if !changed.is_empty() {
+                let mut reply = String::new();
+                for subsystem in &changed {
+                    let _ = writeln!(reply, "changed: {}", subsystem);
+                    match *subsystem {
+                        "player" => seen.player = now.player,
+                        "sticker" => seen.sticker = now.sticker,
+                        "options" => seen.options = now.options,
+                        _ => seen.playlist = now.playlist,
+                    }
+                }
+                reply.push_str("OK\n");
+                writer.write_all(reply.as_bytes()).await?;
+                return Ok(Some(seen));
+            }
+            tokio::select! {
+                changed = self.changes.changed() => {
+                    if changed.is_err() {
+                        return Ok(None);
+                    }
+                }
+                line = lines.next_line() => match line? {
+                    Some(line) if line.trim() == "noidle" => {
+                        writer.write_all(b"OK\n").await?;
+                        return Ok(Some(seen));
+                    }
+                    // Only noidle is allowed while idle
+                    _ => return Ok(None),
+                },
+            }
+        }
+    }
+}
+
+/// Server of a subset of the MPD protocol, letting MPD clients browse the playlist and control
+/// playback.
+pub struct Mpd {
+    state: Arc<Mutex<State>>,
+    changes: watch::Sender<Changes>,
+    commands: UnboundedReceiver<Command>,
+}
+
+impl Mpd {
+    pub async fn start(
+        address: &str,
+        port: u16,
+        database: Database,
+        tasks: &TaskGroup,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind((address, port)).await?;
+        info!("MPD server listening on {}", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(State::default()));
+        let (changes, _) = watch::channel(Changes::default());
+        let (sender, commands) = mpsc::unbounded_channel();
+        let shared = state.clone();
+        let watcher = changes.clone();
+        let group = tasks.clone();
+        let mut bookmarks = database.watch_bookmarks();
+        let stickers = changes.clone();
+        tasks.spawn("MPD stickers".to_string(), async move {
+            // Bookmarks are set by the app as well as by the clients
+            while bookmarks.next().await.is_some() {
+                stickers.send_modify(|changes| changes.sticker += 1);
+            }
+            Ok(())
+        });
+        tasks.spawn("MPD server".to_string(), async move {
+            loop {
+                let (stream, peer) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        warn!("Cannot accept an MPD client: {}", err);
+                        continue;
+                    }
+                };
+                let client = Client {
+                    state: shared.clone(),
+                    changes: watcher.subscribe(),
+                    commands: sender.clone(),
+                    database: database.clone(),
+                };
+                group.spawn(format!("MPD client {}", peer), async move {
+                    if let Err(err) = client.serve(stream).await {
+                        warn!("MPD client {} disconnected: {}", peer, err);
+                    }
+                    Ok(())
+                });
+            }
+        });
+        Ok(Self {
+            state,
+            changes,
+            commands,
+        })
+    }
+
+    /// Next request received from the clients, if any.
+    pub fn command(&mut self) -> Option<Command> {
+        self.commands.try_recv().ok()
+    }
+
+    /// Reports the playlist, the playing track and the modes, waking the idle clients on changes.
+    pub fn update(
+        &self,
+        songs: &[Arc<MusicTrack>],
+        playback: Playback,
+        playing: Option<(usize, &CurrentTrackInfo)>,
+        modes: Modes,
+    ) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let playlist_changed = state.songs.len() != songs.len()
+            || state
+                .songs
+                .iter()
+                .zip(songs)
+                .any(|(shown, song)| !Arc::ptr_eq(shown, song));
+        let player_changed = state.playback != playback
+            || state.current.as_ref().map(|(index, _)| *index) != playing.map(|(index, _)| index);
+        let options_changed = state.modes != modes;
+        if playlist_changed {
+            state.songs = songs.to_vec();
+        }
+        state.playback = playback;
+        state.current = playing.map(|(index, info)| (index, info.clone()));
+        state.modes = modes;
+        drop(state);
+        if playlist_changed || player_changed || options_changed {
+            self.changes.send_modify(|changes| {
+                changes.playlist += playlist_changed as u64;
+                changes.player += player_changed as u64;
+                changes.options += options_changed as u64;
+            });
+        }
+    }
+
+    /// Tells idle clients the position jumped.
+    pub fn seeked(&self) {
+        self.changes.send_modify(|changes| changes.player += 1);
+    }
+}
+
+/// Where `rhap add` puts tracks in the running player.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddMode {
+    /// Queued to play after the playing track
+    Next,
+    /// Queued after the tracks already queued
+    End,
+    /// Played right away in place of the playing track
+    Replace,
+}
+
+/// Quotes an argument the way `split` reads it back.
+fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Connection of `rhap add` to the server of the running player.
+struct Remote {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Remote {
+    async fn connect(address: &str, port: u16) -> Result<Self> {
+        let stream = TcpStream::connect((address, port))
+            .await
+            .map_err(|err| anyhow!("No player listening on {}:{}: {}", address, port, err))?;
+        let (reader, writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let greeting = lines.next_line().await?.unwrap_or_default();
+        if !greeting.starts_with("OK MPD") {
+            return Err(anyhow!("No MPD server on {}:{}", address, port));
+        }
+        Ok(Self { lines, writer })
+    }
+
+    /// Sends a command and waits for it to be acknowledged.
+    async fn run(&mut self, command: &str) -> Result<()> {
+        self.writer
+            .write_all(format!("{}\n", command).as_bytes())
+            .await?;
+        loop {
+            let line = self
+                .lines
+                .next_line()
+                .await?
+                .ok_or(anyhow!("The player closed the connection"))?;
+            if line == "OK" {
+                return Ok(());
+            }
+            if let Some(ack) = line.strip_prefix("ACK ") {
+                return Err(anyhow!("{}", ack));
+            }
+        }
+    }
+}
+
+/// Hands a file or directory to the player running the MPD server at `address` and `port`.
+/// The path is made absolute, the player runs from another directory.
+pub async fn add(address: &str, port: u16, path: &Path, mode: AddMode) -> Result<()> {
+    let path = std::path::absolute(path)?;
+    let path = path
+        .to_str()
+        .ok_or(anyhow!("Invalid path {}", path.display()))?;
+    let mut remote = Remote::connect(local_address(address), port).await?;
+    match mode {
+        AddMode::End => remote.run(&format!("add {}", quote(path))).await?,
+        AddMode::Next => remote.run(&format!("add {} +0", quote(path))).await?,
+        // Queued first, the tracks are what comes next
+        AddMode::Replace => {
+            remote.run(&format!("add {} +0", quote(path))).await?;
+            remote.run("next").await?;
+        }
+    }
+    remote.writer.write_all(b"close\n").await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_arguments() {
+        assert_eq!(
+            split(r#"seek "3" 12.5"#).ok(),
+            Some(vec![
+                "seek".to_string(),
+                "3".to_string(),
+                "12.5".to_string()
+            ])
+        );
+        assert_eq!(
+            split(r#"find "title" "say \"hi\"""#).ok(),
+            Some(vec![
+                "find".to_string(),
+                "title".to_string(),
+                "say \"hi\"".to_string()
+            ])
+        );
+        assert!(split(r#"play "1"#).is_err());
+    }
+
+    #[test]
+    fn quoted_paths_read_back() {
+        let path = r#"C:\Music\"Live" at \\ 1970.flac"#;
+        assert_eq!(
+            split(&format!("add {} +0", quote(path))).ok(),
+            Some(vec!["add".to_string(), path.to_string(), "+0".to_string()])
+        );
+    }
+
+    #[test]
+    fn idle_reports_wanted_changes_only() {
+        let seen = Changes::default();
+        let now = Changes {
+            player: 1,
+            playlist: 2,
+            ..Default::default()
+        };
+        assert_eq!(changed(seen, now, &[]), vec!["playlist", "player"]);
+        assert_eq!(changed(seen, now, &["player".to_string()]), vec!["player"]);
+        assert!(changed(now, now, &[]).is_empty());
+        let toggled = Changes {
+            options: 1,
+            ..Default::default()
+        };
+        assert_eq!(changed(seen, toggled, &[]), vec!["options"]);
+        assert!(changed(seen, toggled, &["player".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn resume_positions_are_stickers() {
+        let database =
+            Database::with_db(sled::Config::new().temporary(true).open().unwrap()).unwrap();
+        let run = |line: &str| sticker(&database, &split(line).ok().unwrap()[1..]);
+        assert!(run(r#"sticker set song "/music/mix.flac" resume 1834.5"#).is_ok());
+        assert_eq!(
+            run(r#"sticker get song "/music/mix.flac" resume"#).ok(),
+            Some("sticker: resume=1834.500\n".to_string())
+        );
+        assert_eq!(
+            run(r#"sticker find song "/music" resume"#).ok(),
+            Some("file: /music/mix.flac\nsticker: resume=1834.500\n".to_string())
+        );
+        assert!(run(r#"sticker delete song "/music/mix.flac""#).is_ok());
+        assert_eq!(
+            run(r#"sticker get song "/music/mix.flac" resume"#)
+                .err()
+                .map(|ack| ack.code),
+            Some(ACK_ERROR_NO_EXIST)
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use zbus::object_server::{InterfaceRef, SignalEmitter};
+use zbus::zvariant::{ObjectPath, OwnedValue, Value};
+use zbus::{interface, Connection};
+
+use crate::musictrack::MusicTrack;
+use crate::player::{CurrentTrackInfo, Playback};
+
+const BUS_NAME: &str = "org.mpris.MediaPlayer2.rhap";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+/// Track ids are object paths, tracks of the playlist are numbered after their index.
+const TRACK_PATH: &str = "/org/rhap/track";
+const NO_TRACK_PATH: &str = "/org/mpris/MediaPlayer2/TrackList/NoTrack";
+
+/// Requests of desktop applets and media keys, run by the app against the playlist.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Play,
+    Pause,
+    PlayPause,
+    Stop,
+    Next,
+    Previous,
+    /// Seconds relative to the current position
+    Seek(f64),
+    /// Seconds from the start of the track, ignored unless the track id is still current
+    SetPosition(String, f64),
+}
+
+fn status(playback: Playback) -> &'static str {
+    match playback {
+        Playback::Playing => "Playing",
+        Playback::Paused => "Paused",
+        Playback::Stopped => "Stopped",
+    }
+}
+
+/// What the app reports of the playlist, compared on each update to signal changes.
+#[derive(Clone, Default, PartialEq)]
+struct Track {
+    id: String,
+    title: String,
+    artist: String,
+    album: String,
+    path: String,
+    /// In microseconds, as every time of the interface
+    length: i64,
+}
+
+#[derive(Default)]
+struct State {
+    playback: Playback,
+    track: Option<Track>,
+    progress: Option<CurrentTrackInfo>,
+}
+
+fn microseconds(seconds: f64) -> i64 {
+    (seconds * 1_000_000.0) as i64
+}
+
+fn seconds(microseconds: i64) -> f64 {
+    microseconds as f64 / 1_000_000.0
+}
+
+struct RootInterface;
+
+#[interface(name = "org.mpris.MediaPlayer2")]
+impl RootInterface {
+    fn raise(&self) {}
+
+    fn quit(&self) {}
+
+    #[zbus(property)]
+    fn can_quit(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn can_raise(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn has_track_list(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn identity(&self) -> &str {
+        "rhap"
+    }
+
+    #[zbus(property)]
+    fn supported_uri_schemes(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[zbus(property)]
+    fn supported_mime_types(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+struct PlayerInterface {
+    state: Arc<Mutex<State>>,
+    commands: UnboundedSender<Command>,
+}
+
+impl PlayerInterface {
+    fn send(&self, command: Command) {
+        // The receiver lives as long as the app
+        let _ = self.commands.send(command);
+    }
+}
+
+#[interface(name = "org.mpris.MediaPlayer2.Player")]
+impl PlayerInterface {
+    fn next(&self) {
+        self.send(Command::Next);
+    }
+
+    fn previous(&self) {
+        self.send(Command::Previous);
+    }
+
+    fn pause(&self) {
+        self.send(Command::Pause);
+    }
+
+    fn play_pause(&self) {
+        self.send(Command::PlayPause);
+    }
+
+    fn stop(&self) {
+        self.send(Command::Stop);
+    }
+
+    fn play(&self) {
+        self.send(Command::Play);
+    }
+
+    fn seek(&self, offset: i64) {
+        self.send(Command::Seek(seconds(offset)));
+    }
+
+    fn set_position(&self, track_id: ObjectPath<'_>, position: i64) {
+        self.send(Command::SetPosition(
+            track_id.to_string(),
+            seconds(position),
+        ));
+    }
+
+    fn open_uri(&self, _uri: &str) {}
+
+    #[zbus(signal)]
+    async fn seeked(emitter: &SignalEmitter<'_>, position: i64) -> zbus::Result<()>;
+
+    #[zbus(property)]
+    fn playback_status(&self) -> &str {
+        self.state
+            .lock()
+            .map_or("Stopped", |state| status(state.playback))
+    }
+
+    #[zbus(property)]
+    fn metadata(&self) -> HashMap<String, OwnedValue> {
+        let state = self.state.lock().ok();
+        let track = state.as_ref().and_then(|state| state.track.as_ref());
+        let mut metadata: HashMap<&str, Value> = HashMap::new();
+        match track {
+            Some(track) => {
+                if let Ok(id) = ObjectPath::try_from(track.id.as_str()) {
+                    metadata.insert("mpris:trackid", Value::from(id));
+                }
+                metadata.insert("mpris:length", Value::from(track.length));
+                metadata.insert("xesam:title", Value::from(track.title.as_str()));
+                metadata.insert("xesam:artist", Value::from(vec![track.artist.as_str()]));
+                metadata.insert("xesam:album", Value::from(track.album.as_str()));
+                if let Ok(path) = std::path::absolute(Path::new(&track.path)) {
+                    let url = format!("file://{}", path.to_string_lossy());
+                    metadata.insert("xesam:url", Value::from(url));
+                }
+            }
+            None => {
+                let id = ObjectPath::from_static_str_unchecked(NO_TRACK_PATH);
+                metadata.insert("mpris:trackid", Value::from(id));
+            }
+        }
+        metadata
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.to_string(), value.try_into().ok()?)))
+            .collect()
+    }
+
+    #[zbus(property)]
+    fn position(&self) -> i64 {
+        self.state
+            .lock()
+            .ok()
+            .and_then(|state| state.progress.as_ref().map(|info| info.elapsed_seconds()))
+            .map_or(0, |elapsed| microseconds(elapsed.max(0.0)))
+    }
+
+    #[zbus(property)]
+    fn rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn minimum_rate(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn maximum_rate(&self) -> f64 {
+        1.0
+    }
+
+    /// The volume of rhap is in dB steps, not controlled from the bus.
+    #[zbus(property)]
+    fn volume(&self) -> f64 {
+        1.0
+    }
+
+    #[zbus(property)]
+    fn can_go_next(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_go_previous(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[zbus(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+}
+
+/// `org.mpris.MediaPlayer2` service on the session bus, controlling rhap from desktop applets
+/// and media keys.
+pub struct Mpris {
+    _connection: Connection,
+    player: InterfaceRef<PlayerInterface>,
+    state: Arc<Mutex<State>>,
+    commands: UnboundedReceiver<Command>,
+}
+
+impl Mpris {
+    pub async fn start() -> Result<Self> {
+        let state = Arc::new(Mutex::new(State::default()));
+        let (sender, commands) = mpsc::unbounded_channel();
+        let player = PlayerInterface {
+            state: state.clone(),
+            commands: sender,
+        };
+        let connection = zbus::connection::Builder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, RootInterface)?
+            .serve_at(OBJECT_PATH, player)?
+            .build()
+            .await?;
+        let player = connection
+            .object_server()
+            .interface::<_, PlayerInterface>(OBJECT_PATH)
+            .await?;
+        Ok(Self {
+            _connection: connection,
+            player,
+            state,
+            commands,
+        })
+    }
+
+    /// Next request received from the bus, if any.
+    pub fn command(&mut self) -> Option<Command> {
+        self.commands.try_recv().ok()
+    }
+
+    /// The object path of the track at `index` of the playlist.
+    pub fn track_id(index: usize) -> String {
+        format!("{}/{}", TRACK_PATH, index)
+    }
+
+    /// Reports the playing track, signalling the properties that changed.
+    pub async fn update(
+        &self,
+        playback: Playback,
+        playing: Option<(usize, &MusicTrack, &CurrentTrackInfo)>,
+    ) -> Result<()> {
+        let track = playing.map(|(index, song, info)| Track {
+            id: Self::track_id(index),
+            title: song.title.clone(),
+            artist: song.artist.clone(),
+            album: song.album.clone(),
+            path: song.path.clone(),
+            length: microseconds(info.duration_seconds()),
+        });
+        let (playback_changed, track_changed) = {
+            let Ok(mut state) = self.state.lock() else {
+                return Ok(());
+            };
+            let changes = (state.playback != playback, state.track != track);
+            state.playback = playback;
+            state.track = track;
+            state.progress = playing.map(|(_, _, info)| info.clone());
+            changes
+        };
+        let emitter = self.player.signal_emitter();
+        let player = self.player.get().await;
+        if playback_changed {
+            player.playback_status_changed(emitter).await?;
+        }
+        if track_changed {
+            player.metadata_changed(emitter).await?;
+        }
+        Ok(())
+    }
+
+    /// Tells clients the position jumped, they extrapolate it otherwise.
+    pub async fn seeked(&self, position: f64) -> Result<()> {
+        PlayerInterface::seeked(
+            self.player.signal_emitter(),
+            microseconds(position.max(0.0)),
+        )
+        .await?;
+        Ok(())
+    }
+}
@@ -1,90 +1,334 @@
-use anyhow::Result;
-use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use std::path::Path;
 use symphonia::core::{
     audio::Layout,
     codecs::{Decoder, DecoderOptions},
-    formats::FormatReader,
+    formats::{FormatOptions, FormatReader, Track},
     io::MediaSourceStream,
-    meta::{MetadataRevision, StandardTagKey},
-    probe::Hint,
+    meta::{MetadataRevision, StandardTagKey, Tag},
+    probe::{Hint, ProbedMetadata},
     units::Time,
 };
-use tokio::sync::Mutex;
 
-use crate::audio::{BitsPerSample, SampleRate};
+use crate::audio::{BitsPerSample, Capabilities, SampleRate};
+use crate::cue::Segment;
+use crate::dsd::{self, dop::DopDecoder, DsdReader, CODEC_TYPE_DSD, DSD64_RATE};
+use crate::radio::{self, HttpSource};
 
+/// File extensions picked up when scanning a directory, matched case insensitively.
+pub const SUPPORTED_EXTENSIONS: [&str; 9] = [
+    "flac", "mp3", "ogg", "m4a", "wav", "aiff", "aif", "dsf", "dff",
+];
+
+/// ReplayGain 2 targets -18 LUFS.
+const REPLAYGAIN_REFERENCE_LUFS: f64 = -18.0;
+/// R128 gains target -23 LUFS and are stored as Q7.8 fixed point.
+const R128_REFERENCE_LUFS: f64 = -23.0;
+
+// RIFF INFO strings keep their NUL terminator
+fn find_tag(tags: &[Tag], key: StandardTagKey) -> Option<String> {
+    tags.iter()
+        .find(|tag| tag.std_key == Some(key))
+        .map(|tag| tag.value.to_string().trim_end_matches('\0').to_string())
+}
+
+fn find_loudness(tags: &[Tag]) -> Option<f64> {
+    if let Some(gain) = tags
+        .iter()
+        .find(|tag| tag.key.eq_ignore_ascii_case("R128_TRACK_GAIN"))
+        .and_then(|tag| tag.value.to_string().trim().parse::<f64>().ok())
+    {
+        return Some(R128_REFERENCE_LUFS - gain / 256.0);
+    }
+    // Values look like "-7.89 dB"
+    find_tag(tags, StandardTagKey::ReplayGainTrackGain)
+        .and_then(|gain| {
+            gain.trim()
+                .trim_end_matches("dB")
+                .trim_end_matches("db")
+                .trim()
+                .parse::<f64>()
+                .ok()
+        })
+        .map(|gain| REPLAYGAIN_REFERENCE_LUFS - gain)
+}
+
+/// Opens the container along with the metadata found ahead of the stream by the probe.
+fn open_format(path: &str) -> Result<(Box<dyn FormatReader>, Option<ProbedMetadata>)> {
+    let mss = if radio::is_url(path) {
+        MediaSourceStream::new(Box::new(HttpSource::open(path)?), Default::default())
+    } else {
+        MediaSourceStream::new(Box::new(std::fs::File::open(path)?), Default::default())
+    };
+    let is_dsd = dsd::is_dsd(Path::new(path));
+    let (format, probed_metadata) = if is_dsd {
+        let format: Box<dyn FormatReader> =
+            Box::new(DsdReader::try_new(mss, &FormatOptions::default())?);
+        (format, None)
+    } else {
+        let mut hint = Hint::new();
+        if let Some(extension) = Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            hint.with_extension(extension);
+        }
+        let meta_opts = Default::default();
+        let fmt_opts = Default::default();
+        let probed = symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts)?;
+        (probed.format, Some(probed.metadata))
+    };
+    Ok((format, probed_metadata))
+}
+
+/// Containers such as MP3 or WAV carry their tags ahead of the stream, found by the probe.
+fn latest_metadata(
+    format: &mut Box<dyn FormatReader>,
+    mut probed_metadata: Option<ProbedMetadata>,
+) -> MetadataRevision {
+    match format.metadata().skip_to_latest() {
+        Some(metadata) => metadata.clone(),
+        None => match probed_metadata.as_mut().and_then(|metadata| metadata.get()) {
+            Some(mut metadata) => metadata.skip_to_latest().cloned().unwrap_or_default(),
+            None => MetadataRevision::default(),
+        },
+    }
+}
+
+fn make_decoder(path: &str, track: &Track) -> Result<Box<dyn Decoder>> {
+    let decoder_opts = DecoderOptions { verify: true };
+    Ok(if dsd::is_dsd(Path::new(path)) {
+        Box::new(DopDecoder::try_new(&track.codec_params, &decoder_opts)?)
+    } else {
+        symphonia::default::get_codecs().make(&track.codec_params, &decoder_opts)?
+    })
+}
+
+/// Whether a decoder is built in for the codec of `track`, told by the registry without making
+/// one. Making one allocates its buffers, they are only needed once playing.
+fn is_decodable(path: &str, track: &Track) -> bool {
+    match dsd::is_dsd(Path::new(path)) {
+        true => track.codec_params.codec == CODEC_TYPE_DSD,
+        false => symphonia::default::get_codecs()
+            .get_codec(track.codec_params.codec)
+            .is_some(),
+    }
+}
+
+/// Format of a track at a glance, shown next to its title.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Badge {
+    Flac,
+    Mp3,
+    Ogg,
+    M4a,
+    Wav,
+    Aiff,
+    Dsd,
+    /// PCM above 16 bits or 48KHz
+    HiRes,
+}
+
+impl Badge {
+    pub const ALL: [Badge; 8] = [
+        Badge::Flac,
+        Badge::Mp3,
+        Badge::Ogg,
+        Badge::M4a,
+        Badge::Wav,
+        Badge::Aiff,
+        Badge::Dsd,
+        Badge::HiRes,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Badge::Flac => "FLAC",
+            Badge::Mp3 => "MP3",
+            Badge::Ogg => "OGG",
+            Badge::M4a => "M4A",
+            Badge::Wav => "WAV",
+            Badge::Aiff => "AIFF",
+            Badge::Dsd => "DSD",
+            Badge::HiRes => "HI-RES",
+        }
+    }
+}
+
+/// Tags and stream format of a file, the file itself is only opened for playback.
+#[derive(Clone)]
 pub struct MusicTrack {
-    pub format: Arc<Mutex<Box<dyn FormatReader>>>,
-    pub decoder: Arc<Mutex<Box<dyn Decoder>>>,
+    pub path: String,
     pub sample: SampleRate,
     pub channels: usize,
     pub bits_per_sample: BitsPerSample,
     pub title: String,
     pub artist: String,
+    pub album: String,
     pub duration: Time,
+    /// Integrated loudness in LUFS derived from ReplayGain or R128 tags
+    pub loudness: Option<f64>,
+    /// DSD rate of DSF and DSDIFF files, streamed as DoP at `sample`
+    pub dsd_rate: Option<u32>,
+    /// Part of the file played for tracks of a CUE sheet
+    pub segment: Option<Segment>,
 }
 
 impl MusicTrack {
-    pub fn new(path: String) -> Result<Self> {
-        let source = std::fs::File::open(path.clone())?;
-        let mss = MediaSourceStream::new(Box::new(source), Default::default());
-        let hint = Hint::new();
-        let meta_opts = Default::default();
-        let fmt_opts = Default::default();
-        let probed = symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts)?;
+    pub fn is_supported(path: &Path) -> bool {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| {
+                SUPPORTED_EXTENSIONS
+                    .iter()
+                    .any(|supported| extension.eq_ignore_ascii_case(supported))
+            })
+            .unwrap_or(false)
+    }
 
-        let mut format = probed.format;
-        let track = format.tracks().get(0).unwrap().clone();
+    /// Probes the file, failing when it cannot be decoded.
+    pub fn new(path: String) -> Result<Self> {
+        let is_dsd = dsd::is_dsd(Path::new(&path));
+        let (mut format, probed_metadata) = open_format(&path)?;
+        let track = format
+            .default_track()
+            .ok_or(anyhow!("No audio track found in {}", path))?
+            .clone();
         let samplerate = track.codec_params.sample_rate.unwrap_or(44100);
         let channels = track
             .codec_params
             .channels
             .unwrap_or(Layout::Stereo.into_channels())
             .count();
-        let bits_per_sample = track.codec_params.bits_per_sample.unwrap_or(16) as u8;
+        let bits_per_sample = track.codec_params.bits_per_sample.unwrap_or(16) as usize;
+        let dsd_rate = is_dsd.then_some(samplerate * 16);
+        if dsd_rate.is_some_and(|rate| rate > 2 * DSD64_RATE) {
+            return Err(anyhow!("Only DSD64 and DSD128 are supported: {}", path));
+        }
 
-        let metadata = match format.metadata().skip_to_latest() {
-            Some(metadata) => metadata.clone(),
-            None => MetadataRevision::default().clone(),
-        };
+        // Anything outside of the known formats would panic once converted
+        let supported = Capabilities::default();
+        let sample = supported
+            .sample_rates
+            .into_iter()
+            .find(|rate| *rate as u32 == samplerate)
+            .ok_or(anyhow!(
+                "Unsupported sample rate {}Hz: {}",
+                samplerate,
+                path
+            ))?;
+        let bits_per_sample = supported
+            .bits_per_samples
+            .into_iter()
+            .find(|bits| *bits as usize == bits_per_sample)
+            .ok_or(anyhow!(
+                "Unsupported {} bits samples: {}",
+                bits_per_sample,
+                path
+            ))?;
+
+        let metadata = latest_metadata(&mut format, probed_metadata);
 
-        let artist = metadata
-            .tags()
-            .iter()
-            .find(|e| e.std_key == Some(StandardTagKey::Artist))
-            .unwrap()
-            .value
-            .to_string();
-        let title = metadata
-            .tags()
-            .iter()
-            .find(|e| e.std_key == Some(StandardTagKey::TrackTitle))
-            .unwrap()
-            .value
-            .to_string();
+        let is_stream = radio::is_url(&path);
+        let artist = find_tag(metadata.tags(), StandardTagKey::Artist)
+            .or_else(|| find_tag(metadata.tags(), StandardTagKey::AlbumArtist))
+            .or_else(|| is_stream.then(|| String::from("Internet radio")))
+            .unwrap_or_else(|| String::from("Unknown artist"));
+        let album = find_tag(metadata.tags(), StandardTagKey::Album)
+            .unwrap_or_else(|| String::from("Unknown album"));
+        // Stations are named after their `icy-name` header, or their URL
+        let title = find_tag(metadata.tags(), StandardTagKey::TrackTitle)
+            .or_else(|| is_stream.then(|| radio::station_name(&path).unwrap_or(path.clone())))
+            .unwrap_or_else(|| {
+                Path::new(&path)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.clone())
+            });
+        let loudness = find_loudness(metadata.tags());
+        // Unsupported codecs are rejected while scanning rather than on playback
+        if !is_decodable(&path, &track) {
+            return Err(anyhow!("Unsupported codec: {}", path));
+        }
         let duration = track
             .codec_params
             .time_base
             .unwrap_or(Default::default())
             .calc_time(track.codec_params.n_frames.unwrap_or(0));
 
-        // Create a decoder for the track.
-        let decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &DecoderOptions { verify: true })?;
-
         Ok(Self {
-            format: Arc::new(Mutex::new(format)),
-            decoder: Arc::new(Mutex::new(decoder)),
-            sample: SampleRate::from(samplerate as usize),
+            path,
+            sample,
             channels,
-            bits_per_sample: BitsPerSample::from(bits_per_sample as usize),
+            bits_per_sample,
             title,
             artist,
+            album,
             duration,
+            loudness,
+            dsd_rate,
+            segment: None,
         })
     }
 
+    /// Internet radio played from a URL, endless and not seekable.
+    pub fn is_stream(&self) -> bool {
+        radio::is_url(&self.path)
+    }
+
+    /// Title of the track, with the song on air for internet radios that announce it.
+    pub fn display_title(&self) -> String {
+        match radio::stream_title(&self.path).filter(|_| self.is_stream()) {
+            Some(on_air) => format!("{} - {}", self.title, on_air),
+            None => self.title.clone(),
+        }
+    }
+
+    /// Opens a fresh stream positioned at the start of the track.
+    pub fn open(&self) -> Result<(Box<dyn FormatReader>, Box<dyn Decoder>)> {
+        let (format, _) = open_format(&self.path)?;
+        let decoder = self.decoder(format.as_ref())?;
+        Ok((format, decoder))
+    }
+
+    /// Decoder for the default track of `format`, made again once a chained stream moves on.
+    pub fn decoder(&self, format: &dyn FormatReader) -> Result<Box<dyn Decoder>> {
+        let track = format
+            .default_track()
+            .ok_or(anyhow!("No audio track found in {}", self.path))?;
+        make_decoder(&self.path, track)
+    }
+
+    /// Every tag and picture of the file, only read when converting or for the album accent.
+    pub fn metadata(&self) -> Result<MetadataRevision> {
+        let (mut format, probed_metadata) = open_format(&self.path)?;
+        Ok(latest_metadata(&mut format, probed_metadata))
+    }
+
+    /// The container from the file extension, then HI-RES for high resolution PCM.
+    pub fn badges(&self) -> Vec<Badge> {
+        let extension = Path::new(&self.path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let format = match extension.as_deref() {
+            Some("flac") => Some(Badge::Flac),
+            Some("mp3") => Some(Badge::Mp3),
+            Some("ogg") => Some(Badge::Ogg),
+            Some("m4a") => Some(Badge::M4a),
+            Some("wav") => Some(Badge::Wav),
+            Some("aiff" | "aif") => Some(Badge::Aiff),
+            Some("dsf" | "dff") => Some(Badge::Dsd),
+            _ => None,
+        };
+        let hires = self.dsd_rate.is_none()
+            && (self.bits_per_sample as usize > 16 || self.sample as usize > 48000);
+        format
+            .into_iter()
+            .chain(hires.then_some(Badge::HiRes))
+            .collect()
+    }
+
     pub fn info(&self) -> String {
+        if let Some(rate) = self.dsd_rate {
+            return format!("DSD{} - DoP", rate / (DSD64_RATE / 64));
+        }
         format!(
             "{}bits - {}KHz",
             self.bits_per_sample as usize,
@@ -92,6 +336,62 @@ impl MusicTrack {
         )
     }
 
+    /// What happens to the track on a device with these capabilities, `None` when it plays
+    /// as is. Unsupported formats fall back like the player does, to the last rate or size.
+    pub fn device_warning(&self, capabilities: &Capabilities) -> Option<String> {
+        if self.dsd_rate.is_some() {
+            return (!capabilities.supports_dop(self.sample)).then(|| {
+                format!(
+                    "The device does not accept DoP at {}KHz, the track cannot play",
+                    self.sample as usize as f32 / 1000.0
+                )
+            });
+        }
+        let mut changes = Vec::new();
+        if !capabilities.sample_rates.contains(&self.sample) {
+            if let Some(rate) = capabilities.sample_rates.last() {
+                changes.push(format!(
+                    "resampled from {}KHz to {}KHz",
+                    self.sample as usize as f32 / 1000.0,
+                    *rate as usize as f32 / 1000.0
+                ));
+            }
+        }
+        if !capabilities
+            .bits_per_samples
+            .contains(&self.bits_per_sample)
+        {
+            if let Some(bits) = capabilities.bits_per_samples.last() {
+                changes.push(format!(
+                    "converted from {} to {} bits",
+                    self.bits_per_sample as usize, *bits as usize
+                ));
+            }
+        }
+        match capabilities.layout_for(self.channels as u8) {
+            Some(channels) if channels as usize == self.channels => (),
+            Some(channels) => changes.push(format!(
+                "played over {} of the {} device channels",
+                self.channels, channels
+            )),
+            // Mono is left to the device to spread
+            None if self.channels == 1 => (),
+            None => changes.push(format!("no device layout holds {} channels", self.channels)),
+        }
+        if changes.is_empty() {
+            return None;
+        }
+        let warning = changes.join(", ");
+        let mut chars = warning.chars();
+        chars
+            .next()
+            .map(|first| first.to_uppercase().chain(chars).collect())
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.duration.seconds as f64 + self.duration.frac
+    }
+
     pub fn formated_duration(&self) -> String {
         let hours = self.duration.seconds / (60 * 60);
         let mins = (self.duration.seconds % (60 * 60)) / 60;
@@ -111,3 +411,154 @@ impl MusicTrack {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fixtures are written by tests/assets/generate.py.
+    fn probe(name: &str) -> Result<MusicTrack> {
+        MusicTrack::new(format!(
+            "{}/tests/assets/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+    }
+
+    fn assert_tags(track: &MusicTrack, title: &str, artist: &str, album: &str) {
+        assert_eq!(track.title, title);
+        assert_eq!(track.artist, artist);
+        assert_eq!(track.album, album);
+    }
+
+    fn assert_format(track: &MusicTrack, sample: SampleRate, bits: BitsPerSample, channels: usize) {
+        assert_eq!(track.sample, sample);
+        assert_eq!(track.bits_per_sample, bits);
+        assert_eq!(track.channels, channels);
+    }
+
+    fn assert_duration(track: &MusicTrack, seconds: u64, frac: f64, formated: &str) {
+        assert_eq!(track.duration.seconds, seconds);
+        assert!(
+            (track.duration.frac - frac).abs() < 1e-3,
+            "{}",
+            track.duration.frac
+        );
+        assert_eq!(track.formated_duration(), formated);
+    }
+
+    #[test]
+    fn flac_with_vorbis_comments() {
+        let track = probe("tagged.flac").unwrap();
+        assert_tags(&track, "Flac Title", "Flac Artist", "Flac Album");
+        assert_format(&track, SampleRate::Rate44100Hz, BitsPerSample::Bits16, 2);
+        assert_duration(&track, 61, 0.0, "01:01");
+        assert_eq!(track.loudness, Some(-11.0));
+        assert_eq!(track.info(), "16bits - 44.1KHz");
+        assert_eq!(track.badges(), vec![Badge::Flac]);
+        assert_eq!(track.dsd_rate, None);
+    }
+
+    #[test]
+    fn mp3_with_id3v2() {
+        let track = probe("tagged.mp3").unwrap();
+        assert_tags(&track, "Mp3 Title", "Mp3 Artist", "Mp3 Album");
+        // Lossy codecs carry no sample size
+        assert_format(&track, SampleRate::Rate44100Hz, BitsPerSample::Bits16, 1);
+        assert_duration(&track, 0, 38.0 * 1152.0 / 44100.0, "00:00");
+        assert_eq!(track.loudness, None);
+    }
+
+    #[test]
+    fn ogg_vorbis_falls_back_to_album_artist() {
+        let track = probe("tagged.ogg").unwrap();
+        assert_tags(&track, "Ogg Title", "Ogg Album Artist", "Ogg Album");
+        assert_format(&track, SampleRate::Rate48000Hz, BitsPerSample::Bits16, 2);
+        assert_duration(&track, 2, 0.0, "00:02");
+        assert_eq!(track.loudness, Some(-18.0));
+        assert_eq!(track.info(), "16bits - 48KHz");
+    }
+
+    #[test]
+    fn wav_with_info_chunk() {
+        let track = probe("tagged.wav").unwrap();
+        assert_tags(&track, "Wav Title", "Wav Artist", "Wav Album");
+        assert_format(&track, SampleRate::Rate96000Hz, BitsPerSample::Bits24, 2);
+        assert_duration(&track, 0, 0.1, "00:00");
+        assert_eq!(track.info(), "24bits - 96KHz");
+        assert_eq!(track.badges(), vec![Badge::Wav, Badge::HiRes]);
+    }
+
+    #[test]
+    fn device_warning_tells_how_the_format_changes() {
+        let track = probe("tagged.wav").unwrap();
+        assert_eq!(track.device_warning(&Capabilities::default()), None);
+
+        let capabilities = Capabilities {
+            sample_rates: vec![SampleRate::Rate44100Hz, SampleRate::Rate48000Hz],
+            bits_per_samples: vec![BitsPerSample::Bits16],
+            channels: vec![2],
+        };
+        assert_eq!(
+            track.device_warning(&capabilities).as_deref(),
+            Some("Resampled from 96KHz to 48KHz, converted from 24 to 16 bits")
+        );
+
+        let surround = probe("multichannel.wav").unwrap();
+        let capabilities = Capabilities {
+            channels: vec![2, 8],
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            surround.device_warning(&capabilities).as_deref(),
+            Some("Played over 6 of the 8 device channels")
+        );
+        let capabilities = Capabilities {
+            channels: vec![2],
+            ..Capabilities::default()
+        };
+        assert_eq!(
+            surround.device_warning(&capabilities).as_deref(),
+            Some("No device layout holds 6 channels")
+        );
+    }
+
+    #[test]
+    fn decoder_is_made_once_opened() {
+        let track = probe("tagged.flac").unwrap();
+        let (mut format, mut decoder) = track.open().unwrap();
+        let packet = format.next_packet().unwrap();
+        assert_eq!(decoder.decode(&packet).unwrap().spec().channels.count(), 2);
+    }
+
+    #[test]
+    fn untagged_file_uses_the_file_name() {
+        let track = probe("untagged.wav").unwrap();
+        assert_tags(&track, "untagged", "Unknown artist", "Unknown album");
+        assert_format(&track, SampleRate::Rate44100Hz, BitsPerSample::Bits16, 2);
+        assert_duration(&track, 0, 0.1, "00:00");
+        assert_eq!(track.loudness, None);
+    }
+
+    #[test]
+    fn multichannel_wav() {
+        let track = probe("multichannel.wav").unwrap();
+        assert_tags(&track, "multichannel", "Unknown artist", "Unknown album");
+        assert_format(&track, SampleRate::Rate48000Hz, BitsPerSample::Bits24, 6);
+        assert_duration(&track, 0, 0.05, "00:00");
+    }
+
+    #[test]
+    fn unsupported_sample_rate_is_an_error() {
+        let err = probe("odd_rate.flac").err().unwrap();
+        assert!(err.to_string().contains("22050Hz"), "{}", err);
+    }
+
+    #[test]
+    fn supported_extensions() {
+        assert!(MusicTrack::is_supported(Path::new("a/b.FLAC")));
+        assert!(MusicTrack::is_supported(Path::new("b.dsf")));
+        assert!(!MusicTrack::is_supported(Path::new("b.txt")));
+        assert!(!MusicTrack::is_supported(Path::new("flac")));
+    }
+}
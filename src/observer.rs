@@ -0,0 +1,121 @@
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+
+use crate::player::{CurrentTrackInfo, Playback};
+use crate::tools::tap::SampleTap;
+
+/// What the player reports to its observers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlayerEvent {
+    /// Playback started, paused, resumed or stopped, the end of the track included
+    State(Playback),
+    /// Seconds into the playing track and its duration, at the observed interval
+    Position { elapsed: f64, duration: f64 },
+    /// Latest decoded samples in mono with their sample rate, while the spectrum is enabled
+    Spectrum { samples: Vec<f32>, sample_rate: u32 },
+}
+
+/// State published by the player each time it changes.
+#[derive(Clone, Default)]
+pub(crate) struct Observed {
+    pub playback: Playback,
+    pub track: Option<CurrentTrackInfo>,
+}
+
+/// Reports the state changes as they happen, the position and the spectrum every `every`.
+/// Returns once `emit` returns false or the player is dropped.
+pub(crate) async fn observe(
+    mut state: watch::Receiver<Observed>,
+    tap: Arc<SampleTap>,
+    every: Duration,
+    mut emit: impl FnMut(PlayerEvent) -> bool,
+) -> Result<()> {
+    let mut ticks = tokio::time::interval(every);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut reported = None;
+    loop {
+        let ticked = tokio::select! {
+            changed = state.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+                false
+            }
+            _ = ticks.tick() => true,
+        };
+        let observed = state.borrow_and_update().clone();
+        let progress = observed.track.as_ref().map(|track| track.progress());
+        // A track done streaming is only replaced once the next one starts
+        let playback = match progress {
+            Some(progress) if !progress.streaming => Playback::Stopped,
+            _ => observed.playback,
+        };
+        if reported != Some(playback) {
+            reported = Some(playback);
+            if !emit(PlayerEvent::State(playback)) {
+                return Ok(());
+            }
+        }
+        if !ticked || playback != Playback::Playing {
+            continue;
+        }
+        if let (Some(track), Some(progress)) = (&observed.track, progress) {
+            let position = PlayerEvent::Position {
+                elapsed: track.elapsed_seconds_at(&progress),
+                duration: track.duration_seconds(),
+            };
+            if !emit(position) {
+                return Ok(());
+            }
+        }
+        if tap.is_enabled() {
+            let (samples, sample_rate) = tap.latest();
+            if !emit(PlayerEvent::Spectrum {
+                samples,
+                sample_rate,
+            }) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn state_changes_are_reported_once_until_the_player_is_dropped() {
+        let (sender, receiver) = watch::channel(Observed::default());
+        let (events, mut received) = tokio::sync::mpsc::unbounded_channel();
+        let observer = tokio::spawn(observe(
+            receiver,
+            Arc::new(SampleTap::default()),
+            Duration::from_millis(5),
+            move |event| events.send(event).is_ok(),
+        ));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        sender.send_replace(Observed {
+            playback: Playback::Paused,
+            track: None,
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(sender);
+        observer.await.unwrap().unwrap();
+
+        let mut states = Vec::new();
+        while let Ok(event) = received.try_recv() {
+            states.push(event);
+        }
+        assert_eq!(
+            states,
+            vec![
+                PlayerEvent::State(Playback::Stopped),
+                PlayerEvent::State(Playback::Paused),
+            ]
+        );
+    }
+}
@@ -1,39 +1,385 @@
-use anyhow::Result;
-use log::error;
+use anyhow::{anyhow, Result};
+use log::{error, info, warn};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
-use std::sync::Arc;
-use symphonia::core::audio::{AudioBufferRef, RawSampleBuffer, SignalSpec};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use symphonia::core::audio::{
+    AsAudioBufferRef, AudioBuffer, AudioBufferRef, RawSampleBuffer, Signal, SignalSpec,
+};
+use symphonia::core::codecs::Decoder;
 use symphonia::core::errors::Error;
-use symphonia::core::formats::{SeekMode, SeekTo};
+use symphonia::core::formats::{FormatReader, Packet, SeekMode, SeekTo};
 use symphonia::core::sample::i24;
-use symphonia::core::units::Time;
-use tokio::sync::mpsc::Sender;
-use tokio::task::JoinHandle;
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver};
+use tokio::sync::{watch, Notify};
+use tokio::task::AbortHandle;
+use tracing::trace_span;
 
+use crate::audio::thread::unblock;
 use crate::audio::{
-    BitsPerSample, Device, DeviceTrait, Host, HostTrait, StreamParams, StreamingData,
+    probe, BitsPerSample, Capabilities, ChannelRouting, Device, DeviceTrait, DeviceWatch,
+    ExclusiveRefused, FadeDurations, Host, HostTrait, PinnedFormat, RingWriter, SampleRate,
+    StreamParams,
 };
+use crate::config::{ChannelDelay, Config, DeviceConfig};
+use crate::cue::Segment;
+use crate::dsp::{DownmixMode, DspChain, DspSettings};
 use crate::musictrack::MusicTrack;
-use crate::tools::resampler::RubatoResampler;
+use crate::observer::{self, Observed, PlayerEvent};
+use crate::session::Session;
+use crate::tasks::TaskGroup;
+use crate::tools::cpu::CpuMeter;
+use crate::tools::crossfade::FadeOut;
+use crate::tools::levels::Levels;
+use crate::tools::resampler::{ResamplerSettings, RubatoResampler};
+use crate::tools::tap::SampleTap;
 
 pub struct Player {
     current_device: Option<Device>,
     host: Host,
     device_id: Option<u32>,
+    /// Name of the device at `device_id` when it was chosen, found again by it once devices
+    /// come and go
+    device_name: Option<String>,
+    /// Name of the device chosen when it was no longer found, see `stale_device`
+    stale_device: Option<String>,
     pollmode: bool,
-    previous_stream: Option<Sender<StreamingData>>,
-    streaming_handle: Option<JoinHandle<Result<()>>>,
+    previous_stream: Option<RingWriter>,
+    streaming_handle: Option<AbortHandle>,
     is_playing: Arc<AtomicBool>,
+    is_paused: bool,
+    dsp_settings: Arc<DspSettings>,
+    config: Config,
+    last_loudness: Option<f64>,
+    decode_cpu: Arc<CpuMeter>,
+    tap: Arc<SampleTap>,
+    /// Levels of the current track, as played
+    levels: Arc<Levels>,
+    /// Set while the current track is resampled
+    resampler: Option<ResamplerSettings>,
+    /// Format the current track plays in, unset while stopped
+    output: Option<OutputFormat>,
+    /// Channels of the current track once downmixed or upmixed, as metered
+    played_channels: usize,
+    /// Channels of the current track as the device gets them, set per device
+    routing: Option<ChannelRouting>,
+    /// Artist and album of the albums never crossfaded into nor out of, live and continuous
+    /// albums
+    strict_gapless: HashSet<(String, String)>,
+    /// Diagnostic playback, in shared mode and without any processing
+    safe_mode: bool,
+    /// Tracks play through the system mixer, at its rate
+    shared_mode: bool,
+    /// Totals of the devices closed and tracks streamed so far
+    session: Session,
+    /// Progress of the last track started, counted once the next one starts
+    streamed: Option<CurrentTrackInfo>,
+    /// Woken when a track is done streaming, so the next one starts without waiting for a tick
+    ended: Arc<Notify>,
+    /// End of the current track, offered to the next one once the crossfade is due
+    handover: Arc<Mutex<Option<Handover>>>,
+    /// Task of the track fading out under the current one
+    fading_handle: Option<AbortHandle>,
+    /// Owner of the streaming tasks, their failures are reported by the app
+    tasks: TaskGroup,
+    /// Playback state and current track, read by the observers
+    observed: watch::Sender<Observed>,
+    /// Capabilities of the output device, probed in the background
+    capabilities: Arc<OnceLock<Capabilities>>,
+    /// Next track opened ahead of time, see `prefetch`
+    prefetched: Arc<Mutex<Option<Prefetched>>>,
+    /// Track last passed to `prefetch`
+    prefetching: Option<Arc<MusicTrack>>,
+    /// Output devices going away, when the host reports them
+    devices: Option<DeviceWatch>,
+    /// Attenuation and length of the fade in of the next track started, see `fade_in_next`
+    fade_in: Option<(f64, f64)>,
+}
+
+/// Decoded buffers of a fading out track queued for the mix.
+const TAIL_CHUNKS: usize = 16;
+
+/// Stream of a track entering its crossfade. The next track claims it to mix the tail in
+/// rather than opening the device again.
+struct Handover {
+    song: Arc<MusicTrack>,
+    params: StreamParams,
+    sender: RingWriter,
+    tail: Receiver<AudioBuffer<f64>>,
+    /// Set once claimed, the tail then goes to the next track instead of the device
+    claimed: Arc<AtomicBool>,
+    /// Frames left to decode
+    remaining: Arc<AtomicU64>,
+}
+
+/// Packets of the next track read ahead, a few seconds for most codecs.
+const PREFETCH_PACKETS: usize = 64;
+
+/// Stream of a track, positioned at its start with its first packets read.
+struct Opened {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    packets: VecDeque<Packet>,
+    /// A new stream started right after the packets read ahead
+    reset_required: bool,
+}
+
+impl Opened {
+    /// Opens the file and seeks to the start of the CUE sheet track, if any.
+    fn open(song: &MusicTrack) -> Result<Self> {
+        let (mut format, mut decoder) = song.open()?;
+        if let Some(segment) = song.segment {
+            let track_id = format.default_track().map_or(0, |track| track.id);
+            format.seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: segment.start,
+                    track_id,
+                },
+            )?;
+            decoder.reset();
+        }
+        Ok(Self {
+            format,
+            decoder,
+            packets: VecDeque::new(),
+            reset_required: false,
+        })
+    }
+
+    /// Packets read ahead come first.
+    fn next_packet(&mut self) -> symphonia::core::errors::Result<Packet> {
+        match self.packets.pop_front() {
+            Some(packet) => Ok(packet),
+            None if std::mem::take(&mut self.reset_required) => Err(Error::ResetRequired),
+            None => self.format.next_packet(),
+        }
+    }
+
+    /// Makes the decoder again for the stream that just started. Chained Ogg streams, as
+    /// internet radios send them, start one on each song.
+    fn reset(&mut self, song: &MusicTrack) -> Result<()> {
+        self.decoder = song.decoder(self.format.as_ref())?;
+        Ok(())
+    }
+}
+
+/// Track opened while the previous one plays, with the device it will play on, so it
+/// starts without waiting for the file nor the driver.
+struct Prefetched {
+    song: Arc<MusicTrack>,
+    device: Device,
+    opened: Opened,
+}
+
+impl Prefetched {
+    fn open(song: Arc<MusicTrack>, device: Device) -> Result<Self> {
+        let mut opened = Opened::open(&song)?;
+        // Errors, such as the end of a short track, are met again once streaming
+        while opened.packets.len() < PREFETCH_PACKETS {
+            match opened.format.next_packet() {
+                Ok(packet) => opened.packets.push_back(packet),
+                Err(Error::ResetRequired) => {
+                    opened.reset_required = true;
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+        // Cached for when the device is started
+        probe::capabilities(&device)?;
+        Ok(Self {
+            song,
+            device,
+            opened,
+        })
+    }
+}
+
+/// Where the device chosen in the list of the host was found when opened.
+enum Located {
+    Same,
+    /// Devices added or removed before it shifted it to this index
+    Moved(u32),
+    /// It is gone, the default device was opened instead
+    Gone,
+}
+
+/// Name of the device at `device_id` in the list of the host.
+fn device_name(host: Host, device_id: Option<u32>) -> Option<String> {
+    let index = device_id? as usize;
+    host.get_devices().ok()?.get(index)?.name().ok()
+}
+
+/// Opens the device called `name` expected at `device_id` in the list of the host, looked up
+/// by name when the list changed since and replaced by the default device when it is gone.
+fn open_device(
+    host: Host,
+    device_id: Option<u32>,
+    name: Option<&str>,
+) -> Result<(Device, Located)> {
+    let Some(index) = device_id else {
+        return Ok((host.create_device(None)?, Located::Same));
+    };
+    match host.create_device(Some(index)) {
+        Ok(device) if name.is_none_or(|name| device.name().is_ok_and(|found| found == name)) => {
+            return Ok((device, Located::Same));
+        }
+        Ok(_) => (),
+        Err(err) => warn!("Cannot open the output device {}: {}", index, err),
+    }
+    let found = name.and_then(|name| {
+        host.get_devices()
+            .ok()?
+            .iter()
+            .position(|device| device.name().is_ok_and(|found| found == name))
+    });
+    match found {
+        Some(found) => Ok((
+            host.create_device(Some(found as u32))?,
+            Located::Moved(found as u32),
+        )),
+        None => Ok((host.create_device(None)?, Located::Gone)),
+    }
+}
+
+/// Same track of the same file, CUE sheet tracks share their file.
+fn is_same_track(track: &MusicTrack, other: &MusicTrack) -> bool {
+    track.path == other.path && track.segment == other.segment
+}
+
+/// Channel routing of a device for tracks with `channels`, padded to the channels set for the
+/// device.
+fn device_routing(config: &DeviceConfig, channels: usize) -> Result<Option<ChannelRouting>> {
+    let routing =
+        ChannelRouting::new(&config.routing)?.filter(|routing| routing.inputs() == channels);
+    match config.channels {
+        Some(pinned) => {
+            let routing = routing.unwrap_or_else(|| ChannelRouting::identity(channels));
+            Ok(Some(routing.padded(pinned as usize)?))
+        }
+        None => Ok(routing),
+    }
+}
+
+/// Side of the handover kept by the fading out track.
+struct Tail {
+    sender: Sender<AudioBuffer<f64>>,
+    claimed: Arc<AtomicBool>,
+    remaining: Arc<AtomicU64>,
+}
+
+impl Handover {
+    /// The mix needs the same format, tracks of an album follow each other gaplessly.
+    fn accepts(&self, song: &MusicTrack) -> bool {
+        let same_album = !song.album.is_empty()
+            && song.album == self.song.album
+            && song.artist == self.song.artist;
+        song.dsd_rate.is_none()
+            && !song.is_stream()
+            && song.sample == self.params.samplerate
+            && song.channels == self.song.channels
+            && !same_album
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Playback {
+    Playing,
+    Paused,
+    #[default]
+    Stopped,
+}
+
+/// Format the device plays the current track in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputFormat {
+    pub samplerate: SampleRate,
+    pub bits_per_sample: BitsPerSample,
+    pub channels: u8,
+    pub exclusive: bool,
+    /// Played through the system mixer, exclusive mode having been refused
+    pub shared_fallback: bool,
+}
+
+/// State of the player taken at once, everything shown of the playing track agrees with it.
+/// Built by the playlist for its widgets and the remote frontends.
+#[derive(Clone, Default)]
+pub struct PlayerSnapshot {
+    pub playback: Playback,
+    /// Index of the playing track in the playlist, with the track
+    pub track: Option<(usize, Arc<MusicTrack>)>,
+    /// Seconds into the track, negative while the gap before a CUE sheet track plays
+    pub elapsed: f64,
+    pub duration: f64,
+    pub output: Option<OutputFormat>,
+    /// Set while the track is resampled to the output rate
+    pub resampler: Option<ResamplerSettings>,
+    pub volume: i8,
+}
+
+/// Snapshot of the streamed track, sent by the stream loop as it goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackProgress {
+    /// Frames decoded since the start of the track
+    pub frames: u64,
+    pub streaming: bool,
+    /// Set when the crossfade into the next track is due
+    pub fading_out: bool,
+}
+
+impl Default for TrackProgress {
+    fn default() -> Self {
+        Self {
+            frames: 0,
+            streaming: true,
+            fading_out: false,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct CurrentTrackInfo {
-    is_streaming: Arc<AtomicBool>,
+    progress: watch::Receiver<TrackProgress>,
+    sample_rate: u64,
+    /// Frames decoded before the track proper starts, the CUE sheet gap, negative once seeked
+    /// past the start
+    start: i64,
+    duration: f64,
 }
 
 impl CurrentTrackInfo {
+    /// Latest snapshot, read once so that everything drawn from it agrees.
+    pub fn progress(&self) -> TrackProgress {
+        *self.progress.borrow()
+    }
+
     pub fn is_streaming(&self) -> bool {
-        self.is_streaming.load(Ordering::Relaxed)
+        self.progress().streaming
+    }
+
+    pub fn is_fading_out(&self) -> bool {
+        self.progress().fading_out
+    }
+
+    /// Seconds streamed since the track started, seeks and gaps included.
+    fn streamed_seconds(&self) -> f64 {
+        self.progress().frames as f64 / self.sample_rate as f64
+    }
+
+    /// Seconds decoded so far at `progress`, a little ahead of what is heard. Negative while
+    /// the gap before a CUE sheet track plays.
+    pub fn elapsed_seconds_at(&self, progress: &TrackProgress) -> f64 {
+        (progress.frames as f64 - self.start as f64) / self.sample_rate as f64
+    }
+
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed_seconds_at(&self.progress())
+    }
+
+    pub fn duration_seconds(&self) -> f64 {
+        self.duration
     }
 }
 
@@ -89,6 +435,7 @@ impl Resampler {
         output_samplerate: usize,
         frames: usize,
         channels: usize,
+        settings: ResamplerSettings,
     ) -> Result<Self> {
         match output_bits_per_sample {
             BitsPerSample::Bits16 => Ok(Resampler::I16(RubatoResampler::<i16>::new(
@@ -98,6 +445,7 @@ impl Resampler {
                 output_bits_per_sample,
                 frames,
                 channels,
+                settings,
             )?)),
             BitsPerSample::Bits24 => Ok(Resampler::I24(RubatoResampler::<i24>::new(
                 input_sample_rate,
@@ -106,6 +454,7 @@ impl Resampler {
                 output_bits_per_sample,
                 frames,
                 channels,
+                settings,
             )?)),
             BitsPerSample::Bits32 => Ok(Resampler::F32(RubatoResampler::<f32>::new(
                 input_sample_rate,
@@ -114,68 +463,155 @@ impl Resampler {
                 output_bits_per_sample,
                 frames,
                 channels,
+                settings,
             )?)),
         }
     }
 
+    /// Resamples a decoded buffer and streams it, through `bytes` kept from one buffer to the
+    /// next to spare an allocation per packet.
     pub async fn send_resampled_data(
         &mut self,
         streambuffer: &AudioBufferRef<'_>,
-        streamer: &Sender<StreamingData>,
+        streamer: &RingWriter,
+        routing: Option<&ChannelRouting>,
+        cpu: &CpuMeter,
+        bytes: &mut Vec<u8>,
     ) -> Result<()> {
-        match self {
+        bytes.clear();
+        let sample_size = match self {
             Resampler::I16(resampler) => {
-                let output = resampler.resample(streambuffer)?;
-                for i in output.iter() {
-                    for j in i.to_ne_bytes().iter() {
-                        streamer.send(StreamingData::Data(*j)).await?
-                    }
-                }
+                let output = {
+                    let _busy = cpu.busy();
+                    resampler.resample(streambuffer)?
+                };
+                bytes.extend(output.iter().flat_map(|sample| sample.to_ne_bytes()));
+                2
             }
             Resampler::I24(resampler) => {
-                let output = resampler.resample(streambuffer)?;
-                for i in output.iter() {
-                    for j in i.to_ne_bytes().iter() {
-                        streamer.send(StreamingData::Data(*j)).await?
-                    }
-                }
+                let output = {
+                    let _busy = cpu.busy();
+                    resampler.resample(streambuffer)?
+                };
+                bytes.extend(output.iter().flat_map(|sample| sample.to_ne_bytes()));
+                3
             }
             Resampler::F32(resampler) => {
-                let output = resampler.resample(streambuffer)?;
-                for i in output.iter() {
-                    for j in i.to_ne_bytes().iter() {
-                        streamer.send(StreamingData::Data(*j)).await?
-                    }
-                }
+                let output = {
+                    let _busy = cpu.busy();
+                    resampler.resample(streambuffer)?
+                };
+                bytes.extend(output.iter().flat_map(|sample| sample.to_ne_bytes()));
+                4
             }
+        };
+        match routing {
+            Some(routing) => streamer.write(&routing.route(bytes, sample_size)).await,
+            None => streamer.write(bytes).await,
         }
-        Ok(())
     }
 }
 
 impl Player {
-    pub fn new(host: Host, device_id: Option<u32>, pollmode: bool) -> Result<Self> {
-        Ok(Player {
+    pub fn new(
+        host: Host,
+        device_id: Option<u32>,
+        pollmode: bool,
+        config: Config,
+        safe_mode: bool,
+        tasks: TaskGroup,
+    ) -> Result<Self> {
+        let shared_mode = config.output.shared;
+        let player = Player {
             current_device: None,
             host,
             device_id,
+            device_name: device_name(host, device_id),
+            stale_device: None,
             pollmode,
             previous_stream: None,
             streaming_handle: None,
             is_playing: Arc::new(AtomicBool::new(false)),
-        })
+            is_paused: false,
+            dsp_settings: Arc::new(DspSettings::default()),
+            config,
+            last_loudness: None,
+            decode_cpu: Arc::new(CpuMeter::default()),
+            tap: Arc::new(SampleTap::default()),
+            levels: Arc::new(Levels::new(0)),
+            resampler: None,
+            output: None,
+            played_channels: 2,
+            strict_gapless: HashSet::new(),
+            routing: None,
+            safe_mode,
+            shared_mode,
+            session: Session::default(),
+            streamed: None,
+            ended: Arc::new(Notify::new()),
+            handover: Arc::new(Mutex::new(None)),
+            fading_handle: None,
+            tasks,
+            observed: watch::Sender::new(Observed::default()),
+            capabilities: Arc::new(OnceLock::new()),
+            prefetched: Arc::new(Mutex::new(None)),
+            prefetching: None,
+            devices: host
+                .watch_devices()
+                .inspect_err(|err| warn!("Cannot watch the output devices: {}", err))
+                .ok()
+                .flatten(),
+            fade_in: None,
+        };
+        player.probe_capabilities();
+        Ok(player)
+    }
+
+    /// Tracks can be checked against the device before playing, the probe result is cached.
+    fn probe_capabilities(&self) {
+        let (host, device_id, name) = (self.host, self.device_id, self.device_name.clone());
+        let capabilities = self.capabilities.clone();
+        self.tasks.spawn(String::from("Probe device"), async move {
+            let probed = unblock(move || {
+                let (device, _) = open_device(host, device_id, name.as_deref())?;
+                probe::capabilities(&device)
+            })
+            .await;
+            match probed {
+                Ok(probed) => {
+                    let _ = capabilities.set(probed);
+                }
+                Err(err) => warn!("Cannot probe the output device: {}", err),
+            }
+            Ok(())
+        });
     }
 
     pub async fn stop(&mut self) -> Result<()> {
         self.is_playing.store(false, Ordering::Relaxed);
-        if let Some(device) = &mut self.current_device {
-            device.stop()?;
+        self.observed.send_replace(Observed::default());
+        self.output = None;
+        self.levels.clear();
+        if let Ok(mut handover) = self.handover.lock() {
+            handover.take();
+        }
+        if let Some(mut device) = self.current_device.take() {
+            let (device, stopped) = unblock(move || {
+                let stopped = device.stop();
+                Ok((device, stopped))
+            })
+            .await?;
+            self.current_device = Some(device);
+            stopped?;
         }
         if let Some(stream) = self.previous_stream.take() {
             stream.closed().await;
             drop(stream);
         }
-        if let Some(handle) = self.streaming_handle.take() {
+        for handle in [self.streaming_handle.take(), self.fading_handle.take()]
+            .into_iter()
+            .flatten()
+        {
             handle.abort();
         }
         Ok(())
@@ -183,52 +619,676 @@ impl Player {
 
     pub fn pause(&mut self) -> Result<()> {
         if let Some(device) = &mut self.current_device {
-            device.pause()?;
+            if self.is_paused {
+                device.resume()?;
+            } else {
+                device.pause()?;
+            }
+            self.is_paused = !self.is_paused;
+            self.observed.send_modify(|observed| {
+                observed.playback = if self.is_paused {
+                    Playback::Paused
+                } else {
+                    Playback::Playing
+                };
+            });
         }
         Ok(())
     }
 
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
+    pub fn toggle_karaoke(&mut self) {
+        self.dsp_settings.set_karaoke(!self.dsp_settings.karaoke());
+    }
+
+    pub fn is_karaoke_enabled(&self) -> bool {
+        self.dsp_settings.karaoke()
+    }
+
+    pub fn toggle_spectrum(&mut self) {
+        self.tap.set_enabled(!self.tap.is_enabled());
+    }
+
+    pub fn is_spectrum_enabled(&self) -> bool {
+        self.tap.is_enabled()
+    }
+
+    /// Latest decoded samples in mono with their sample rate, empty unless the spectrum is on.
+    pub fn tapped_samples(&self) -> (Vec<f32>, u32) {
+        self.tap.latest()
+    }
+
+    /// Peak and RMS level of each channel of the current track, after the DSP.
+    pub fn levels(&self) -> (Vec<f32>, Vec<f32>) {
+        (self.levels.get(), self.levels.rms())
+    }
+
+    pub fn volume(&self) -> i8 {
+        self.dsp_settings.volume()
+    }
+
+    pub fn change_volume(&mut self, step: i8) {
+        self.dsp_settings
+            .set_volume(self.dsp_settings.volume().saturating_add(step));
+    }
+
+    pub fn toggle_loudness(&mut self) {
+        self.dsp_settings
+            .set_loudness(!self.dsp_settings.loudness());
+    }
+
+    pub fn is_loudness_enabled(&self) -> bool {
+        self.dsp_settings.loudness()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Applies from the next track, the shared mode set in the app is kept.
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    pub fn resampler(&self) -> Option<ResamplerSettings> {
+        self.resampler
+    }
+
+    pub fn is_shared_mode(&self) -> bool {
+        self.shared_mode
+    }
+
+    /// Tracks started from now on play through the system mixer, or in exclusive mode.
+    pub fn set_shared_mode(&mut self, shared: bool) {
+        self.shared_mode = shared;
+    }
+
+    /// Format the device plays the current track in.
+    pub fn output(&self) -> Option<OutputFormat> {
+        self.output
+    }
+
+    /// Whether `song` would fade in over the end of the current track, which is then left
+    /// playing.
+    pub fn crossfades_into(&self, song: &MusicTrack) -> bool {
+        !self.is_strict_gapless(&song.artist, &song.album)
+            && self.handover.lock().is_ok_and(|handover| {
+                handover
+                    .as_ref()
+                    .is_some_and(|handover| handover.accepts(song))
+            })
+    }
+
+    pub fn is_strict_gapless(&self, artist: &str, album: &str) -> bool {
+        self.strict_gapless
+            .contains(&(artist.to_string(), album.to_string()))
+    }
+
+    /// Tracks of a strict gapless album are never crossfaded, the album starts and ends as
+    /// recorded.
+    pub fn set_strict_gapless(&mut self, artist: &str, album: &str, strict: bool) {
+        let key = (artist.to_string(), album.to_string());
+        match strict {
+            true => self.strict_gapless.insert(key),
+            false => self.strict_gapless.remove(&key),
+        };
+    }
+
+    /// Calls `observer` on each state change, with the position and the spectrum every
+    /// `every` while playing, until it returns false or the player is dropped.
+    pub fn observe(
+        &self,
+        every: Duration,
+        observer: impl FnMut(PlayerEvent) -> bool + Send + 'static,
+    ) {
+        let state = self.observed.subscribe();
+        let observing = observer::observe(state, self.tap.clone(), every, observer);
+        self.tasks.spawn("Observe player".to_string(), observing);
+    }
+
+    /// Same events as `observe`, as a stream ending with the player.
+    pub fn subscribe(&self, every: Duration) -> UnboundedReceiver<PlayerEvent> {
+        let (sender, receiver) = unbounded_channel();
+        self.observe(every, move |event| sender.send(event).is_ok());
+        receiver
+    }
+
+    /// Notified each time a track is done streaming.
+    pub fn ended(&self) -> Arc<Notify> {
+        self.ended.clone()
+    }
+
+    /// Opens `song` and its device in the background, so it starts right away once played
+    /// next. Internet radios are only opened when played.
+    pub fn prefetch(&mut self, song: Arc<MusicTrack>) {
+        if song.is_stream()
+            || self
+                .prefetching
+                .as_ref()
+                .is_some_and(|prefetching| is_same_track(prefetching, &song))
+        {
+            return;
+        }
+        self.prefetching = Some(song.clone());
+        let (host, device_id, device) = (self.host, self.device_id, self.device_name.clone());
+        let prefetched = self.prefetched.clone();
+        let name = format!("Prefetch {}", song.title);
+        self.tasks.spawn(name, async move {
+            let path = song.path.clone();
+            // Changes of the devices are left to the track start, which reports them
+            let opened = unblock(
+                move || match open_device(host, device_id, device.as_deref())? {
+                    (device, Located::Same) => Prefetched::open(song, device),
+                    _ => Err(anyhow!("The output devices changed")),
+                },
+            )
+            .await;
+            match opened {
+                Ok(opened) => {
+                    if let Ok(mut prefetched) = prefetched.lock() {
+                        *prefetched = Some(opened);
+                    }
+                }
+                // Reported again if it fails to play
+                Err(err) => warn!("Cannot prefetch {}: {}", path, err),
+            }
+            Ok(())
+        });
+    }
+
+    /// Capabilities of the output device, `None` until probed.
+    pub fn device_capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.get()
+    }
+
+    /// Name of the device opened for the last track.
+    pub fn device_name(&self) -> Option<String> {
+        self.current_device.as_ref()?.name().ok()
+    }
+
+    /// Name of the default output device, `None` when there is no device left.
+    pub fn default_device_name(&self) -> Option<String> {
+        self.host
+            .get_default_device()
+            .and_then(|device| device.name())
+            .ok()
+    }
+
+    /// Name of the device opened for the last track when it went away since the last call.
+    /// The default device is used from then on.
+    pub fn removed_device(&mut self) -> Option<String> {
+        let current = self.device_name();
+        let removed = std::iter::from_fn(|| self.devices.as_ref()?.removed())
+            .find(|name| Some(name) == current.as_ref())?;
+        self.switch_device(None);
+        Some(removed)
+    }
+
+    /// Name of the device chosen when it was found gone on opening it since the last call. The
+    /// default device is used from then on.
+    pub fn stale_device(&mut self) -> Option<String> {
+        self.stale_device.take()
+    }
+
+    /// Tracks started from now on play on the device at `device_id` in the list of the host,
+    /// the default one for `None`. Returns whether it changed.
+    pub fn set_device(&mut self, device_id: Option<u32>) -> bool {
+        if device_id == self.device_id {
+            return false;
+        }
+        self.switch_device(device_id);
+        true
+    }
+
+    /// Position of the output device called `name` in the list of the host, as taken by
+    /// `set_device`.
+    pub fn device_id(&self, name: &str) -> Result<u32> {
+        self.host
+            .get_devices()?
+            .iter()
+            .position(|device| device.name().is_ok_and(|device| device == name))
+            .map(|index| index as u32)
+            .ok_or(anyhow!("No output device called {}", name))
+    }
+
+    /// Another player on the device at `device_id`, playing alongside this one with its own
+    /// stream, volume and DSP settings.
+    pub fn sibling(&self, device_id: Option<u32>) -> Result<Player> {
+        #[cfg(all(windows, target_pointer_width = "64"))]
+        if matches!(self.host, Host::Asio(_)) {
+            return Err(anyhow!("ASIO plays on a single device at a time"));
+        }
+        Player::new(
+            self.host,
+            device_id,
+            self.pollmode,
+            self.config.clone(),
+            self.safe_mode,
+            self.tasks.clone(),
+        )
+    }
+
+    /// Keeps to the device chosen once the list of the host changed, or to the default one
+    /// opened in its place.
+    fn follow_located(&mut self, located: Located) {
+        match located {
+            Located::Same => (),
+            Located::Moved(index) => self.device_id = Some(index),
+            Located::Gone => {
+                let gone = self.device_name.clone().unwrap_or_else(|| {
+                    format!("Output device {}", self.device_id.unwrap_or_default())
+                });
+                warn!("{} is gone, falling back to the default device", gone);
+                self.switch_device(None);
+                self.stale_device = Some(gone);
+            }
+        }
+    }
+
+    /// Drops what was opened or probed on the previous device.
+    fn switch_device(&mut self, device_id: Option<u32>) {
+        self.device_id = device_id;
+        self.device_name = device_name(self.host, device_id);
+        if let Ok(mut prefetched) = self.prefetched.lock() {
+            prefetched.take();
+        }
+        self.prefetching = None;
+        self.capabilities = Arc::new(OnceLock::new());
+        self.probe_capabilities();
+    }
+
+    pub fn is_pollmode(&self) -> bool {
+        self.pollmode
+    }
+
+    /// Shares of one core used by decoding, including DSP and resampling, and by rendering.
+    pub fn cpu_usage(&self) -> (f64, f64) {
+        let render = self
+            .current_device
+            .as_ref()
+            .map_or(0.0, |device| device.cpu_usage());
+        (self.decode_cpu.usage(), render)
+    }
+
+    /// Starts the next track `gain_db` down, back to its volume after `seconds`, in place of
+    /// the smart volume ramp.
+    pub fn fade_in_next(&mut self, gain_db: f64, seconds: f64) {
+        self.fade_in = Some((gain_db, seconds));
+    }
+
+    /// Returns the attenuation and ramp length to apply when the next track is much louder
+    /// than the previous one, tracks without loudness data keep the previous reference.
+    fn smart_volume_ramp(&mut self, loudness: Option<f64>) -> Option<(f64, f64)> {
+        let smart_volume = self.config.smart_volume;
+        let previous = self.last_loudness;
+        let loudness = loudness?;
+        self.last_loudness = Some(loudness);
+        let jump = loudness - previous?;
+        if smart_volume.enabled && jump > smart_volume.threshold_db {
+            Some((-jump, smart_volume.ramp_seconds))
+        } else {
+            None
+        }
+    }
+
     pub async fn play(&mut self, song: Arc<MusicTrack>) -> Result<CurrentTrackInfo> {
+        // Vocal attenuation is enabled per track
+        self.dsp_settings.set_karaoke(false);
+        let start = song.segment.map_or(0, |segment| segment.pregap as i64);
+        let info = self.start(song, start).await?;
+        self.session.tracks += 1;
+        Ok(info)
+    }
+
+    /// Plays a track from `position` seconds, where it was left.
+    pub async fn resume(
+        &mut self,
+        song: Arc<MusicTrack>,
+        position: f64,
+    ) -> Result<CurrentTrackInfo> {
+        self.dsp_settings.set_karaoke(false);
+        let info = self.seek(song, position).await?;
+        self.session.tracks += 1;
+        Ok(info)
+    }
+
+    /// Totals since rhap started, the current device and track included.
+    pub fn session(&self) -> Session {
+        let mut session = self.session.clone();
+        Self::count(
+            &mut session,
+            self.current_device.as_ref(),
+            self.streamed.as_ref(),
+        );
+        session
+    }
+
+    fn count(session: &mut Session, device: Option<&Device>, streamed: Option<&CurrentTrackInfo>) {
+        if let Some(device) = device {
+            session.underruns += device.underruns();
+            session.render_cpu += device.cpu_time();
+        }
+        if let Some(streamed) = streamed {
+            session.listening += Duration::from_secs_f64(streamed.streamed_seconds());
+        }
+    }
+
+    /// Restarts the track `position` seconds into it, from the start of its pre-gap at most.
+    /// DoP frames cannot be trimmed, DSD tracks are not seekable, nor are internet radios.
+    pub async fn seek(&mut self, song: Arc<MusicTrack>, position: f64) -> Result<CurrentTrackInfo> {
+        if song.dsd_rate.is_some() {
+            return Err(anyhow!("DSD tracks cannot be seeked: {}", song.path));
+        }
+        if song.is_stream() {
+            return Err(anyhow!("Internet radios cannot be seeked: {}", song.path));
+        }
+        self.stop().await?;
+        let segment = song.segment.unwrap_or(Segment {
+            start: 0,
+            end: None,
+            pregap: 0,
+        });
+        let rate = song.sample as u64 as f64;
+        let origin = (segment.start + segment.pregap) as i64;
+        let frame = (origin + (position * rate) as i64).max(segment.start as i64);
+        let seeked = MusicTrack {
+            segment: Some(Segment {
+                start: frame as u64,
+                pregap: 0,
+                ..segment
+            }),
+            ..(*song).clone()
+        };
+        self.start(Arc::new(seeked), origin - frame).await
+    }
+
+    async fn start(&mut self, song: Arc<MusicTrack>, start: i64) -> Result<CurrentTrackInfo> {
         let streamparams = StreamParams {
             samplerate: song.sample,
             channels: song.channels as u8,
             bits_per_sample: song.bits_per_sample,
-            exclusive: true,
+            // DoP is garbled by the mixer
+            exclusive: !self.safe_mode && (!self.shared_mode || song.dsd_rate.is_some()),
             pollmode: self.pollmode,
+            // Scaling DoP frames would corrupt the markers, pause switches abruptly
+            fade: match song.dsd_rate {
+                Some(_) => FadeDurations::default(),
+                None => self.config.fade.durations(),
+            },
         };
-        let mut device = self.host.create_device(self.device_id)?;
-        let adjusted_params = device.adjust_stream_params(&streamparams)?;
-        let data_sender = device.start(&adjusted_params)?;
-        self.current_device = Some(device);
+        // DoP must reach the DAC bit perfect, it cannot be resampled nor converted
+        let is_dop = song.dsd_rate.is_some();
+        // Another track opened ahead is dropped
+        let prefetched = self
+            .prefetched
+            .lock()
+            .ok()
+            .and_then(|mut prefetched| prefetched.take())
+            .filter(|prefetched| is_same_track(&prefetched.song, &song));
+        self.prefetching = None;
+        let (prefetched_device, opened) = match prefetched {
+            Some(prefetched) => (Some(prefetched.device), Some(prefetched.opened)),
+            None => (None, None),
+        };
+        let strict_gapless = self.is_strict_gapless(&song.artist, &song.album);
+        // Claimed under the lock, the outgoing track cannot give up on the handover meanwhile
+        let handover = self.handover.lock().ok().and_then(|mut handover| {
+            let handover = handover
+                .take()
+                .filter(|handover| !strict_gapless && handover.accepts(&song))?;
+            handover.claimed.store(true, Ordering::Relaxed);
+            Some(handover)
+        });
+        let (adjusted_params, data_sender, fade_out) = if let Some(handover) = handover {
+            info!("Crossfading {} into {}", handover.song.path, song.path);
+            // The device stays open, its counters keep going
+            Self::count(&mut self.session, None, self.streamed.take().as_ref());
+            if let Some(handle) = self.fading_handle.take() {
+                handle.abort();
+            }
+            self.fading_handle = self.streaming_handle.take();
+            let remaining = handover.remaining.load(Ordering::Relaxed);
+            (
+                handover.params,
+                handover.sender,
+                Some(FadeOut::new(handover.tail, remaining)),
+            )
+        } else {
+            let mut device = match prefetched_device {
+                Some(device) => device,
+                None => {
+                    let (host, device_id) = (self.host, self.device_id);
+                    let name = self.device_name.clone();
+                    let (device, located) =
+                        unblock(move || open_device(host, device_id, name.as_deref())).await?;
+                    self.follow_located(located);
+                    device
+                }
+            };
+            let device_config = self
+                .config
+                .device(&device.name()?)
+                .cloned()
+                .unwrap_or_default();
+            self.dsp_settings.set_speaker_delays(
+                device_config
+                    .delays
+                    .iter()
+                    .map(ChannelDelay::as_seconds)
+                    .collect(),
+            );
+            self.dsp_settings
+                .set_limits(device_config.max_volume_db, device_config.headroom_db);
+            // Safe mode plays tracks untouched
+            let downmix = song.channels > 2
+                && !is_dop
+                && !self.safe_mode
+                && match self.config.output.downmix {
+                    DownmixMode::Never => false,
+                    DownmixMode::Always => true,
+                    DownmixMode::Auto => match device_config.channels {
+                        Some(pinned) => (pinned as usize) < song.channels,
+                        None => {
+                            let (probed, capabilities) = unblock(move || {
+                                let capabilities = probe::capabilities(&device);
+                                Ok((device, capabilities))
+                            })
+                            .await?;
+                            device = probed;
+                            capabilities.is_ok_and(|capabilities| {
+                                capabilities.layout_for(song.channels as u8).is_none()
+                            })
+                        }
+                    },
+                };
+            self.dsp_settings.set_downmix(downmix);
+            // Only stereo is upmixed
+            let upmix = device_config
+                .upmix
+                .filter(|_| song.channels == 2 && !is_dop && !self.safe_mode);
+            self.dsp_settings.set_upmix(upmix);
+            let channels = match upmix {
+                _ if downmix => 2,
+                Some(upmix) => upmix.channels(),
+                None => song.channels,
+            };
+            self.played_channels = channels;
+            self.routing = match device_routing(&device_config, channels) {
+                Ok(routing) => routing,
+                Err(err) => {
+                    warn!(
+                        "Ignoring the channel routing of {}: {}",
+                        device.name()?,
+                        err
+                    );
+                    None
+                }
+            };
+            // DoP cannot be resampled nor converted
+            let mut pinned = match device_config.pinned_format() {
+                Ok(_) if is_dop => PinnedFormat::default(),
+                Ok(pinned) => pinned,
+                Err(err) => {
+                    warn!("Ignoring the format set for {}: {}", device.name()?, err);
+                    PinnedFormat::default()
+                }
+            };
+            // Routed tracks and DoP keep their channels
+            pinned.channels = match &self.routing {
+                Some(routing) => Some(routing.channels() as u8),
+                None if is_dop => Some(channels as u8),
+                None => None,
+            };
+            // The device gets the channels the track is routed to
+            let device_params = StreamParams {
+                channels: self
+                    .routing
+                    .as_ref()
+                    .map_or(channels as u8, |routing| routing.channels() as u8),
+                ..streamparams
+            };
+            let path = song.path.clone();
+            let samplerate = song.sample;
+            let shared_mode = !device_params.exclusive && !self.safe_mode;
+            // Probing and opening the device block on the driver
+            let (device, adjusted_params, data_sender) = unblock(move || {
+                let mut device = device;
+                if is_dop && !probe::capabilities(&device)?.supports_dop(samplerate) {
+                    return Err(anyhow!(
+                        "{} does not accept DoP at {}Hz",
+                        device.name()?,
+                        samplerate as usize
+                    ));
+                }
+
+                let mut adjusted_params = device.adjust_stream_params(&device_params, &pinned)?;
+                if shared_mode {
+                    adjusted_params = device.shared_params(&adjusted_params)?;
+                }
+                info!(
+                    "Playing {} on {} at {}Hz {} bits, exclusive: {}",
+                    path,
+                    device.name()?,
+                    adjusted_params.samplerate as usize,
+                    adjusted_params.bits_per_sample as usize,
+                    adjusted_params.exclusive
+                );
+                let data_sender = match device.start(&adjusted_params) {
+                    Ok(data_sender) => data_sender,
+                    // DoP cannot go through the mixer
+                    Err(err) if !is_dop && err.is::<ExclusiveRefused>() => {
+                        adjusted_params = device.shared_params(&adjusted_params)?;
+                        warn!(
+                            "{}, playing in shared mode at {}Hz {} bits",
+                            err,
+                            adjusted_params.samplerate as usize,
+                            adjusted_params.bits_per_sample as usize
+                        );
+                        device.start(&adjusted_params)?
+                    }
+                    // The stored capabilities may be stale, such as after a firmware update
+                    Err(err) => {
+                        probe::forget(&device);
+                        return Err(err);
+                    }
+                };
+                Ok((device, adjusted_params, data_sender))
+            })
+            .await?;
+            // The device took a larger layout, each speaker goes to its place in it
+            if adjusted_params.channels != device_params.channels {
+                self.routing = ChannelRouting::between(
+                    device_params.channels as usize,
+                    adjusted_params.channels as usize,
+                );
+            }
+            Self::count(
+                &mut self.session,
+                self.current_device.as_ref(),
+                self.streamed.take().as_ref(),
+            );
+            self.current_device = Some(device);
+            (adjusted_params, data_sender, None)
+        };
+        self.is_paused = false;
         self.previous_stream = Some(data_sender);
         let stream = self.previous_stream.clone();
-        let progress = Arc::new(AtomicU64::new(0));
-        let is_streaming = Arc::new(AtomicBool::new(true));
-        let report_streaming = Arc::clone(&is_streaming);
+        let (report_progress, progress) = watch::channel(TrackProgress::default());
         let is_playing = self.is_playing.clone();
-        self.streaming_handle = Some(tokio::spawn(async move {
-            let mut format = song.format.lock().await;
-            format.seek(
-                SeekMode::Accurate,
-                SeekTo::Time {
-                    time: Time::default(),
-                    track_id: None,
-                },
-            )?;
-            let mut decoder = song.decoder.lock().await;
-            decoder.reset();
+        let dsp_settings = self.dsp_settings.clone();
+        let bypass_dsp = self.safe_mode;
+        let smart_volume_ramp = self.smart_volume_ramp(song.loudness);
+        let gain_ramp = self.fade_in.take().or(smart_volume_ramp);
+        let decode_cpu = self.decode_cpu.clone();
+        let tap = self.tap.clone();
+        let ended = self.ended.clone();
+        self.levels = Arc::new(Levels::new(self.played_channels));
+        let levels = self.levels.clone();
+        let resampler_settings = self.config.resampler;
+        let routing = self.routing.clone();
+        let song_rate = song.sample as u64;
+        let duration = song.duration.seconds as f64 + song.duration.frac;
+        self.resampler = (song.sample != adjusted_params.samplerate).then_some(resampler_settings);
+        self.output = Some(OutputFormat {
+            samplerate: adjusted_params.samplerate,
+            bits_per_sample: adjusted_params.bits_per_sample,
+            channels: adjusted_params.channels,
+            exclusive: adjusted_params.exclusive,
+            shared_fallback: streamparams.exclusive && !adjusted_params.exclusive,
+        });
+        let handover = self.handover.clone();
+        // Progress at which the crossfade starts and at which the track ends
+        let fade_bounds = self
+            .config
+            .crossfade
+            .seconds()
+            .filter(|_| {
+                !is_dop
+                    && !strict_gapless
+                    && !song.is_stream()
+                    && song.sample == adjusted_params.samplerate
+            })
+            .map(|seconds| {
+                let frames = |seconds: f64| (seconds.max(0.0) * song_rate as f64) as i64;
+                (start + frames(duration - seconds), start + frames(duration))
+            });
+        let mut fade_out = fade_out;
+        let name = format!("Stream {}", song.title);
+        self.streaming_handle = Some(self.tasks.spawn(name, async move {
+            let mut opened = match opened {
+                Some(opened) => opened,
+                None => Opened::open(&song)?,
+            };
+            let segment = song.segment;
             is_playing.store(true, Ordering::Relaxed);
+            let mut handed_over = false;
+            let mut decoded_frames = 0u64;
             if let Some(streamer) = stream {
                 let mut buffer: Option<StreamBuffer> = None;
                 let mut resampler: Option<Resampler> = None;
+                let mut resampled = Vec::new();
+                let mut dsp = DspChain::new(dsp_settings);
+                // Packets straddling the bounds of a CUE sheet track
+                let mut trimmed: Option<AudioBuffer<f64>> = None;
+                // Packets mixed with the end of the previous track
+                let mut mixed: Option<AudioBuffer<f64>> = None;
+                // End of this track, sent to the next one once it claims the handover
+                let mut tail: Option<Tail> = None;
+                if let Some((gain_db, seconds)) = gain_ramp {
+                    dsp.start_gain_ramp(gain_db, seconds);
+                }
                 loop {
                     if !is_playing.load(Ordering::Relaxed) {
                         break;
                     }
-                    let packet = match format.next_packet() {
+                    let packet = match opened.next_packet() {
                         Ok(packet) => packet,
                         Err(Error::ResetRequired) => {
-                            unimplemented!();
+                            opened.reset(&song)?;
+                            continue;
                         }
                         Err(Error::IoError(err)) => {
                             // Error reading packet: IoError(Custom { kind: UnexpectedEof, error: "end of stream" })
@@ -247,11 +1307,108 @@ impl Player {
                             break;
                         }
                     };
-                    progress.store(
-                        progress.load(Ordering::Relaxed) + packet.dur,
-                        Ordering::Relaxed,
-                    );
-                    let decoded = decoder.decode(&packet)?;
+                    let decoded = {
+                        let _busy = decode_cpu.busy();
+                        let decoded = trace_span!("decode", ts = packet.ts)
+                            .in_scope(|| opened.decoder.decode(&packet))?;
+                        let frames = decoded.frames();
+                        let decoded = match segment.map(|segment| segment.trim(packet.ts, frames)) {
+                            None | Some(Some((0, 0))) => decoded,
+                            Some(None) => break,
+                            Some(Some((start, end))) if start + end >= frames => continue,
+                            Some(Some((start, end))) => {
+                                let reusable = match trimmed.take() {
+                                    Some(buffer)
+                                        if buffer.capacity() >= decoded.capacity()
+                                            && buffer.spec() == decoded.spec() =>
+                                    {
+                                        buffer
+                                    }
+                                    _ => decoded.make_equivalent(),
+                                };
+                                let buffer = trimmed.insert(reusable);
+                                decoded.convert(buffer);
+                                buffer.trim(start, end);
+                                buffer.as_audio_buffer_ref()
+                            }
+                        };
+                        decoded_frames += decoded.frames() as u64;
+                        report_progress.send_modify(|progress| progress.frames = decoded_frames);
+                        if let Some((fade_at, end)) = fade_bounds {
+                            let remaining = (end - decoded_frames as i64).max(0) as u64;
+                            match &tail {
+                                Some(tail) => tail.remaining.store(remaining, Ordering::Relaxed),
+                                None if decoded_frames as i64 >= fade_at => {
+                                    let (sender, receiver) = channel(TAIL_CHUNKS);
+                                    let claimed = Arc::new(AtomicBool::new(false));
+                                    let left = Arc::new(AtomicU64::new(remaining));
+                                    if let Ok(mut handover) = handover.lock() {
+                                        *handover = Some(Handover {
+                                            song: song.clone(),
+                                            params: adjusted_params,
+                                            sender: streamer.clone(),
+                                            tail: receiver,
+                                            claimed: claimed.clone(),
+                                            remaining: left.clone(),
+                                        });
+                                    }
+                                    tail = Some(Tail {
+                                        sender,
+                                        claimed,
+                                        remaining: left,
+                                    });
+                                    report_progress
+                                        .send_modify(|progress| progress.fading_out = true);
+                                    ended.notify_one();
+                                }
+                                None => (),
+                            }
+                        }
+                        if dsp.is_active() && !is_dop && !bypass_dsp {
+                            dsp.process(&decoded)
+                        } else {
+                            decoded
+                        }
+                    };
+                    let decoded = match fade_out.as_mut() {
+                        Some(fade) => {
+                            let reusable = match mixed.take() {
+                                Some(buffer)
+                                    if buffer.capacity() >= decoded.capacity()
+                                        && buffer.spec() == decoded.spec() =>
+                                {
+                                    buffer
+                                }
+                                _ => decoded.make_equivalent(),
+                            };
+                            let buffer = mixed.insert(reusable);
+                            decoded.convert(buffer);
+                            if !fade.mix(buffer).await {
+                                fade_out = None;
+                            }
+                            buffer.as_audio_buffer_ref()
+                        }
+                        None => decoded,
+                    };
+                    if let Some(Tail {
+                        sender, claimed, ..
+                    }) = &tail
+                    {
+                        if claimed.load(Ordering::Relaxed) {
+                            let mut chunk: AudioBuffer<f64> = decoded.make_equivalent();
+                            decoded.convert(&mut chunk);
+                            if sender.send(chunk).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        }
+                    }
+                    if !is_dop {
+                        levels.store_buffer(&decoded);
+                        if tap.is_enabled() {
+                            tap.push(&decoded);
+                        }
+                    }
                     let spec = decoded.spec();
                     let frames = decoded.capacity();
                     let sample_buffer = buffer.get_or_insert_with(|| {
@@ -266,37 +1423,234 @@ impl Player {
                                 streamparams.samplerate as usize,
                                 adjusted_params.samplerate as usize,
                                 frames,
-                                adjusted_params.channels as usize,
+                                spec.channels.count(),
+                                resampler_settings,
                             )
                             .unwrap()
                         });
                         if resampled_sender
-                            .send_resampled_data(&decoded, &streamer)
+                            .send_resampled_data(
+                                &decoded,
+                                &streamer,
+                                routing.as_ref(),
+                                &decode_cpu,
+                                &mut resampled,
+                            )
                             .await
                             .is_err()
                         {
                             break;
                         }
                     } else {
-                        sample_buffer.copy_interleaved_ref(decoded);
-                        for i in sample_buffer.as_bytes().iter() {
-                            if streamer.send(StreamingData::Data(*i)).await.is_err() {
-                                break;
-                            }
+                        let routed = {
+                            let _busy = decode_cpu.busy();
+                            sample_buffer.copy_interleaved_ref(decoded);
+                            routing.as_ref().map(|routing| {
+                                let sample_size = adjusted_params.bits_per_sample as usize / 8;
+                                routing.route(sample_buffer.as_bytes(), sample_size)
+                            })
+                        };
+                        let bytes = routed.as_deref().unwrap_or(sample_buffer.as_bytes());
+                        if streamer.write(bytes).await.is_err() {
+                            break;
                         }
                     }
                 }
-                streamer.send(StreamingData::EndOfStream).await?;
-                streamer.closed().await;
+                // The next track goes on streaming once it claimed the tail
+                if let Some(Tail { claimed, .. }) = &tail {
+                    if let Ok(mut handover) = handover.lock() {
+                        handed_over = claimed.load(Ordering::Relaxed);
+                        if handover
+                            .as_ref()
+                            .is_some_and(|handover| Arc::ptr_eq(&handover.claimed, claimed))
+                        {
+                            handover.take();
+                        }
+                    }
+                }
+                if !handed_over {
+                    streamer.end();
+                    streamer.closed().await;
+                }
             }
 
-            is_streaming.store(false, Ordering::Relaxed);
-            is_playing.store(false, Ordering::Relaxed);
+            report_progress.send_modify(|progress| progress.streaming = false);
+            if !handed_over {
+                is_playing.store(false, Ordering::Relaxed);
+            }
+            ended.notify_one();
             Ok::<(), anyhow::Error>(())
         }));
 
-        Ok(CurrentTrackInfo {
-            is_streaming: report_streaming,
-        })
+        let info = CurrentTrackInfo {
+            progress,
+            sample_rate: song_rate,
+            start,
+            duration,
+        };
+        self.streamed = Some(info.clone());
+        self.observed.send_replace(Observed {
+            playback: Playback::Playing,
+            track: Some(info.clone()),
+        });
+        Ok(info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use symphonia::core::audio::{AsAudioBufferRef, AudioBuffer, Channels, Signal};
+    use symphonia::core::sample::Sample;
+
+    fn spec() -> SignalSpec {
+        SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT)
+    }
+
+    /// Packs stereo frames the way they are streamed to the device.
+    fn pack<S: Sample>(bits_per_sample: BitsPerSample, frames: &[(S, S)]) -> Vec<u8>
+    where
+        AudioBuffer<S>: AsAudioBufferRef,
+    {
+        let mut decoded = AudioBuffer::<S>::new(frames.len() as u64, spec());
+        decoded.render_reserved(Some(frames.len()));
+        let (left, right) = decoded.chan_pair_mut(0, 1);
+        for (index, (l, r)) in frames.iter().enumerate() {
+            left[index] = *l;
+            right[index] = *r;
+        }
+        let mut buffer = StreamBuffer::new(bits_per_sample, frames.len(), spec());
+        buffer.copy_interleaved_ref(decoded.as_audio_buffer_ref());
+        buffer.as_bytes().to_vec()
+    }
+
+    fn interleave<S: Copy>(frames: &[(S, S)]) -> Vec<S> {
+        frames.iter().flat_map(|(l, r)| [*l, *r]).collect()
+    }
+
+    fn unpack_i16(bytes: &[u8]) -> Vec<i16> {
+        bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect()
+    }
+
+    fn unpack_i24(bytes: &[u8]) -> Vec<i32> {
+        bytes
+            .chunks_exact(3)
+            .map(|b| i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8)
+            .collect()
+    }
+
+    fn unpack_f32(bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()
+    }
+
+    fn i24_sample() -> impl Strategy<Value = i24> {
+        (-(1 << 23)..(1 << 23)).prop_map(i24::from)
+    }
+
+    fn f32_sample() -> impl Strategy<Value = f32> {
+        -1.0f32..=1.0
+    }
+
+    #[test]
+    fn chained_ogg_streams_go_on_with_a_new_decoder() {
+        // Two songs of about one second, see tests/assets/generate.py
+        let path = format!("{}/tests/assets/chained.ogg", env!("CARGO_MANIFEST_DIR"));
+        let song = MusicTrack::new(path).unwrap();
+        let mut opened = Opened::open(&song).unwrap();
+        let mut resets = 0;
+        let mut frames = 0;
+        loop {
+            match opened.next_packet() {
+                Ok(packet) => frames += opened.decoder.decode(&packet).unwrap().frames(),
+                Err(Error::ResetRequired) => {
+                    resets += 1;
+                    opened.reset(&song).unwrap();
+                }
+                Err(_) => break,
+            }
+        }
+
+        assert_eq!(resets, 1);
+        assert_eq!(frames, 2 * 344 * 128);
+    }
+
+    proptest! {
+        #[test]
+        fn i16_round_trips(frames in prop::collection::vec(any::<(i16, i16)>(), 1..512)) {
+            let bytes = pack(BitsPerSample::Bits16, &frames);
+            prop_assert_eq!(unpack_i16(&bytes), interleave(&frames));
+        }
+
+        #[test]
+        fn i24_round_trips_packed(
+            frames in prop::collection::vec((i24_sample(), i24_sample()), 1..512),
+        ) {
+            let bytes = pack(BitsPerSample::Bits24, &frames);
+            prop_assert_eq!(bytes.len(), frames.len() * 2 * 3);
+            let expected: Vec<i32> = interleave(&frames).iter().map(|s| s.inner()).collect();
+            prop_assert_eq!(unpack_i24(&bytes), expected);
+        }
+
+        #[test]
+        fn f32_round_trips(frames in prop::collection::vec((f32_sample(), f32_sample()), 1..512)) {
+            let bytes = pack(BitsPerSample::Bits32, &frames);
+            prop_assert_eq!(unpack_f32(&bytes), interleave(&frames));
+        }
+
+        #[test]
+        fn f32_to_i16_stays_within_one_step(
+            frames in prop::collection::vec((f32_sample(), f32_sample()), 1..512),
+        ) {
+            let bytes = pack(BitsPerSample::Bits16, &frames);
+            for (packed, sample) in unpack_i16(&bytes).iter().zip(interleave(&frames)) {
+                let error = (*packed as f32 / 32768.0 - sample).abs();
+                prop_assert!(error <= 1.0 / 32768.0, "{} from {}", packed, sample);
+            }
+        }
+
+        #[test]
+        fn integers_to_f32_are_bounded(
+            frames in prop::collection::vec(any::<(i16, i16)>(), 1..512),
+            frames_24 in prop::collection::vec((i24_sample(), i24_sample()), 1..512),
+        ) {
+            let mut samples = unpack_f32(&pack(BitsPerSample::Bits32, &frames));
+            samples.extend(unpack_f32(&pack(BitsPerSample::Bits32, &frames_24)));
+            for sample in samples {
+                prop_assert!(!sample.is_nan());
+                prop_assert!((-1.0..=1.0).contains(&sample), "{}", sample);
+            }
+        }
+
+        #[test]
+        fn i24_to_i16_keeps_the_upper_bits(
+            frames in prop::collection::vec((i24_sample(), i24_sample()), 1..512),
+        ) {
+            let bytes = pack(BitsPerSample::Bits16, &frames);
+            for (packed, sample) in unpack_i16(&bytes).iter().zip(interleave(&frames)) {
+                prop_assert!((*packed as i32 - (sample.inner() >> 8)).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn packets_read_ahead_are_streamed_first() {
+        let path = format!("{}/tests/assets/tagged.flac", env!("CARGO_MANIFEST_DIR"));
+        let mut opened = Opened::open(&MusicTrack::new(path).unwrap()).unwrap();
+        for _ in 0..2 {
+            let packet = opened.format.next_packet().unwrap();
+            opened.packets.push_back(packet);
+        }
+        let ahead: Vec<u64> = opened.packets.iter().map(|packet| packet.ts).collect();
+
+        assert_eq!(opened.next_packet().unwrap().ts, ahead[0]);
+        assert_eq!(opened.next_packet().unwrap().ts, ahead[1]);
+        assert!(opened.next_packet().unwrap().ts > ahead[1]);
     }
 }
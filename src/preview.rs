@@ -0,0 +1,69 @@
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::config::PreviewConfig;
+use crate::musictrack::MusicTrack;
+use crate::player::{CurrentTrackInfo, Player};
+
+/// Pre-listening of tracks on a second output device, e.g. headphones, while the main player
+/// goes on with the playlist on its own device.
+pub struct Preview {
+    player: Player,
+    /// Settings the device was opened with
+    config: PreviewConfig,
+    device: String,
+    /// Playlist index of the track previewed, with its progress
+    playing: Option<(usize, CurrentTrackInfo)>,
+}
+
+impl Preview {
+    /// Opens the preview device set in the config of `main`, `None` when there is none.
+    pub fn new(main: &Player) -> Result<Option<Self>> {
+        let config = main.config().preview.clone();
+        let Some(device) = config.device.clone() else {
+            return Ok(None);
+        };
+        let mut player = main.sibling(Some(main.device_id(&device)?))?;
+        player.change_volume(config.volume_db);
+        Ok(Some(Self {
+            player,
+            config,
+            device,
+            playing: None,
+        }))
+    }
+
+    pub fn config(&self) -> &PreviewConfig {
+        &self.config
+    }
+
+    pub fn device(&self) -> &str {
+        &self.device
+    }
+
+    /// Playlist index of the track previewed, until it ends.
+    pub fn playing(&self) -> Option<usize> {
+        self.playing
+            .as_ref()
+            .filter(|(_, info)| info.is_streaming())
+            .map(|(index, _)| *index)
+    }
+
+    /// Plays `song` in place of the track previewed. `main` must play on another device, both
+    /// cannot hold the same one.
+    pub async fn play(&mut self, main: &Player, index: usize, song: Arc<MusicTrack>) -> Result<()> {
+        let main_device = main.device_name().or_else(|| main.default_device_name());
+        if main_device.as_deref() == Some(self.device.as_str()) {
+            return Err(anyhow!("{} plays the playlist", self.device));
+        }
+        self.stop().await?;
+        let info = self.player.play(song).await?;
+        self.playing = Some((index, info));
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        self.playing = None;
+        self.player.stop().await
+    }
+}
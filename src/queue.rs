@@ -0,0 +1,241 @@
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::VecDeque;
+
+/// Where tracks added from outside the app land in the playlist.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Placement {
+    /// At the end, queued after the tracks already queued
+    End,
+    /// Right after the playing track, at the start when stopped, queued to play next
+    Next,
+    /// At this position, not queued
+    At(usize),
+}
+
+/// Tracks to play before the playlist resumes its linear order, stored as playlist indexes.
+#[derive(Default)]
+pub struct Queue {
+    /// Albums played right after the current track, each one through before anything else
+    blocks: VecDeque<VecDeque<usize>>,
+    /// Set once the first album started, nothing goes in front of it until it is done
+    block_started: bool,
+    /// Played right after the current track and the albums, ahead of the regular entries
+    priority: VecDeque<usize>,
+    entries: VecDeque<usize>,
+}
+
+impl Queue {
+    pub fn add(&mut self, index: usize) {
+        self.entries.push_back(index);
+    }
+
+    pub fn play_next(&mut self, index: usize) {
+        self.entries.push_front(index);
+    }
+
+    /// Tracks prioritized in a row play in the order they were added.
+    pub fn prioritize(&mut self, index: usize) {
+        self.priority.push_back(index);
+    }
+
+    /// Queues an album as a block, after the album playing if any.
+    pub fn add_block(&mut self, indexes: Vec<usize>) {
+        if indexes.is_empty() {
+            return;
+        }
+        let at = usize::from(self.block_started);
+        self.blocks.insert(at, indexes.into());
+    }
+
+    pub fn pop(&mut self) -> Option<usize> {
+        if let Some(block) = self.blocks.front_mut() {
+            let index = block.pop_front();
+            self.block_started = !block.is_empty();
+            if block.is_empty() {
+                self.blocks.pop_front();
+            }
+            return index;
+        }
+        self.priority
+            .pop_front()
+            .or_else(|| self.entries.pop_front())
+    }
+
+    /// Drops the tracks about to play while `skipped` holds for them, through albums and
+    /// entries alike.
+    pub fn skip_while(&mut self, mut skipped: impl FnMut(usize) -> bool) {
+        while self.iter().next().is_some_and(|(index, _)| skipped(index)) {
+            self.pop();
+        }
+    }
+
+    /// Drops a track removed from the playlist and shifts the indexes following it.
+    pub fn remove(&mut self, index: usize) {
+        let lists = self
+            .blocks
+            .iter_mut()
+            .chain([&mut self.priority, &mut self.entries]);
+        for entries in lists {
+            entries.retain(|entry| *entry != index);
+            for entry in entries.iter_mut().filter(|entry| **entry > index) {
+                *entry -= 1;
+            }
+        }
+        if self.blocks.front().is_none_or(VecDeque::is_empty) {
+            self.block_started = false;
+        }
+        self.blocks.retain(|block| !block.is_empty());
+    }
+
+    /// Follows tracks moved or inserted in the playlist, `moved` giving the new index of each.
+    pub fn remap(&mut self, mut moved: impl FnMut(usize) -> usize) {
+        let lists = self
+            .blocks
+            .iter_mut()
+            .chain([&mut self.priority, &mut self.entries]);
+        for entry in lists.flatten() {
+            *entry = moved(*entry);
+        }
+    }
+
+    /// Shuffles the regular entries and the order of the albums, the tracks of an album stay
+    /// together and in order, the album playing stays in front.
+    pub fn shuffle(&mut self, rng: &mut impl Rng) {
+        self.entries.make_contiguous().shuffle(rng);
+        let start = usize::from(self.block_started);
+        self.blocks.make_contiguous()[start..].shuffle(rng);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.priority.is_empty() && self.entries.is_empty()
+    }
+
+    /// Queued tracks in play order with when each one starts, `offset` seconds from now and
+    /// after the `duration` of the tracks ahead of it.
+    pub fn starts<'a>(
+        &'a self,
+        offset: f64,
+        duration: impl Fn(usize) -> f64 + 'a,
+    ) -> impl Iterator<Item = (usize, f64)> + 'a {
+        self.iter().scan(offset, move |start, (index, _)| {
+            let starts = *start;
+            *start += duration(index);
+            Some((index, starts))
+        })
+    }
+
+    /// Iterates in play order, telling whether each track is prioritized, albums included.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, bool)> + '_ {
+        self.blocks
+            .iter()
+            .flatten()
+            .chain(self.priority.iter())
+            .map(|index| (*index, true))
+            .chain(self.entries.iter().map(|index| (*index, false)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn drain(queue: &mut Queue) -> Vec<usize> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn albums_play_through_before_tracks_queued_meanwhile() {
+        let mut queue = Queue::default();
+        queue.add(9);
+        queue.add_block(vec![1, 2, 3]);
+        assert_eq!(queue.pop(), Some(1));
+        queue.prioritize(8);
+        queue.add_block(vec![4, 5]);
+
+        assert_eq!(drain(&mut queue), vec![2, 3, 4, 5, 8, 9]);
+    }
+
+    #[test]
+    fn an_album_not_started_is_overtaken_by_the_next_one() {
+        let mut queue = Queue::default();
+        queue.add_block(vec![1, 2]);
+        queue.add_block(vec![3, 4]);
+
+        assert_eq!(drain(&mut queue), vec![3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn shuffle_keeps_albums_together_and_the_started_one_first() {
+        let mut queue = Queue::default();
+        queue.add_block(vec![1, 2, 3]);
+        queue.pop();
+        for album in [vec![10, 11, 12], vec![20, 21], vec![30, 31, 32, 33]] {
+            queue.add_block(album);
+        }
+        (40..50).for_each(|index| queue.add(index));
+        queue.shuffle(&mut StdRng::seed_from_u64(7));
+
+        let order = drain(&mut queue);
+        assert_eq!(&order[..2], &[2, 3]);
+        for album in [&[10, 11, 12][..], &[20, 21], &[30, 31, 32, 33]] {
+            let start = order.iter().position(|index| *index == album[0]).unwrap();
+            assert_eq!(&order[start..start + album.len()], album);
+        }
+        assert!(order[11..].iter().all(|index| (40..50).contains(index)));
+    }
+
+    #[test]
+    fn removing_the_rest_of_the_started_album_lets_the_next_one_be_overtaken() {
+        let mut queue = Queue::default();
+        queue.add_block(vec![1, 2]);
+        queue.pop();
+        queue.remove(2);
+        queue.add_block(vec![3]);
+        queue.add_block(vec![4]);
+
+        assert_eq!(drain(&mut queue), vec![4, 3]);
+    }
+
+    #[test]
+    fn skipping_an_album_drops_the_rest_of_its_block_only() {
+        let mut queue = Queue::default();
+        queue.add(9);
+        queue.add_block(vec![1, 2, 3]);
+        queue.pop();
+        queue.add_block(vec![4, 5]);
+        queue.skip_while(|index| index < 4);
+
+        assert_eq!(drain(&mut queue), vec![4, 5, 9]);
+    }
+
+    #[test]
+    fn entries_follow_the_tracks_moved_in_the_playlist() {
+        let mut queue = Queue::default();
+        queue.add(5);
+        queue.prioritize(1);
+        queue.add_block(vec![2, 3]);
+        // Track 2 moved down one row, then a track inserted at 4
+        queue.remap(|index| match index {
+            2 => 3,
+            3 => 2,
+            index => index,
+        });
+        queue.remap(|index| if index >= 4 { index + 1 } else { index });
+
+        assert_eq!(drain(&mut queue), vec![3, 2, 1, 6]);
+    }
+
+    #[test]
+    fn tracks_start_once_the_ones_ahead_are_played() {
+        let mut queue = Queue::default();
+        queue.add(3);
+        queue.prioritize(2);
+        queue.add_block(vec![1]);
+
+        let starts: Vec<(usize, f64)> = queue.starts(10.0, |index| index as f64 * 60.0).collect();
+        assert_eq!(starts, vec![(1, 10.0), (2, 70.0), (3, 190.0)]);
+    }
+}
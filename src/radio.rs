@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::thread;
+use symphonia::core::io::MediaSource;
+
+/// Chunks read ahead of the decoder, about 1MB or a minute of a 128kbps stream.
+const BUFFERED_CHUNKS: usize = 64;
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Station name and current song of each stream opened, keyed by URL.
+static STATIONS: Mutex<BTreeMap<String, Station>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Station {
+    /// From the `icy-name` header
+    name: Option<String>,
+    /// Last `StreamTitle` of the ICY metadata
+    title: Option<String>,
+}
+
+pub fn is_url(path: &str) -> bool {
+    let path = path.to_ascii_lowercase();
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Name the station gives itself, known once the stream has been opened.
+pub fn station_name(url: &str) -> Option<String> {
+    STATIONS.lock().ok()?.get(url)?.name.clone()
+}
+
+/// Song currently on air, for stations sending ICY metadata.
+pub fn stream_title(url: &str) -> Option<String> {
+    STATIONS.lock().ok()?.get(url)?.title.clone()
+}
+
+fn update(url: &str, update: impl FnOnce(&mut Station)) {
+    if let Ok(mut stations) = STATIONS.lock() {
+        update(stations.entry(url.to_string()).or_default());
+    }
+}
+
+/// `StreamTitle='Artist - Song';StreamUrl='';` padded with NULs.
+fn parse_stream_title(metadata: &[u8]) -> Option<String> {
+    let metadata = String::from_utf8_lossy(metadata);
+    let start = metadata.find("StreamTitle='")? + "StreamTitle='".len();
+    let end = metadata[start..]
+        .find("';")
+        .or_else(|| metadata[start..].rfind('\''))?;
+    let title = metadata[start..start + end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+/// Strips the ICY metadata blocks sent every `interval` bytes of audio, passing their titles
+/// to `on_title`.
+struct IcyReader<R, F> {
+    inner: R,
+    interval: usize,
+    /// Audio bytes left before the next metadata block
+    remaining: usize,
+    on_title: F,
+}
+
+impl<R: Read, F: FnMut(String)> IcyReader<R, F> {
+    fn new(inner: R, interval: usize, on_title: F) -> Self {
+        Self {
+            inner,
+            interval,
+            remaining: interval,
+            on_title,
+        }
+    }
+
+    /// Returns false at the end of the stream.
+    fn read_metadata(&mut self) -> io::Result<bool> {
+        let mut length = [0u8; 1];
+        if self.inner.read(&mut length)? == 0 {
+            return Ok(false);
+        }
+        let mut metadata = vec![0u8; length[0] as usize * 16];
+        self.inner.read_exact(&mut metadata)?;
+        if let Some(title) = parse_stream_title(&metadata) {
+            (self.on_title)(title);
+        }
+        self.remaining = self.interval;
+        Ok(true)
+    }
+}
+
+impl<R: Read, F: FnMut(String)> Read for IcyReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.interval == 0 {
+            return self.inner.read(buf);
+        }
+        if self.remaining == 0 && !self.read_metadata()? {
+            return Ok(0);
+        }
+        let length = buf.len().min(self.remaining);
+        let read = self.inner.read(&mut buf[..length])?;
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// HTTP stream read ahead by a thread, so a slow network does not stall the decoder until the
+/// buffer runs dry. Streams cannot be seeked.
+pub struct HttpSource {
+    /// Only read by the decoder, symphonia wants sources shareable between threads
+    chunks: Mutex<Receiver<io::Result<Vec<u8>>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl HttpSource {
+    pub fn open(url: &str) -> Result<Self> {
+        let response = ureq::get(url)
+            .set("Icy-MetaData", "1")
+            .call()
+            .map_err(|err| anyhow!("Cannot open {}: {}", url, err))?;
+        let interval = response
+            .header("icy-metaint")
+            .and_then(|interval| interval.trim().parse().ok())
+            .unwrap_or(0);
+        let name = response
+            .header("icy-name")
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        update(url, |station| station.name = name);
+        let station = url.to_string();
+        let mut reader = IcyReader::new(response.into_reader(), interval, move |title| {
+            update(&station, |station| station.title = Some(title))
+        });
+        let (sender, chunks) = mpsc::sync_channel(BUFFERED_CHUNKS);
+        let url = url.to_string();
+        thread::Builder::new()
+            .name("rhap-radio".to_string())
+            .spawn(move || loop {
+                let mut chunk = vec![0u8; CHUNK_SIZE];
+                let read = match reader.read(&mut chunk) {
+                    Ok(0) => return,
+                    Ok(read) => read,
+                    Err(err) => {
+                        warn!("Stream {} interrupted: {}", url, err);
+                        let _ = sender.send(Err(err));
+                        return;
+                    }
+                };
+                chunk.truncate(read);
+                // The decoder is gone once the track stops
+                if sender.send(Ok(chunk)).is_err() {
+                    return;
+                }
+            })?;
+        Ok(Self {
+            chunks: Mutex::new(chunks),
+            chunk: Vec::new(),
+            position: 0,
+        })
+    }
+}
+
+impl Read for HttpSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.chunk.len() {
+            let chunks = self.chunks.get_mut().map_err(|_| io::ErrorKind::Other)?;
+            self.chunk = match chunks.recv() {
+                Ok(chunk) => chunk?,
+                Err(_) => return Ok(0),
+            };
+            self.position = 0;
+        }
+        let length = buf.len().min(self.chunk.len() - self.position);
+        buf[..length].copy_from_slice(&self.chunk[self.position..self.position + length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+impl Seek for HttpSource {
+    fn seek(&mut self, _: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "HTTP streams cannot be seeked",
+        ))
+    }
+}
+
+impl MediaSource for HttpSource {
+    fn is_seekable(&self) -> bool {
+        false
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn parses_stream_titles() {
+        assert_eq!(
+            parse_stream_title(b"StreamTitle='Artist - It's a song';StreamUrl='';\0\0\0"),
+            Some("Artist - It's a song".to_string())
+        );
+        assert_eq!(parse_stream_title(b"StreamTitle='';\0\0"), None);
+        assert_eq!(parse_stream_title(b"\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn strips_metadata_blocks() {
+        let metadata = b"StreamTitle='Song';\0\0\0\0\0\0\0\0\0\0\0\0\0";
+        assert_eq!(metadata.len(), 32);
+        let mut stream = b"abcd".to_vec();
+        stream.push(2);
+        stream.extend_from_slice(metadata);
+        stream.extend_from_slice(b"efgh");
+        stream.push(0);
+        stream.extend_from_slice(b"ij");
+        let mut titles = Vec::new();
+        let mut audio = Vec::new();
+        IcyReader::new(Cursor::new(stream), 4, |title| titles.push(title))
+            .read_to_end(&mut audio)
+            .unwrap();
+        assert_eq!(audio, b"abcdefghij");
+        assert_eq!(titles, vec!["Song".to_string()]);
+    }
+}
@@ -0,0 +1,215 @@
+use anyhow::{anyhow, Result};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+
+use crate::audio::{
+    BitsPerSample, Device, DeviceTrait, FadeDurations, StreamParams, StreamingData,
+};
+use crate::tools::flac::FlacWriter;
+use crate::tools::levels::Levels;
+
+/// File being recorded, FLAC stores float captures as 24 bits integers.
+enum Target {
+    Wav(WavWriter<BufWriter<File>>),
+    Flac(FlacWriter<BufWriter<File>>),
+}
+
+impl Target {
+    fn create(path: &Path, params: &StreamParams) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("wav") => {
+                let spec = WavSpec {
+                    channels: params.channels as u16,
+                    sample_rate: params.samplerate as u32,
+                    bits_per_sample: params.bits_per_sample as u16,
+                    sample_format: match params.bits_per_sample {
+                        BitsPerSample::Bits32 => SampleFormat::Float,
+                        _ => SampleFormat::Int,
+                    },
+                };
+                Ok(Target::Wav(WavWriter::create(path, spec)?))
+            }
+            Some("flac") => {
+                let bits = match params.bits_per_sample {
+                    BitsPerSample::Bits16 => 16,
+                    _ => 24,
+                };
+                let writer = FlacWriter::new(
+                    BufWriter::new(File::create(path)?),
+                    params.samplerate as u32,
+                    params.channels as usize,
+                    bits,
+                    &[],
+                    &[],
+                )?;
+                Ok(Target::Flac(writer))
+            }
+            _ => Err(anyhow!("Only WAV and FLAC recording is supported")),
+        }
+    }
+
+    fn write_int(&mut self, value: i32) -> Result<()> {
+        match self {
+            Target::Wav(writer) => writer.write_sample(value)?,
+            Target::Flac(writer) => writer.write(&[value])?,
+        }
+        Ok(())
+    }
+
+    fn write_float(&mut self, value: f32) -> Result<()> {
+        match self {
+            Target::Wav(writer) => writer.write_sample(value)?,
+            Target::Flac(writer) => {
+                writer.write(&[(value.clamp(-1.0, 1.0) * 8388607.0).round() as i32])?
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            Target::Wav(writer) => writer.finalize()?,
+            Target::Flac(writer) => {
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Recorder {
+    device: Device,
+    params: StreamParams,
+    writer_handle: Option<JoinHandle<Result<()>>>,
+    levels: Arc<Levels>,
+    recorded_frames: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    pub fn new(device: Device, pollmode: bool) -> Result<Self> {
+        let capabilities = device.get_capabilities()?;
+        let params = StreamParams {
+            channels: 2,
+            samplerate: *capabilities
+                .sample_rates
+                .last()
+                .ok_or(anyhow!("No supported sample rate found"))?,
+            bits_per_sample: *capabilities
+                .bits_per_samples
+                .last()
+                .ok_or(anyhow!("No supported bits per sample found"))?,
+            exclusive: true,
+            pollmode,
+            fade: FadeDurations::default(),
+        };
+        Ok(Self {
+            device,
+            params,
+            writer_handle: None,
+            levels: Arc::new(Levels::new(params.channels as usize)),
+            recorded_frames: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    pub fn device_name(&self) -> Result<String> {
+        self.device.name()
+    }
+
+    pub fn info(&self) -> String {
+        format!(
+            "{}bits - {}KHz",
+            self.params.bits_per_sample as usize,
+            (self.params.samplerate as usize) as f32 / 1000.0
+        )
+    }
+
+    pub fn levels(&self) -> Vec<f32> {
+        self.levels.get()
+    }
+
+    pub fn rms(&self) -> Vec<f32> {
+        self.levels.rms()
+    }
+
+    pub fn elapsed_seconds(&self) -> u64 {
+        self.recorded_frames.load(Ordering::Relaxed) / self.params.samplerate as u64
+    }
+
+    pub fn start(&mut self, path: &Path) -> Result<()> {
+        let mut target = Target::create(path, &self.params)?;
+        let mut receiver = self.device.start_capture(&self.params)?;
+        let params = self.params;
+        let levels = self.levels.clone();
+        let recorded_frames = self.recorded_frames.clone();
+        self.writer_handle = Some(tokio::spawn(async move {
+            let channels = params.channels as usize;
+            let sample_size = params.bits_per_sample as usize / 8;
+            // Publish levels about 25 times per second
+            let refresh_frames = params.samplerate as u64 / 25;
+            let mut sample = Vec::with_capacity(sample_size);
+            let mut channel = 0;
+            let mut peaks = vec![0.0f32; channels];
+            let mut powers = vec![0.0f32; channels];
+            while let Some(StreamingData::Data(byte)) = receiver.recv().await {
+                sample.push(byte);
+                if sample.len() < sample_size {
+                    continue;
+                }
+                let level = match params.bits_per_sample {
+                    BitsPerSample::Bits16 => {
+                        let value = i16::from_le_bytes([sample[0], sample[1]]);
+                        target.write_int(value as i32)?;
+                        value as f32 / i16::MAX as f32
+                    }
+                    BitsPerSample::Bits24 => {
+                        let value = i32::from_le_bytes([0, sample[0], sample[1], sample[2]]) >> 8;
+                        target.write_int(value)?;
+                        value as f32 / 8388607.0
+                    }
+                    BitsPerSample::Bits32 => {
+                        let value =
+                            f32::from_le_bytes([sample[0], sample[1], sample[2], sample[3]]);
+                        target.write_float(value)?;
+                        value
+                    }
+                };
+                sample.clear();
+                peaks[channel] = peaks[channel].max(level.abs());
+                powers[channel] += level * level;
+                channel += 1;
+                if channel == channels {
+                    channel = 0;
+                    let frames = recorded_frames.fetch_add(1, Ordering::Relaxed) + 1;
+                    if frames.is_multiple_of(refresh_frames) {
+                        for (index, (peak, power)) in
+                            peaks.iter_mut().zip(powers.iter_mut()).enumerate()
+                        {
+                            levels.store(index, *peak, (*power / refresh_frames as f32).sqrt());
+                            (*peak, *power) = (0.0, 0.0);
+                        }
+                    }
+                }
+            }
+            target.finish()?;
+            Ok::<(), anyhow::Error>(())
+        }));
+        Ok(())
+    }
+
+    pub async fn stop(&mut self) -> Result<()> {
+        self.device.stop()?;
+        if let Some(handle) = self.writer_handle.take() {
+            handle.await??;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use log::warn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::musictrack::MusicTrack;
+
+/// Gitignore-style patterns excluding files and directories from the music directory.
+pub const IGNORE_FILE: &str = ".rhapignore";
+
+fn load(dir: &Path) -> Option<Gitignore> {
+    let path = dir.join(IGNORE_FILE);
+    if !path.is_file() {
+        return None;
+    }
+    let mut builder = GitignoreBuilder::new(dir);
+    if let Some(err) = builder.add(&path) {
+        warn!("Invalid pattern in {}: {}", path.display(), err);
+    }
+    builder
+        .build()
+        .inspect_err(|err| warn!("Cannot read {}: {}", path.display(), err))
+        .ok()
+}
+
+/// Lists the supported files under a directory, skipping what its ignore files exclude.
+pub struct Scanner {
+    root: PathBuf,
+    /// Parsed ignore file of each visited directory, `None` when it has none
+    ignores: HashMap<PathBuf, Option<Gitignore>>,
+}
+
+impl Scanner {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            ignores: HashMap::new(),
+        }
+    }
+
+    /// Whether `path` or one of its parents is excluded, the deepest ignore file taking
+    /// precedence like with git.
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        let depth = relative.components().count();
+        for dir in path.ancestors().skip(1).take(depth) {
+            let ignore = self
+                .ignores
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| load(dir));
+            match ignore.as_ref().map_or(Match::None, |ignore| {
+                ignore.matched_path_or_any_parents(path, is_dir)
+            }) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => (),
+            }
+        }
+        false
+    }
+
+    /// Drops the cached patterns of `dir`, for when its ignore file changed.
+    pub fn forget(&mut self, dir: &Path) {
+        self.ignores.remove(dir);
+    }
+
+    fn walk(&mut self, dir: &Path, include: impl Fn(&Path) -> bool) -> Vec<String> {
+        WalkDir::new(dir)
+            .follow_links(true)
+            .into_iter()
+            .filter_entry(|entry| !self.is_ignored(entry.path(), entry.file_type().is_dir()))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_file() && include(entry.path()))
+            .filter_map(|entry| entry.path().to_str().map(str::to_string))
+            .collect()
+    }
+
+    pub fn files(&mut self, dir: &Path) -> Vec<String> {
+        self.walk(dir, MusicTrack::is_supported)
+    }
+
+    pub fn cue_sheets(&mut self, dir: &Path) -> Vec<String> {
+        self.walk(dir, is_cue_sheet)
+    }
+}
+
+pub fn is_cue_sheet(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("cue"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Creates the files under a fresh temporary directory, with their content.
+    fn tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rhap-scanner-{}", name));
+        let _ = fs::remove_dir_all(&root);
+        for (path, content) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn deepest_ignore_file_takes_precedence() {
+        let root = tree(
+            "precedence",
+            &[
+                (IGNORE_FILE, "*.wav\nlive/\n"),
+                ("album/.rhapignore", "!*.wav\n"),
+                ("album/disc/.rhapignore", "bonus.wav\n"),
+            ],
+        );
+        let mut scanner = Scanner::new(&root);
+        assert!(!scanner.is_ignored(&root.join("track.flac"), false));
+        assert!(scanner.is_ignored(&root.join("track.wav"), false));
+        assert!(scanner.is_ignored(&root.join("live"), true));
+        // Whitelisted below, for the nested folders without their own rule as well
+        assert!(!scanner.is_ignored(&root.join("album/track.wav"), false));
+        assert!(!scanner.is_ignored(&root.join("album/disc/track.wav"), false));
+        assert!(scanner.is_ignored(&root.join("album/disc/bonus.wav"), false));
+        // Files inside an ignored directory are ignored with it
+        assert!(scanner.is_ignored(&root.join("live/track.flac"), false));
+        // Paths outside of the root are never matched
+        assert!(!scanner.is_ignored(Path::new("/elsewhere/track.wav"), false));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignored_directories_are_not_walked() {
+        let root = tree(
+            "walk",
+            &[
+                (IGNORE_FILE, "live/\n*.wav\n"),
+                ("album/track.flac", ""),
+                ("album/track.wav", ""),
+                ("live/track.flac", ""),
+            ],
+        );
+        let mut scanner = Scanner::new(&root);
+        let files = scanner.files(&root);
+        assert_eq!(files, vec![root.join("album/track.flac").to_str().unwrap()]);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn changed_ignore_files_apply_once_forgotten() {
+        let root = tree("forget", &[(IGNORE_FILE, "*.wav\n")]);
+        let mut scanner = Scanner::new(&root);
+        let track = root.join("track.wav");
+        assert!(scanner.is_ignored(&track, false));
+        fs::write(root.join(IGNORE_FILE), "*.mp3\n").unwrap();
+        // Patterns are cached until the watcher reports the change
+        assert!(scanner.is_ignored(&track, false));
+        scanner.forget(&root);
+        assert!(!scanner.is_ignored(&track, false));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::time::Duration;
+
+/// What was played since rhap started, reported on quit.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub tracks: u32,
+    /// Audio streamed to the devices
+    pub listening: Duration,
+    /// Periods played short of data, across every device used
+    pub underruns: u64,
+    /// CPU time of the render threads
+    pub render_cpu: Duration,
+}
+
+impl Session {
+    /// Average share of one core used by the render threads while playing.
+    pub fn render_cpu_usage(&self) -> f64 {
+        if self.listening.is_zero() {
+            return 0.0;
+        }
+        self.render_cpu.as_secs_f64() / self.listening.as_secs_f64()
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.listening.as_secs();
+        write!(
+            f,
+            "{} tracks played, {}:{:02}:{:02} listened, {} underruns, render thread at {:.1}% CPU",
+            self.tracks,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            self.underruns,
+            self.render_cpu_usage() * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let session = Session {
+            tracks: 12,
+            listening: Duration::from_secs(3725),
+            underruns: 2,
+            render_cpu: Duration::from_millis(37250),
+        };
+        assert_eq!(
+            session.to_string(),
+            "12 tracks played, 1:02:05 listened, 2 underruns, render thread at 1.0% CPU"
+        );
+        assert_eq!(Session::default().render_cpu_usage(), 0.0);
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use windows::core::HSTRING;
+use windows::Foundation::{TimeSpan, TypedEventHandler};
+use windows::Media::Playback::MediaPlayer;
+use windows::Media::{
+    MediaPlaybackStatus, MediaPlaybackType, SystemMediaTransportControls,
+    SystemMediaTransportControlsButton, SystemMediaTransportControlsButtonPressedEventArgs,
+    SystemMediaTransportControlsTimelineProperties,
+};
+
+use crate::musictrack::MusicTrack;
+use crate::player::CurrentTrackInfo;
+use crate::ui::keyboard::KeyboardEvent;
+
+/// Times of the timeline are in 100 nanoseconds units.
+fn timespan(seconds: f64) -> TimeSpan {
+    TimeSpan {
+        Duration: (seconds.max(0.0) * 10_000_000.0) as i64,
+    }
+}
+
+/// The key matching a button, depending on the status shown by Windows.
+fn event(
+    button: SystemMediaTransportControlsButton,
+    status: MediaPlaybackStatus,
+) -> Option<KeyboardEvent> {
+    match button {
+        SystemMediaTransportControlsButton::Play => match status {
+            MediaPlaybackStatus::Paused => Some(KeyboardEvent::Pause),
+            MediaPlaybackStatus::Playing => None,
+            _ => Some(KeyboardEvent::Play),
+        },
+        SystemMediaTransportControlsButton::Pause => {
+            (status == MediaPlaybackStatus::Playing).then_some(KeyboardEvent::Pause)
+        }
+        SystemMediaTransportControlsButton::Stop => Some(KeyboardEvent::Stop),
+        SystemMediaTransportControlsButton::Next => Some(KeyboardEvent::Next),
+        SystemMediaTransportControlsButton::Previous => Some(KeyboardEvent::Previous),
+        _ => None,
+    }
+}
+
+/// System media transport controls, showing the playing track in the volume flyout and
+/// receiving the media keys.
+pub struct MediaControls {
+    /// Console apps have no window, the controls come from a player left unused
+    _player: MediaPlayer,
+    controls: SystemMediaTransportControls,
+    status: Arc<Mutex<MediaPlaybackStatus>>,
+    events: UnboundedReceiver<KeyboardEvent>,
+    /// Title, artist and album shown
+    shown: Option<(String, String, String)>,
+    /// Whole seconds of the timeline shown, elapsed and duration
+    position: Option<(u64, u64)>,
+}
+
+impl MediaControls {
+    pub fn start() -> Result<Self> {
+        let player = MediaPlayer::new()?;
+        player.CommandManager()?.SetIsEnabled(false)?;
+        let controls = player.SystemMediaTransportControls()?;
+        controls.SetIsEnabled(true)?;
+        controls.SetIsPlayEnabled(true)?;
+        controls.SetIsPauseEnabled(true)?;
+        controls.SetIsStopEnabled(true)?;
+        controls.SetIsNextEnabled(true)?;
+        controls.SetIsPreviousEnabled(true)?;
+        controls.SetPlaybackStatus(MediaPlaybackStatus::Closed)?;
+        let status = Arc::new(Mutex::new(MediaPlaybackStatus::Closed));
+        let (sender, events) = mpsc::unbounded_channel();
+        let shown = status.clone();
+        // Buttons are pressed on a thread of the system
+        controls.ButtonPressed(&TypedEventHandler::new(
+            move |_, args: &Option<SystemMediaTransportControlsButtonPressedEventArgs>| {
+                let Some(args) = args else {
+                    return Ok(());
+                };
+                let status = shown
+                    .lock()
+                    .map_or(MediaPlaybackStatus::Closed, |status| *status);
+                if let Some(event) = event(args.Button()?, status) {
+                    // The receiver lives as long as the app
+                    let _ = sender.send(event);
+                }
+                Ok(())
+            },
+        ))?;
+        Ok(Self {
+            _player: player,
+            controls,
+            status,
+            events,
+            shown: None,
+            position: None,
+        })
+    }
+
+    /// Next key pressed on the media controls, if any.
+    pub fn event(&mut self) -> Option<KeyboardEvent> {
+        self.events.try_recv().ok()
+    }
+
+    /// Reports the playing track, paused or not, updating what changed only.
+    pub fn update(
+        &mut self,
+        playing: Option<(&MusicTrack, &CurrentTrackInfo)>,
+        paused: bool,
+    ) -> Result<()> {
+        let status = match playing {
+            None => MediaPlaybackStatus::Stopped,
+            Some(_) if paused => MediaPlaybackStatus::Paused,
+            Some(_) => MediaPlaybackStatus::Playing,
+        };
+        if let Ok(mut shown) = self.status.lock() {
+            if *shown != status {
+                self.controls.SetPlaybackStatus(status)?;
+                *shown = status;
+            }
+        }
+        let track =
+            playing.map(|(song, _)| (song.title.clone(), song.artist.clone(), song.album.clone()));
+        if track != self.shown {
+            let updater = self.controls.DisplayUpdater()?;
+            match &track {
+                Some((title, artist, album)) => {
+                    updater.SetType(MediaPlaybackType::Music)?;
+                    let properties = updater.MusicProperties()?;
+                    properties.SetTitle(&HSTRING::from(title))?;
+                    properties.SetArtist(&HSTRING::from(artist))?;
+                    properties.SetAlbumTitle(&HSTRING::from(album))?;
+                }
+                None => updater.ClearAll()?,
+            }
+            updater.Update()?;
+            self.shown = track;
+        }
+        let Some((_, info)) = playing else {
+            self.position = None;
+            return Ok(());
+        };
+        let (elapsed, duration) = (info.elapsed_seconds(), info.duration_seconds());
+        let position = Some((elapsed.max(0.0) as u64, duration as u64));
+        if position != self.position {
+            let timeline = SystemMediaTransportControlsTimelineProperties::new()?;
+            timeline.SetStartTime(timespan(0.0))?;
+            timeline.SetEndTime(timespan(duration))?;
+            timeline.SetMinSeekTime(timespan(0.0))?;
+            timeline.SetMaxSeekTime(timespan(duration))?;
+            timeline.SetPosition(timespan(elapsed))?;
+            self.controls.UpdateTimelineProperties(&timeline)?;
+            self.position = position;
+        }
+        Ok(())
+    }
+}
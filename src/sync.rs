@@ -0,0 +1,340 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::{error, warn};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::task::JoinHandle;
+
+use crate::convert::{convert, Format, Target};
+use crate::export::write_m3u;
+use crate::library::{Database, PlaylistEntry};
+use crate::musictrack::MusicTrack;
+use crate::scanner::Scanner;
+use crate::tools::resampler::ResamplerSettings;
+
+/// Highest sample rate of transcoded files, CD quality plays everywhere.
+const TRANSCODE_SAMPLE_RATE: usize = 44100;
+
+/// Files written in place of the source ones.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Transcode {
+    /// Hi-res PCM converted to 16 bits FLAC at 44.1kHz at most
+    Flac,
+    /// Lossless mono and stereo tracks converted to Opus, needs the opus feature
+    Opus,
+}
+
+impl Transcode {
+    fn format(&self) -> Format {
+        match self {
+            Transcode::Flac => Format::Flac,
+            Transcode::Opus => Format::Opus,
+        }
+    }
+
+    fn applies(&self, track: &MusicTrack) -> bool {
+        match self {
+            Transcode::Flac => is_hi_res(track),
+            Transcode::Opus => is_lossless(track) && track.channels <= 2,
+        }
+    }
+
+    fn target(&self, track: &MusicTrack) -> Target {
+        match self {
+            Transcode::Flac => Target {
+                format: Format::Flac,
+                sample_rate: Some((track.sample as usize).min(TRANSCODE_SAMPLE_RATE)),
+                bits: Some(16),
+            },
+            Transcode::Opus => Target {
+                format: Format::Opus,
+                sample_rate: None,
+                bits: None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Copy,
+    /// Converted as the plan transcode asks
+    Transcode,
+    UpToDate,
+}
+
+pub struct Job {
+    pub track: Arc<MusicTrack>,
+    pub destination: PathBuf,
+    pub action: Action,
+}
+
+/// Files to mirror on the device and the playlists referring to them.
+pub struct Plan {
+    pub destination: PathBuf,
+    pub transcode: Option<Transcode>,
+    pub jobs: Vec<Job>,
+    /// Job indexes of each synced playlist
+    pub playlists: Vec<(String, Vec<usize>)>,
+}
+
+impl Plan {
+    pub fn count(&self, action: Action) -> usize {
+        self.jobs.iter().filter(|job| job.action == action).count()
+    }
+}
+
+/// Beyond what most portable players and car head units decode.
+fn is_hi_res(track: &MusicTrack) -> bool {
+    track.dsd_rate.is_none()
+        && (track.sample as usize > 48000 || track.bits_per_sample as usize > 16)
+}
+
+/// Lossy sources are copied, encoding them again would only lose more.
+fn is_lossless(track: &MusicTrack) -> bool {
+    let extension = Path::new(&track.path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    track.dsd_rate.is_none()
+        && matches!(
+            extension.as_deref(),
+            Some("flac") | Some("wav") | Some("aiff") | Some("aif")
+        )
+}
+
+fn is_up_to_date(source: &Path, destination: &Path) -> bool {
+    let modified =
+        |path: &Path| -> Option<SystemTime> { std::fs::metadata(path).ok()?.modified().ok() };
+    match (modified(source), modified(destination)) {
+        (Some(source), Some(destination)) => destination >= source,
+        _ => false,
+    }
+}
+
+/// Characters most device file systems reject in playlist file names.
+fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if "<>:\"/\\|?*".contains(c) { '_' } else { c })
+        .collect()
+}
+
+/// Plans the mirror of the saved `playlists`, or of the whole music directory when none is
+/// given. Tracks keep their path relative to `root` on the device.
+pub fn plan(
+    library: &Database,
+    root: &Path,
+    destination: &Path,
+    playlists: &[String],
+    transcode: Option<Transcode>,
+) -> Result<Plan> {
+    if let Some(transcode) = transcode {
+        transcode.format().check_available()?;
+    }
+    let root = std::path::absolute(root)?;
+    let mut sources: Vec<(String, Vec<String>)> = Vec::new();
+    if playlists.is_empty() {
+        sources.push((String::new(), Scanner::new(&root).files(&root)));
+    } else {
+        let saved: HashMap<String, Vec<PlaylistEntry>> = library.playlists()?.into_iter().collect();
+        for name in playlists {
+            let entries = saved
+                .get(name)
+                .ok_or(anyhow!("No saved playlist named {}", name))?;
+            sources.push((
+                name.clone(),
+                entries.iter().map(|entry| entry.path.clone()).collect(),
+            ));
+        }
+    }
+
+    let mut plan = Plan {
+        destination: destination.to_path_buf(),
+        transcode,
+        jobs: Vec::new(),
+        playlists: Vec::new(),
+    };
+    let mut indexes: HashMap<String, usize> = HashMap::new();
+    for (name, paths) in sources {
+        let mut tracks = Vec::new();
+        for path in paths {
+            if let Some(index) = indexes.get(&path) {
+                tracks.push(*index);
+                continue;
+            }
+            let track = match library.track(path.clone()) {
+                Ok(track) => Arc::new(track),
+                Err(err) => {
+                    warn!("Skipping {}: {}", path, err);
+                    continue;
+                }
+            };
+            let source = PathBuf::from(&path);
+            let relative = match std::path::absolute(&source)?.strip_prefix(&root) {
+                Ok(relative) => relative.to_path_buf(),
+                Err(_) => PathBuf::from(source.file_name().unwrap_or_default()),
+            };
+            let mut target = destination.join(relative);
+            let mut action = Action::Copy;
+            if let Some(transcode) = transcode.filter(|transcode| transcode.applies(&track)) {
+                target.set_extension(transcode.format().extension());
+                action = Action::Transcode;
+            }
+            if is_up_to_date(&source, &target) {
+                action = Action::UpToDate;
+            }
+            indexes.insert(path, plan.jobs.len());
+            tracks.push(plan.jobs.len());
+            plan.jobs.push(Job {
+                track,
+                destination: target,
+                action,
+            });
+        }
+        if !name.is_empty() {
+            plan.playlists.push((name, tracks));
+        }
+    }
+    Ok(plan)
+}
+
+/// Writes next to the destination first, a cancelled or failed job leaves no partial file.
+fn run_job(job: &Job, transcode: Option<Transcode>, resampler: ResamplerSettings) -> Result<()> {
+    if let Some(parent) = job.destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut partial = job.destination.clone().into_os_string();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let result = match job.action {
+        Action::Copy => std::fs::copy(&job.track.path, &partial)
+            .map(|_| ())
+            .map_err(Into::into),
+        Action::Transcode => match transcode {
+            Some(transcode) => convert(
+                &job.track,
+                &partial,
+                &transcode.target(&job.track),
+                resampler,
+            ),
+            None => Err(anyhow!("Nothing to transcode to")),
+        },
+        Action::UpToDate => return Ok(()),
+    };
+    match result {
+        Ok(()) => Ok(std::fs::rename(&partial, &job.destination)?),
+        Err(err) => {
+            let _ = std::fs::remove_file(&partial);
+            Err(err)
+        }
+    }
+}
+
+/// Playlists use paths relative to the device folder, the device mounts it anywhere.
+fn write_playlists(plan: &Plan) -> Result<()> {
+    for (name, indexes) in &plan.playlists {
+        let tracks: Vec<Arc<MusicTrack>> = indexes
+            .iter()
+            .map(|index| {
+                let job = &plan.jobs[*index];
+                Arc::new(MusicTrack {
+                    path: job.destination.to_string_lossy().into_owned(),
+                    ..(*job.track).clone()
+                })
+            })
+            .collect();
+        let file = plan.destination.join(format!("{}.m3u8", file_name(name)));
+        write_m3u(&file, &plan.destination, &tracks)?;
+    }
+    Ok(())
+}
+
+/// Runs a plan in the background, jobs are run one at a time as devices are slow to write.
+pub struct DeviceSync {
+    plan: Arc<Plan>,
+    resampler: ResamplerSettings,
+    done: Arc<AtomicUsize>,
+    failed: Arc<AtomicUsize>,
+    cancelled: Arc<AtomicBool>,
+    current: Arc<Mutex<String>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl DeviceSync {
+    pub fn new(plan: Plan, resampler: ResamplerSettings) -> Self {
+        Self {
+            plan: Arc::new(plan),
+            resampler,
+            done: Arc::new(AtomicUsize::new(0)),
+            failed: Arc::new(AtomicUsize::new(0)),
+            cancelled: Arc::new(AtomicBool::new(false)),
+            current: Arc::new(Mutex::new(String::new())),
+            handle: None,
+        }
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.plan.destination
+    }
+
+    pub fn start(&mut self) {
+        let plan = self.plan.clone();
+        let resampler = self.resampler;
+        let done = self.done.clone();
+        let failed = self.failed.clone();
+        let cancelled = self.cancelled.clone();
+        let current = self.current.clone();
+        self.handle = Some(tokio::task::spawn_blocking(move || {
+            for job in &plan.jobs {
+                if cancelled.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                if let Ok(mut current) = current.lock() {
+                    *current = job.track.path.clone();
+                }
+                if let Err(err) = run_job(job, plan.transcode, resampler) {
+                    error!("Cannot sync {}: {}", job.track.path, err);
+                    failed.fetch_add(1, Ordering::Relaxed);
+                }
+                done.fetch_add(1, Ordering::Relaxed);
+            }
+            write_playlists(&plan)
+        }));
+    }
+
+    /// Jobs done and total jobs.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.done.load(Ordering::Relaxed), self.plan.jobs.len())
+    }
+
+    pub fn failed(&self) -> usize {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    pub fn current(&self) -> String {
+        self.current
+            .lock()
+            .map(|current| current.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_none_or(|handle| handle.is_finished())
+    }
+
+    /// Stops after the current job.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub async fn wait(&mut self) -> Result<()> {
+        match self.handle.take() {
+            Some(handle) => handle.await?,
+            None => Ok(()),
+        }
+    }
+}
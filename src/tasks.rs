@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use log::{error, warn};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use tokio::task::{self, AbortHandle, JoinSet};
+
+/// Finished tasks kept for the tasks popup.
+const FINISHED_HISTORY: usize = 20;
+
+type Job = Box<dyn FnOnce() -> Result<()> + Send>;
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<(String, Job)>,
+    running: Vec<String>,
+    /// Most recent last, with whether the task succeeded
+    finished: VecDeque<(String, bool)>,
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    available: Condvar,
+    shutdown: AtomicBool,
+}
+
+/// What the workers are doing, for display.
+pub struct TasksSnapshot {
+    pub running: Vec<String>,
+    /// First queued tasks in run order
+    pub queued: Vec<String>,
+    pub queued_count: usize,
+    pub finished: Vec<(String, bool)>,
+}
+
+/// Heavy offline work such as track analyses runs on a fixed number of low priority threads,
+/// away from the tokio runtime and the render threads.
+pub struct TaskPool {
+    shared: Arc<Shared>,
+}
+
+/// Playback threads run at a raised priority, workers give way to everything else.
+#[cfg(windows)]
+fn lower_thread_priority() {
+    use windows::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_LOWEST,
+    };
+    if let Err(err) = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_LOWEST) } {
+        warn!("Cannot lower the worker thread priority: {}", err);
+    }
+}
+
+#[cfg(not(windows))]
+fn lower_thread_priority() {}
+
+fn work(shared: Arc<Shared>) {
+    lower_thread_priority();
+    loop {
+        let (name, job) = {
+            let Ok(mut state) = shared.state.lock() else {
+                return;
+            };
+            loop {
+                if shared.shutdown.load(Ordering::Relaxed) {
+                    return;
+                }
+                if let Some(task) = state.queue.pop_front() {
+                    state.running.push(task.0.clone());
+                    break task;
+                }
+                state = match shared.available.wait(state) {
+                    Ok(state) => state,
+                    Err(_) => return,
+                };
+            }
+        };
+        let succeeded = match job() {
+            Ok(()) => true,
+            Err(err) => {
+                error!("Task {} failed: {}", name, err);
+                false
+            }
+        };
+        let Ok(mut state) = shared.state.lock() else {
+            return;
+        };
+        if let Some(index) = state.running.iter().position(|running| *running == name) {
+            state.running.remove(index);
+        }
+        state.finished.push_back((name, succeeded));
+        if state.finished.len() > FINISHED_HISTORY {
+            state.finished.pop_front();
+        }
+    }
+}
+
+impl TaskPool {
+    /// No worker is started with `workers` at 0, tasks then stay queued.
+    pub fn new(workers: usize) -> Self {
+        let shared = Arc::new(Shared::default());
+        for index in 0..workers {
+            let shared = shared.clone();
+            if let Err(err) = std::thread::Builder::new()
+                .name(format!("rhap-worker-{}", index))
+                .spawn(move || work(shared))
+            {
+                warn!("Cannot start worker {}: {}", index, err);
+            }
+        }
+        Self { shared }
+    }
+
+    pub fn submit(&self, name: String, job: impl FnOnce() -> Result<()> + Send + 'static) {
+        if let Ok(mut state) = self.shared.state.lock() {
+            state.queue.push_back((name, Box::new(job)));
+            self.shared.available.notify_one();
+        }
+    }
+
+    pub fn snapshot(&self, queued: usize) -> TasksSnapshot {
+        let Ok(state) = self.shared.state.lock() else {
+            return TasksSnapshot {
+                running: Vec::new(),
+                queued: Vec::new(),
+                queued_count: 0,
+                finished: Vec::new(),
+            };
+        };
+        TasksSnapshot {
+            running: state.running.clone(),
+            queued: state
+                .queue
+                .iter()
+                .take(queued)
+                .map(|(name, _)| name.clone())
+                .collect(),
+            queued_count: state.queue.len(),
+            finished: state.finished.iter().rev().cloned().collect(),
+        }
+    }
+}
+
+/// Running tasks complete, queued tasks are dropped.
+impl Drop for TaskPool {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Relaxed);
+        self.shared.available.notify_all();
+    }
+}
+
+#[derive(Default)]
+struct Group {
+    tasks: JoinSet<Result<()>>,
+    names: HashMap<task::Id, String>,
+}
+
+/// Owner of the async tasks of the app, the streaming of tracks and the servers. Failed tasks
+/// are kept until collected rather than lost with their handle, and the tasks left are
+/// aborted and waited for on shutdown.
+#[derive(Clone, Default)]
+pub struct TaskGroup {
+    group: Arc<Mutex<Group>>,
+}
+
+impl TaskGroup {
+    pub fn spawn<F>(&self, name: String, task: F) -> AbortHandle
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        let mut group = self
+            .group
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let handle = group.tasks.spawn(task);
+        group.names.insert(handle.id(), name);
+        handle
+    }
+
+    /// Tasks finished with an error or a panic since the last call, with their name. Aborted
+    /// tasks are not failures.
+    pub fn failures(&self) -> Vec<(String, anyhow::Error)> {
+        let Ok(mut group) = self.group.lock() else {
+            return Vec::new();
+        };
+        let mut failures = Vec::new();
+        while let Some(joined) = group.tasks.try_join_next_with_id() {
+            let (id, result) = match joined {
+                Ok((id, result)) => (id, result),
+                Err(err) if err.is_cancelled() => (err.id(), Ok(())),
+                Err(err) => (err.id(), Err(anyhow!("panicked"))),
+            };
+            let name = group.names.remove(&id).unwrap_or_default();
+            if let Err(err) = result {
+                error!("Task {} failed: {}", name, err);
+                failures.push((name, err));
+            }
+        }
+        failures
+    }
+
+    /// Aborts the tasks left and waits for them to be dropped, once playback is stopped.
+    pub async fn shutdown(&self) {
+        let mut tasks = match self.group.lock() {
+            Ok(mut group) => {
+                group.names.clear();
+                std::mem::take(&mut group.tasks)
+            }
+            Err(_) => return,
+        };
+        tasks.shutdown().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn failures_are_reported_once_with_their_name() {
+        let tasks = TaskGroup::default();
+        tasks.spawn("Ok".to_string(), async { Ok(()) });
+        tasks.spawn("Broken".to_string(), async { Err(anyhow!("broken")) });
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        let failures = tasks.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "Broken");
+        assert_eq!(failures[0].1.to_string(), "broken");
+        assert!(tasks.failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn aborted_tasks_are_not_failures() {
+        let tasks = TaskGroup::default();
+        let handle = tasks.spawn("Pending".to_string(), std::future::pending());
+        handle.abort();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+
+        assert!(tasks.failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn shutdown_drops_the_running_tasks() {
+        let tasks = TaskGroup::default();
+        let (sender, receiver) = tokio::sync::oneshot::channel::<()>();
+        tasks.spawn("Holding".to_string(), async move {
+            let _sender = sender;
+            std::future::pending().await
+        });
+        tasks.shutdown().await;
+
+        assert!(receiver.await.is_err());
+    }
+}
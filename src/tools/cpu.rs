@@ -0,0 +1,94 @@
+use cpu_time::ThreadTime;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Refresh period of the sampled usage, shorter periods mostly show scheduling noise.
+const SAMPLE_PERIOD: Duration = Duration::from_secs(1);
+
+struct Sample {
+    at: Instant,
+    busy_nanos: u64,
+    usage: f64,
+}
+
+/// CPU time spent by an audio thread, shared with the UI as a share of one core, and the
+/// periods it ran short of data.
+#[derive(Default)]
+pub struct CpuMeter {
+    busy_nanos: AtomicU64,
+    last: Mutex<Option<Sample>>,
+    underruns: AtomicU64,
+}
+
+/// Adds the CPU time of the current thread to the meter when dropped.
+pub struct Busy<'a> {
+    meter: &'a CpuMeter,
+    started: Option<ThreadTime>,
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        if let Some(elapsed) = self
+            .started
+            .as_ref()
+            .and_then(|started| started.try_elapsed().ok())
+        {
+            self.meter
+                .busy_nanos
+                .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl CpuMeter {
+    /// Must not be held across an await, the task may resume on another thread.
+    pub fn busy(&self) -> Busy<'_> {
+        Busy {
+            meter: self,
+            started: ThreadTime::try_now().ok(),
+        }
+    }
+
+    /// CPU time spent since the meter was created.
+    pub fn busy_time(&self) -> Duration {
+        Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed))
+    }
+
+    /// Counts a period padded with silence while the stream was still running.
+    pub fn underrun(&self) {
+        tracing::trace!("underrun");
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// Share of one core used over the last sample period, 1.0 being a full core.
+    pub fn usage(&self) -> f64 {
+        let Ok(mut last) = self.last.lock() else {
+            return 0.0;
+        };
+        let now = Instant::now();
+        let busy_nanos = self.busy_nanos.load(Ordering::Relaxed);
+        match last.as_mut() {
+            Some(sample) if now.duration_since(sample.at) < SAMPLE_PERIOD => sample.usage,
+            Some(sample) => {
+                sample.usage = (busy_nanos - sample.busy_nanos) as f64
+                    / now.duration_since(sample.at).as_nanos() as f64;
+                sample.at = now;
+                sample.busy_nanos = busy_nanos;
+                sample.usage
+            }
+            None => {
+                *last = Some(Sample {
+                    at: now,
+                    busy_nanos,
+                    usage: 0.0,
+                });
+                0.0
+            }
+        }
+    }
+}
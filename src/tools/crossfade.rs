@@ -0,0 +1,99 @@
+use std::f64::consts::FRAC_PI_2;
+use symphonia::core::audio::{AudioBuffer, Signal};
+use tokio::sync::mpsc::Receiver;
+
+/// End of the outgoing track, decoded and processed, mixed under the start of the incoming
+/// one with equal power gains.
+pub struct FadeOut {
+    tail: Receiver<AudioBuffer<f64>>,
+    chunk: Option<AudioBuffer<f64>>,
+    /// Frames of `chunk` already mixed
+    position: usize,
+    mixed: u64,
+    /// Frames the tail was expected to last when the fade started
+    length: u64,
+}
+
+impl FadeOut {
+    pub fn new(tail: Receiver<AudioBuffer<f64>>, length: u64) -> Self {
+        Self {
+            tail,
+            chunk: None,
+            position: 0,
+            mixed: 0,
+            length: length.max(1),
+        }
+    }
+
+    /// Mixes the tail into `buffer`, returns false once the tail is over.
+    pub async fn mix(&mut self, buffer: &mut AudioBuffer<f64>) -> bool {
+        let channels = buffer.spec().channels.count();
+        let mut frame = 0;
+        while frame < buffer.frames() {
+            let chunk = match &self.chunk {
+                Some(chunk) if self.position < chunk.frames() => chunk,
+                _ => match self.tail.recv().await {
+                    Some(chunk) => {
+                        self.position = 0;
+                        self.chunk.insert(chunk)
+                    }
+                    None => return false,
+                },
+            };
+            let frames = (buffer.frames() - frame).min(chunk.frames() - self.position);
+            for offset in 0..frames {
+                let progress = ((self.mixed + offset as u64) as f64 / self.length as f64).min(1.0);
+                let (incoming, outgoing) = (progress * FRAC_PI_2).sin_cos();
+                for channel in 0..channels.min(chunk.spec().channels.count()) {
+                    let tail = chunk.chan(channel)[self.position + offset];
+                    let sample = &mut buffer.chan_mut(channel)[frame + offset];
+                    *sample = *sample * incoming + tail * outgoing;
+                }
+            }
+            frame += frames;
+            self.position += frames;
+            self.mixed += frames as u64;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::audio::{Channels, SignalSpec};
+    use tokio::sync::mpsc::channel;
+
+    fn buffer(value: f64, frames: usize) -> AudioBuffer<f64> {
+        let spec = SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buffer = AudioBuffer::<f64>::new(frames as u64, spec);
+        buffer.render_reserved(Some(frames));
+        for channel in 0..2 {
+            buffer.chan_mut(channel).fill(value);
+        }
+        buffer
+    }
+
+    #[tokio::test]
+    async fn fades_the_tail_out() {
+        let (sender, tail) = channel(4);
+        sender.send(buffer(1.0, 3)).await.unwrap();
+        sender.send(buffer(1.0, 3)).await.unwrap();
+        drop(sender);
+        let mut fade = FadeOut::new(tail, 4);
+
+        let mut incoming = buffer(0.0, 4);
+        assert!(fade.mix(&mut incoming).await);
+        let left = incoming.chan(0);
+        assert_eq!(left[0], 1.0);
+        assert!(left.windows(2).all(|pair| pair[1] < pair[0]));
+
+        // Past the expected length only the incoming track is heard
+        let mut incoming = buffer(0.5, 4);
+        assert!(!fade.mix(&mut incoming).await);
+        assert!(incoming.chan(1)[..2]
+            .iter()
+            .all(|sample| (sample - 0.5).abs() < 1e-9));
+        assert!(incoming.chan(1)[2..].iter().all(|sample| *sample == 0.5));
+    }
+}
@@ -0,0 +1,507 @@
+use anyhow::{anyhow, Result};
+use std::io::{Seek, SeekFrom, Write};
+
+/// Frames per FLAC frame, the reference encoder default.
+const BLOCK_SIZE: usize = 4096;
+const STREAMINFO_LENGTH: usize = 34;
+const MAX_FIXED_ORDER: usize = 4;
+
+/// MSB first bit packing, FLAC frames are not byte aligned until their footer.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    accumulator: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        if bits == 0 {
+            return;
+        }
+        self.accumulator = (self.accumulator << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.accumulator >> self.bits) as u8);
+        }
+        self.accumulator &= (1 << self.bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_unary(&mut self, mut zeros: u64) {
+        while zeros >= 32 {
+            self.write(0, 32);
+            zeros -= 32;
+        }
+        self.write(1, zeros as u32 + 1);
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |crc, byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Frame numbers use the UTF-8 variable length coding, extended to 36 bits.
+fn write_utf8(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+    let bytes = (2..=7u32)
+        .find(|bytes| value < 1 << (5 * bytes + 1))
+        .unwrap_or(7);
+    let prefix = (0xFF00u64 >> bytes) & 0xFF;
+    writer.write(prefix | (value >> (6 * (bytes - 1))), 8);
+    for index in (0..bytes - 1).rev() {
+        writer.write(0x80 | ((value >> (6 * index)) & 0x3F), 8);
+    }
+}
+
+/// Residual of the fixed polynomial predictor of the given order at `index`.
+fn fixed_residual(samples: &[i64], order: usize, index: usize) -> i64 {
+    let s = |offset: usize| samples[index - offset];
+    match order {
+        0 => s(0),
+        1 => s(0) - s(1),
+        2 => s(0) - 2 * s(1) + s(2),
+        3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+        _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+    }
+}
+
+fn zigzag(residual: i64) -> u64 {
+    ((residual << 1) ^ (residual >> 63)) as u64
+}
+
+enum Subframe {
+    Constant,
+    Verbatim,
+    Fixed { order: usize, rice: u32 },
+}
+
+/// Size in bits of the residuals of `order` coded with the Rice parameter `rice`, in a
+/// single partition.
+fn rice_bits(samples: &[i64], order: usize, rice: u32) -> u64 {
+    (order..samples.len())
+        .map(|index| (zigzag(fixed_residual(samples, order, index)) >> rice) + 1 + rice as u64)
+        .sum()
+}
+
+/// Picks the smallest subframe type for one channel, returning it with its size in bits.
+fn plan_subframe(samples: &[i64], bits: u32) -> (Subframe, u64) {
+    if samples.iter().all(|sample| *sample == samples[0]) {
+        return (Subframe::Constant, 8 + bits as u64);
+    }
+    let mut best = (Subframe::Verbatim, 8 + samples.len() as u64 * bits as u64);
+    let max_order = MAX_FIXED_ORDER.min(samples.len() - 1);
+    let Some((order, sum)) = (0..=max_order)
+        .map(|order| {
+            let sum: u64 = (order..samples.len())
+                .map(|index| fixed_residual(samples, order, index).unsigned_abs())
+                .sum();
+            (order, sum)
+        })
+        .min_by_key(|(_, sum)| *sum)
+    else {
+        return best;
+    };
+    // The mean magnitude gives the optimal parameter within one
+    let mean = sum / (samples.len() - order) as u64;
+    let estimate = 64 - mean.leading_zeros();
+    for rice in estimate.saturating_sub(1)..=(estimate + 1).min(30) {
+        let parameter_bits = if rice > 14 { 5 } else { 4 };
+        let size = 8
+            + order as u64 * bits as u64
+            + 2
+            + 4
+            + parameter_bits
+            + rice_bits(samples, order, rice);
+        if size < best.1 {
+            best = (Subframe::Fixed { order, rice }, size);
+        }
+    }
+    best
+}
+
+fn write_subframe(writer: &mut BitWriter, samples: &[i64], bits: u32, subframe: &Subframe) {
+    match subframe {
+        Subframe::Constant => {
+            writer.write(0b0000_0000, 8);
+            writer.write_signed(samples[0], bits);
+        }
+        Subframe::Verbatim => {
+            writer.write(0b0000_0010, 8);
+            for sample in samples {
+                writer.write_signed(*sample, bits);
+            }
+        }
+        Subframe::Fixed { order, rice } => {
+            writer.write(0b0001_0000 | (*order as u64) << 1, 8);
+            for sample in &samples[..*order] {
+                writer.write_signed(*sample, bits);
+            }
+            // Partitioned Rice with 5 bit parameters for large residuals, a single partition
+            let parameter_bits = if *rice > 14 { 5 } else { 4 };
+            writer.write(parameter_bits as u64 - 4, 2);
+            writer.write(0, 4);
+            writer.write(*rice as u64, parameter_bits);
+            for index in *order..samples.len() {
+                let value = zigzag(fixed_residual(samples, *order, index));
+                writer.write_unary(value >> rice);
+                writer.write(value, *rice);
+            }
+        }
+    }
+}
+
+/// Embedded artwork, written as a PICTURE metadata block.
+pub struct Picture {
+    /// ID3v2 picture type, 3 for the front cover
+    pub kind: u32,
+    pub media_type: String,
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub data: Box<[u8]>,
+}
+
+impl Picture {
+    pub(crate) fn block(&self) -> Vec<u8> {
+        let mut block = Vec::with_capacity(32 + self.media_type.len() + self.data.len());
+        block.extend(self.kind.to_be_bytes());
+        block.extend((self.media_type.len() as u32).to_be_bytes());
+        block.extend(self.media_type.as_bytes());
+        // No description
+        block.extend(0u32.to_be_bytes());
+        block.extend(self.width.to_be_bytes());
+        block.extend(self.height.to_be_bytes());
+        block.extend(self.depth.to_be_bytes());
+        // Colors of indexed images, unknown
+        block.extend(0u32.to_be_bytes());
+        block.extend((self.data.len() as u32).to_be_bytes());
+        block.extend(&self.data);
+        block
+    }
+}
+
+/// Minimal FLAC encoder with fixed predictors, good enough to shrink transcoded files to
+/// about the size of reference encoder output at its fastest settings.
+pub struct FlacWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    channels: usize,
+    bits: u32,
+    /// Interleaved samples not encoded yet
+    pending: Vec<i32>,
+    frame_number: u64,
+    total_frames: u64,
+    min_frame_size: usize,
+    max_frame_size: usize,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    /// `tags` are written as Vorbis comments, e.g. `("TITLE", "...")`.
+    pub fn new(
+        mut writer: W,
+        sample_rate: u32,
+        channels: usize,
+        bits: u32,
+        tags: &[(String, String)],
+        pictures: &[Picture],
+    ) -> Result<Self> {
+        if !(1..=8).contains(&channels) || !(4..=24).contains(&bits) {
+            return Err(anyhow!(
+                "Unsupported FLAC format {} channels {} bits",
+                channels,
+                bits
+            ));
+        }
+        writer.write_all(b"fLaC")?;
+        writer.write_all(&[0x00, 0x00, 0x00, STREAMINFO_LENGTH as u8])?;
+        writer.write_all(&[0; STREAMINFO_LENGTH])?;
+        let vendor = concat!("rhap ", env!("CARGO_PKG_VERSION"));
+        let mut comments = Vec::new();
+        comments.extend((vendor.len() as u32).to_le_bytes());
+        comments.extend(vendor.as_bytes());
+        comments.extend((tags.len() as u32).to_le_bytes());
+        for (key, value) in tags {
+            let comment = format!("{}={}", key, value);
+            comments.extend((comment.len() as u32).to_le_bytes());
+            comments.extend(comment.as_bytes());
+        }
+        let blocks = std::iter::once((4u8, comments))
+            .chain(pictures.iter().map(|picture| (6u8, picture.block())))
+            .collect::<Vec<_>>();
+        for (index, (kind, block)) in blocks.iter().enumerate() {
+            if block.len() >= 1 << 24 {
+                return Err(anyhow!("Metadata block too large for FLAC"));
+            }
+            let last = if index + 1 == blocks.len() { 0x80 } else { 0 };
+            writer.write_all(&[last | kind])?;
+            writer.write_all(&(block.len() as u32).to_be_bytes()[1..])?;
+            writer.write_all(block)?;
+        }
+        Ok(Self {
+            writer,
+            sample_rate,
+            channels,
+            bits,
+            pending: Vec::with_capacity(BLOCK_SIZE * channels),
+            frame_number: 0,
+            total_frames: 0,
+            min_frame_size: usize::MAX,
+            max_frame_size: 0,
+        })
+    }
+
+    /// Appends interleaved samples, right aligned to the stream bit depth.
+    pub fn write(&mut self, samples: &[i32]) -> Result<()> {
+        let mut pending = std::mem::take(&mut self.pending);
+        pending.extend_from_slice(samples);
+        let block = BLOCK_SIZE * self.channels;
+        let mut start = 0;
+        while pending.len() - start >= block {
+            let frame = self.encode_frame(&pending[start..start + block]);
+            self.write_frame(&frame)?;
+            start += block;
+        }
+        pending.drain(..start);
+        self.pending = pending;
+        Ok(())
+    }
+
+    /// Encodes the last partial frame and fills in the stream info.
+    pub fn finish(mut self) -> Result<W> {
+        let pending = std::mem::take(&mut self.pending);
+        let remaining = pending.len() - pending.len() % self.channels;
+        if remaining > 0 {
+            let frame = self.encode_frame(&pending[..remaining]);
+            self.write_frame(&frame)?;
+        }
+        let mut info = BitWriter::default();
+        let block = BLOCK_SIZE.min(self.total_frames.max(16) as usize) as u64;
+        info.write(block, 16);
+        info.write(block, 16);
+        info.write(self.min_frame_size.min(self.max_frame_size) as u64, 24);
+        info.write(self.max_frame_size as u64, 24);
+        info.write(self.sample_rate as u64, 20);
+        info.write(self.channels as u64 - 1, 3);
+        info.write(self.bits as u64 - 1, 5);
+        info.write(self.total_frames >> 32, 4);
+        info.write(self.total_frames, 32);
+        // The MD5 signature is optional, zero means unknown
+        info.bytes.extend([0; 16]);
+        self.writer.seek(SeekFrom::Start(8))?;
+        self.writer.write_all(&info.bytes)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        self.writer.write_all(frame)?;
+        self.min_frame_size = self.min_frame_size.min(frame.len());
+        self.max_frame_size = self.max_frame_size.max(frame.len());
+        Ok(())
+    }
+
+    fn encode_frame(&mut self, samples: &[i32]) -> Vec<u8> {
+        let frames = samples.len() / self.channels;
+        let channel = |index: usize| -> Vec<i64> {
+            samples
+                .iter()
+                .skip(index)
+                .step_by(self.channels)
+                .map(|sample| *sample as i64)
+                .collect()
+        };
+        let mut channels: Vec<(Vec<i64>, u32)> = (0..self.channels)
+            .map(|index| (channel(index), self.bits))
+            .collect();
+        let mut assignment = self.channels as u64 - 1;
+        let mut subframes: Vec<(Subframe, u64)> = channels
+            .iter()
+            .map(|(samples, bits)| plan_subframe(samples, *bits))
+            .collect();
+        if self.channels == 2 {
+            // Side channels need one more bit, pick the cheapest stereo decorrelation
+            let (left, right) = (&channels[0].0, &channels[1].0);
+            let side: Vec<i64> = left.iter().zip(right).map(|(l, r)| l - r).collect();
+            let mid: Vec<i64> = left.iter().zip(right).map(|(l, r)| (l + r) >> 1).collect();
+            let side_plan = plan_subframe(&side, self.bits + 1);
+            let mid_plan = plan_subframe(&mid, self.bits);
+            let independent = subframes[0].1 + subframes[1].1;
+            let left_side = subframes[0].1 + side_plan.1;
+            let side_right = side_plan.1 + subframes[1].1;
+            let mid_side = mid_plan.1 + side_plan.1;
+            let best = independent.min(left_side).min(side_right).min(mid_side);
+            if best == mid_side {
+                assignment = 10;
+                channels = vec![(mid, self.bits), (side, self.bits + 1)];
+                subframes = vec![mid_plan, side_plan];
+            } else if best == left_side {
+                assignment = 8;
+                channels[1] = (side, self.bits + 1);
+                subframes[1] = side_plan;
+            } else if best == side_right {
+                assignment = 9;
+                channels[0] = (side, self.bits + 1);
+                subframes[0] = side_plan;
+            }
+        }
+
+        let mut writer = BitWriter::default();
+        writer.write(0b11_1111_1111_1110, 14);
+        // Reserved bit then fixed blocking strategy
+        writer.write(0, 2);
+        // Block size as a 16 bit value after the frame number, sample rate from the stream info
+        writer.write(0b0111, 4);
+        writer.write(0b0000, 4);
+        writer.write(assignment, 4);
+        let size_code = match self.bits {
+            8 => 0b001,
+            12 => 0b010,
+            16 => 0b100,
+            20 => 0b101,
+            24 => 0b110,
+            _ => 0b000,
+        };
+        writer.write(size_code, 3);
+        writer.write(0, 1);
+        write_utf8(&mut writer, self.frame_number);
+        writer.write(frames as u64 - 1, 16);
+        let crc = crc8(&writer.bytes);
+        writer.write(crc as u64, 8);
+        for ((samples, bits), (subframe, _)) in channels.iter().zip(&subframes) {
+            write_subframe(&mut writer, samples, *bits, subframe);
+        }
+        writer.align();
+        let crc = crc16(&writer.bytes);
+        writer.write(crc as u64, 16);
+
+        self.frame_number += 1;
+        self.total_frames += frames as u64;
+        writer.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use std::io::Cursor;
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::formats::FormatOptions;
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::MetadataOptions;
+    use symphonia::core::probe::Hint;
+
+    fn encode(samples: &[i32], channels: usize, bits: u32) -> Vec<u8> {
+        let mut writer = FlacWriter::new(
+            Cursor::new(Vec::new()),
+            44100,
+            channels,
+            bits,
+            &[(String::from("TITLE"), String::from("Test"))],
+            &[],
+        )
+        .unwrap();
+        // Uneven writes must not change the frames
+        for chunk in samples.chunks(1000 * channels + 1) {
+            writer.write(chunk).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Decodes with symphonia, which checks the frame CRCs.
+    fn decode(flac: Vec<u8>, bits: u32) -> Vec<i32> {
+        let source = MediaSourceStream::new(Box::new(Cursor::new(flac)), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension("flac");
+        let mut format = symphonia::default::get_probe()
+            .format(
+                &hint,
+                source,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )
+            .unwrap()
+            .format;
+        let params = &format.default_track().unwrap().codec_params;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(params, &DecoderOptions { verify: true })
+            .unwrap();
+        let mut samples = Vec::new();
+        while let Ok(packet) = format.next_packet() {
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<i32>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            // Decoded samples are left aligned to 32 bits
+            samples.extend(buffer.samples().iter().map(|sample| sample >> (32 - bits)));
+        }
+        samples
+    }
+
+    fn samples(channels: usize, bits: u32) -> impl Strategy<Value = Vec<i32>> {
+        let max = (1i32 << (bits - 1)) - 1;
+        (1usize..10000).prop_flat_map(move |frames| {
+            prop_oneof![
+                prop::collection::vec(-max - 1..=max, frames * channels),
+                // Smooth signals exercise the predictors and the stereo modes
+                (0.0f64..0.2, 0.0f64..1.0).prop_map(move |(step, gain)| {
+                    (0..frames * channels)
+                        .map(|i| ((i / channels) as f64 * step).sin() * gain * max as f64)
+                        .map(|sample| sample as i32)
+                        .collect()
+                }),
+                Just(vec![0; frames * channels]),
+            ]
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(24))]
+
+        #[test]
+        fn round_trips_stereo_16_bits(samples in samples(2, 16)) {
+            prop_assert_eq!(decode(encode(&samples, 2, 16), 16), samples);
+        }
+
+        #[test]
+        fn round_trips_mono_24_bits(samples in samples(1, 24)) {
+            prop_assert_eq!(decode(encode(&samples, 1, 24), 24), samples);
+        }
+    }
+}
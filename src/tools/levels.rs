@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
+use symphonia::core::conv::IntoSample;
+use symphonia::core::sample::Sample;
+
+/// Per channel peak and RMS levels shared between an audio thread and the UI, stored as f32
+/// bits.
+pub struct Levels {
+    peaks: Vec<AtomicU32>,
+    rms: Vec<AtomicU32>,
+}
+
+fn measure<S>(buffer: &AudioBuffer<S>, levels: &Levels)
+where
+    S: Sample + IntoSample<f32>,
+{
+    if buffer.frames() == 0 {
+        return;
+    }
+    for channel in 0..buffer.spec().channels.count() {
+        let (peak, power) =
+            buffer
+                .chan(channel)
+                .iter()
+                .fold((0.0f32, 0.0f32), |(peak, power), sample| {
+                    let sample: f32 = (*sample).into_sample();
+                    (peak.max(sample.abs()), power + sample * sample)
+                });
+        levels.store(channel, peak, (power / buffer.frames() as f32).sqrt());
+    }
+}
+
+impl Levels {
+    pub fn new(channels: usize) -> Self {
+        Self {
+            peaks: (0..channels).map(|_| AtomicU32::new(0)).collect(),
+            rms: (0..channels).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    pub fn store(&self, channel: usize, peak: f32, rms: f32) {
+        if let (Some(level), Some(mean)) = (self.peaks.get(channel), self.rms.get(channel)) {
+            level.store(peak.to_bits(), Ordering::Relaxed);
+            mean.store(rms.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Measures a decoded packet, as played.
+    pub fn store_buffer(&self, decoded: &AudioBufferRef<'_>) {
+        match decoded {
+            AudioBufferRef::U8(buffer) => measure(buffer, self),
+            AudioBufferRef::U16(buffer) => measure(buffer, self),
+            AudioBufferRef::U24(buffer) => measure(buffer, self),
+            AudioBufferRef::U32(buffer) => measure(buffer, self),
+            AudioBufferRef::S8(buffer) => measure(buffer, self),
+            AudioBufferRef::S16(buffer) => measure(buffer, self),
+            AudioBufferRef::S24(buffer) => measure(buffer, self),
+            AudioBufferRef::S32(buffer) => measure(buffer, self),
+            AudioBufferRef::F32(buffer) => measure(buffer, self),
+            AudioBufferRef::F64(buffer) => measure(buffer, self),
+        }
+    }
+
+    pub fn clear(&self) {
+        for level in self.peaks.iter().chain(&self.rms) {
+            level.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn get(&self) -> Vec<f32> {
+        self.peaks
+            .iter()
+            .map(|level| f32::from_bits(level.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    pub fn rms(&self) -> Vec<f32> {
+        self.rms
+            .iter()
+            .map(|level| f32::from_bits(level.load(Ordering::Relaxed)))
+            .collect()
+    }
+}
@@ -1 +1,12 @@
-pub(crate) mod resampler;
+pub(crate) mod cpu;
+pub(crate) mod crossfade;
+pub(crate) mod flac;
+pub(crate) mod levels;
+#[cfg(feature = "mp3")]
+pub(crate) mod mp3;
+#[cfg(feature = "opus")]
+pub(crate) mod opus;
+pub mod resampler;
+#[cfg(feature = "soxr")]
+pub(crate) mod soxr;
+pub(crate) mod tap;
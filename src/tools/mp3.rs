@@ -0,0 +1,210 @@
+use anyhow::{anyhow, Result};
+use std::ffi::{c_char, c_int, c_ushort};
+use std::io::Write;
+
+use super::flac::Picture;
+
+#[repr(C)]
+struct LameGlobalFlags {
+    _private: [u8; 0],
+}
+
+#[link(name = "mp3lame")]
+extern "C" {
+    fn lame_init() -> *mut LameGlobalFlags;
+    fn lame_set_num_channels(flags: *mut LameGlobalFlags, channels: c_int) -> c_int;
+    fn lame_set_in_samplerate(flags: *mut LameGlobalFlags, rate: c_int) -> c_int;
+    fn lame_set_brate(flags: *mut LameGlobalFlags, kbps: c_int) -> c_int;
+    fn lame_set_quality(flags: *mut LameGlobalFlags, quality: c_int) -> c_int;
+    fn lame_set_bWriteVbrTag(flags: *mut LameGlobalFlags, write: c_int) -> c_int;
+    fn lame_init_params(flags: *mut LameGlobalFlags) -> c_int;
+    fn lame_encode_buffer_ieee_float(
+        flags: *mut LameGlobalFlags,
+        left: *const f32,
+        right: *const f32,
+        frames: c_int,
+        output: *mut u8,
+        size: c_int,
+    ) -> c_int;
+    fn lame_encode_flush(flags: *mut LameGlobalFlags, output: *mut u8, size: c_int) -> c_int;
+    fn lame_close(flags: *mut LameGlobalFlags) -> c_int;
+    fn id3tag_init(flags: *mut LameGlobalFlags);
+    fn id3tag_add_v2(flags: *mut LameGlobalFlags);
+    fn id3tag_v2_only(flags: *mut LameGlobalFlags);
+    fn id3tag_set_fieldvalue_utf16(flags: *mut LameGlobalFlags, value: *const c_ushort) -> c_int;
+    fn id3tag_set_albumart(flags: *mut LameGlobalFlags, image: *const c_char, size: usize)
+        -> c_int;
+}
+
+/// Rates of MPEG-1 layer III, the only ones written.
+const SAMPLE_RATES: [usize; 3] = [32000, 44100, 48000];
+
+/// Constant bitrate, the highest of MPEG-1 layer III, so that no Xing header is needed.
+const BITRATE_KBPS: c_int = 320;
+
+/// ID3v2 frames of the Vorbis comment names, the others go to TXXX frames.
+const FRAMES: &[(&str, &str)] = &[
+    ("TITLE", "TIT2"),
+    ("ARTIST", "TPE1"),
+    ("ALBUMARTIST", "TPE2"),
+    ("ALBUM", "TALB"),
+    ("TRACKNUMBER", "TRCK"),
+    ("DISCNUMBER", "TPOS"),
+    ("DATE", "TYER"),
+    ("GENRE", "TCON"),
+    ("COMPOSER", "TCOM"),
+    ("CONDUCTOR", "TPE3"),
+    ("LABEL", "TPUB"),
+    ("COMMENT", "COMM"),
+    ("ISRC", "TSRC"),
+];
+
+/// Encoder state, closed once dropped.
+struct Lame(*mut LameGlobalFlags);
+
+impl Drop for Lame {
+    fn drop(&mut self) {
+        unsafe { lame_close(self.0) };
+    }
+}
+
+/// MP3 encoder backed by LAME, for mono and stereo. Tags and the front cover go to an
+/// ID3v2 tag written by LAME ahead of the first frame.
+pub struct Mp3Writer<W: Write> {
+    writer: W,
+    lame: Lame,
+    channels: usize,
+    /// Samples of each channel, the right one is left empty in mono
+    left: Vec<f32>,
+    right: Vec<f32>,
+    output: Vec<u8>,
+}
+
+impl<W: Write> Mp3Writer<W> {
+    /// `tags` are named as Vorbis comments, e.g. `("TITLE", "...")`.
+    pub fn new(
+        writer: W,
+        sample_rate: usize,
+        channels: usize,
+        tags: &[(String, String)],
+        pictures: &[Picture],
+    ) -> Result<Self> {
+        if !(1..=2).contains(&channels) {
+            return Err(anyhow!("MP3 files are written in mono or stereo only"));
+        }
+        if !SAMPLE_RATES.contains(&sample_rate) {
+            return Err(anyhow!("MP3 files are written at 32000, 44100 or 48000 Hz"));
+        }
+        let flags = unsafe { lame_init() };
+        if flags.is_null() {
+            return Err(anyhow!("Cannot start the MP3 encoder"));
+        }
+        let lame = Lame(flags);
+        unsafe {
+            lame_set_num_channels(flags, channels as c_int);
+            lame_set_in_samplerate(flags, sample_rate as c_int);
+            lame_set_brate(flags, BITRATE_KBPS);
+            // The highest quality that is not far slower
+            lame_set_quality(flags, 2);
+            lame_set_bWriteVbrTag(flags, 0);
+            id3tag_init(flags);
+            id3tag_add_v2(flags);
+            id3tag_v2_only(flags);
+        }
+        let tracks = tags.iter().find(|(key, _)| key == "TRACKTOTAL");
+        let discs = tags.iter().find(|(key, _)| key == "DISCTOTAL");
+        for (key, value) in tags {
+            let value = match (key.as_str(), tracks, discs) {
+                ("TRACKTOTAL" | "DISCTOTAL", _, _) => continue,
+                ("TRACKNUMBER", Some((_, total)), _) | ("DISCNUMBER", _, Some((_, total))) => {
+                    format!("{}/{}", value, total)
+                }
+                _ => value.clone(),
+            };
+            let field = match FRAMES.iter().find(|(name, _)| name == key) {
+                Some((_, frame)) => format!("{}={}", frame, value),
+                None => format!("TXXX={}={}", key, value),
+            };
+            // UTF-16 with a byte order mark, for tags outside of Latin-1
+            let field: Vec<c_ushort> = std::iter::once(0xfeff)
+                .chain(field.encode_utf16())
+                .chain(std::iter::once(0))
+                .collect();
+            unsafe { id3tag_set_fieldvalue_utf16(flags, field.as_ptr()) };
+        }
+        // ID3v2 tags written by LAME hold a single picture
+        let cover = pictures
+            .iter()
+            .find(|picture| picture.kind == 3)
+            .or(pictures.first());
+        if let Some(cover) = cover {
+            let data = cover.data.as_ptr() as *const c_char;
+            unsafe { id3tag_set_albumart(flags, data, cover.data.len()) };
+        }
+        if unsafe { lame_init_params(flags) } < 0 {
+            return Err(anyhow!("The MP3 encoder refused its settings"));
+        }
+        Ok(Self {
+            writer,
+            lame,
+            channels,
+            left: Vec::new(),
+            right: Vec::new(),
+            output: Vec::new(),
+        })
+    }
+
+    /// Appends interleaved samples, full scale at 1.0.
+    pub fn write(&mut self, samples: &[f64]) -> Result<()> {
+        self.left.clear();
+        self.right.clear();
+        for frame in samples.chunks_exact(self.channels) {
+            self.left.push(frame[0] as f32);
+            if let Some(right) = frame.get(1) {
+                self.right.push(*right as f32);
+            }
+        }
+        let frames = self.left.len();
+        // Only read in stereo
+        let right = match self.channels {
+            1 => self.left.as_ptr(),
+            _ => self.right.as_ptr(),
+        };
+        // Worst case given by the LAME documentation
+        self.output.resize(frames * 5 / 4 + 7200, 0);
+        let size = unsafe {
+            lame_encode_buffer_ieee_float(
+                self.lame.0,
+                self.left.as_ptr(),
+                right,
+                frames as c_int,
+                self.output.as_mut_ptr(),
+                self.output.len() as c_int,
+            )
+        };
+        self.flush_output(size)
+    }
+
+    /// Encodes the samples LAME holds back and the last frame.
+    pub fn finish(mut self) -> Result<W> {
+        self.output.resize(7200, 0);
+        let size = unsafe {
+            lame_encode_flush(
+                self.lame.0,
+                self.output.as_mut_ptr(),
+                self.output.len() as c_int,
+            )
+        };
+        self.flush_output(size)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn flush_output(&mut self, size: c_int) -> Result<()> {
+        if size < 0 {
+            return Err(anyhow!("MP3 encoding failed with error {}", size));
+        }
+        self.writer.write_all(&self.output[..size as usize])?;
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use audiopus::coder::Encoder;
+use audiopus::{Application, Bitrate, Channels, SampleRate};
+use base64::Engine;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+use std::io::Write;
+
+use super::flac::Picture;
+
+/// Frames per packet, 20 ms, the frame size Opus is tuned for.
+const FRAME_SIZE: usize = 960;
+
+/// Largest packet, as advised by the libopus documentation.
+const MAX_PACKET_SIZE: usize = 4000;
+
+/// Serial of the only logical stream, "rhap".
+const SERIAL: u32 = 0x72686170;
+
+/// Ogg Opus encoder for mono and stereo, at 160 kbps in stereo and 96 kbps in mono, about
+/// transparent for music.
+pub struct OpusWriter<W: Write> {
+    writer: PacketWriter<W>,
+    encoder: Encoder,
+    channels: usize,
+    /// Interleaved samples not encoded yet
+    pending: Vec<f32>,
+    packet: Vec<u8>,
+    /// Frames of encoder delay, dropped by decoders
+    pre_skip: u64,
+    /// Frames written, the padding of the last packet excluded
+    written: u64,
+    /// Frames encoded so far
+    encoded: u64,
+    /// Packet held back with its granule position, the last one ends the stream
+    held: Option<(Vec<u8>, u64)>,
+}
+
+impl<W: Write> OpusWriter<W> {
+    /// `input_rate` is the rate of the source, only kept as information in the header. `tags`
+    /// are written as Vorbis comments, e.g. `("TITLE", "...")`.
+    pub fn new(
+        writer: W,
+        input_rate: u32,
+        channels: usize,
+        tags: &[(String, String)],
+        pictures: &[Picture],
+    ) -> Result<Self> {
+        let (layout, bitrate) = match channels {
+            1 => (Channels::Mono, 96_000),
+            2 => (Channels::Stereo, 160_000),
+            _ => return Err(anyhow!("Opus files are written in mono or stereo only")),
+        };
+        let mut encoder = Encoder::new(SampleRate::Hz48000, layout, Application::Audio)?;
+        encoder.set_bitrate(Bitrate::BitsPerSecond(bitrate))?;
+        let pre_skip = encoder.lookahead()?;
+
+        let mut writer = PacketWriter::new(writer);
+        let mut head = Vec::with_capacity(19);
+        head.extend(b"OpusHead");
+        head.push(1);
+        head.push(channels as u8);
+        head.extend((pre_skip as u16).to_le_bytes());
+        head.extend(input_rate.to_le_bytes());
+        // No output gain, channel mapping family 0
+        head.extend(0i16.to_le_bytes());
+        head.push(0);
+        writer.write_packet(head.into(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        let vendor = concat!("rhap ", env!("CARGO_PKG_VERSION"));
+        let comments = tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .chain(pictures.iter().map(|picture| {
+                let block = base64::engine::general_purpose::STANDARD.encode(picture.block());
+                format!("METADATA_BLOCK_PICTURE={}", block)
+            }))
+            .collect::<Vec<_>>();
+        let mut header = Vec::new();
+        header.extend(b"OpusTags");
+        header.extend((vendor.len() as u32).to_le_bytes());
+        header.extend(vendor.as_bytes());
+        header.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            header.extend((comment.len() as u32).to_le_bytes());
+            header.extend(comment.as_bytes());
+        }
+        writer.write_packet(header.into(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+        Ok(Self {
+            writer,
+            encoder,
+            channels,
+            pending: Vec::with_capacity(FRAME_SIZE * channels),
+            packet: vec![0; MAX_PACKET_SIZE],
+            pre_skip: pre_skip as u64,
+            written: 0,
+            encoded: 0,
+            held: None,
+        })
+    }
+
+    /// Appends interleaved samples at 48 kHz, full scale at 1.0.
+    pub fn write(&mut self, samples: &[f64]) -> Result<()> {
+        self.written += (samples.len() / self.channels) as u64;
+        let frame = FRAME_SIZE * self.channels;
+        for sample in samples {
+            self.pending.push(*sample as f32);
+            if self.pending.len() == frame {
+                self.encode()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes what is left, padded with silence until the encoder delay is out, and ends
+    /// the stream.
+    pub fn finish(mut self) -> Result<W> {
+        let frame = FRAME_SIZE * self.channels;
+        while self.encoded < self.written + self.pre_skip || !self.pending.is_empty() {
+            self.pending.resize(frame, 0.0);
+            self.encode()?;
+        }
+        if let Some((packet, _)) = self.held.take() {
+            // Decoders drop the padding past the last granule position
+            let granule = self.pre_skip + self.written;
+            self.writer.write_packet(
+                packet.into(),
+                SERIAL,
+                PacketWriteEndInfo::EndStream,
+                granule,
+            )?;
+        }
+        Ok(self.writer.into_inner())
+    }
+
+    fn encode(&mut self) -> Result<()> {
+        let size = self.encoder.encode_float(&self.pending, &mut self.packet)?;
+        self.pending.clear();
+        self.encoded += FRAME_SIZE as u64;
+        let packet = (self.packet[..size].to_vec(), self.encoded);
+        if let Some((packet, granule)) = self.held.replace(packet) {
+            self.writer.write_packet(
+                packet.into(),
+                SERIAL,
+                PacketWriteEndInfo::NormalPacket,
+                granule,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audiopus::coder::Decoder;
+    use ogg::reading::PacketReader;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trips_without_the_encoder_delay() {
+        let frames = 48000 + 123;
+        let samples: Vec<f64> = (0..frames * 2)
+            .map(|i| ((i / 2) as f64 * 0.05).sin() * 0.5)
+            .collect();
+        let mut writer = OpusWriter::new(
+            Cursor::new(Vec::new()),
+            44100,
+            2,
+            &[(String::from("TITLE"), String::from("Test"))],
+            &[],
+        )
+        .unwrap();
+        // Uneven writes must not change the packets
+        for chunk in samples.chunks(1001 * 2) {
+            writer.write(chunk).unwrap();
+        }
+        let file = writer.finish().unwrap().into_inner();
+
+        let mut reader = PacketReader::new(Cursor::new(file));
+        let head = reader.read_packet_expected().unwrap();
+        assert_eq!(&head.data[..8], b"OpusHead");
+        assert_eq!(head.data[9], 2);
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as usize;
+        assert_eq!(&head.data[12..16], &44100u32.to_le_bytes());
+        let tags = reader.read_packet_expected().unwrap();
+        assert_eq!(&tags.data[..8], b"OpusTags");
+        assert!(tags.data.ends_with(b"TITLE=Test"));
+
+        let mut decoder = Decoder::new(SampleRate::Hz48000, Channels::Stereo).unwrap();
+        let mut output = vec![0.0f32; FRAME_SIZE * 2];
+        let mut decoded = Vec::new();
+        let mut last = None;
+        while let Some(packet) = reader.read_packet().unwrap() {
+            let size = decoder
+                .decode_float(
+                    Some((&packet.data).try_into().unwrap()),
+                    (&mut output).try_into().unwrap(),
+                    false,
+                )
+                .unwrap();
+            decoded.extend_from_slice(&output[..size * 2]);
+            last = Some(packet);
+        }
+        let last = last.unwrap();
+        assert!(last.last_in_stream());
+        assert_eq!(last.absgp_page() as usize, pre_skip + frames);
+
+        // Players drop the delay and what is past the last granule position
+        let decoded = &decoded[pre_skip * 2..(pre_skip + frames) * 2];
+        let error = samples
+            .iter()
+            .zip(decoded)
+            .map(|(sample, decoded)| (sample - *decoded as f64).powi(2))
+            .sum::<f64>()
+            / samples.len() as f64;
+        assert!(error < 1e-4, "mean square error {}", error);
+    }
+}
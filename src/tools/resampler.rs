@@ -1,16 +1,141 @@
 use anyhow::Result;
+use clap::ValueEnum;
 use log::error;
-use rubato::{FftFixedIn, Resampler};
+use rubato::{
+    calculate_cutoff, FftFixedIn, SincFixedIn, SincInterpolationParameters, SincInterpolationType,
+    VecResampler, WindowFunction,
+};
+use serde::Deserialize;
+use std::fmt::{self, Display};
 use symphonia::core::{
     audio::{AudioBuffer, AudioBufferRef, Signal},
     conv::{FromSample, IntoSample},
     sample::Sample,
 };
+use tracing::trace_span;
 
+#[cfg(feature = "soxr")]
+use super::soxr::SoxrResampler;
 use crate::audio::BitsPerSample;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ResamplerEngine {
+    /// Synchronous FFT resampling, cheap and exact for fixed ratios
+    #[default]
+    Fft,
+    /// Windowed sinc interpolation, costlier but with a steeper anti-aliasing filter
+    Sinc,
+    /// libsoxr, needs the soxr feature
+    #[cfg(feature = "soxr")]
+    Soxr,
+}
+
+/// Trades CPU time for a longer filter, i.e. less aliasing and roll-off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ResamplerQuality {
+    Low,
+    Medium,
+    #[default]
+    High,
+}
+
+/// Resampler used when the output device does not support the track sample rate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ResamplerSettings {
+    pub engine: ResamplerEngine,
+    pub quality: ResamplerQuality,
+}
+
+impl Display for ResamplerSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let engine = match self.engine {
+            ResamplerEngine::Fft => "fft",
+            ResamplerEngine::Sinc => "sinc",
+            #[cfg(feature = "soxr")]
+            ResamplerEngine::Soxr => "soxr",
+        };
+        let quality = match self.quality {
+            ResamplerQuality::Low => "low",
+            ResamplerQuality::Medium => "medium",
+            ResamplerQuality::High => "high",
+        };
+        write!(f, "{} {}", engine, quality)
+    }
+}
+
+impl ResamplerSettings {
+    fn build(
+        &self,
+        from_samplerate: usize,
+        to_samplerate: usize,
+        frames: usize,
+        channels: usize,
+    ) -> Result<Box<dyn VecResampler<f64>>> {
+        Ok(match self.engine {
+            // Fewer sub chunks mean longer FFTs, hence a steeper filter
+            ResamplerEngine::Fft => {
+                let sub_chunks = match self.quality {
+                    ResamplerQuality::Low => 4,
+                    ResamplerQuality::Medium => 2,
+                    ResamplerQuality::High => 1,
+                };
+                Box::new(FftFixedIn::<f64>::new(
+                    from_samplerate,
+                    to_samplerate,
+                    frames,
+                    sub_chunks,
+                    channels,
+                )?)
+            }
+            ResamplerEngine::Sinc => {
+                let (sinc_len, interpolation, window) = match self.quality {
+                    ResamplerQuality::Low => {
+                        (64, SincInterpolationType::Linear, WindowFunction::Hann2)
+                    }
+                    ResamplerQuality::Medium => (
+                        128,
+                        SincInterpolationType::Quadratic,
+                        WindowFunction::Blackman2,
+                    ),
+                    ResamplerQuality::High => (
+                        256,
+                        SincInterpolationType::Cubic,
+                        WindowFunction::BlackmanHarris2,
+                    ),
+                };
+                let parameters = SincInterpolationParameters {
+                    sinc_len,
+                    f_cutoff: calculate_cutoff::<f32>(sinc_len, window),
+                    oversampling_factor: 128,
+                    interpolation,
+                    window,
+                };
+                Box::new(SincFixedIn::<f64>::new(
+                    to_samplerate as f64 / from_samplerate as f64,
+                    1.0,
+                    parameters,
+                    frames,
+                    channels,
+                )?)
+            }
+            #[cfg(feature = "soxr")]
+            ResamplerEngine::Soxr => Box::new(SoxrResampler::new(
+                from_samplerate,
+                to_samplerate,
+                frames,
+                channels,
+                self.quality,
+            )?),
+        })
+    }
+}
+
 pub struct RubatoResampler<O> {
-    resampler: FftFixedIn<f64>,
+    resampler: Box<dyn VecResampler<f64>>,
+    settings: ResamplerSettings,
     input: Vec<Vec<f64>>,
     output: Vec<Vec<f64>>,
     interleaved_output: Vec<O>,
@@ -31,16 +156,17 @@ where
         _to_bits_per_sample: BitsPerSample,
         frames: usize,
         channels: usize,
+        settings: ResamplerSettings,
     ) -> Result<Self> {
-        let resampler =
-            rubato::FftFixedIn::<f64>::new(from_samplerate, to_samplerate, frames, 1, channels)?;
+        let resampler = settings.build(from_samplerate, to_samplerate, frames, channels)?;
 
         let output = resampler.output_buffer_allocate(true);
-        let input = resampler.input_buffer_allocate(true);
+        let input = resampler.input_buffer_allocate(false);
         let interleaved_output = Vec::<O>::with_capacity(frames * channels);
 
         Ok(Self {
             resampler,
+            settings,
             input,
             output,
             interleaved_output,
@@ -51,48 +177,77 @@ where
         })
     }
 
+    /// Frames of output lagging behind the input, silence at the start of the output.
+    pub fn output_delay(&self) -> usize {
+        self.resampler.output_delay()
+    }
+
     pub fn resample(&mut self, input: &AudioBufferRef<'_>) -> Result<&[O]> {
-        if input.frames() != self.frames {
+        let _span = trace_span!("resample", frames = input.frames()).entered();
+        if input.frames() > self.frames {
             self.frames = input.frames();
-            self.resampler = rubato::FftFixedIn::<f64>::new(
+            self.resampler = self.settings.build(
                 self.from_samplerate,
                 self.to_samplerate,
                 self.frames,
-                1,
                 self.channels,
-            )
-            .unwrap();
+            )?;
             self.output = self.resampler.output_buffer_allocate(true);
-            self.input = self.resampler.input_buffer_allocate(true);
+            self.input = self.resampler.input_buffer_allocate(false);
         }
         match input {
-            AudioBufferRef::S32(buffer) => {
-                copy_samples_vec(buffer, &mut self.input);
-                self.resampler
-                    .process_into_buffer(&self.input, &mut self.output, None)?;
-
-                self.input.iter_mut().for_each(|channel| {
-                    channel.drain(0..self.frames);
-                });
-
-                self.interleaved_output
-                    .resize(self.channels * self.output[0].len(), O::MID);
-
-                self.interleaved_output
-                    .chunks_exact_mut(self.channels)
-                    .enumerate()
-                    .for_each(|(i, frame)| {
-                        frame.iter_mut().enumerate().for_each(|(ch, s)| {
-                            *s = self.output[ch][i].into_sample();
-                        })
-                    });
-            }
+            AudioBufferRef::S16(buffer) => copy_samples_vec(buffer, &mut self.input),
+            AudioBufferRef::S24(buffer) => copy_samples_vec(buffer, &mut self.input),
+            AudioBufferRef::S32(buffer) => copy_samples_vec(buffer, &mut self.input),
+            AudioBufferRef::F32(buffer) => copy_samples_vec(buffer, &mut self.input),
+            AudioBufferRef::F64(buffer) => copy_samples_vec(buffer, &mut self.input),
             _ => {
                 error!("Unsupported sample format");
+                return Ok(&self.interleaved_output);
             }
         }
+        // Both engines keep part of their input, the output length varies between calls. Short
+        // chunks, such as the last packet of a file, are padded with silence.
+        let (_, written) = if input.frames() < self.frames {
+            self.resampler
+                .process_partial_into_buffer(Some(&self.input), &mut self.output, None)?
+        } else {
+            self.resampler
+                .process_into_buffer(&self.input, &mut self.output, None)?
+        };
+
+        self.input.iter_mut().for_each(|channel| {
+            channel.drain(0..input.frames());
+        });
+
+        Ok(self.interleave(written))
+    }
+
+    /// Pushes a chunk of silence through, releasing the output held back by the filter at the
+    /// end of a stream.
+    pub fn flush(&mut self) -> Result<&[O]> {
+        let (_, written) = self.resampler.process_partial_into_buffer(
+            None::<&[Vec<f64>]>,
+            &mut self.output,
+            None,
+        )?;
+        Ok(self.interleave(written))
+    }
 
-        Ok(&self.interleaved_output)
+    fn interleave(&mut self, written: usize) -> &[O] {
+        self.interleaved_output
+            .resize(self.channels * written, O::MID);
+
+        self.interleaved_output
+            .chunks_exact_mut(self.channels)
+            .enumerate()
+            .for_each(|(i, frame)| {
+                frame.iter_mut().enumerate().for_each(|(ch, s)| {
+                    *s = self.output[ch][i].into_sample();
+                })
+            });
+
+        &self.interleaved_output
     }
 }
 
@@ -109,3 +264,122 @@ where
             samples.extend(source.iter().map(|&s| s.into_sample()));
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use symphonia::core::audio::{AsAudioBufferRef, Channels, SignalSpec};
+    use symphonia::core::sample::i24;
+
+    const FRAMES: usize = 1024;
+    /// Resamplers buffer part of their input, a single chunk may not output anything
+    const CHUNKS: usize = 4;
+
+    fn engines() -> Vec<ResamplerEngine> {
+        vec![
+            ResamplerEngine::Fft,
+            ResamplerEngine::Sinc,
+            #[cfg(feature = "soxr")]
+            ResamplerEngine::Soxr,
+        ]
+    }
+
+    /// Stereo buffer of `FRAMES` frames cycling through `samples`.
+    fn buffer<S: Sample>(samples: &[S]) -> AudioBuffer<S> {
+        let spec = SignalSpec::new(44100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buffer = AudioBuffer::<S>::new(FRAMES as u64, spec);
+        buffer.render_reserved(Some(FRAMES));
+        for channel in 0..2 {
+            for (index, sample) in buffer.chan_mut(channel).iter_mut().enumerate() {
+                *sample = samples[(index * 2 + channel) % samples.len()];
+            }
+        }
+        buffer
+    }
+
+    fn resample<O>(
+        engine: ResamplerEngine,
+        to_samplerate: usize,
+        input: &AudioBufferRef<'_>,
+    ) -> Vec<O>
+    where
+        O: Sample + FromSample<f64> + IntoSample<f64> + Default + Clone,
+    {
+        let mut resampler = RubatoResampler::<O>::new(
+            44100,
+            to_samplerate,
+            BitsPerSample::Bits24,
+            BitsPerSample::Bits32,
+            FRAMES,
+            2,
+            ResamplerSettings {
+                engine,
+                quality: ResamplerQuality::High,
+            },
+        )
+        .unwrap();
+        (0..CHUNKS)
+            .flat_map(|_| resampler.resample(input).unwrap().to_vec())
+            .collect()
+    }
+
+    fn assert_bounded(output: &[f32]) -> Result<(), TestCaseError> {
+        prop_assert!(!output.is_empty());
+        for sample in output {
+            prop_assert!(sample.is_finite(), "{}", sample);
+            // Leaves room for the ringing of the anti-aliasing filter
+            prop_assert!(sample.abs() <= 2.0, "{}", sample);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn identity_rate_keeps_the_frame_count(
+            samples in prop::collection::vec(-1.0f32..=1.0, 1..64),
+        ) {
+            let input = buffer(&samples);
+            let output = resample::<f32>(ResamplerEngine::Fft, 44100, &input.as_audio_buffer_ref());
+            prop_assert_eq!(output.len(), CHUNKS * FRAMES * 2);
+            assert_bounded(&output)?;
+        }
+
+        #[test]
+        fn integer_inputs_are_bounded(
+            samples in prop::collection::vec(any::<i16>(), 1..64),
+            samples_24 in prop::collection::vec((-(1 << 23))..(1 << 23), 1..64),
+            to_samplerate in prop::sample::select(vec![44100usize, 48000, 88200, 96000]),
+            engine in prop::sample::select(engines()),
+        ) {
+            let input = buffer(&samples);
+            assert_bounded(&resample::<f32>(engine, to_samplerate, &input.as_audio_buffer_ref()))?;
+            let samples_24: Vec<i24> = samples_24.into_iter().map(i24::from).collect();
+            let input = buffer(&samples_24);
+            assert_bounded(&resample::<f32>(engine, to_samplerate, &input.as_audio_buffer_ref()))?;
+        }
+
+        #[test]
+        fn float_inputs_are_bounded(
+            samples in prop::collection::vec(-1.0f64..=1.0, 1..64),
+            to_samplerate in prop::sample::select(vec![44100usize, 48000, 88200, 96000]),
+            engine in prop::sample::select(engines()),
+        ) {
+            let input = buffer(&samples);
+            assert_bounded(&resample::<f32>(engine, to_samplerate, &input.as_audio_buffer_ref()))?;
+        }
+    }
+
+    #[test]
+    fn silence_stays_silent() {
+        let input = buffer(&[0i16]);
+        for engine in engines() {
+            let output = resample::<i16>(engine, 44100, &input.as_audio_buffer_ref());
+            assert!(output.iter().all(|sample| *sample == 0));
+            let output = resample::<i24>(engine, 96000, &input.as_audio_buffer_ref());
+            assert!(output.iter().all(|sample| sample.inner() == 0));
+        }
+    }
+}
@@ -0,0 +1,254 @@
+use anyhow::{anyhow, Result};
+use log::error;
+use rubato::{ResampleError, ResampleResult, Resampler};
+use std::ffi::{c_char, c_double, c_uint, c_ulong, c_void, CStr};
+
+use super::resampler::ResamplerQuality;
+
+#[repr(C)]
+struct SoxrState {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+struct SoxrIoSpec {
+    itype: c_uint,
+    otype: c_uint,
+    scale: c_double,
+    e: *mut c_void,
+    flags: c_ulong,
+}
+
+#[repr(C)]
+struct SoxrQualitySpec {
+    precision: c_double,
+    phase_response: c_double,
+    passband_end: c_double,
+    stopband_begin: c_double,
+    e: *mut c_void,
+    flags: c_ulong,
+}
+
+/// Error strings are static, null when all went well
+type SoxrError = *const c_char;
+
+#[link(name = "soxr")]
+extern "C" {
+    fn soxr_create(
+        input_rate: c_double,
+        output_rate: c_double,
+        channels: c_uint,
+        error: *mut SoxrError,
+        io_spec: *const SoxrIoSpec,
+        quality_spec: *const SoxrQualitySpec,
+        runtime_spec: *const c_void,
+    ) -> *mut SoxrState;
+    fn soxr_process(
+        state: *mut SoxrState,
+        input: *const *const f64,
+        input_frames: usize,
+        input_done: *mut usize,
+        output: *const *mut f64,
+        output_frames: usize,
+        output_done: *mut usize,
+    ) -> SoxrError;
+    fn soxr_clear(state: *mut SoxrState) -> SoxrError;
+    fn soxr_delete(state: *mut SoxrState);
+    fn soxr_quality_spec(recipe: c_ulong, flags: c_ulong) -> SoxrQualitySpec;
+}
+
+/// Samples as one 64 bits float buffer per channel
+const SOXR_FLOAT64_S: c_uint = 5;
+const SOXR_LQ: c_ulong = 1;
+const SOXR_HQ: c_ulong = 4;
+const SOXR_VHQ: c_ulong = 6;
+
+/// Resampler state, deleted once dropped.
+struct Soxr(*mut SoxrState);
+
+// libsoxr keeps no thread local state, a resampler may move to another thread
+unsafe impl Send for Soxr {}
+
+impl Drop for Soxr {
+    fn drop(&mut self) {
+        unsafe { soxr_delete(self.0) };
+    }
+}
+
+fn message(error: SoxrError) -> Option<String> {
+    (!error.is_null()).then(|| {
+        unsafe { CStr::from_ptr(error) }
+            .to_string_lossy()
+            .into_owned()
+    })
+}
+
+/// libsoxr behind the rubato interface, taking chunks of `chunk` frames like the rubato fixed
+/// input resamplers. Its output comes aligned with the input, there is no delay to drop.
+pub struct SoxrResampler {
+    soxr: Soxr,
+    channels: usize,
+    chunk: usize,
+    ratio: f64,
+}
+
+impl SoxrResampler {
+    pub fn new(
+        from_samplerate: usize,
+        to_samplerate: usize,
+        chunk: usize,
+        channels: usize,
+        quality: ResamplerQuality,
+    ) -> Result<Self> {
+        let recipe = match quality {
+            ResamplerQuality::Low => SOXR_LQ,
+            ResamplerQuality::Medium => SOXR_HQ,
+            ResamplerQuality::High => SOXR_VHQ,
+        };
+        let io_spec = SoxrIoSpec {
+            itype: SOXR_FLOAT64_S,
+            otype: SOXR_FLOAT64_S,
+            scale: 1.0,
+            e: std::ptr::null_mut(),
+            flags: 0,
+        };
+        let quality_spec = unsafe { soxr_quality_spec(recipe, 0) };
+        let mut error: SoxrError = std::ptr::null();
+        let state = unsafe {
+            soxr_create(
+                from_samplerate as c_double,
+                to_samplerate as c_double,
+                channels as c_uint,
+                &mut error,
+                &io_spec,
+                &quality_spec,
+                std::ptr::null(),
+            )
+        };
+        if let Some(message) = message(error) {
+            return Err(anyhow!("libsoxr: {}", message));
+        }
+        if state.is_null() {
+            return Err(anyhow!("libsoxr cannot resample to {}Hz", to_samplerate));
+        }
+        Ok(Self {
+            soxr: Soxr(state),
+            channels,
+            chunk,
+            ratio: to_samplerate as f64 / from_samplerate as f64,
+        })
+    }
+}
+
+impl Resampler<f64> for SoxrResampler {
+    fn process_into_buffer<Vin: AsRef<[f64]>, Vout: AsMut<[f64]>>(
+        &mut self,
+        wave_in: &[Vin],
+        wave_out: &mut [Vout],
+        _active_channels_mask: Option<&[bool]>,
+    ) -> ResampleResult<(usize, usize)> {
+        if wave_in.len() != self.channels {
+            return Err(ResampleError::WrongNumberOfInputChannels {
+                expected: self.channels,
+                actual: wave_in.len(),
+            });
+        }
+        if wave_out.len() != self.channels {
+            return Err(ResampleError::WrongNumberOfOutputChannels {
+                expected: self.channels,
+                actual: wave_out.len(),
+            });
+        }
+        let mut inputs = Vec::with_capacity(self.channels);
+        for (channel, samples) in wave_in.iter().enumerate() {
+            let samples = samples.as_ref();
+            if samples.len() < self.chunk {
+                return Err(ResampleError::InsufficientInputBufferSize {
+                    channel,
+                    expected: self.chunk,
+                    actual: samples.len(),
+                });
+            }
+            inputs.push(samples.as_ptr());
+        }
+        let mut outputs: Vec<&mut [f64]> = wave_out.iter_mut().map(|out| out.as_mut()).collect();
+        let room = outputs.iter().map(|out| out.len()).min().unwrap_or(0);
+        let (mut read, mut written) = (0, 0);
+        // libsoxr stops early when the output is full, it is sized for a whole chunk
+        while read < self.chunk {
+            let input: Vec<*const f64> = inputs
+                .iter()
+                .map(|input| unsafe { input.add(read) })
+                .collect();
+            let output: Vec<*mut f64> = outputs
+                .iter_mut()
+                .map(|output| unsafe { output.as_mut_ptr().add(written) })
+                .collect();
+            let (mut input_done, mut output_done) = (0, 0);
+            let error = unsafe {
+                soxr_process(
+                    self.soxr.0,
+                    input.as_ptr(),
+                    self.chunk - read,
+                    &mut input_done,
+                    output.as_ptr(),
+                    room - written,
+                    &mut output_done,
+                )
+            };
+            if let Some(message) = message(error) {
+                error!("libsoxr: {}", message);
+            }
+            read += input_done;
+            written += output_done;
+            // Also where libsoxr fails, its message logged above
+            if input_done == 0 && output_done == 0 {
+                return Err(ResampleError::InsufficientOutputBufferSize {
+                    channel: 0,
+                    expected: self.output_frames_max(),
+                    actual: room,
+                });
+            }
+        }
+        Ok((read, written))
+    }
+
+    fn input_frames_max(&self) -> usize {
+        self.chunk
+    }
+
+    fn input_frames_next(&self) -> usize {
+        self.chunk
+    }
+
+    fn nbr_channels(&self) -> usize {
+        self.channels
+    }
+
+    /// A chunk and what the filter may have held back from the previous one.
+    fn output_frames_max(&self) -> usize {
+        (self.chunk as f64 * self.ratio).ceil() as usize * 2 + 64
+    }
+
+    fn output_frames_next(&self) -> usize {
+        self.output_frames_max()
+    }
+
+    fn output_delay(&self) -> usize {
+        0
+    }
+
+    fn set_resample_ratio(&mut self, _new_ratio: f64, _ramp: bool) -> ResampleResult<()> {
+        Err(ResampleError::SyncNotAdjustable)
+    }
+
+    fn set_resample_ratio_relative(&mut self, _rel_ratio: f64, _ramp: bool) -> ResampleResult<()> {
+        Err(ResampleError::SyncNotAdjustable)
+    }
+
+    fn reset(&mut self) {
+        if let Some(message) = message(unsafe { soxr_clear(self.soxr.0) }) {
+            error!("libsoxr: {}", message);
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use symphonia::core::audio::{AudioBuffer, AudioBufferRef, Signal};
+use symphonia::core::conv::IntoSample;
+use symphonia::core::sample::Sample;
+
+/// Samples kept for the visualizations, enough for one FFT at any supported rate.
+pub const TAP_LENGTH: usize = 4096;
+
+#[derive(Default)]
+struct Tapped {
+    samples: VecDeque<f32>,
+    sample_rate: u32,
+}
+
+/// Latest decoded samples, downmixed to mono, shared between the streaming task and the UI.
+/// Nothing is copied while disabled.
+#[derive(Default)]
+pub struct SampleTap {
+    enabled: AtomicBool,
+    tapped: Mutex<Tapped>,
+}
+
+fn downmix<S>(buffer: &AudioBuffer<S>, samples: &mut VecDeque<f32>)
+where
+    S: Sample + IntoSample<f32>,
+{
+    let channels = buffer.spec().channels.count();
+    for frame in 0..buffer.frames() {
+        let sum: f32 = (0..channels)
+            .map(|channel| buffer.chan(channel)[frame].into_sample())
+            .sum();
+        samples.push_back(sum / channels as f32);
+    }
+}
+
+impl SampleTap {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            if let Ok(mut tapped) = self.tapped.lock() {
+                tapped.samples.clear();
+            }
+        }
+    }
+
+    pub fn push(&self, decoded: &AudioBufferRef<'_>) {
+        let Ok(mut tapped) = self.tapped.lock() else {
+            return;
+        };
+        tapped.sample_rate = decoded.spec().rate;
+        let samples = &mut tapped.samples;
+        match decoded {
+            AudioBufferRef::U8(buffer) => downmix(buffer, samples),
+            AudioBufferRef::U16(buffer) => downmix(buffer, samples),
+            AudioBufferRef::U24(buffer) => downmix(buffer, samples),
+            AudioBufferRef::U32(buffer) => downmix(buffer, samples),
+            AudioBufferRef::S8(buffer) => downmix(buffer, samples),
+            AudioBufferRef::S16(buffer) => downmix(buffer, samples),
+            AudioBufferRef::S24(buffer) => downmix(buffer, samples),
+            AudioBufferRef::S32(buffer) => downmix(buffer, samples),
+            AudioBufferRef::F32(buffer) => downmix(buffer, samples),
+            AudioBufferRef::F64(buffer) => downmix(buffer, samples),
+        }
+        let excess = samples.len().saturating_sub(TAP_LENGTH);
+        samples.drain(..excess);
+    }
+
+    /// Copies the tapped samples, oldest first, with their sample rate.
+    pub fn latest(&self) -> (Vec<f32>, u32) {
+        match self.tapped.lock() {
+            Ok(tapped) => (tapped.samples.iter().copied().collect(), tapped.sample_rate),
+            Err(_) => (Vec::new(), 0),
+        }
+    }
+}
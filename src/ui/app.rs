@@ -1,35 +1,412 @@
-use super::{screens::Playlist, utils::bottom_right_fixed_size, widgets::DeviceSelector};
-use crate::{audio::Host, player::Player};
+use super::{
+    keyboard::{KeyboardEvent, KeyboardManager},
+    screens::{Library, LibraryAction, Playlist},
+    set_accent,
+    utils::{bottom_right_fixed_size, is_interrupt},
+    widgets::{
+        AlarmSettingsPane, AlbumInfoPopup, ArtistPane, DebugOverlay, DeviceSelector, HistoryPopup,
+        NoticePopup, PathPrompt, PromptAction, SearchAction, SearchPrompt, TasksPopup,
+        TrackInfoPopup,
+    },
+};
+use crate::events::EventStream;
+use crate::mpd::{self, Mpd};
+#[cfg(all(target_os = "linux", feature = "mpris"))]
+use crate::mpris::{Command, Mpris};
+#[cfg(windows)]
+use crate::smtc::MediaControls;
+use crate::{
+    alarm::Alarm,
+    analysis,
+    artwork::Accents,
+    audio::Host,
+    config::{Config, Overrides},
+    library::Database,
+    metadata::{AlbumLookup, ArtistLookup, MetadataProviders},
+    musictrack::MusicTrack,
+    player::Player,
+    session::Session,
+    tasks::{TaskGroup, TaskPool},
+    watcher::FileWatcher,
+};
 use anyhow::Result;
-use crossterm::event::{self, Event, KeyCode};
+use crossterm::event::{self, Event};
 use crossterm::terminal::SetTitle;
 use crossterm::ExecutableCommand;
-use log::error;
+use futures_util::StreamExt;
+use log::warn;
+use log::{error, info};
 use ratatui::{DefaultTerminal, Frame};
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+/// How long the failure of a background task stays shown.
+const NOTICE_DURATION: Duration = Duration::from_secs(6);
 
 pub enum Screens {
     OutputSelector(Rc<RefCell<DeviceSelector>>),
-    Default(Rc<RefCell<Playlist>>),
+    Default,
+    Library(Rc<RefCell<Library>>),
+    Artist(Rc<RefCell<ArtistPane>>),
+    Alarm(Rc<RefCell<AlarmSettingsPane>>),
+    Prompt(Rc<RefCell<PathPrompt>>),
+    Search(Rc<RefCell<SearchPrompt>>),
 }
 
 pub struct App {
     layers: Vec<Screens>,
-    output_selector: Rc<RefCell<DeviceSelector>>,
-    playlist: Rc<RefCell<Playlist>>,
+    host: Host,
+    /// Created the first time it is opened, it keeps the selected device afterwards
+    output_selector: Option<Rc<RefCell<DeviceSelector>>>,
+    playlist: RefCell<Playlist>,
+    database: Database,
+    keys: KeyboardManager,
+    tasks: TaskPool,
+    /// Streaming and server tasks, aborted once playback is stopped
+    background: TaskGroup,
+    /// Last background task failure and when it happened
+    notice: Option<(String, Instant)>,
+    show_debug: bool,
+    show_tasks: bool,
+    show_history: bool,
+    show_track_info: bool,
+    metadata: Arc<MetadataProviders>,
+    /// Album of the album info popup, while shown
+    album_lookup: Option<AlbumLookup>,
+    /// Desktop media controls, started with the app
+    #[cfg(all(target_os = "linux", feature = "mpris"))]
+    mpris: Option<Mpris>,
+    /// Media keys and the volume flyout, started with the app
+    #[cfg(windows)]
+    media_controls: Option<MediaControls>,
+    /// MPD clients, when a port is configured
+    mpd: Option<Mpd>,
+    /// Clients of the event stream, when a port is configured
+    events: Option<EventStream>,
+    /// Highlight colors drawn from album art, when enabled in the config
+    accents: Accents,
+    /// Config file applied again on changes, with the command line on top of it
+    config_watch: Option<(FileWatcher, Overrides)>,
 }
 
 impl App {
-    pub fn new(host: Host, player: Player, path: PathBuf) -> Result<Self> {
+    /// Only what the first frame needs is set up here, the music directory is read in the
+    /// background and the other screens are built when opened. The config file is
+    /// reloaded on changes unless `reload` is `None`.
+    pub fn new(
+        host: Host,
+        player: Player,
+        path: PathBuf,
+        library: &Database,
+        background: TaskGroup,
+        reload: Option<Overrides>,
+    ) -> Result<Self> {
+        let keys = KeyboardManager::new(&player.config().keys);
+        let tasks = TaskPool::new(player.config().analysis.workers);
+        let metadata = MetadataProviders::new(&player.config().metadata, library.clone());
+        let playlist = Playlist::new(path, player, library)?;
+        let config_watch = reload.and_then(|overrides| {
+            let path = Config::path()?;
+            FileWatcher::new(&path)
+                .inspect_err(|err| warn!("Cannot watch {}: {}", path.display(), err))
+                .ok()
+                .map(|watcher| (watcher, overrides))
+        });
         Ok(Self {
             layers: vec![],
-            output_selector: Rc::new(RefCell::new(DeviceSelector::new(host)?)),
-            playlist: Rc::new(RefCell::new(Playlist::new(path, player)?)),
+            host,
+            output_selector: None,
+            playlist: RefCell::new(playlist),
+            database: library.clone(),
+            keys,
+            tasks,
+            background,
+            notice: None,
+            show_debug: false,
+            show_tasks: false,
+            show_history: false,
+            show_track_info: false,
+            metadata: Arc::new(metadata),
+            album_lookup: None,
+            #[cfg(all(target_os = "linux", feature = "mpris"))]
+            mpris: None,
+            #[cfg(windows)]
+            media_controls: None,
+            mpd: None,
+            events: None,
+            accents: Accents::default(),
+            config_watch,
         })
     }
 
+    /// Runs the requests of the bus as the matching keys would, then reports the playlist.
+    #[cfg(all(target_os = "linux", feature = "mpris"))]
+    async fn handle_mpris(&mut self) -> Result<()> {
+        let Some(mpris) = &mut self.mpris else {
+            return Ok(());
+        };
+        while let Some(command) = mpris.command() {
+            let playlist = self.playlist.get_mut();
+            let playing = playlist
+                .playing()
+                .map(|(index, _, info)| (index, info.elapsed_seconds(), info.duration_seconds()));
+            let paused = playlist.player().is_paused();
+            let (event, position) = match command {
+                Command::Play | Command::PlayPause if playing.is_none() => {
+                    (Some(KeyboardEvent::Play), None)
+                }
+                Command::Play => (paused.then_some(KeyboardEvent::Pause), None),
+                Command::Pause => ((!paused).then_some(KeyboardEvent::Pause), None),
+                Command::PlayPause => (Some(KeyboardEvent::Pause), None),
+                Command::Stop => (Some(KeyboardEvent::Stop), None),
+                Command::Next => (Some(KeyboardEvent::Next), None),
+                Command::Previous => (Some(KeyboardEvent::Previous), None),
+                Command::Seek(offset) => (None, playing.map(|(_, elapsed, _)| elapsed + offset)),
+                // Positions past the end are ignored, as well as requests for a previous track
+                Command::SetPosition(id, position) => match playing {
+                    Some((index, _, duration))
+                        if Mpris::track_id(index) == id && position <= duration =>
+                    {
+                        (None, Some(position))
+                    }
+                    _ => (None, None),
+                },
+            };
+            match (event, position, playing) {
+                // Seeking past the end of the track goes to the next one
+                (_, Some(position), Some((_, _, duration))) if position >= duration => {
+                    playlist.event_hanlder(KeyboardEvent::Next).await?;
+                }
+                (_, Some(position), _) => {
+                    playlist.seek(position).await?;
+                    mpris.seeked(position).await?;
+                }
+                (Some(event), None, _) => playlist.event_hanlder(event).await?,
+                (None, None, _) => (),
+            }
+        }
+        let playlist = self.playlist.get_mut();
+        mpris.update(playlist.playback(), playlist.playing()).await
+    }
+
+    /// Runs the requests of the MPD clients as the matching keys would, then reports the
+    /// playlist.
+    async fn handle_mpd(&mut self) -> Result<()> {
+        let Some(server) = &mut self.mpd else {
+            return Ok(());
+        };
+        let playlist = self.playlist.get_mut();
+        while let Some(command) = server.command() {
+            let playing = playlist
+                .playing()
+                .map(|(index, _, info)| (index, info.elapsed_seconds(), info.duration_seconds()));
+            let paused = playlist.player().is_paused();
+            let (event, position) = match command {
+                mpd::Command::Play(Some(index)) => {
+                    playlist.play_tracks(vec![index]).await?;
+                    (None, None)
+                }
+                mpd::Command::Play(None) if playing.is_none() => (Some(KeyboardEvent::Play), None),
+                mpd::Command::Play(None) => (paused.then_some(KeyboardEvent::Pause), None),
+                mpd::Command::Pause(Some(pause)) => {
+                    ((pause != paused).then_some(KeyboardEvent::Pause), None)
+                }
+                mpd::Command::Pause(None) => (Some(KeyboardEvent::Pause), None),
+                mpd::Command::Stop => (Some(KeyboardEvent::Stop), None),
+                mpd::Command::Next => (Some(KeyboardEvent::Next), None),
+                mpd::Command::Previous => (Some(KeyboardEvent::Previous), None),
+                mpd::Command::Seek(Some(index), position)
+                    if playing.is_none_or(|(playing, _, _)| playing != index) =>
+                {
+                    playlist.play_tracks(vec![index]).await?;
+                    (None, Some(position))
+                }
+                mpd::Command::Seek(_, position) => (None, Some(position)),
+                mpd::Command::SeekBy(offset) => {
+                    (None, playing.map(|(_, elapsed, _)| elapsed + offset))
+                }
+                mpd::Command::Add(path, placement) => {
+                    if let Err(err) = playlist.add_files(Path::new(&path), placement) {
+                        warn!("Cannot add {}: {}", path, err);
+                    }
+                    (None, None)
+                }
+            };
+            let duration = playlist
+                .playing()
+                .map(|(_, _, info)| info.duration_seconds());
+            match (event, position, duration) {
+                // Seeking past the end of the track goes to the next one
+                (_, Some(position), Some(duration)) if position >= duration => {
+                    playlist.event_hanlder(KeyboardEvent::Next).await?;
+                }
+                (_, Some(position), _) => {
+                    playlist.seek(position.max(0.0)).await?;
+                    server.seeked();
+                }
+                (Some(event), None, _) => playlist.event_hanlder(event).await?,
+                (None, None, _) => (),
+            }
+        }
+        server.update(
+            playlist.songs(),
+            playlist.playback(),
+            playlist.playing().map(|(index, _, info)| (index, info)),
+            playlist.modes(),
+        );
+        Ok(())
+    }
+
+    /// Applies the config file once it changed, telling what waits for a restart.
+    fn reload_config(&mut self) {
+        let Some((watcher, overrides)) = &self.config_watch else {
+            return;
+        };
+        if !watcher.changed() {
+            return;
+        }
+        let mut config = match Config::load() {
+            Ok(config) => config,
+            // Kept as it was, e.g. while the file is half written
+            Err(err) => {
+                let notice = format!("Cannot reload the config: {}", err);
+                self.notice = Some((notice, Instant::now()));
+                return;
+            }
+        };
+        overrides.apply(&mut config);
+        let mut playlist = self.playlist.borrow_mut();
+        let changes = playlist.player().config().changes(&config);
+        if changes.is_empty() {
+            return;
+        }
+        info!("{}", changes);
+        self.keys = KeyboardManager::new(&config.keys);
+        playlist.set_config(config);
+        self.notice = Some((changes.to_string(), Instant::now()));
+    }
+
+    /// Sends the events of the playlist and the player to the event stream clients.
+    fn handle_events(&mut self) {
+        let Some(events) = &mut self.events else {
+            return;
+        };
+        let playlist = self.playlist.borrow();
+        events.update(&playlist.snapshot(), playlist.player().device_name());
+    }
+
+    /// Highlights in the color of the album playing, once drawn from its art.
+    fn follow_accent(&self) {
+        let playlist = self.playlist.borrow();
+        let accent = match playlist.playing() {
+            Some((_, song, _)) if playlist.player().config().ui.album_accent => {
+                self.accents.get(song, &self.background)
+            }
+            _ => None,
+        };
+        set_accent(accent);
+    }
+
+    /// Runs the media keys as the matching keys, then reports the playlist.
+    #[cfg(windows)]
+    async fn handle_media_controls(&mut self) -> Result<()> {
+        let Some(controls) = &mut self.media_controls else {
+            return Ok(());
+        };
+        let playlist = self.playlist.get_mut();
+        while let Some(event) = controls.event() {
+            playlist.event_hanlder(event).await?;
+        }
+        let paused = playlist.player().is_paused();
+        let playing = playlist.playing().map(|(_, song, info)| (song, info));
+        controls.update(playing, paused)
+    }
+
+    /// Queues the analysis of tracks newly added to the playlist.
+    fn analyze(&self, songs: Vec<Arc<MusicTrack>>) {
+        for song in songs {
+            // Tracks of CUE sheets share their file, analyzed whole, radios never end
+            if song.dsd_rate.is_some()
+                || song.is_stream()
+                || song.segment.is_some()
+                || self.database.analysis(&song.path).is_some()
+            {
+                continue;
+            }
+            let database = self.database.clone();
+            self.tasks
+                .submit(format!("Analyze {}", song.title), move || {
+                    database.save_analysis(&song.path, analysis::analyze(&song)?)
+                });
+        }
+    }
+
+    /// Totals of the session so far.
+    pub fn session(&self) -> Session {
+        self.playlist.borrow().player().session()
+    }
+
     fn render(&mut self, frame: &mut Frame) -> Result<()> {
         self.playlist.borrow_mut().render(frame, frame.area())?;
+        if self.show_debug {
+            let playlist = self.playlist.borrow();
+            let player = playlist.player();
+            let (decode_cpu, render_cpu) = player.cpu_usage();
+            frame.render_widget(
+                DebugOverlay::new(decode_cpu, render_cpu, player.is_pollmode()),
+                bottom_right_fixed_size(24, 5, frame.area()),
+            );
+        }
+        if self.show_history {
+            let playlist = self.playlist.borrow();
+            frame.render_widget(
+                HistoryPopup::new(playlist.history()),
+                bottom_right_fixed_size(60, 14, frame.area()),
+            );
+        }
+        if self.show_track_info {
+            let playlist = self.playlist.borrow();
+            if let Some(song) = playlist.selected_song() {
+                frame.render_widget(
+                    TrackInfoPopup::new(song, playlist.device_warning(song)),
+                    bottom_right_fixed_size(60, 8, frame.area()),
+                );
+            }
+        }
+        if let Some(lookup) = &self.album_lookup {
+            let strict_gapless = self
+                .playlist
+                .borrow()
+                .player()
+                .is_strict_gapless(&lookup.artist, &lookup.album);
+            frame.render_widget(
+                AlbumInfoPopup::new(lookup, self.metadata.is_enabled(), strict_gapless),
+                bottom_right_fixed_size(60, 14, frame.area()),
+            );
+        }
+        if let Some((notice, _)) = self
+            .notice
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < NOTICE_DURATION)
+        {
+            frame.render_widget(
+                NoticePopup::new(notice),
+                bottom_right_fixed_size(60, 3, frame.area()),
+            );
+        }
+        if self.show_tasks {
+            let snapshot = self.tasks.snapshot(5);
+            frame.render_widget(
+                TasksPopup::new(&snapshot),
+                bottom_right_fixed_size(60, 14, frame.area()),
+            );
+        }
         let layer = if self.layers.is_empty() {
             return Ok(());
         } else {
@@ -37,70 +414,357 @@ impl App {
         };
         match layer {
             Screens::OutputSelector(selector) => {
-                let area = bottom_right_fixed_size(40, 6, frame.area());
+                let area = bottom_right_fixed_size(40, 10, frame.area());
                 (*selector).borrow_mut().render(frame, area)?;
             }
+            Screens::Library(library) => {
+                library.borrow_mut().render(frame, frame.area())?;
+            }
+            Screens::Artist(pane) => {
+                let area = bottom_right_fixed_size(60, 20, frame.area());
+                pane.borrow_mut().render(frame, area)?;
+            }
+            Screens::Alarm(pane) => {
+                let area = bottom_right_fixed_size(40, 7, frame.area());
+                pane.borrow_mut().render(frame, area)?;
+            }
+            Screens::Prompt(prompt) => {
+                let width = frame.area().width.min(80);
+                let area = bottom_right_fixed_size(width, 3, frame.area());
+                prompt.borrow_mut().render(frame, area)?;
+            }
+            Screens::Search(prompt) => {
+                let width = frame.area().width.min(80);
+                let area = bottom_right_fixed_size(width, 3, frame.area());
+                let status = self.playlist.borrow().search_status();
+                prompt.borrow_mut().render(frame, area, status)?;
+            }
             _ => (),
         }
         Ok(())
     }
 
-    pub async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+    /// Runs until quit, interrupted or failed. Playback is stopped, then the background tasks,
+    /// before returning either way, the terminal is left to restore.
+    pub async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        shutdown: &AtomicBool,
+    ) -> Result<()> {
+        let result = self.run_screens(terminal, shutdown).await;
+        let stopped = self.playlist.get_mut().stop().await;
+        self.background.shutdown().await;
+        result.and(stopped)
+    }
+
+    async fn run_screens(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        shutdown: &AtomicBool,
+    ) -> Result<()> {
         terminal
             .backend_mut()
             .execute(SetTitle("rhap - Rust Handcrafted Audio Player"))?;
-        let default = Screens::Default(self.playlist.clone());
+        let default = Screens::Default;
+        #[cfg(all(target_os = "linux", feature = "mpris"))]
+        {
+            self.mpris = Mpris::start()
+                .await
+                .inspect_err(|err| warn!("Cannot register on the session bus: {}", err))
+                .ok();
+        }
+        let config = self.playlist.borrow().player().config().mpd.clone();
+        if config.enabled {
+            let (address, port) = (config.address, config.port);
+            self.mpd = Mpd::start(&address, port, self.database.clone(), &self.background)
+                .await
+                .inspect_err(|err| warn!("Cannot start the MPD server on port {}: {}", port, err))
+                .ok();
+        }
+        let config = self.playlist.borrow().player().config().alarm.clone();
+        let alarm = Alarm::start(&config, self.database.clone(), &self.background);
+        self.playlist.borrow_mut().set_alarm(alarm);
+        let config = self.playlist.borrow().player().config().events.clone();
+        if let Some(port) = config.port {
+            self.events =
+                EventStream::start(&config.address, port, &self.database, &self.background)
+                    .await
+                    .inspect_err(|err| {
+                        warn!("Cannot start the event stream on port {}: {}", port, err)
+                    })
+                    .ok();
+        }
+        #[cfg(windows)]
+        {
+            self.media_controls = MediaControls::start()
+                .inspect_err(|err| warn!("Cannot register the media controls: {}", err))
+                .ok();
+        }
+        let ended = self.playlist.borrow().player().ended();
+        let mut input = event::EventStream::new();
+        let mut last_frame: Option<Instant> = None;
         loop {
-            terminal.draw(|frame| match self.render(frame) {
-                Ok(ok) => ok,
-                Err(err) => {
-                    error!("error while drawing {}", err.to_string());
-                    ()
-                }
-            })?;
+            if shutdown.load(Ordering::Relaxed) || self.playlist.borrow().is_over() {
+                return Ok(());
+            }
+            self.reload_config();
+            let ui = self.playlist.borrow().player().config().ui;
+            if let Some((name, err)) = self.background.failures().pop() {
+                self.notice = Some((format!("{} failed: {}", name, err), Instant::now()));
+            }
+            // Shown over the failure of the stream which died with the device
+            if let Some(notice) = self.playlist.borrow_mut().take_device_notice() {
+                self.notice = Some((notice, Instant::now()));
+            }
 
-            // handle crossterm events
-            if event::poll(std::time::Duration::from_millis(100))? {
+            // Wakeups coming faster than the frame rate cap are drawn together
+            let capped = last_frame.is_some_and(|last| last.elapsed() < ui.frame());
+            if !capped {
+                terminal.draw(|frame| {
+                    if let Err(err) = self.render(frame) {
+                        error!("error while drawing {}", err);
+                    }
+                })?;
+                last_frame = Some(Instant::now());
+            }
+            let wait = match last_frame {
+                Some(last) if capped => ui.frame().saturating_sub(last.elapsed()),
+                _ if self.playlist.borrow().is_animated() => ui.frame(),
+                _ => ui.tick(),
+            };
+            let event = tokio::select! {
+                Some(event) = input.next() => Some(event?),
+                _ = ended.notified() => None,
+                _ = tokio::time::sleep(wait) => None,
+            };
+            if let Some(Event::Key(key)) = event {
                 let current_screen = self.layers.last().unwrap_or(&default);
-                if let Event::Key(key) = event::read()? {
-                    match current_screen {
-                        Screens::OutputSelector(selector) => {
-                            selector.borrow_mut().event_handler(key)?;
-                            if key.kind == event::KeyEventKind::Press {
-                                match key.code {
-                                    KeyCode::Char('q') => {
-                                        self.layers.pop();
-                                    }
-                                    _ => {}
+                if key.kind == event::KeyEventKind::Press && is_interrupt(&key) {
+                    return Ok(());
+                }
+                let keyboard_event = if key.kind == event::KeyEventKind::Press {
+                    self.keys.event(&key)
+                } else {
+                    None
+                };
+                match current_screen {
+                    Screens::OutputSelector(selector) => {
+                        let chosen = selector.borrow_mut().event_handler(key)?;
+                        let shared = selector.borrow().is_shared();
+                        self.playlist.borrow_mut().set_shared_mode(shared);
+                        if chosen {
+                            let device_id = selector.borrow().selected_id();
+                            self.playlist.get_mut().set_device(device_id).await?;
+                        }
+                        if keyboard_event == Some(KeyboardEvent::Quit) {
+                            self.layers.pop();
+                        }
+                    }
+                    Screens::Library(library) => {
+                        let action = library.borrow_mut().event_handler(key);
+                        match action {
+                            Some(LibraryAction::Enqueue(tracks)) => {
+                                self.playlist.borrow_mut().enqueue(tracks);
+                            }
+                            Some(LibraryAction::Play(tracks)) => {
+                                self.playlist.get_mut().play_tracks(tracks).await?;
+                            }
+                            Some(LibraryAction::QueueAlbum(tracks)) => {
+                                self.playlist.borrow_mut().queue_album(tracks);
+                            }
+                            None => (),
+                        }
+                        if keyboard_event == Some(KeyboardEvent::Quit) {
+                            self.layers.pop();
+                        }
+                    }
+                    Screens::Artist(pane) => {
+                        let artist = pane.borrow_mut().event_handler(key);
+                        if let Some(artist) = artist {
+                            let mut library =
+                                Library::new(self.playlist.borrow().songs(), &self.database)?;
+                            library.show_artist(&artist);
+                            self.layers.pop();
+                            self.layers
+                                .push(Screens::Library(Rc::new(RefCell::new(library))));
+                        } else if keyboard_event == Some(KeyboardEvent::Quit) {
+                            self.layers.pop();
+                        }
+                    }
+                    Screens::Alarm(pane) => {
+                        let changed = pane.borrow_mut().event_handler(key);
+                        if let Some(settings) = changed {
+                            if let Some(alarm) = self.playlist.borrow().alarm() {
+                                alarm.update(settings);
+                            }
+                        } else if keyboard_event == Some(KeyboardEvent::Quit) {
+                            self.layers.pop();
+                        }
+                    }
+                    // Keys are typed in, the bindings do not apply
+                    Screens::Prompt(prompt) => {
+                        let action = prompt.borrow_mut().event_handler(key);
+                        match action {
+                            Some(PromptAction::Insert(path)) => {
+                                self.layers.pop();
+                                let inserted =
+                                    self.playlist.borrow_mut().insert_files(Path::new(&path));
+                                if let Err(err) = inserted {
+                                    let notice = format!("Cannot insert {}: {}", path, err);
+                                    self.notice = Some((notice, Instant::now()));
                                 }
                             }
+                            Some(PromptAction::Cancel) => {
+                                self.layers.pop();
+                            }
+                            None => (),
+                        }
+                    }
+                    Screens::Search(prompt) => {
+                        let action = prompt.borrow_mut().event_handler(key);
+                        let mut playlist = self.playlist.borrow_mut();
+                        match action {
+                            Some(SearchAction::Query(query)) => playlist.search(&query),
+                            Some(SearchAction::NextMatch) => playlist.next_match(true),
+                            Some(SearchAction::PreviousMatch) => playlist.next_match(false),
+                            Some(SearchAction::Done) => {
+                                playlist.end_search(true);
+                                self.layers.pop();
+                            }
+                            Some(SearchAction::Cancel) => {
+                                playlist.end_search(false);
+                                self.layers.pop();
+                            }
+                            None => (),
                         }
-                        Screens::Default(playlist) => {
-                            playlist.borrow_mut().event_hanlder(key).await?;
-                            if key.kind == event::KeyEventKind::Press {
-                                match key.code {
-                                    KeyCode::Char('q') => {
-                                        playlist.borrow_mut().stop().await?;
-                                        return Ok(());
+                    }
+                    Screens::Default => {
+                        if let Some(keyboard_event) = keyboard_event {
+                            self.playlist
+                                .get_mut()
+                                .event_hanlder(keyboard_event)
+                                .await?;
+                            match keyboard_event {
+                                KeyboardEvent::Quit => return Ok(()),
+                                KeyboardEvent::Library => {
+                                    // The playlist follows the music directory
+                                    let library = Library::new(
+                                        self.playlist.borrow().songs(),
+                                        &self.database,
+                                    )?;
+                                    self.layers
+                                        .push(Screens::Library(Rc::new(RefCell::new(library))));
+                                }
+                                KeyboardEvent::Debug => {
+                                    self.show_debug = !self.show_debug;
+                                }
+                                KeyboardEvent::Tasks => {
+                                    self.show_tasks = !self.show_tasks;
+                                }
+                                KeyboardEvent::History => {
+                                    self.show_history = !self.show_history;
+                                }
+                                KeyboardEvent::TrackInfo => {
+                                    self.show_track_info = !self.show_track_info;
+                                }
+                                KeyboardEvent::AlbumInfo => {
+                                    self.album_lookup = match self.album_lookup {
+                                        Some(_) => None,
+                                        None => {
+                                            self.playlist.borrow().selected_song().map(|song| {
+                                                AlbumLookup::start(
+                                                    &self.metadata,
+                                                    &self.background,
+                                                    &song.artist,
+                                                    &song.album,
+                                                )
+                                            })
+                                        }
+                                    };
+                                }
+                                KeyboardEvent::ArtistInfo => {
+                                    let playlist = self.playlist.borrow();
+                                    if let Some(song) = playlist.selected_song() {
+                                        let lookup = ArtistLookup::start(
+                                            &self.metadata,
+                                            &self.background,
+                                            &song.artist,
+                                        );
+                                        let pane = ArtistPane::new(
+                                            lookup,
+                                            self.metadata.is_enabled(),
+                                            playlist.songs(),
+                                        );
+                                        self.layers
+                                            .push(Screens::Artist(Rc::new(RefCell::new(pane))));
                                     }
-                                    KeyCode::Char('o') => {
-                                        self.output_selector.borrow_mut().refresh_device_list()?;
-                                        self.layers.push(Screens::OutputSelector(
-                                            self.output_selector.clone(),
-                                        ));
+                                }
+                                KeyboardEvent::Alarm => {
+                                    let settings =
+                                        self.playlist.borrow().alarm().map(Alarm::settings);
+                                    if let Some(settings) = settings {
+                                        let playlists = self
+                                            .database
+                                            .playlists()?
+                                            .into_iter()
+                                            .map(|(name, _)| name)
+                                            .collect();
+                                        let pane = AlarmSettingsPane::new(settings, playlists);
+                                        self.layers
+                                            .push(Screens::Alarm(Rc::new(RefCell::new(pane))));
                                     }
-                                    _ => {}
                                 }
+                                KeyboardEvent::OutputSelector => {
+                                    let selector = match &self.output_selector {
+                                        Some(selector) => selector.clone(),
+                                        None => {
+                                            let playlist = self.playlist.borrow();
+                                            let shared = playlist.player().is_shared_mode();
+                                            let playing = playlist.player().device_name();
+                                            Rc::new(RefCell::new(DeviceSelector::new(
+                                                self.host, playing, shared,
+                                            )?))
+                                        }
+                                    };
+                                    self.output_selector = Some(selector.clone());
+                                    selector.borrow_mut().refresh_device_list()?;
+                                    self.layers.push(Screens::OutputSelector(selector));
+                                }
+                                KeyboardEvent::InsertFiles => {
+                                    let prompt = Rc::new(RefCell::new(PathPrompt::new()));
+                                    self.layers.push(Screens::Prompt(prompt));
+                                }
+                                KeyboardEvent::Search => {
+                                    self.playlist.borrow_mut().search("");
+                                    let prompt = Rc::new(RefCell::new(SearchPrompt::new()));
+                                    self.layers.push(Screens::Search(prompt));
+                                }
+                                _ => {}
                             }
                         }
                     }
                 }
             }
 
+            #[cfg(all(target_os = "linux", feature = "mpris"))]
+            self.handle_mpris().await?;
+            #[cfg(windows)]
+            self.handle_media_controls().await?;
+            self.handle_mpd().await?;
+            self.handle_events();
+            self.follow_accent();
+            let loaded = self.playlist.borrow_mut().take_loaded();
+            self.analyze(loaded);
             let current_screen = self.layers.last().unwrap_or(&default);
             match current_screen {
-                Screens::Default(playlist) => {
-                    playlist.borrow_mut().run().await?;
+                // Browsing the library keeps the playlist going
+                Screens::Default
+                | Screens::Library(_)
+                | Screens::Artist(_)
+                | Screens::Alarm(_)
+                | Screens::Prompt(_)
+                | Screens::Search(_) => {
+                    self.playlist.get_mut().run().await?;
                 }
                 _ => {}
             }
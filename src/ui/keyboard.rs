@@ -0,0 +1,344 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MediaKeyCode};
+use log::warn;
+use std::collections::HashMap;
+
+use crate::config::KeyChords;
+
+/// Actions of the playlist screen and the global keys, bound to key chords.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyboardEvent {
+    Quit,
+    Library,
+    OutputSelector,
+    Debug,
+    Tasks,
+    History,
+    TrackInfo,
+    AlbumInfo,
+    ArtistInfo,
+    Alarm,
+    StrictGapless,
+    Preview,
+    SelectPrevious,
+    SelectNext,
+    SelectPlaying,
+    Play,
+    Stop,
+    Next,
+    Previous,
+    NextAlbum,
+    PreviousAlbum,
+    Pause,
+    Karaoke,
+    Spectrum,
+    Meters,
+    Lyrics,
+    VolumeUp,
+    VolumeDown,
+    Loudness,
+    Enqueue,
+    PlayNext,
+    Prioritize,
+    Repeat,
+    Consume,
+    ExportQueue,
+    ShuffleQueue,
+    DeleteTrack,
+    UndoDelete,
+    MoveUp,
+    MoveDown,
+    InsertFiles,
+    Search,
+}
+
+/// Names used in the `[keys]` section of the config, with their default chords.
+const BINDINGS: &[(&str, KeyboardEvent, &[&str])] = &[
+    ("quit", KeyboardEvent::Quit, &["q"]),
+    ("library", KeyboardEvent::Library, &["b"]),
+    ("output_selector", KeyboardEvent::OutputSelector, &["o"]),
+    ("debug", KeyboardEvent::Debug, &["d"]),
+    ("tasks", KeyboardEvent::Tasks, &["t"]),
+    ("history", KeyboardEvent::History, &["h"]),
+    ("track_info", KeyboardEvent::TrackInfo, &["i"]),
+    ("album_info", KeyboardEvent::AlbumInfo, &["I"]),
+    ("artist_info", KeyboardEvent::ArtistInfo, &["g"]),
+    ("alarm", KeyboardEvent::Alarm, &["w"]),
+    ("strict_gapless", KeyboardEvent::StrictGapless, &["G"]),
+    ("preview", KeyboardEvent::Preview, &["P"]),
+    (
+        "select_previous",
+        KeyboardEvent::SelectPrevious,
+        &["up", "k"],
+    ),
+    ("select_next", KeyboardEvent::SelectNext, &["down", "j"]),
+    ("select_playing", KeyboardEvent::SelectPlaying, &["."]),
+    ("play", KeyboardEvent::Play, &["enter"]),
+    ("stop", KeyboardEvent::Stop, &["s"]),
+    (
+        "next",
+        KeyboardEvent::Next,
+        &[
+            "N",
+            "media_next",
+            "media_previous",
+            "media_stop",
+            "media_pause",
+        ],
+    ),
+    ("previous", KeyboardEvent::Previous, &["p"]),
+    ("next_album", KeyboardEvent::NextAlbum, &["alt+l"]),
+    ("previous_album", KeyboardEvent::PreviousAlbum, &["alt+h"]),
+    ("pause", KeyboardEvent::Pause, &["space"]),
+    ("karaoke", KeyboardEvent::Karaoke, &["v"]),
+    ("spectrum", KeyboardEvent::Spectrum, &["f"]),
+    ("meters", KeyboardEvent::Meters, &["m"]),
+    ("lyrics", KeyboardEvent::Lyrics, &["y"]),
+    ("volume_up", KeyboardEvent::VolumeUp, &["+", "="]),
+    ("volume_down", KeyboardEvent::VolumeDown, &["-"]),
+    ("loudness", KeyboardEvent::Loudness, &["l"]),
+    ("enqueue", KeyboardEvent::Enqueue, &["a"]),
+    ("play_next", KeyboardEvent::PlayNext, &["A"]),
+    ("prioritize", KeyboardEvent::Prioritize, &["n"]),
+    ("repeat", KeyboardEvent::Repeat, &["r"]),
+    ("consume", KeyboardEvent::Consume, &["c"]),
+    ("export_queue", KeyboardEvent::ExportQueue, &["e"]),
+    ("shuffle_queue", KeyboardEvent::ShuffleQueue, &["S"]),
+    ("delete_track", KeyboardEvent::DeleteTrack, &["x", "delete"]),
+    ("undo_delete", KeyboardEvent::UndoDelete, &["u"]),
+    ("move_up", KeyboardEvent::MoveUp, &["K"]),
+    ("move_down", KeyboardEvent::MoveDown, &["J"]),
+    ("insert_files", KeyboardEvent::InsertFiles, &["O"]),
+    ("search", KeyboardEvent::Search, &["/"]),
+];
+
+type Chord = (KeyCode, KeyModifiers);
+
+/// Shift is part of the character itself, `A` is the same chord with or without it.
+fn normalize(code: KeyCode, modifiers: KeyModifiers) -> Chord {
+    match code {
+        KeyCode::Char(_) => (code, modifiers - KeyModifiers::SHIFT),
+        _ => (code, modifiers),
+    }
+}
+
+/// Parses chords such as `ctrl+right`, `N`, `space` or `f5`.
+fn parse_chord(chord: &str) -> Option<Chord> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut parts: Vec<&str> = chord.split('+').collect();
+    // `+` alone or `ctrl++` end with an empty part for the plus key itself
+    let key = match parts.as_slice() {
+        [.., "", ""] => {
+            parts.truncate(parts.len() - 2);
+            "+"
+        }
+        _ => parts.pop()?,
+    };
+    for modifier in parts {
+        modifiers |= match modifier.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+    }
+    let mut chars = key.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) if modifiers.contains(KeyModifiers::SHIFT) => {
+            KeyCode::Char(c.to_ascii_uppercase())
+        }
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match key.to_lowercase().as_str() {
+            "enter" => KeyCode::Enter,
+            "space" => KeyCode::Char(' '),
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "esc" => KeyCode::Esc,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "media_play_pause" => KeyCode::Media(MediaKeyCode::PlayPause),
+            "media_pause" => KeyCode::Media(MediaKeyCode::Pause),
+            "media_stop" => KeyCode::Media(MediaKeyCode::Stop),
+            "media_next" => KeyCode::Media(MediaKeyCode::TrackNext),
+            "media_previous" => KeyCode::Media(MediaKeyCode::TrackPrevious),
+            function => KeyCode::F(function.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+    Some(normalize(code, modifiers))
+}
+
+/// Resolves key presses to events, the `[keys]` section of the config replaces the default
+/// chords of the events it names:
+///
+/// ```toml
+/// [keys]
+/// next = ["N", "ctrl+right"]
+/// pause = "p"
+/// ```
+pub struct KeyboardManager {
+    bindings: HashMap<Chord, KeyboardEvent>,
+}
+
+impl KeyboardManager {
+    /// Invalid entries are reported and leave the default chords of their event in place.
+    pub fn new(keys: &HashMap<String, KeyChords>) -> Self {
+        let mut custom = HashMap::new();
+        for (name, chords) in keys {
+            let Some((_, event, _)) = BINDINGS.iter().find(|(n, _, _)| n == name) else {
+                warn!("Unknown key binding {}", name);
+                continue;
+            };
+            let parsed: Option<Vec<Chord>> = chords.iter().map(parse_chord).collect();
+            match parsed {
+                Some(parsed) => {
+                    custom.insert(*event, parsed);
+                }
+                None => warn!("Invalid key chord for {}: {:?}", name, chords),
+            }
+        }
+        let mut bindings = HashMap::new();
+        for (_, event, chords) in BINDINGS {
+            if !custom.contains_key(event) {
+                for chord in chords.iter().filter_map(|chord| parse_chord(chord)) {
+                    bindings.insert(chord, *event);
+                }
+            }
+        }
+        // Custom chords win over the defaults they collide with
+        for (event, chords) in custom {
+            for chord in chords {
+                if let Some(previous) = bindings.insert(chord, event) {
+                    if custom_conflict(keys, previous) {
+                        warn!("{:?} and {:?} share the same key", previous, event);
+                    }
+                }
+            }
+        }
+        Self { bindings }
+    }
+
+    pub fn event(&self, key: &KeyEvent) -> Option<KeyboardEvent> {
+        self.bindings
+            .get(&normalize(key.code, key.modifiers))
+            .copied()
+    }
+}
+
+/// Whether the event displaced by a custom chord was itself customized.
+fn custom_conflict(keys: &HashMap<String, KeyChords>, event: KeyboardEvent) -> bool {
+    BINDINGS
+        .iter()
+        .any(|(name, bound, _)| *bound == event && keys.contains_key(*name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bindings from a `[keys]` section.
+    fn manager(keys: &str) -> KeyboardManager {
+        KeyboardManager::new(&toml::from_str(keys).unwrap())
+    }
+
+    fn press(
+        manager: &KeyboardManager,
+        code: KeyCode,
+        modifiers: KeyModifiers,
+    ) -> Option<KeyboardEvent> {
+        manager.event(&KeyEvent::new(code, modifiers))
+    }
+
+    fn key(manager: &KeyboardManager, c: char) -> Option<KeyboardEvent> {
+        press(manager, KeyCode::Char(c), KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn chords() {
+        for (chord, parsed) in [
+            ("q", Some((KeyCode::Char('q'), KeyModifiers::NONE))),
+            ("ctrl+right", Some((KeyCode::Right, KeyModifiers::CONTROL))),
+            ("Alt+Enter", Some((KeyCode::Enter, KeyModifiers::ALT))),
+            ("shift+a", Some((KeyCode::Char('A'), KeyModifiers::NONE))),
+            ("+", Some((KeyCode::Char('+'), KeyModifiers::NONE))),
+            ("ctrl++", Some((KeyCode::Char('+'), KeyModifiers::CONTROL))),
+            ("space", Some((KeyCode::Char(' '), KeyModifiers::NONE))),
+            ("f5", Some((KeyCode::F(5), KeyModifiers::NONE))),
+            (
+                "media_next",
+                Some((KeyCode::Media(MediaKeyCode::TrackNext), KeyModifiers::NONE)),
+            ),
+            ("hyper+x", None),
+            ("fx", None),
+            ("", None),
+        ] {
+            assert_eq!(parse_chord(chord), parsed, "{:?}", chord);
+        }
+    }
+
+    #[test]
+    fn defaults_apply_without_a_keys_section() {
+        let manager = manager("");
+        assert_eq!(key(&manager, 'q'), Some(KeyboardEvent::Quit));
+        assert_eq!(key(&manager, ' '), Some(KeyboardEvent::Pause));
+        // Shift comes with the character or not depending on the terminal
+        assert_eq!(
+            press(&manager, KeyCode::Char('A'), KeyModifiers::SHIFT),
+            Some(KeyboardEvent::PlayNext)
+        );
+        assert_eq!(key(&manager, 'A'), Some(KeyboardEvent::PlayNext));
+        assert_eq!(key(&manager, 'z'), None);
+    }
+
+    #[test]
+    fn overrides_replace_the_default_chords() {
+        let manager = manager(
+            r#"
+            pause = "p"
+            next = ["N", "ctrl+right"]
+            "#,
+        );
+        assert_eq!(key(&manager, 'p'), Some(KeyboardEvent::Pause));
+        // The default chord of an overridden event is released
+        assert_eq!(key(&manager, ' '), None);
+        assert_eq!(
+            press(&manager, KeyCode::Right, KeyModifiers::CONTROL),
+            Some(KeyboardEvent::Next)
+        );
+        assert_eq!(
+            press(
+                &manager,
+                KeyCode::Media(MediaKeyCode::TrackNext),
+                KeyModifiers::NONE
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn unknown_actions_are_ignored() {
+        let manager = manager(r#"rewind = "z""#);
+        assert_eq!(key(&manager, 'z'), None);
+        assert_eq!(key(&manager, 'q'), Some(KeyboardEvent::Quit));
+    }
+
+    #[test]
+    fn invalid_chords_keep_the_defaults() {
+        let manager = manager(r#"next = ["ctrl+right", "hyper+x"]"#);
+        assert_eq!(press(&manager, KeyCode::Right, KeyModifiers::CONTROL), None);
+        assert_eq!(key(&manager, 'N'), Some(KeyboardEvent::Next));
+        assert_eq!(
+            press(
+                &manager,
+                KeyCode::Media(MediaKeyCode::TrackNext),
+                KeyModifiers::NONE
+            ),
+            Some(KeyboardEvent::Next)
+        );
+    }
+}
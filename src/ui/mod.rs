@@ -1,8 +1,12 @@
 use ratatui::style::Color;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::artwork::Rgb;
 
 mod app;
+pub(crate) mod keyboard;
 mod utils;
-pub(crate) mod screens;
+pub mod screens;
 pub(crate) mod widgets;
 
 pub use app::App;
@@ -12,3 +16,24 @@ const ROW_COLOR_COL: Color = Color::Rgb(85, 85, 85);
 const ROW_ALTERNATE_COLOR: Color = Color::Rgb(50, 50, 50);
 const ROW_ALTERNATE_COLOR_COL: Color = Color::Rgb(55, 55, 55);
 const HIGHLIGHT_COLOR: Color = Color::Rgb(255, 191, 0);
+/// Accent drawn from the album art of the playing track as `0x01RRGGBB`, 0 for none
+static ACCENT: AtomicU32 = AtomicU32::new(0);
+const WARNING_COLOR: Color = Color::Rgb(255, 140, 0);
+/// Marks tracks the output device cannot play as they are.
+const WARNING_ICON: &str = "";
+
+/// Highlight color of the theme: the accent of the playing album when set, amber otherwise.
+fn highlight_color() -> Color {
+    match ACCENT.load(Ordering::Relaxed) {
+        0 => HIGHLIGHT_COLOR,
+        accent => Color::from_u32(accent & 0xFFFFFF),
+    }
+}
+
+/// Sets the accent taking over the highlight color, `None` for the default.
+fn set_accent(accent: Option<Rgb>) {
+    let packed = accent.map_or(0, |(r, g, b)| {
+        0x1000000 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+    });
+    ACCENT.store(packed, Ordering::Relaxed);
+}
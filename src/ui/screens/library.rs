@@ -0,0 +1,454 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use log::warn;
+use ratatui::{
+    prelude::{Alignment, Constraint, Rect},
+    style::Style,
+    widgets::{Block, BorderType, Borders, Cell, Clear, Row, Table, TableState},
+    Frame,
+};
+
+use crate::{
+    library::{Database, PlaylistEntry},
+    musictrack::MusicTrack,
+    ui::{highlight_color, ROW_ALTERNATE_COLOR, ROW_COLOR},
+};
+
+/// Playlist indexes grouped by album, albums grouped by artist.
+type Artists = BTreeMap<String, BTreeMap<String, Vec<usize>>>;
+
+/// Playlist indexes grouped by the folder holding the files, in file name order.
+type Folders = BTreeMap<String, Vec<usize>>;
+
+/// What the selection is used for once the library returns it.
+pub enum LibraryAction {
+    Enqueue(Vec<usize>),
+    /// Plays the first track right away, the others follow
+    Play(Vec<usize>),
+    /// Album played through right after the current track, in album order
+    QueueAlbum(Vec<usize>),
+}
+
+enum Level {
+    Artists,
+    Albums(String),
+    Tracks(String, String),
+    Playlists,
+    Playlist(String),
+    Folders,
+    Folder(String),
+}
+
+/// Folders are named relative to the directory they all share, the music directory most of
+/// the time.
+fn folders(songs: &[Arc<MusicTrack>]) -> Folders {
+    let mut by_path: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (index, song) in songs.iter().enumerate() {
+        let parent = Path::new(&song.path).parent().unwrap_or(Path::new(""));
+        by_path.entry(parent.to_path_buf()).or_default().push(index);
+    }
+    let mut common: Option<PathBuf> = None;
+    for path in by_path.keys() {
+        common = Some(match common {
+            None => path.clone(),
+            Some(common) => common
+                .components()
+                .zip(path.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    let common = common.unwrap_or_default();
+    by_path
+        .into_iter()
+        .map(|(path, mut indexes)| {
+            indexes.sort_by(|a, b| songs[*a].path.cmp(&songs[*b].path));
+            let relative = path.strip_prefix(&common).unwrap_or(&path);
+            let name = match relative.as_os_str().is_empty() {
+                true => path.file_name().unwrap_or(path.as_os_str()),
+                false => relative.as_os_str(),
+            };
+            (name.to_string_lossy().into_owned(), indexes)
+        })
+        .collect()
+}
+
+/// Tracks of a CUE sheet share their file, they are ordered by their start.
+fn album_order(song: &MusicTrack) -> (&str, Option<u64>) {
+    (&song.path, song.segment.map(|segment| segment.start))
+}
+
+/// Position from 1 of a track in its album, with the number of tracks of the album. Albums are
+/// grouped as in the library and ordered by file name, the way they are usually numbered.
+pub(crate) fn album_position(songs: &[Arc<MusicTrack>], index: usize) -> Option<(usize, usize)> {
+    let song = songs.get(index)?;
+    let mut album: Vec<&Arc<MusicTrack>> = songs
+        .iter()
+        .filter(|other| other.artist == song.artist && other.album == song.album)
+        .collect();
+    album.sort_by(|a, b| album_order(a).cmp(&album_order(b)));
+    let position = album.iter().position(|other| Arc::ptr_eq(other, song))?;
+    Some((position + 1, album.len()))
+}
+
+/// Drill-down view of the playlist tracks, artist then album then track, saved playlist then
+/// track, or folder then track.
+pub struct Library {
+    state: TableState,
+    songs: Vec<Arc<MusicTrack>>,
+    artists: Artists,
+    folders: Folders,
+    /// Saved playlists as they are stored, tracks outside the music directory included
+    saved: BTreeMap<String, Vec<PlaylistEntry>>,
+    /// Tracks of the saved playlists found in the music directory, as their position in the
+    /// saved playlist and their playlist index
+    playlists: BTreeMap<String, Vec<(usize, usize)>>,
+    database: Database,
+    level: Level,
+    /// Selection of the parent levels, restored when going back up
+    parents: Vec<usize>,
+}
+
+impl Library {
+    /// Saved tracks are found by path, or by their tags once the file is gone, e.g. renamed by
+    /// a tagger. Their stored path and tags are updated to what was found.
+    pub fn new(songs: &[Arc<MusicTrack>], database: &Database) -> Result<Self> {
+        let mut artists = Artists::new();
+        let mut indexes = HashMap::new();
+        for (index, song) in songs.iter().enumerate() {
+            artists
+                .entry(song.artist.clone())
+                .or_default()
+                .entry(song.album.clone())
+                .or_default()
+                .push(index);
+            if let Ok(path) = std::path::absolute(Path::new(&song.path)) {
+                indexes.insert(path.to_string_lossy().into_owned(), index);
+            }
+        }
+        let mut saved = BTreeMap::new();
+        let mut playlists = BTreeMap::new();
+        for (name, mut entries) in database.playlists()? {
+            let mut tracks = Vec::new();
+            let mut repaired = false;
+            for (position, entry) in entries.iter_mut().enumerate() {
+                let index = indexes.get(&entry.path).copied().or_else(|| {
+                    if Path::new(&entry.path).exists() {
+                        return None;
+                    }
+                    songs.iter().position(|song| entry.matches(song))
+                });
+                let Some(index) = index else {
+                    continue;
+                };
+                let found = PlaylistEntry::new(&songs[index])?;
+                if *entry != found {
+                    *entry = found;
+                    repaired = true;
+                }
+                tracks.push((position, index));
+            }
+            if repaired {
+                database.save_playlist_entries(&name, &entries)?;
+            }
+            saved.insert(name.clone(), entries);
+            playlists.insert(name, tracks);
+        }
+        let mut state = TableState::default();
+        state.select(Some(0));
+        Ok(Self {
+            state,
+            songs: songs.to_vec(),
+            artists,
+            folders: folders(songs),
+            saved,
+            playlists,
+            database: database.clone(),
+            level: Level::Artists,
+            parents: Vec::new(),
+        })
+    }
+
+    fn albums(&self, artist: &str) -> Option<&BTreeMap<String, Vec<usize>>> {
+        self.artists.get(artist)
+    }
+
+    fn tracks(&self, artist: &str, album: &str) -> &[usize] {
+        self.albums(artist)
+            .and_then(|albums| albums.get(album))
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    fn folder(&self, name: &str) -> &[usize] {
+        self.folders
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Playlist indexes of the tracks of a saved playlist found in the music directory.
+    pub(crate) fn playlist(&self, name: &str) -> Vec<usize> {
+        self.playlists
+            .get(name)
+            .map(|tracks| tracks.iter().map(|(_, index)| *index).collect())
+            .unwrap_or_default()
+    }
+
+    fn entries(&self) -> Vec<String> {
+        let titles = |tracks: &[usize]| {
+            tracks
+                .iter()
+                .map(|index| self.songs[*index].title.clone())
+                .collect()
+        };
+        match &self.level {
+            Level::Artists => self.artists.keys().cloned().collect(),
+            Level::Albums(artist) => self
+                .albums(artist)
+                .map(|albums| albums.keys().cloned().collect())
+                .unwrap_or_default(),
+            Level::Tracks(artist, album) => titles(self.tracks(artist, album)),
+            Level::Playlists => self.playlists.keys().cloned().collect(),
+            Level::Playlist(name) => titles(&self.playlist(name)),
+            Level::Folders => self.folders.keys().cloned().collect(),
+            Level::Folder(name) => titles(self.folder(name)),
+        }
+    }
+
+    fn selected_name(&self) -> Option<String> {
+        self.state
+            .selected()
+            .and_then(|index| self.entries().into_iter().nth(index))
+    }
+
+    /// Playlist indexes of every track under the selected entry.
+    fn selected_tracks(&self) -> Vec<usize> {
+        let Some(index) = self.state.selected() else {
+            return Vec::new();
+        };
+        match &self.level {
+            Level::Artists => self
+                .artists
+                .values()
+                .nth(index)
+                .map(|albums| albums.values().flatten().copied().collect())
+                .unwrap_or_default(),
+            Level::Albums(artist) => self
+                .albums(artist)
+                .and_then(|albums| albums.values().nth(index))
+                .cloned()
+                .unwrap_or_default(),
+            Level::Tracks(artist, album) => self
+                .tracks(artist, album)
+                .get(index)
+                .map(|index| vec![*index])
+                .unwrap_or_default(),
+            Level::Playlists => self
+                .playlists
+                .keys()
+                .nth(index)
+                .map(|name| self.playlist(name))
+                .unwrap_or_default(),
+            Level::Playlist(name) => self
+                .playlist(name)
+                .get(index)
+                .map(|index| vec![*index])
+                .unwrap_or_default(),
+            Level::Folders => self
+                .folders
+                .values()
+                .nth(index)
+                .cloned()
+                .unwrap_or_default(),
+            Level::Folder(name) => self
+                .folder(name)
+                .get(index)
+                .map(|index| vec![*index])
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Playlist indexes of the selected album, or of the album browsed, in album order.
+    fn selected_album(&self) -> Vec<usize> {
+        let mut tracks = match &self.level {
+            Level::Albums(_) => self.selected_tracks(),
+            Level::Tracks(artist, album) => self.tracks(artist, album).to_vec(),
+            _ => return Vec::new(),
+        };
+        tracks.sort_by(|a, b| album_order(&self.songs[*a]).cmp(&album_order(&self.songs[*b])));
+        tracks
+    }
+
+    fn select_next(&mut self) {
+        let len = self.entries().len();
+        if len > 0 {
+            let i = self.state.selected().map_or(0, |i| (i + 1) % len);
+            self.state.select(Some(i));
+        }
+    }
+
+    fn select_previous(&mut self) {
+        let len = self.entries().len();
+        if len > 0 {
+            let i = self.state.selected().map_or(0, |i| (i + len - 1) % len);
+            self.state.select(Some(i));
+        }
+    }
+
+    fn enter(&mut self) {
+        let Some(name) = self.selected_name() else {
+            return;
+        };
+        let level = match &self.level {
+            Level::Artists => Level::Albums(name),
+            Level::Albums(artist) => Level::Tracks(artist.clone(), name),
+            Level::Playlists => Level::Playlist(name),
+            Level::Folders => Level::Folder(name),
+            Level::Tracks(..) | Level::Playlist(_) | Level::Folder(_) => return,
+        };
+        self.parents.push(self.state.selected().unwrap_or(0));
+        self.level = level;
+        self.state.select(Some(0));
+    }
+
+    /// Albums of an artist, going back lists the artists from it.
+    pub(crate) fn show_artist(&mut self, artist: &str) {
+        let Some(position) = self.artists.keys().position(|name| name == artist) else {
+            return;
+        };
+        self.level = Level::Albums(artist.to_string());
+        self.parents = vec![position];
+        self.state.select(Some(0));
+    }
+
+    fn back(&mut self) {
+        self.level = match &self.level {
+            Level::Artists | Level::Playlists | Level::Folders => return,
+            Level::Albums(_) => Level::Artists,
+            Level::Tracks(artist, _) => Level::Albums(artist.clone()),
+            Level::Playlist(_) => Level::Playlists,
+            Level::Folder(_) => Level::Folders,
+        };
+        self.state.select(Some(self.parents.pop().unwrap_or(0)));
+    }
+
+    /// Moves the selected track of a saved playlist up or down and saves the new order.
+    fn move_track(&mut self, up: bool) {
+        let Level::Playlist(name) = &self.level else {
+            return;
+        };
+        let (Some(selected), Some(tracks), Some(entries)) = (
+            self.state.selected(),
+            self.playlists.get_mut(name),
+            self.saved.get_mut(name),
+        ) else {
+            return;
+        };
+        let other = match up {
+            true => selected.checked_sub(1),
+            false => Some(selected + 1).filter(|other| *other < tracks.len()),
+        };
+        let Some(other) = other.filter(|_| selected < tracks.len()) else {
+            return;
+        };
+        // Tracks missing from the music directory keep their place
+        entries.swap(tracks[selected].0, tracks[other].0);
+        let index = tracks[selected].1;
+        tracks[selected].1 = tracks[other].1;
+        tracks[other].1 = index;
+        self.state.select(Some(other));
+        if let Err(err) = self.database.save_playlist_entries(name, entries) {
+            warn!("Cannot save the playlist {}: {}", name, err);
+        }
+    }
+
+    /// Switches between browsing artists and saved playlists.
+    fn toggle_playlists(&mut self) {
+        self.level = match &self.level {
+            Level::Playlists | Level::Playlist(_) => Level::Artists,
+            _ => Level::Playlists,
+        };
+        self.parents.clear();
+        self.state.select(Some(0));
+    }
+
+    /// Switches between browsing artists and folders, for music organized by directory.
+    fn toggle_folders(&mut self) {
+        self.level = match &self.level {
+            Level::Folders | Level::Folder(_) => Level::Artists,
+            _ => Level::Folders,
+        };
+        self.parents.clear();
+        self.state.select(Some(0));
+    }
+
+    /// Returns the playlist indexes of the selection when it is appended to the queue or
+    /// played, or of the album queued after the current track.
+    pub fn event_handler(&mut self, key: KeyEvent) -> Option<LibraryAction> {
+        if key.kind == KeyEventKind::Press {
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => self.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => self.select_next(),
+                KeyCode::Enter | KeyCode::Right | KeyCode::Char('l') => self.enter(),
+                KeyCode::Backspace | KeyCode::Left | KeyCode::Char('h') => self.back(),
+                KeyCode::Char('a') => return Some(LibraryAction::Enqueue(self.selected_tracks())),
+                KeyCode::Char('P') => return Some(LibraryAction::Play(self.selected_tracks())),
+                KeyCode::Char('n') => {
+                    let album = self.selected_album();
+                    if !album.is_empty() {
+                        return Some(LibraryAction::QueueAlbum(album));
+                    }
+                }
+                KeyCode::Char('p') => self.toggle_playlists(),
+                KeyCode::Char('f') => self.toggle_folders(),
+                KeyCode::Char('K') => self.move_track(true),
+                KeyCode::Char('J') => self.move_track(false),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let title = match &self.level {
+            Level::Artists => String::from("Library"),
+            Level::Albums(artist) => format!("Library - {}", artist),
+            Level::Tracks(artist, album) => format!("Library - {} - {}", artist, album),
+            Level::Playlists => String::from("Playlists"),
+            Level::Playlist(name) => format!("Playlists - {}", name),
+            Level::Folders => String::from("Folders"),
+            Level::Folder(name) => format!("Folders - {}", name),
+        };
+        let rows = self
+            .entries()
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                Row::new(vec![Cell::from(entry)]).style(Style::default().bg(if index % 2 == 0 {
+                    ROW_COLOR
+                } else {
+                    ROW_ALTERNATE_COLOR
+                }))
+            });
+        let table = Table::new(rows, &[Constraint::Fill(1)])
+            .row_highlight_style(Style::default().fg(highlight_color()))
+            .block(
+                Block::default()
+                    .title(title)
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(highlight_color())),
+            );
+
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(table, area, &mut self.state);
+        Ok(())
+    }
+}
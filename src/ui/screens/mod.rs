@@ -1,3 +1,9 @@
+mod library;
 mod playlist;
+mod recorder;
+mod sync;
 
+pub(crate) use library::{album_position, Library, LibraryAction};
 pub(crate) use playlist::Playlist;
+pub use recorder::RecorderScreen;
+pub use sync::SyncScreen;
@@ -1,22 +1,88 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use anyhow::Result;
-use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, MediaKeyCode};
-use rand::{seq::SliceRandom, thread_rng};
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use rand::thread_rng;
 use ratatui::{
-    prelude::{Alignment, Constraint, Rect},
-    style::Style,
-    widgets::{Block, BorderType, Borders, Cell, Clear, Row, Table, TableState},
+    prelude::{Alignment, Constraint, Direction, Layout, Margin, Rect},
+    style::{Modifier, Style},
+    symbols,
+    text::{Line, Span},
+    widgets::{Block, BorderType, Borders, Cell, Clear, LineGauge, Row, Table, TableState},
     Frame,
 };
-use walkdir::WalkDir;
 
 use crate::{
-    player::{CurrentTrackInfo, Player},
+    alarm::{Alarm, AlarmCommand, FADE_IN_DB},
+    autodj,
+    config::{Config, EndOfQueue},
+    export::write_m3u,
+    fuzzy::fuzzy_match,
+    history::History,
+    library::Database,
+    loader::{add_cue_sheet, Loaded, Loader},
+    lyrics::Lyrics,
+    mpd::Modes,
+    player::{CurrentTrackInfo, Playback, Player, PlayerSnapshot},
+    preview::Preview,
     musictrack::MusicTrack,
-    ui::{HIGHLIGHT_COLOR, ROW_ALTERNATE_COLOR, ROW_ALTERNATE_COLOR_COL, ROW_COLOR, ROW_COLOR_COL},
+    queue::{Placement, Queue},
+    radio,
+    scanner::{is_cue_sheet, Scanner, IGNORE_FILE},
+    ui::{
+        highlight_color,
+        keyboard::KeyboardEvent,
+        screens::{album_position, Library},
+        widgets::{
+            hyperlinks_enabled, track_url, BadgeColors, Badges, Hyperlinks, LevelMeter, LyricsPane,
+            NowPlaying, QueuePane, Spectrum, SpectrumAnalyzer,
+        },
+        ROW_ALTERNATE_COLOR, ROW_ALTERNATE_COLOR_COL, ROW_COLOR, ROW_COLOR_COL, WARNING_COLOR,
+        WARNING_ICON,
+    },
+    watcher::{Change, DirWatcher},
 };
 
+/// Rows of the spectrum analyzer.
+const SPECTRUM_HEIGHT: u16 = 8;
+/// Time spent appending the tracks read from the music directory between two frames.
+const LOAD_BUDGET: Duration = Duration::from_millis(20);
+/// Columns of the loading gauge on the top border, at most.
+const LOADING_WIDTH: u16 = 40;
+/// Share of the playing track after which the next one is opened ahead.
+const PREFETCH_AT: f64 = 0.9;
+/// How long the album position stays shown after moving on to the next track of an album.
+const ALBUM_NOTICE_DURATION: Duration = Duration::from_secs(4);
+/// Tracks from this long resume where they were left, mixes and audiobooks.
+const BOOKMARK_DURATION: f64 = 600.0;
+/// Left closer than this to either end, a track starts over next time.
+const BOOKMARK_MARGIN: f64 = 30.0;
+/// Deleted tracks which can be put back, older ones are forgotten.
+const UNDO_LENGTH: usize = 50;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+impl RepeatMode {
+    fn cycle(self) -> Self {
+        match self {
+            Self::Off => Self::One,
+            Self::One => Self::All,
+            Self::All => Self::Off,
+        }
+    }
+}
+
 pub struct Playlist {
     state: TableState,
     songs: Vec<Arc<MusicTrack>>,
@@ -24,47 +90,602 @@ pub struct Playlist {
     playing_track: Option<CurrentTrackInfo>,
     playing_track_list_index: usize,
     automatically_play_next: bool,
+    queue: Queue,
+    history: History,
+    repeat: RepeatMode,
+    /// Removes tracks from the playlist once played, the files are left untouched
+    consume: bool,
+    /// Queueing picks of the auto DJ, from the end of the playlist until a track is played
+    auto_dj: bool,
+    /// Set once the end of the playlist asked to quit, see `is_over`
+    over: bool,
+    library: Database,
+    scanner: Scanner,
+    watcher: Option<DirWatcher>,
+    /// Music directory, or the directory of the played file
+    root: PathBuf,
+    spectrum: SpectrumAnalyzer,
+    show_meters: bool,
+    /// Lyrics of the playing track, only read while shown
+    lyrics: Option<Lyrics>,
+    show_lyrics: bool,
+    badge_colors: BadgeColors,
+    /// Shows the format badges of every track rather than only the playing one
+    badges_column: bool,
+    /// Files played through the tracks of a CUE sheet rather than whole
+    cue_files: HashSet<String>,
+    /// Tracks of the music directory still being read, `None` once all are in
+    loader: Option<Loader>,
+    /// Tracks appended since the app last took them, see `take_loaded`
+    loaded: Vec<Arc<MusicTrack>>,
+    created: Instant,
+    /// "Track N/M - Album" of the track played next within the same album, with when it started
+    album_notice: Option<(String, Instant)>,
+    /// What became of playback once its device went away, see `take_device_notice`
+    device_notice: Option<String>,
+    /// Pre-listening on the second device, opened on first use
+    preview: Option<Preview>,
+    /// Rows the table showed when last drawn
+    rows_shown: usize,
+    /// Tracks deleted from the app with where they were, the last one on top
+    deleted: Vec<(usize, Arc<MusicTrack>)>,
+    /// Wake-up alarm, set by the app once its tasks run
+    alarm: Option<Alarm>,
+    /// Tracks found while the search prompt is open
+    search: Option<Search>,
+}
+
+/// Track found by the search, with the chars matched in its title and artist.
+struct Found {
+    song: Arc<MusicTrack>,
+    title: Vec<usize>,
+    artist: Vec<usize>,
+}
+
+/// Tracks matching the search, best first.
+struct Search {
+    found: Vec<Found>,
+    /// Match selected in the table
+    current: usize,
+    /// Selection before the search, back once cancelled
+    selected: Option<usize>,
+}
+
+/// Matches every word of `query` on the title, artist or album of `song`, the best field for
+/// each, and sums their scores. Album matches count without being shown, it has no column.
+fn search_song(query: &str, song: &Arc<MusicTrack>) -> Option<(i32, Found)> {
+    let title = song.display_title();
+    let mut found = Found {
+        song: song.clone(),
+        title: Vec::new(),
+        artist: Vec::new(),
+    };
+    let mut score = 0;
+    for word in query.split_whitespace() {
+        let fields = [
+            fuzzy_match(word, &title).map(|found| (found, 0)),
+            fuzzy_match(word, &song.artist).map(|found| (found, 1)),
+            fuzzy_match(word, &song.album).map(|found| (found, 2)),
+        ];
+        let (best, field) = fields
+            .into_iter()
+            .flatten()
+            .max_by_key(|(found, field)| (found.score, std::cmp::Reverse(*field)))?;
+        score += best.score;
+        match field {
+            0 => found.title.extend(best.positions),
+            1 => found.artist.extend(best.positions),
+            _ => (),
+        }
+    }
+    Some((score, found))
+}
+
+/// `text` with the chars at `positions` stood out.
+fn highlighted(text: String, positions: &[usize]) -> Line<'static> {
+    if positions.is_empty() {
+        return Line::from(text);
+    }
+    let style = Style::default().add_modifier(Modifier::BOLD | Modifier::UNDERLINED);
+    let mut spans: Vec<Span> = Vec::new();
+    let mut run = String::new();
+    let mut run_matched = false;
+    for (index, c) in text.chars().enumerate() {
+        let matched = positions.contains(&index);
+        if matched != run_matched && !run.is_empty() {
+            let span = std::mem::take(&mut run);
+            spans.push(match run_matched {
+                true => Span::styled(span, style),
+                false => Span::raw(span),
+            });
+        }
+        run_matched = matched;
+        run.push(c);
+    }
+    spans.push(match run_matched {
+        true => Span::styled(run, style),
+        false => Span::raw(run),
+    });
+    Line::from(spans)
+}
+
+/// Elapsed time as `mm:ss`, negative while the pre-gap of a CUE sheet track plays.
+fn format_elapsed(seconds: f64) -> String {
+    let sign = if seconds < 0.0 { "-" } else { "" };
+    let seconds = seconds.abs() as u64;
+    format!("{}{:0>2}:{:0>2}", sign, seconds / 60, seconds % 60)
+}
+
+/// Time left as `h:mm:ss`, or as `mm:ss` under an hour.
+fn format_remaining(seconds: f64) -> String {
+    let seconds = seconds.max(0.0).round() as u64;
+    match seconds / 3600 {
+        0 => format_elapsed(seconds as f64),
+        hours => format!("{}:{:0>2}:{:0>2}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+/// Long files played whole, that can be started past their beginning.
+fn resumable(song: &MusicTrack) -> bool {
+    song.duration_seconds() >= BOOKMARK_DURATION
+        && song.dsd_rate.is_none()
+        && song.segment.is_none()
+        && !song.is_stream()
+}
+
+/// Albums are grouped as in the library, by artist and title.
+fn same_album(a: &MusicTrack, b: &MusicTrack) -> bool {
+    a.album == b.album && a.artist == b.artist
+}
+
+/// First index of the run of tracks of the album at `index` in the playlist.
+fn album_start(songs: &[Arc<MusicTrack>], index: usize) -> usize {
+    songs[..index]
+        .iter()
+        .rposition(|other| !same_album(other, &songs[index]))
+        .map_or(0, |before| before + 1)
 }
 
 impl Playlist {
-    pub fn new(path: PathBuf, player: Player) -> Result<Self> {
+    pub fn new(path: PathBuf, mut player: Player, library: &Database) -> Result<Self> {
+        let created = Instant::now();
         let mut songs = vec![];
+        let mut loader = None;
+        let mut watcher = None;
+        let scanner = Scanner::new(&path);
+        let root = if path.is_dir() {
+            path.clone()
+        } else if path.to_str().is_some_and(radio::is_url) {
+            PathBuf::new()
+        } else {
+            path.parent().map(Path::to_path_buf).unwrap_or_default()
+        };
+        let pregap = player.config().cue.pregap;
+        let badge_colors = BadgeColors::new(&player.config().badges.colors);
+        let badges_column = player.config().badges.column;
+        let mut cue_files = HashSet::new();
         if path.is_dir() {
-            let mut files = WalkDir::new(path.clone())
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| {
-                    e.file_type().is_file()
-                        && e.file_name()
-                            .to_str()
-                            .map(|s| s.ends_with(".flac"))
-                            .unwrap_or(false)
-                })
-                .map(|e| e.path().to_str().unwrap().to_string())
-                .collect::<Vec<String>>();
-            files.shuffle(&mut thread_rng());
-            for f in files {
-                songs.push(Arc::new(MusicTrack::new(f)?));
-            }
+            loader = Some(Loader::new(path.clone(), pregap, library.clone()));
+            watcher = DirWatcher::new(&path)
+                .inspect_err(|err| warn!("Cannot watch {}: {}", path.display(), err))
+                .ok();
+        } else if is_cue_sheet(&path) {
+            add_cue_sheet(&path, pregap, library, &mut songs, &mut cue_files);
+        } else if let Some(url) = path.to_str().filter(|path| radio::is_url(path)) {
+            // Streams change all the time, they are not kept in the library
+            songs.push(Arc::new(MusicTrack::new(url.to_string())?));
         } else if path.is_file() {
-            songs.push(Arc::new(MusicTrack::new(
-                path.into_os_string().into_string().unwrap(),
-            )?));
+            songs.push(Arc::new(
+                library.track(path.into_os_string().into_string().unwrap())?,
+            ));
+        }
+        for (artist, album) in library.strict_gapless_albums()? {
+            player.set_strict_gapless(&artist, &album, true);
         }
         let mut state = TableState::default();
         state.select(Some(0));
         Ok(Self {
             state,
+            loaded: songs.clone(),
             songs,
             player,
             playing_track: None,
             playing_track_list_index: 0,
             automatically_play_next: true,
+            queue: Queue::default(),
+            history: History::default(),
+            repeat: RepeatMode::All,
+            consume: false,
+            auto_dj: false,
+            over: false,
+            library: library.clone(),
+            scanner,
+            watcher,
+            root,
+            spectrum: SpectrumAnalyzer::default(),
+            show_meters: false,
+            lyrics: None,
+            show_lyrics: false,
+            badge_colors,
+            badges_column,
+            cue_files,
+            loader,
+            created,
+            album_notice: None,
+            device_notice: None,
+            preview: None,
+            rows_shown: 0,
+            deleted: Vec::new(),
+            alarm: None,
+            search: None,
         })
     }
 
+    /// Probes a new or rewritten file, replacing the previous entry for the same path.
+    fn add_file(&mut self, path: &Path) {
+        if !MusicTrack::is_supported(path) || self.scanner.is_ignored(path, false) {
+            return;
+        }
+        let Some(path) = path.to_str() else {
+            return;
+        };
+        if self.cue_files.contains(path) {
+            return;
+        }
+        if let Some(loader) = &mut self.loader {
+            loader.added(path);
+        }
+        // Files still being copied fail to probe, they are added once complete
+        let Ok(track) = self.library.track(path.to_string()) else {
+            return;
+        };
+        match self.songs.iter().position(|song| song.path == path) {
+            Some(index) => self.songs[index] = Arc::new(track),
+            None => self.songs.push(Arc::new(track)),
+        }
+    }
+
+    /// Removes the tracks at or under `path`, keeping the playing track and queue in place.
+    fn remove_files(&mut self, path: &Path) {
+        if let Some(loader) = &mut self.loader {
+            loader.removed(path);
+        }
+        while let Some(index) = self
+            .songs
+            .iter()
+            .position(|song| Path::new(&song.path).starts_with(path))
+        {
+            self.remove_song(index);
+        }
+    }
+
+    fn remove_song(&mut self, index: usize) {
+        self.songs.remove(index);
+        self.queue.remove(index);
+        self.history.remove(index);
+        if self.playing_track_list_index > index {
+            self.playing_track_list_index -= 1;
+        }
+        if self.songs.is_empty() {
+            self.state.select(None);
+        } else if self.state.selected().is_some_and(|i| i >= self.songs.len()) {
+            self.state.select(Some(self.songs.len() - 1));
+        }
+    }
+
+    /// Puts `song` at `index`, the tracks from there shift down.
+    fn insert_song(&mut self, index: usize, song: Arc<MusicTrack>) {
+        self.songs.insert(index, song);
+        let shifted = |entry: usize| if entry >= index { entry + 1 } else { entry };
+        self.queue.remap(shifted);
+        self.history.remap(shifted);
+        self.playing_track_list_index = shifted(self.playing_track_list_index);
+    }
+
+    /// Deletes the selected track, stopped first when playing. It can be put back with
+    /// `undo_delete`.
+    async fn delete_selected(&mut self) -> Result<()> {
+        let Some(index) = self
+            .state
+            .selected()
+            .filter(|index| *index < self.songs.len())
+        else {
+            return Ok(());
+        };
+        if index == self.playing_track_list_index && self.playing_track.is_some() {
+            self.bookmark();
+            self.stop().await?;
+        }
+        if self.deleted.len() == UNDO_LENGTH {
+            self.deleted.remove(0);
+        }
+        self.deleted.push((index, self.songs[index].clone()));
+        self.remove_song(index);
+        Ok(())
+    }
+
+    /// Puts the last deleted track back where it was, selected. It is no longer queued.
+    fn undo_delete(&mut self) {
+        let Some((index, song)) = self.deleted.pop() else {
+            return;
+        };
+        let index = index.min(self.songs.len());
+        self.insert_song(index, song);
+        self.state.select(Some(index));
+    }
+
+    /// Swaps the selected track with the one above or below it, the selection follows it.
+    fn move_selected(&mut self, up: bool) {
+        let Some(index) = self
+            .state
+            .selected()
+            .filter(|index| *index < self.songs.len())
+        else {
+            return;
+        };
+        let other = match up {
+            true if index > 0 => index - 1,
+            false if index + 1 < self.songs.len() => index + 1,
+            _ => return,
+        };
+        self.songs.swap(index, other);
+        let swapped = |entry: usize| match entry {
+            entry if entry == index => other,
+            entry if entry == other => index,
+            entry => entry,
+        };
+        self.queue.remap(swapped);
+        self.history.remap(swapped);
+        self.playing_track_list_index = swapped(self.playing_track_list_index);
+        self.state.select(Some(other));
+    }
+
+    /// Probes a file, or the files under a directory, and inserts them after the selected
+    /// track, at the end when none is.
+    pub fn insert_files(&mut self, path: &Path) -> Result<()> {
+        let at = self
+            .state
+            .selected()
+            .map_or(self.songs.len(), |index| (index + 1).min(self.songs.len()));
+        self.insert_files_at(path, at)?;
+        Ok(())
+    }
+
+    /// Probes a file, or the files under a directory, and puts them where `placement` says,
+    /// queued unless placed at a given position.
+    pub fn add_files(&mut self, path: &Path, placement: Placement) -> Result<()> {
+        let playing = self
+            .playing_track
+            .as_ref()
+            .map(|_| self.playing_track_list_index);
+        let at = match placement {
+            Placement::End => self.songs.len(),
+            Placement::Next => playing.map_or(0, |index| index + 1),
+            Placement::At(index) => index.min(self.songs.len()),
+        };
+        let added = self.insert_files_at(path, at)?;
+        match placement {
+            Placement::End => added.for_each(|index| self.queue.add(index)),
+            Placement::Next => added.rev().for_each(|index| self.queue.play_next(index)),
+            Placement::At(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Inserts the tracks of `path` from `at` on, in the order of their files, and returns
+    /// where they went. Unreadable files are skipped.
+    fn insert_files_at(&mut self, path: &Path, at: usize) -> Result<Range<usize>> {
+        let files = if path.is_dir() {
+            let mut files = self.scanner.files(path);
+            files.sort();
+            files
+        } else if !path.is_file() {
+            return Err(anyhow!("{} does not exist", path.display()));
+        } else if !MusicTrack::is_supported(path) {
+            return Err(anyhow!("{} is not a supported audio file", path.display()));
+        } else {
+            let file = path
+                .to_str()
+                .ok_or(anyhow!("Invalid path {}", path.display()))?;
+            vec![file.to_string()]
+        };
+        let mut end = at;
+        for file in files {
+            match self.library.track(file) {
+                Ok(track) => {
+                    let track = Arc::new(track);
+                    self.insert_song(end, track.clone());
+                    self.loaded.push(track);
+                    end += 1;
+                }
+                Err(err) => warn!("Skipping unreadable file: {}", err),
+            }
+        }
+        let inserted = end - at;
+        let plural = if inserted == 1 { "" } else { "s" };
+        let notice = format!(" {} track{} inserted ", inserted, plural);
+        self.album_notice = Some((notice, Instant::now()));
+        Ok(at..end)
+    }
+
+    /// Applies an edited ignore file to the tracks under its directory.
+    fn rescan(&mut self, dir: &Path) {
+        self.scanner.forget(dir);
+        let ignored: Vec<PathBuf> = self
+            .songs
+            .iter()
+            .map(|song| PathBuf::from(&song.path))
+            .filter(|path| path.starts_with(dir))
+            .collect();
+        for path in ignored {
+            if self.scanner.is_ignored(&path, false) {
+                self.remove_files(&path);
+            }
+        }
+        for file in self.scanner.files(dir) {
+            if !self.songs.iter().any(|song| song.path == file) {
+                self.add_file(Path::new(&file));
+            }
+        }
+    }
+
+    fn apply_changes(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        let mut changes: Vec<Change> = watcher.changes().collect();
+        // Writes come as bursts of events for the same file
+        changes.dedup();
+        for change in changes {
+            match change {
+                Change::Added(path) | Change::Modified(path) | Change::Removed(path)
+                    if path.file_name().is_some_and(|name| name == IGNORE_FILE) =>
+                {
+                    if let Some(dir) = path.parent() {
+                        self.rescan(dir);
+                    }
+                }
+                Change::Added(path) if path.is_dir() => {
+                    for file in self.scanner.files(&path) {
+                        self.add_file(Path::new(&file));
+                    }
+                }
+                Change::Added(path) => self.add_file(&path),
+                Change::Modified(path) if path.is_file() => {
+                    if let Some(file) = path.to_str() {
+                        if let Err(err) = self.library.forget(file) {
+                            warn!("Cannot refresh {}: {}", file, err);
+                        }
+                    }
+                    self.add_file(&path);
+                }
+                Change::Modified(_) => (),
+                Change::Removed(path) => self.remove_files(&path),
+            }
+        }
+    }
+
+    /// Appends the tracks read by the loader until the frame budget is spent.
+    fn load_pending(&mut self) {
+        let Some(loader) = &mut self.loader else {
+            return;
+        };
+        let started = Instant::now();
+        while started.elapsed() < LOAD_BUDGET {
+            match loader.try_next() {
+                Some(Loaded::Listed { cue_files, .. }) => self.cue_files.extend(cue_files),
+                Some(Loaded::Track(track)) => {
+                    self.songs.push(track.clone());
+                    self.loaded.push(track);
+                }
+                Some(Loaded::Skipped) => (),
+                None => break,
+            }
+        }
+        if loader.is_finished() {
+            self.loader = None;
+            info!(
+                "Loaded {} tracks in {:?}",
+                self.songs.len(),
+                self.created.elapsed()
+            );
+        }
+    }
+
+    /// Tracks added to the playlist since the last call.
+    pub fn take_loaded(&mut self) -> Vec<Arc<MusicTrack>> {
+        std::mem::take(&mut self.loaded)
+    }
+
+    /// Applies from the next track on.
+    pub fn set_shared_mode(&mut self, shared: bool) {
+        self.player.set_shared_mode(shared);
+    }
+
+    pub fn set_alarm(&mut self, alarm: Alarm) {
+        self.alarm = Some(alarm);
+    }
+
+    pub fn alarm(&self) -> Option<&Alarm> {
+        self.alarm.as_ref()
+    }
+
+    /// Plays the saved playlist of the alarm, fading in, or stops playback once due.
+    async fn follow_alarm(&mut self) -> Result<()> {
+        let Some(command) = self.alarm.as_mut().and_then(Alarm::command) else {
+            return Ok(());
+        };
+        match command {
+            AlarmCommand::Start {
+                playlist,
+                fade_in_seconds,
+            } => {
+                let tracks = Library::new(&self.songs, &self.library)?.playlist(&playlist);
+                if tracks.is_empty() {
+                    warn!("The alarm playlist {} has no track to play", playlist);
+                    return Ok(());
+                }
+                info!("Alarm playing {}", playlist);
+                self.player.fade_in_next(FADE_IN_DB, fade_in_seconds as f64);
+                self.play_tracks(tracks).await
+            }
+            AlarmCommand::Stop => {
+                info!("Alarm stopping playback");
+                self.stop().await
+            }
+        }
+    }
+
+    /// Message for the user once playback moved off a device which went away.
+    pub fn take_device_notice(&mut self) -> Option<String> {
+        self.device_notice.take()
+    }
+
+    /// The playing track moves to the default device where it was when its device goes away,
+    /// it stops there when no device is left. Tracks which cannot be seeked start over.
+    async fn follow_removed_device(&mut self) -> Result<()> {
+        // Found gone when the track started, it already plays on the default device
+        if let Some(gone) = self.player.stale_device() {
+            let default = self.player.device_name().unwrap_or_default();
+            self.device_notice = Some(format!("{} is gone, playing on {}", gone, default));
+        }
+        let Some(removed) = self.player.removed_device() else {
+            return Ok(());
+        };
+        let position = self
+            .playing_track
+            .as_ref()
+            .map(|track| track.elapsed_seconds());
+        let notice = match (self.player.default_device_name(), position) {
+            (Some(default), Some(position)) => {
+                let seekable = self
+                    .playable(self.playing_track_list_index)
+                    .is_some_and(|song| song.dsd_rate.is_none() && !song.is_stream());
+                match seekable {
+                    true => self.seek(position).await?,
+                    false => self.play().await?,
+                }
+                format!("{} is gone, playing on {}", removed, default)
+            }
+            (None, Some(position)) => {
+                self.stop().await?;
+                format!(
+                    "{} is gone, stopped at {}",
+                    removed,
+                    format_elapsed(position)
+                )
+            }
+            (Some(default), None) => format!("{} is gone, {} is used instead", removed, default),
+            (None, None) => format!("{} is gone", removed),
+        };
+        self.device_notice = Some(notice);
+        Ok(())
+    }
+
     pub fn select_next(&mut self) {
+        if self.songs.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i >= self.songs.len() - 1 {
@@ -79,6 +700,9 @@ impl Playlist {
     }
 
     pub fn select_previous(&mut self) {
+        if self.songs.is_empty() {
+            return;
+        }
         let i = match self.state.selected() {
             Some(i) => {
                 if i == 0 {
@@ -92,18 +716,130 @@ impl Playlist {
         self.state.select(Some(i));
     }
 
+    /// Selects the playing track, scrolled to the middle of the table unless near either end.
+    fn select_playing(&mut self) {
+        let index = self.playing_track_list_index;
+        if index >= self.songs.len() {
+            return;
+        }
+        self.select_centered(index);
+    }
+
+    fn select_centered(&mut self, index: usize) {
+        self.state.select(Some(index));
+        *self.state.offset_mut() = index
+            .saturating_sub(self.rows_shown / 2)
+            .min(self.songs.len().saturating_sub(self.rows_shown));
+    }
+
+    /// Ranks the tracks whose title, artist or album fuzzy match every word of `query` and
+    /// selects the best one. The selection comes back once the search is cancelled.
+    pub fn search(&mut self, query: &str) {
+        let selected = match &self.search {
+            Some(search) => search.selected,
+            None => self.state.selected(),
+        };
+        let mut found: Vec<(i32, Found)> = match query.trim().is_empty() {
+            true => Vec::new(),
+            false => self
+                .songs
+                .iter()
+                .filter_map(|song| search_song(query, song))
+                .collect(),
+        };
+        // Ties stay in playlist order
+        found.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        self.search = Some(Search {
+            found: found.into_iter().map(|(_, found)| found).collect(),
+            current: 0,
+            selected,
+        });
+        self.select_found();
+    }
+
+    /// Selects the match after the current one, or before it, wrapping around.
+    pub fn next_match(&mut self, forward: bool) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+        let count = search.found.len();
+        if count == 0 {
+            return;
+        }
+        search.current = match forward {
+            true => (search.current + 1) % count,
+            false => (search.current + count - 1) % count,
+        };
+        self.select_found();
+    }
+
+    /// Closes the search, keeping the match selected or going back to the previous selection.
+    pub fn end_search(&mut self, keep: bool) {
+        let Some(search) = self.search.take() else {
+            return;
+        };
+        if !keep {
+            self.state.select(search.selected);
+        }
+    }
+
+    /// Rank of the match selected, from 1, and how many there are.
+    pub fn search_status(&self) -> Option<(usize, usize)> {
+        let search = self.search.as_ref()?;
+        let count = search.found.len();
+        Some(((search.current + 1).min(count), count))
+    }
+
+    /// Selects the current match, found again by identity as tracks may have moved since.
+    fn select_found(&mut self) {
+        let Some(search) = &self.search else {
+            return;
+        };
+        let Some(found) = search.found.get(search.current) else {
+            return;
+        };
+        let index = self
+            .songs
+            .iter()
+            .position(|song| Arc::ptr_eq(song, &found.song));
+        if let Some(index) = index {
+            self.select_centered(index);
+        }
+    }
+
+    /// Wraps around to the first track only when repeating the whole playlist.
     async fn next(&mut self) -> Result<()> {
-        self.playing_track_list_index = if self.playing_track_list_index + 1 > self.songs.len() - 1
-        {
+        self.next_from(self.playing_track_list_index + 1).await
+    }
+
+    /// Plays the queue, or else the playlist from `following`.
+    async fn next_from(&mut self, mut following: usize) -> Result<()> {
+        // The tracks following a consumed one shift down
+        if self.consume && self.playing_track.is_some() {
+            self.remove_song(self.playing_track_list_index);
+            if following > self.playing_track_list_index {
+                following -= 1;
+            }
+        }
+        self.playing_track_list_index = if let Some(index) = self.queue.pop() {
+            index
+        } else if following >= self.songs.len() {
+            if self.repeat != RepeatMode::All || self.songs.is_empty() {
+                return self.stop().await;
+            }
             0
         } else {
-            self.playing_track_list_index + 1
+            following
         };
         self.play().await
     }
 
+    /// Goes back to the track played before the current one, shuffled or queued alike. Without
+    /// history the track preceding it in the playlist is played.
     async fn previous(&mut self) -> Result<()> {
-        self.playing_track_list_index = if self.playing_track_list_index == 0 {
+        self.playing_track_list_index = if let Some(index) = self.history.back() {
+            index
+        } else if self.playing_track_list_index == 0 {
             self.songs.len() - 1
         } else {
             self.playing_track_list_index - 1
@@ -111,17 +847,515 @@ impl Playlist {
         self.play().await
     }
 
+    /// Skips the rest of the playing album, queued or in the playlist, to the first track of
+    /// another one.
+    async fn next_album(&mut self) -> Result<()> {
+        let index = self.playing_track_list_index;
+        let Some(song) = self
+            .songs
+            .get(index)
+            .filter(|_| self.playing_track.is_some())
+        else {
+            return self.next().await;
+        };
+        let song = song.clone();
+        let songs = &self.songs;
+        self.queue.skip_while(|queued| {
+            songs
+                .get(queued)
+                .is_some_and(|other| same_album(other, &song))
+        });
+        let following = index
+            + self.songs[index..]
+                .iter()
+                .take_while(|other| same_album(other, &song))
+                .count();
+        self.next_from(following).await
+    }
+
+    /// Goes back to the first track of the album preceding the playing one in the playlist,
+    /// wrapping around to the last album.
+    async fn previous_album(&mut self) -> Result<()> {
+        if self.songs.is_empty() {
+            return Ok(());
+        }
+        let index = self.playing_track_list_index.min(self.songs.len() - 1);
+        let start = album_start(&self.songs, index);
+        let last = start.checked_sub(1).unwrap_or(self.songs.len() - 1);
+        self.playing_track_list_index = album_start(&self.songs, last);
+        self.play().await
+    }
+
+    /// Lyrics of a CUE sheet file would span all of its tracks, they are left out.
+    fn load_lyrics(&mut self) {
+        self.lyrics = None;
+        let Some(song) = self.songs.get(self.playing_track_list_index) else {
+            return;
+        };
+        if !self.show_lyrics || self.playing_track.is_none() || song.segment.is_some() {
+            return;
+        }
+        match Lyrics::load(song) {
+            Ok(lyrics) => self.lyrics = lyrics,
+            Err(err) => warn!("Cannot read the lyrics of {}: {}", song.path, err),
+        }
+    }
+
+    /// Tracks without loudness tags use the background analysis, once done.
+    fn playable(&self, index: usize) -> Option<Arc<MusicTrack>> {
+        let song = self.songs.get(index)?;
+        Some(match self.library.analysis(&song.path) {
+            Some(analysis) if song.loudness.is_none() && song.segment.is_none() => {
+                Arc::new(MusicTrack {
+                    loudness: analysis.loudness,
+                    ..(**song).clone()
+                })
+            }
+            _ => song.clone(),
+        })
+    }
+
+    /// Track `next` moves to, indexed as before the playing track is consumed.
+    fn upcoming(&self) -> Option<usize> {
+        let index = self.playing_track_list_index;
+        if self.repeat == RepeatMode::One {
+            return Some(index);
+        }
+        if let Some((queued, _)) = self.queue.iter().next() {
+            return Some(queued);
+        }
+        if index + 1 < self.songs.len() {
+            Some(index + 1)
+        } else {
+            (self.repeat == RepeatMode::All && !(self.consume && index == 0)).then_some(0)
+        }
+    }
+
+    fn song_seconds(&self, index: usize) -> f64 {
+        self.songs
+            .get(index)
+            .map_or(0.0, |song| song.duration_seconds())
+    }
+
+    /// Seconds left of the playing track, `None` while stopped and for radios which never end.
+    fn seconds_left(&self) -> Option<f64> {
+        let (_, song, info) = self.playing()?;
+        if song.is_stream() {
+            return None;
+        }
+        Some((info.duration_seconds() - info.elapsed_seconds()).max(0.0))
+    }
+
+    /// Seconds before the queue is played through.
+    fn queue_seconds_left(&self) -> Option<f64> {
+        let queued: f64 = self
+            .queue
+            .iter()
+            .map(|(index, _)| self.song_seconds(index))
+            .sum();
+        Some(self.seconds_left()? + queued)
+    }
+
+    /// Seconds before a track starts, when it is queued or follows the playing track in the
+    /// playlist.
+    fn seconds_until(&self, index: usize) -> Option<f64> {
+        let playing = self.playing_track_list_index;
+        if index == playing || self.repeat == RepeatMode::One {
+            return None;
+        }
+        let left = self.seconds_left()?;
+        let queued = self
+            .queue
+            .starts(left, |queued| self.song_seconds(queued))
+            .find(|(queued, _)| *queued == index);
+        if let Some((_, start)) = queued {
+            return Some(start);
+        }
+        if index < playing {
+            return None;
+        }
+        // The playlist resumes after the queue, from the track following the playing one
+        let between: f64 = (playing + 1..index)
+            .map(|index| self.song_seconds(index))
+            .sum();
+        Some(self.queue_seconds_left()? + between)
+    }
+
+    /// Time left before the queue is through and before the selected track plays.
+    fn queue_status(&self) -> Line<'static> {
+        let mut status = Vec::new();
+        if !self.queue.is_empty() {
+            if let Some(left) = self.queue_seconds_left() {
+                status.push(format!("queue {}", format_remaining(left)));
+            }
+        }
+        let selected = self
+            .state
+            .selected()
+            .and_then(|index| self.seconds_until(index));
+        if let Some(until) = selected {
+            status.push(format!("selected in {}", format_remaining(until)));
+        }
+        match status.is_empty() {
+            true => Line::default(),
+            false => Line::from(format!(" {} ", status.join(" - "))).right_aligned(),
+        }
+    }
+
     async fn play(&mut self) -> Result<()> {
-        self.stop().await?;
-        if let Some(song) = self.songs.get(self.playing_track_list_index) {
-            let current_track_info = self.player.play(song.clone()).await?;
+        self.bookmark();
+        let song = self.playable(self.playing_track_list_index);
+        // The playing track keeps going under the next one while they crossfade
+        if !song
+            .as_ref()
+            .is_some_and(|song| self.player.crossfades_into(song))
+        {
+            self.stop().await?;
+        }
+        self.album_notice = None;
+        if let Some(song) = song {
+            let current_track_info = match self.bookmarked(&song) {
+                Some(position) => self.player.resume(song.clone(), position).await?,
+                None => self.player.play(song.clone()).await?,
+            };
             self.playing_track = Some(current_track_info);
+            self.history.push(self.playing_track_list_index);
+            self.load_lyrics();
+            if let Err(err) = self.library.add_play(Path::new(&song.path)) {
+                warn!("Cannot count the play of {}: {}", song.path, err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remembers where the playing track was left when it is long enough, forgets it once
+    /// played through.
+    fn bookmark(&self) {
+        let Some((_, song, info)) = self.playing() else {
+            return;
+        };
+        if !resumable(song) {
+            return;
+        }
+        let elapsed = info.elapsed_seconds();
+        let position = (elapsed > BOOKMARK_MARGIN
+            && elapsed < song.duration_seconds() - BOOKMARK_MARGIN)
+            .then_some(elapsed);
+        if let Err(err) = self.library.set_bookmark(Path::new(&song.path), position) {
+            warn!("Cannot bookmark {}: {}", song.path, err);
+        }
+    }
+
+    /// Where a track was left, by this app or a remote client.
+    fn bookmarked(&self, song: &MusicTrack) -> Option<f64> {
+        if !resumable(song) {
+            return None;
+        }
+        self.library
+            .bookmark(Path::new(&song.path))
+            .inspect_err(|err| warn!("Cannot read the bookmark of {}: {}", song.path, err))
+            .ok()?
+    }
+
+    /// Writes the queue in play order to `rhap-queue.m3u8` in the export root.
+    fn export_queue(&self) -> Result<()> {
+        let root = self
+            .player
+            .config()
+            .export
+            .root
+            .clone()
+            .unwrap_or_else(|| self.root.clone());
+        let tracks: Vec<Arc<MusicTrack>> = self
+            .queue
+            .iter()
+            .filter_map(|(index, _)| self.songs.get(index).cloned())
+            .collect();
+        write_m3u(&root.join("rhap-queue.m3u8"), &root, &tracks)
+    }
+
+    /// Notes where the playing track sits in its album, when `previous` was from the same
+    /// album.
+    fn notify_album_position(&mut self, previous: Option<&MusicTrack>) {
+        let Some((index, song, _)) = self.playing() else {
+            return;
+        };
+        if previous
+            .is_none_or(|previous| previous.album != song.album || previous.artist != song.artist)
+        {
+            return;
+        }
+        if let Some((position, count)) = album_position(&self.songs, index) {
+            let notice = format!(" Track {}/{} - {} ", position, count, song.album);
+            self.album_notice = Some((notice, Instant::now()));
+        }
+    }
+
+    pub fn player(&self) -> &Player {
+        &self.player
+    }
+
+    /// Takes a reloaded config, the badges at once and the audio settings from the next track.
+    pub fn set_config(&mut self, config: Config) {
+        self.badge_colors = BadgeColors::new(&config.badges.colors);
+        self.badges_column = config.badges.column;
+        self.player.set_config(config);
+    }
+
+    /// Turns crossfading off for the album of the selected track, or back on.
+    fn toggle_strict_gapless(&mut self) -> Result<()> {
+        let Some(song) = self.selected_song().cloned() else {
+            return Ok(());
+        };
+        let strict = !self.player.is_strict_gapless(&song.artist, &song.album);
+        self.library
+            .set_strict_gapless(&song.artist, &song.album, strict)?;
+        self.player
+            .set_strict_gapless(&song.artist, &song.album, strict);
+        let notice = match strict {
+            true => format!(" {} - strict gapless ", song.album),
+            false => format!(" {} - crossfaded ", song.album),
+        };
+        self.album_notice = Some((notice, Instant::now()));
+        Ok(())
+    }
+
+    /// Plays the selected track on the preview device, or stops it when already previewed.
+    /// Failures are told without stopping the playlist.
+    async fn toggle_preview(&mut self) {
+        let notice = self.preview_selected().await.unwrap_or_else(|err| {
+            warn!("Cannot preview: {}", err);
+            format!(" Cannot preview: {} ", err)
+        });
+        self.album_notice = Some((notice, Instant::now()));
+    }
+
+    async fn preview_selected(&mut self) -> Result<String> {
+        // The device is opened again once its settings changed in the config
+        let stale = self
+            .preview
+            .as_ref()
+            .is_some_and(|preview| preview.config() != &self.player.config().preview);
+        if let Some(mut preview) = self.preview.take_if(|_| stale) {
+            preview.stop().await?;
+        }
+        if self.preview.is_none() {
+            self.preview = Preview::new(&self.player)?;
+        }
+        let selected = self.state.selected();
+        let song = selected.and_then(|index| self.playable(index));
+        let Some(preview) = self.preview.as_mut() else {
+            return Ok(" No preview device set ".to_string());
+        };
+        match (selected, song) {
+            (Some(index), Some(song)) if preview.playing() != Some(index) => {
+                preview.play(&self.player, index, song.clone()).await?;
+                Ok(format!(
+                    " Previewing {} on {} ",
+                    song.display_title(),
+                    preview.device()
+                ))
+            }
+            _ => {
+                preview.stop().await?;
+                Ok(" Preview stopped ".to_string())
+            }
+        }
+    }
+
+    pub fn songs(&self) -> &[Arc<MusicTrack>] {
+        &self.songs
+    }
+
+    pub fn selected_song(&self) -> Option<&MusicTrack> {
+        self.songs.get(self.state.selected()?).map(Arc::as_ref)
+    }
+
+    /// How the output device will play `song`, `None` when it plays as is or the device is
+    /// not probed yet.
+    pub fn device_warning(&self, song: &MusicTrack) -> Option<String> {
+        song.device_warning(self.player.device_capabilities()?)
+    }
+
+    /// Format of a track, flagged when the device cannot play it as is.
+    fn format_cell(&self, song: &MusicTrack) -> Cell<'static> {
+        match self.device_warning(song) {
+            Some(_) => Cell::from(format!("{} {}", WARNING_ICON, song.info()))
+                .style(Style::default().fg(WARNING_COLOR)),
+            None => Cell::from(song.info()),
+        }
+    }
+
+    /// Titles of the tracks played this session, most recent first.
+    pub fn history(&self) -> Vec<&str> {
+        self.history
+            .iter()
+            .filter_map(|index| self.songs.get(index))
+            .map(|song| song.title.as_str())
+            .collect()
+    }
+
+    pub fn enqueue(&mut self, indexes: Vec<usize>) {
+        for index in indexes {
+            self.queue.add(index);
+        }
+    }
+
+    /// Queues the tracks of an album right after the current track, shuffling the queue keeps
+    /// them together.
+    pub fn queue_album(&mut self, indexes: Vec<usize>) {
+        self.queue.add_block(indexes);
+    }
+
+    /// Plays the first track now and the others right after it, ahead of the queue.
+    pub async fn play_tracks(&mut self, indexes: Vec<usize>) -> Result<()> {
+        let Some((first, others)) = indexes.split_first() else {
+            return Ok(());
+        };
+        for index in others.iter().rev() {
+            self.queue.play_next(*index);
+        }
+        self.auto_dj = false;
+        self.playing_track_list_index = *first;
+        self.play().await
+    }
+
+    /// Whether the end of the playlist asked the app to quit.
+    pub fn is_over(&self) -> bool {
+        self.over
+    }
+
+    /// Once the last track played with repeat off, as set in the config.
+    async fn end_of_queue(&mut self) -> Result<()> {
+        match self.player.config().queue.end {
+            EndOfQueue::Stop => self.next().await,
+            EndOfQueue::Repeat => self.next_from(0).await,
+            EndOfQueue::AutoDj => {
+                self.auto_dj = true;
+                self.queue_auto_dj();
+                self.next().await
+            }
+            EndOfQueue::Quit => {
+                self.over = true;
+                self.stop().await
+            }
+        }
+    }
+
+    /// Queues a track of the artist playing or of a similar one, as the providers told.
+    fn queue_auto_dj(&mut self) {
+        let last = self
+            .songs
+            .get(self.playing_track_list_index)
+            .filter(|_| self.playing_track.is_some())
+            .cloned();
+        let similar = last
+            .as_ref()
+            .and_then(|song| self.library.artist_info(&song.artist).ok().flatten())
+            .map(|info| info.similar)
+            .unwrap_or_default();
+        let picked = autodj::pick(
+            &self.songs,
+            last.as_deref(),
+            &similar,
+            self.history.iter(),
+            &mut thread_rng(),
+        );
+        if let Some(index) = picked {
+            self.queue.add(index);
+        }
+    }
+
+    /// Moves the playing track to `position` seconds, nothing happens while stopped.
+    pub async fn seek(&mut self, position: f64) -> Result<()> {
+        if self.playing_track.is_none() {
+            return Ok(());
+        }
+        if let Some(song) = self.playable(self.playing_track_list_index) {
+            self.playing_track = Some(self.player.seek(song, position).await?);
         }
         Ok(())
     }
 
+    /// Moves playback to another device, the playing track goes on from where it was.
+    pub async fn set_device(&mut self, device_id: Option<u32>) -> Result<()> {
+        if !self.player.set_device(device_id) {
+            return Ok(());
+        }
+        let Some((index, _, info)) = self.playing() else {
+            return Ok(());
+        };
+        let position = info.elapsed_seconds().max(0.0);
+        let Some(song) = self.playable(index) else {
+            return Ok(());
+        };
+        let paused = self.player.is_paused();
+        // DSD tracks and radios cannot be seeked, they start over
+        let info = if song.dsd_rate.is_none() && !song.is_stream() {
+            self.player.seek(song, position).await?
+        } else {
+            self.player.stop().await?;
+            self.player.play(song).await?
+        };
+        self.playing_track = Some(info);
+        if paused {
+            self.player.pause()?;
+        }
+        Ok(())
+    }
+
+    /// The playing track with its index and progress.
+    pub fn playing(&self) -> Option<(usize, &MusicTrack, &CurrentTrackInfo)> {
+        let track = self.playing_track.as_ref()?;
+        let song = self.songs.get(self.playing_track_list_index)?;
+        Some((self.playing_track_list_index, song, track))
+    }
+
+    /// The player as of now, for everything drawn or reported of the playing track.
+    pub fn snapshot(&self) -> PlayerSnapshot {
+        let playing = self.playing_track.as_ref().and_then(|track| {
+            let song = self.songs.get(self.playing_track_list_index)?;
+            Some((track, song))
+        });
+        let (elapsed, duration) = playing.map_or((0.0, 0.0), |(track, _)| {
+            (track.elapsed_seconds(), track.duration_seconds())
+        });
+        PlayerSnapshot {
+            playback: self.playback(),
+            track: playing.map(|(_, song)| (self.playing_track_list_index, song.clone())),
+            elapsed,
+            duration,
+            output: self.player.output(),
+            resampler: self.player.resampler(),
+            volume: self.player.volume(),
+        }
+    }
+
+    /// Whether the spectrum or the meters move on their own, redrawn at the frame rate cap.
+    pub fn is_animated(&self) -> bool {
+        self.player.is_spectrum_enabled() || (self.show_meters && self.playing_track.is_some())
+    }
+
+    /// Repeat and consume modes, as reported to MPD clients.
+    pub fn modes(&self) -> Modes {
+        Modes {
+            repeat: self.repeat != RepeatMode::Off,
+            single: self.repeat == RepeatMode::One,
+            consume: self.consume,
+        }
+    }
+
+    pub fn playback(&self) -> Playback {
+        match self.playing_track {
+            None => Playback::Stopped,
+            Some(_) if self.player.is_paused() => Playback::Paused,
+            Some(_) => Playback::Playing,
+        }
+    }
+
     pub async fn stop(&mut self) -> Result<()> {
         self.playing_track = None;
+        self.lyrics = None;
         self.player.stop().await
     }
 
@@ -129,111 +1363,414 @@ impl Playlist {
         self.player.pause()
     }
 
-    pub async fn event_hanlder(&mut self, key: KeyEvent) -> Result<()> {
-        if key.kind == KeyEventKind::Press {
-            match key.code {
-                KeyCode::Up | KeyCode::Char('k') => self.select_previous(),
-                KeyCode::Down | KeyCode::Char('j') => self.select_next(),
-                KeyCode::Enter => {
-                    if let Some(index) = self.state.selected() {
-                        self.playing_track_list_index = index;
-                    } else {
-                        self.playing_track_list_index = 0;
-                    }
-                    self.play().await?;
-                },
-                KeyCode::Char('s') => {
-                    self.stop().await?;
-                },
-                KeyCode::Media(MediaKeyCode::Stop) => {
-                    self.next().await?;
-                },
-                KeyCode::Char('n') => {
-                    self.next().await?;
-                },
-                KeyCode::Media(MediaKeyCode::TrackNext) => {
-                    self.next().await?;
-                },
-                KeyCode::Char('p') => {
-                    self.previous().await?;
-                },
-                KeyCode::Media(MediaKeyCode::TrackPrevious) => {
-                    self.next().await?;
-                },
-                KeyCode::Char(' ') => {
-                    self.pause().await?;
-                },
-                KeyCode::Media(MediaKeyCode::Pause) => {
-                    self.next().await?;
-                },
-                _ => (),
+    pub async fn event_hanlder(&mut self, event: KeyboardEvent) -> Result<()> {
+        match event {
+            KeyboardEvent::SelectPrevious => self.select_previous(),
+            KeyboardEvent::SelectNext => self.select_next(),
+            KeyboardEvent::SelectPlaying => self.select_playing(),
+            KeyboardEvent::Play => {
+                self.auto_dj = false;
+                if let Some(index) = self.state.selected() {
+                    self.playing_track_list_index = index;
+                } else {
+                    self.playing_track_list_index = 0;
+                }
+                self.play().await?;
+            }
+            KeyboardEvent::Stop => {
+                self.auto_dj = false;
+                self.bookmark();
+                self.stop().await?;
+            }
+            KeyboardEvent::Next => {
+                self.next().await?;
+            }
+            KeyboardEvent::Previous => {
+                self.previous().await?;
+            }
+            KeyboardEvent::NextAlbum => {
+                self.next_album().await?;
+            }
+            KeyboardEvent::PreviousAlbum => {
+                self.previous_album().await?;
+            }
+            KeyboardEvent::Pause => {
+                self.bookmark();
+                self.pause().await?;
+            }
+            KeyboardEvent::Karaoke => {
+                self.player.toggle_karaoke();
+            }
+            KeyboardEvent::Spectrum => {
+                self.player.toggle_spectrum();
+            }
+            KeyboardEvent::Meters => {
+                self.show_meters = !self.show_meters;
+            }
+            KeyboardEvent::Lyrics => {
+                self.show_lyrics = !self.show_lyrics;
+                self.load_lyrics();
+            }
+            KeyboardEvent::VolumeUp => {
+                self.player.change_volume(1);
+            }
+            KeyboardEvent::VolumeDown => {
+                self.player.change_volume(-1);
+            }
+            KeyboardEvent::Loudness => {
+                self.player.toggle_loudness();
+            }
+            KeyboardEvent::Enqueue => {
+                if let Some(index) = self.state.selected() {
+                    self.queue.add(index);
+                }
+            }
+            KeyboardEvent::PlayNext => {
+                if let Some(index) = self.state.selected() {
+                    self.queue.play_next(index);
+                }
+            }
+            KeyboardEvent::Prioritize => {
+                if let Some(index) = self.state.selected() {
+                    self.queue.prioritize(index);
+                }
+            }
+            KeyboardEvent::Repeat => {
+                self.repeat = self.repeat.cycle();
             }
+            KeyboardEvent::Consume => {
+                self.consume = !self.consume;
+            }
+            KeyboardEvent::ExportQueue => {
+                if let Err(err) = self.export_queue() {
+                    warn!("Cannot export the queue: {}", err);
+                }
+            }
+            KeyboardEvent::ShuffleQueue => {
+                self.queue.shuffle(&mut thread_rng());
+            }
+            KeyboardEvent::StrictGapless => {
+                self.toggle_strict_gapless()?;
+            }
+            KeyboardEvent::Preview => {
+                self.toggle_preview().await;
+            }
+            KeyboardEvent::DeleteTrack => {
+                self.delete_selected().await?;
+            }
+            KeyboardEvent::UndoDelete => self.undo_delete(),
+            KeyboardEvent::MoveUp => self.move_selected(true),
+            KeyboardEvent::MoveDown => self.move_selected(false),
+            // The app quits right after
+            KeyboardEvent::Quit => {
+                self.bookmark();
+            }
+            // Handled by the app
+            KeyboardEvent::Library
+            | KeyboardEvent::OutputSelector
+            | KeyboardEvent::Debug
+            | KeyboardEvent::Tasks
+            | KeyboardEvent::History
+            | KeyboardEvent::TrackInfo
+            | KeyboardEvent::AlbumInfo
+            | KeyboardEvent::ArtistInfo
+            | KeyboardEvent::Alarm
+            | KeyboardEvent::InsertFiles
+            | KeyboardEvent::Search => (),
         }
         Ok(())
     }
 
     pub async fn run(&mut self) -> Result<()> {
+        self.load_pending();
+        self.apply_changes();
+        // Before the track is taken for ended, its stream dies with the device
+        self.follow_removed_device().await?;
+        self.follow_alarm().await?;
+        // Picked ahead, the pick is prefetched and crossfaded into like any queued track
+        if self.auto_dj && self.queue.is_empty() && self.playing_track.is_some() {
+            self.queue_auto_dj();
+        }
         if let Some(current_track) = self.playing_track.clone() {
-            if !current_track.is_streaming() && self.automatically_play_next {
-                self.next().await?;
+            let progress = current_track.progress();
+            let duration = current_track.duration_seconds();
+            let elapsed = current_track.elapsed_seconds_at(&progress);
+            if duration > 0.0 && elapsed >= duration * PREFETCH_AT {
+                if let Some(song) = self.upcoming().and_then(|index| self.playable(index)) {
+                    self.player.prefetch(song);
+                }
+            }
+            let crossfade = || {
+                progress.fading_out
+                    && self
+                        .upcoming()
+                        .and_then(|index| self.playable(index))
+                        .is_some_and(|song| self.player.crossfades_into(&song))
+            };
+            if self.automatically_play_next && (!progress.streaming || crossfade()) {
+                match self.repeat {
+                    RepeatMode::One => self.play().await?,
+                    RepeatMode::Off | RepeatMode::All => {
+                        let previous = self.songs.get(self.playing_track_list_index).cloned();
+                        match self.upcoming() {
+                            Some(_) => self.next().await?,
+                            None => self.end_of_queue().await?,
+                        }
+                        self.notify_album_position(previous.as_deref());
+                    }
+                }
             }
         }
         Ok(())
     }
 
+    /// Links the rows shown in the bordered `area` of the table to the folder of their track,
+    /// in the terminals which open them.
+    fn link_rows(&self, frame: &mut Frame, area: Rect) {
+        if !hyperlinks_enabled(self.player.config().ui.hyperlinks) {
+            return;
+        }
+        let rows = area.inner(Margin::new(1, 1));
+        let urls = self
+            .songs
+            .iter()
+            .skip(self.state.offset())
+            .take(rows.height as usize)
+            .map(|song| track_url(song))
+            .collect();
+        frame.render_widget(Hyperlinks::new(urls), rows);
+    }
+
+    /// Progress of the loader on the top border, right of the title `title` columns wide.
+    fn render_loading(&self, frame: &mut Frame, area: Rect, title: usize) {
+        let Some(loader) = &self.loader else {
+            return;
+        };
+        // A column apart from the title, the corners left alone
+        let left = area.left() + 2 + title as u16;
+        let width = area.right().saturating_sub(left + 1).min(LOADING_WIDTH);
+        if width < LOADING_WIDTH / 2 {
+            return;
+        }
+        let gauge = match loader.progress() {
+            (done, Some(total)) => LineGauge::default()
+                .ratio(if total > 0 {
+                    done as f64 / total as f64
+                } else {
+                    1.0
+                })
+                .label(format!("loading {}/{}", done, total)),
+            (_, None) => LineGauge::default().label("listing files"),
+        };
+        let gauge = gauge
+            .filled_style(Style::default().fg(highlight_color()))
+            .unfilled_style(Style::default().fg(ROW_COLOR))
+            .line_set(symbols::line::THICK);
+        let area = Rect::new(area.right() - 1 - width, area.top(), width, 1);
+        frame.render_widget(Clear, area);
+        frame.render_widget(gauge, area);
+    }
+
     pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        // One snapshot for the whole frame, the progress and the lyrics stay in step
+        let snapshot = self.snapshot();
+        let elapsed = snapshot.track.as_ref().map(|_| snapshot.elapsed);
+        // The spectrum runs along the bottom, under the playlist and queue
+        let area = if self.player.is_spectrum_enabled() {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(SPECTRUM_HEIGHT)])
+                .split(area);
+            let (samples, sample_rate) = self.player.tapped_samples();
+            let bars = self
+                .spectrum
+                .update(&samples, sample_rate, rows[1].width as usize);
+            frame.render_widget(Clear, rows[1]);
+            frame.render_widget(Spectrum::new(bars), rows[1]);
+            rows[0]
+        } else {
+            area
+        };
+        // Meters are shown while a track plays, one row per channel
+        let (peaks, rms) = self.player.levels();
+        let area = if self.show_meters && self.playing_track.is_some() && !peaks.is_empty() {
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Min(0), Constraint::Length(peaks.len() as u16)])
+                .split(area);
+            frame.render_widget(Clear, rows[1]);
+            frame.render_widget(LevelMeter::new(&peaks).rms(&rms), rows[1]);
+            rows[0]
+        } else {
+            area
+        };
+        let found: HashMap<*const MusicTrack, &Found> = self
+            .search
+            .iter()
+            .flat_map(|search| &search.found)
+            .map(|found| (Arc::as_ptr(&found.song), found))
+            .collect();
         let mut items = Vec::new();
         for index in 0..self.songs.len() {
             if let Some(song) = self.songs.get(index) {
-                let row = Row::new(vec![
+                let (title, artist) = match found.get(&Arc::as_ptr(song)) {
+                    Some(found) => (found.title.as_slice(), found.artist.as_slice()),
+                    None => (&[][..], &[][..]),
+                };
+                let title = highlighted(song.display_title(), title);
+                let artist = highlighted(song.artist.clone(), artist);
+                let mut cells = vec![
                     Cell::from(if self.playing_track_list_index == index {
                         "󰐊"
                     } else {
                         "  "
                     }),
-                    Cell::from(song.title.clone()).style(Style::default().bg(
-                        if items.len() % 2 == 0 {
-                            ROW_COLOR_COL
-                        } else {
-                            ROW_ALTERNATE_COLOR_COL
-                        },
-                    )),
-                    Cell::from(song.artist.clone()),
-                    Cell::from(song.info()).style(Style::default().bg(if items.len() % 2 == 0 {
+                    Cell::from(title).style(Style::default().bg(if items.len() % 2 == 0 {
                         ROW_COLOR_COL
                     } else {
                         ROW_ALTERNATE_COLOR_COL
                     })),
-                    Cell::from(song.formated_duration()),
-                ])
-                .height(1)
-                .style(Style::default().bg(if items.len() % 2 == 0 {
-                    ROW_COLOR
-                } else {
-                    ROW_ALTERNATE_COLOR
-                }));
+                    Cell::from(artist),
+                    self.format_cell(song)
+                        .style(Style::default().bg(if items.len() % 2 == 0 {
+                            ROW_COLOR_COL
+                        } else {
+                            ROW_ALTERNATE_COLOR_COL
+                        })),
+                    Cell::from(match elapsed {
+                        Some(elapsed) if self.playing_track_list_index == index => {
+                            format!("{} / {}", format_elapsed(elapsed), song.formated_duration())
+                        }
+                        _ => song.formated_duration(),
+                    }),
+                ];
+                if self.badges_column {
+                    let badges = Badges::new(song.badges(), &self.badge_colors);
+                    cells.insert(4, Cell::from(badges.line()));
+                }
+                let row =
+                    Row::new(cells)
+                        .height(1)
+                        .style(Style::default().bg(if items.len() % 2 == 0 {
+                            ROW_COLOR
+                        } else {
+                            ROW_ALTERNATE_COLOR
+                        }));
                 items.push(row);
             }
         }
-        let table = Table::new(items, &[
-                Constraint::Length(1),
-                Constraint::Percentage(20),
-                Constraint::Percentage(60),
-                Constraint::Percentage(10),
-                Constraint::Percentage(10),
-            ])
-            .row_highlight_style(Style::default().fg(HIGHLIGHT_COLOR))
+        let mut widths = vec![
+            Constraint::Length(1),
+            Constraint::Percentage(20),
+            Constraint::Percentage(60),
+            Constraint::Percentage(10),
+            Constraint::Percentage(10),
+        ];
+        if self.badges_column {
+            // Wide enough for a container and HI-RES
+            widths.insert(4, Constraint::Length(15));
+        }
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(70), Constraint::Percentage(30)])
+            .split(area);
+        let side_column = !self.queue.is_empty() || self.show_lyrics;
+        let table_area = if side_column { panes[0] } else { area };
+        let album_notice = match &self.album_notice {
+            Some((notice, shown)) if shown.elapsed() < ALBUM_NOTICE_DURATION => {
+                Line::from(notice.as_str()).right_aligned()
+            }
+            _ => Line::default(),
+        };
+        let queue_status = self.queue_status();
+        // The playing track along the bottom border, with its badges, scrolling through what
+        // the notices on the right leave
+        let mut now_playing = NowPlaying::new(&snapshot, &self.badge_colors);
+        let elapsed_ms = (snapshot.elapsed.max(0.0) * 1000.0) as u64;
+        if let Some(offset) = elapsed_ms.checked_div(self.player.config().ui.marquee_ms) {
+            let width = (table_area.width as usize)
+                .saturating_sub(2 + album_notice.width() + queue_status.width());
+            now_playing = now_playing.scroll(width, offset as usize);
+        }
+        let now_playing = now_playing.line();
+        let title = format!(
+            "Playlist - {}{}{}{}{}{}",
+            self.songs.len(),
+            match self.repeat {
+                RepeatMode::Off => "",
+                RepeatMode::One => " - repeat one",
+                RepeatMode::All => " - repeat all",
+            },
+            if self.consume { " - consume" } else { "" },
+            if self.player.volume() < 0 {
+                format!(" - {}dB", self.player.volume())
+            } else {
+                String::new()
+            },
+            if self.player.is_loudness_enabled() {
+                " - loudness"
+            } else {
+                ""
+            },
+            if self.player.is_karaoke_enabled() {
+                " - karaoke"
+            } else {
+                ""
+            },
+        );
+        let title = Line::from(title);
+        let title_width = title.width();
+        let table = Table::new(items, widths)
+            .row_highlight_style(Style::default().fg(highlight_color()))
             .block(
                 Block::default()
-                    .title(format!("Playlist - {}", self.songs.len()))
+                    .title_bottom(now_playing)
+                    .title_bottom(album_notice)
+                    .title_bottom(queue_status)
+                    .title(title)
                     .title_alignment(Alignment::Left)
                     .borders(Borders::ALL)
                     .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(HIGHLIGHT_COLOR)),
+                    .border_style(Style::default().fg(highlight_color())),
             );
 
+        // Less the top and bottom borders
+        self.rows_shown = area.height.saturating_sub(2) as usize;
         frame.render_widget(Clear, area);
-        frame.render_stateful_widget(table, area, &mut self.state);
+        frame.render_stateful_widget(table, table_area, &mut self.state);
+        self.link_rows(frame, table_area);
+        self.render_loading(frame, table_area, title_width);
+        if !side_column {
+            return Ok(());
+        }
+        // The queue and the lyrics share the side column
+        let side = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(match (self.queue.is_empty(), self.show_lyrics) {
+                (false, true) => vec![Constraint::Percentage(40), Constraint::Percentage(60)],
+                (false, false) => vec![Constraint::Percentage(100), Constraint::Length(0)],
+                (true, _) => vec![Constraint::Length(0), Constraint::Percentage(100)],
+            })
+            .split(panes[1]);
+        if !self.queue.is_empty() {
+            let titles = self
+                .queue
+                .iter()
+                .filter_map(|(index, priority)| {
+                    self.songs
+                        .get(index)
+                        .map(|song| (song.title.as_str(), priority))
+                })
+                .collect();
+            frame.render_widget(QueuePane::new(titles), side[0]);
+        }
+        if self.show_lyrics {
+            let (lines, current) = match (&self.lyrics, elapsed) {
+                (Some(lyrics), Some(elapsed)) => (lyrics.lines(), lyrics.current(elapsed)),
+                _ => (Vec::new(), None),
+            };
+            frame.render_widget(LyricsPane::new(lines, current), side[1]);
+        }
         Ok(())
     }
 }
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::SetTitle;
+use crossterm::ExecutableCommand;
+use log::error;
+use ratatui::{
+    prelude::{Alignment, Constraint, Direction, Layout, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    recorder::Recorder,
+    ui::{highlight_color, utils::is_interrupt, widgets::LevelMeter},
+};
+
+pub struct RecorderScreen {
+    recorder: Recorder,
+    path: PathBuf,
+}
+
+impl RecorderScreen {
+    pub fn new(recorder: Recorder, path: PathBuf) -> Self {
+        Self { recorder, path }
+    }
+
+    pub async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        shutdown: &AtomicBool,
+    ) -> Result<()> {
+        terminal
+            .backend_mut()
+            .execute(SetTitle("rhap - Recording"))?;
+        self.recorder.start(&self.path)?;
+        while !shutdown.load(Ordering::Relaxed) {
+            terminal.draw(|frame| {
+                if let Err(err) = self.render(frame, frame.area()) {
+                    error!("error while drawing {}", err);
+                }
+            })?;
+
+            if event::poll(std::time::Duration::from_millis(40))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press
+                        && (key.code == KeyCode::Char('q') || is_interrupt(&key))
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+        self.recorder.stop().await
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let levels = self.recorder.levels();
+        let rms = self.recorder.rms();
+        let elapsed = self.recorder.elapsed_seconds();
+        let block = Block::default()
+            .title(format!("Recording - {}", self.path.display()))
+            .title_alignment(Alignment::Left)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(highlight_color()));
+        let inner = block.inner(area);
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(3),
+                Constraint::Length(levels.len() as u16),
+                Constraint::Min(0),
+            ])
+            .split(inner);
+
+        let info = Paragraph::new(vec![
+            Line::from(self.recorder.device_name()?),
+            Line::from(self.recorder.info()),
+            Line::from(format!(
+                "{:0>2}:{:0>2}:{:0>2}",
+                elapsed / 3600,
+                (elapsed % 3600) / 60,
+                elapsed % 60
+            )),
+        ]);
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+        frame.render_widget(info, layout[0]);
+        frame.render_widget(LevelMeter::new(&levels).rms(&rms), layout[1]);
+        frame.render_widget(Paragraph::new("Press q to stop recording"), layout[2]);
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::terminal::SetTitle;
+use crossterm::ExecutableCommand;
+use log::error;
+use ratatui::{
+    prelude::{Alignment, Constraint, Direction, Layout, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Gauge, Paragraph},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    sync::DeviceSync,
+    ui::{highlight_color, utils::is_interrupt},
+};
+
+pub struct SyncScreen {
+    sync: DeviceSync,
+}
+
+impl SyncScreen {
+    pub fn new(sync: DeviceSync) -> Self {
+        Self { sync }
+    }
+
+    pub async fn run(
+        &mut self,
+        terminal: &mut DefaultTerminal,
+        shutdown: &AtomicBool,
+    ) -> Result<()> {
+        terminal.backend_mut().execute(SetTitle("rhap - Sync"))?;
+        self.sync.start();
+        while !self.sync.is_finished() {
+            if shutdown.load(Ordering::Relaxed) {
+                self.sync.cancel();
+            }
+            terminal.draw(|frame| {
+                if let Err(err) = self.render(frame, frame.area()) {
+                    error!("error while drawing {}", err);
+                }
+            })?;
+
+            if event::poll(std::time::Duration::from_millis(40))? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press
+                        && (key.code == KeyCode::Char('q') || is_interrupt(&key))
+                    {
+                        self.sync.cancel();
+                    }
+                }
+            }
+        }
+        self.sync.wait().await
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let (done, total) = self.sync.progress();
+        let block = Block::default()
+            .title(format!("Sync - {}", self.sync.destination().display()))
+            .title_alignment(Alignment::Left)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(highlight_color()));
+        let inner = block.inner(area);
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(2),
+                Constraint::Length(1),
+                Constraint::Min(0),
+            ])
+            .split(inner);
+
+        let info = Paragraph::new(vec![
+            Line::from(self.sync.current()),
+            Line::from(format!("{} failed", self.sync.failed())),
+        ]);
+        let gauge = Gauge::default()
+            .gauge_style(Style::default().fg(highlight_color()))
+            .ratio(if total > 0 {
+                done as f64 / total as f64
+            } else {
+                1.0
+            })
+            .label(format!("{}/{}", done, total));
+
+        frame.render_widget(Clear, area);
+        frame.render_widget(block, area);
+        frame.render_widget(info, layout[0]);
+        frame.render_widget(gauge, layout[1]);
+        frame.render_widget(
+            Paragraph::new("Press q to stop after the current file"),
+            layout[2],
+        );
+        Ok(())
+    }
+}
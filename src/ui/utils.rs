@@ -1,5 +1,11 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use ratatui::prelude::{Layout, Direction, Constraint, Rect};
 
+/// Raw mode delivers Ctrl+C as a key press instead of a signal.
+pub fn is_interrupt(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL)
+}
+
 //pub fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
 //    let popup_layout = Layout::default()
 //        .direction(Direction::Vertical)
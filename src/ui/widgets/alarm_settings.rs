@@ -0,0 +1,149 @@
+use crate::{
+    alarm::{format_time, AlarmSettings},
+    ui::{highlight_color, ROW_ALTERNATE_COLOR, ROW_COLOR},
+};
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{
+    prelude::{Alignment, Constraint, Rect},
+    style::Style,
+    widgets::{Block, BorderType, Borders, Cell, Clear, Row, Table, TableState},
+    Frame,
+};
+
+/// Minutes the start and stop times move by.
+const TIME_STEP: i16 = 5;
+/// Seconds the fade in changes by.
+const FADE_STEP: u32 = 10;
+
+const FIELDS: usize = 5;
+
+/// Settings of the wake-up alarm, each change applied right away.
+pub struct AlarmSettingsPane {
+    settings: AlarmSettings,
+    /// Saved playlists the alarm can play
+    playlists: Vec<String>,
+    state: TableState,
+}
+
+impl AlarmSettingsPane {
+    pub fn new(settings: AlarmSettings, playlists: Vec<String>) -> Self {
+        Self {
+            settings,
+            playlists,
+            state: TableState::default().with_selected(Some(0)),
+        }
+    }
+
+    /// Next saved playlist, or previous one, the first one when none was set.
+    fn cycle_playlist(&mut self, forward: bool) {
+        let count = self.playlists.len();
+        if count == 0 {
+            return;
+        }
+        let current = self
+            .settings
+            .playlist
+            .as_ref()
+            .and_then(|name| self.playlists.iter().position(|other| other == name));
+        let next = match (current, forward) {
+            (None, _) => 0,
+            (Some(current), true) => (current + 1) % count,
+            (Some(current), false) => (current + count - 1) % count,
+        };
+        self.settings.playlist = Some(self.playlists[next].clone());
+    }
+
+    /// Moves the selected setting up or down.
+    fn change(&mut self, up: bool) {
+        let step = if up { TIME_STEP } else { -TIME_STEP };
+        match self.state.selected().unwrap_or(0) {
+            0 => self.settings.enabled = !self.settings.enabled,
+            1 => self.settings.shift_start(step),
+            2 => self.settings.shift_stop(step),
+            3 => self.cycle_playlist(up),
+            _ => {
+                self.settings.fade_in_seconds = match up {
+                    true => self.settings.fade_in_seconds + FADE_STEP,
+                    false => self.settings.fade_in_seconds.saturating_sub(FADE_STEP),
+                }
+            }
+        }
+    }
+
+    /// Returns the settings once changed.
+    pub fn event_handler(&mut self, key: KeyEvent) -> Option<AlarmSettings> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        let selected = self.state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.state.select(Some((selected + FIELDS - 1) % FIELDS));
+                return None;
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.state.select(Some((selected + 1) % FIELDS));
+                return None;
+            }
+            KeyCode::Right | KeyCode::Char('l') | KeyCode::Char('+') => self.change(true),
+            KeyCode::Left | KeyCode::Char('h') | KeyCode::Char('-') => self.change(false),
+            KeyCode::Enter | KeyCode::Char(' ') => {
+                self.settings.enabled = !self.settings.enabled;
+            }
+            // Playback is then left running
+            KeyCode::Backspace | KeyCode::Delete if selected == 2 => self.settings.stop = None,
+            _ => return None,
+        }
+        Some(self.settings.clone())
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let settings = &self.settings;
+        let fields = [
+            (
+                "Alarm",
+                String::from(if settings.enabled { "on" } else { "off" }),
+            ),
+            ("Start", format_time(settings.start)),
+            (
+                "Stop",
+                settings.stop.map_or(String::from("never"), format_time),
+            ),
+            (
+                "Playlist",
+                settings
+                    .playlist
+                    .clone()
+                    .unwrap_or_else(|| String::from("none")),
+            ),
+            ("Fade in", format!("{}s", settings.fade_in_seconds)),
+        ];
+        let rows = fields
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, value))| {
+                Row::new(vec![Cell::from(name), Cell::from(value)]).style(Style::default().bg(
+                    if index % 2 == 0 {
+                        ROW_COLOR
+                    } else {
+                        ROW_ALTERNATE_COLOR
+                    },
+                ))
+            });
+        let table = Table::new(rows, &[Constraint::Length(10), Constraint::Fill(1)])
+            .highlight_symbol("=>")
+            .row_highlight_style(Style::default().fg(highlight_color()))
+            .block(
+                Block::default()
+                    .title("Alarm - left/right to change")
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(highlight_color())),
+            );
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(table, area, &mut self.state);
+        Ok(())
+    }
+}
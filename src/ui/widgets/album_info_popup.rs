@@ -0,0 +1,83 @@
+use crate::metadata::AlbumLookup;
+use crate::ui::highlight_color;
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget, Wrap},
+};
+
+/// Year, genre and artist biography of an album, as found by the metadata providers, with
+/// whether it is crossfaded.
+pub struct AlbumInfoPopup<'a> {
+    lookup: &'a AlbumLookup,
+    enabled: bool,
+    strict_gapless: bool,
+}
+
+impl<'a> AlbumInfoPopup<'a> {
+    pub fn new(lookup: &'a AlbumLookup, enabled: bool, strict_gapless: bool) -> Self {
+        Self {
+            lookup,
+            enabled,
+            strict_gapless,
+        }
+    }
+}
+
+impl Widget for AlbumInfoPopup<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        let mut lines = vec![Line::from(format!(
+            "{} - {}",
+            self.lookup.artist, self.lookup.album
+        ))];
+        lines.push(Line::from(match self.strict_gapless {
+            true => "Strict gapless, never crossfaded - G to change",
+            false => "Crossfaded with other albums - G for strict gapless",
+        }));
+        let title = match self.lookup.found() {
+            _ if !self.enabled => {
+                lines.push(Line::from(
+                    "No metadata provider, see [metadata] in the config",
+                ));
+                String::from("Album info")
+            }
+            None => {
+                lines.push(Line::from("Looking up..."));
+                String::from("Album info")
+            }
+            Some(info) if info.is_empty() => {
+                lines.push(Line::from("Nothing found"));
+                String::from("Album info")
+            }
+            Some(info) => {
+                let mut details = Vec::new();
+                if let Some(year) = info.year {
+                    details.push(year.to_string());
+                }
+                if let Some(genre) = &info.genre {
+                    details.push(genre.clone());
+                }
+                lines.push(Line::from(details.join(" - ")));
+                if let Some(bio) = &info.artist_bio {
+                    lines.push(Line::default());
+                    lines.extend(bio.lines().map(|line| Line::from(line.to_string())));
+                }
+                format!("Album info - {}", info.sources.join(", "))
+            }
+        };
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::default()
+                    .title(title)
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(highlight_color())),
+            )
+            .render(area, buf);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{
+    metadata::ArtistLookup,
+    musictrack::MusicTrack,
+    ui::{highlight_color, ROW_ALTERNATE_COLOR, ROW_COLOR},
+};
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{
+    prelude::{Alignment, Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    widgets::{Block, BorderType, Borders, Cell, Clear, Paragraph, Row, Table, TableState, Wrap},
+    Frame,
+};
+
+/// Biography and similar artists of an artist, as found by the metadata providers. The
+/// similar artists found in the library can be opened there.
+pub struct ArtistPane {
+    lookup: ArtistLookup,
+    enabled: bool,
+    /// Artists of the library by their lowercase name, providers do not always agree on case
+    library: HashMap<String, String>,
+    state: TableState,
+}
+
+impl ArtistPane {
+    pub fn new(lookup: ArtistLookup, enabled: bool, songs: &[Arc<MusicTrack>]) -> Self {
+        let library = songs
+            .iter()
+            .map(|song| (song.artist.to_lowercase(), song.artist.clone()))
+            .collect();
+        Self {
+            lookup,
+            enabled,
+            library,
+            state: TableState::default().with_selected(Some(0)),
+        }
+    }
+
+    fn similar(&self) -> &[String] {
+        self.lookup
+            .found()
+            .map(|info| info.similar.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Name of a similar artist as it is in the library, `None` when it is not there.
+    fn in_library(&self, artist: &str) -> Option<&String> {
+        self.library.get(&artist.to_lowercase())
+    }
+
+    fn select(&mut self, down: bool) {
+        let count = self.similar().len();
+        if count == 0 {
+            return;
+        }
+        let selected = self.state.selected().unwrap_or(0).min(count - 1);
+        self.state.select(Some(match down {
+            true => (selected + 1) % count,
+            false => (selected + count - 1) % count,
+        }));
+    }
+
+    /// Returns the library artist to open when a similar artist of the library is chosen.
+    pub fn event_handler(&mut self, key: KeyEvent) -> Option<String> {
+        if key.kind == KeyEventKind::Press {
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => self.select(false),
+                KeyCode::Down | KeyCode::Char('j') => self.select(true),
+                KeyCode::Enter => {
+                    let selected = self.state.selected()?;
+                    let artist = self.similar().get(selected)?;
+                    return self.in_library(artist).cloned();
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        let block = |title: String| {
+            Block::default()
+                .title(title)
+                .title_alignment(Alignment::Left)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(highlight_color()))
+        };
+        let (title, bio) = match self.lookup.found() {
+            _ if !self.enabled => (
+                String::from("Artist"),
+                String::from("No metadata provider, see [metadata] in the config"),
+            ),
+            None => (String::from("Artist"), String::from("Looking up...")),
+            Some(info) if info.is_empty() => {
+                (String::from("Artist"), String::from("Nothing found"))
+            }
+            Some(info) => (
+                format!("Artist - {}", info.sources.join(", ")),
+                info.bio.clone().unwrap_or_default(),
+            ),
+        };
+        let rows: Vec<Row> = self
+            .similar()
+            .iter()
+            .enumerate()
+            .map(|(index, artist)| {
+                // Only the artists of the library can be opened
+                let style = match self.in_library(artist) {
+                    Some(_) => Style::default(),
+                    None => Style::default().fg(Color::DarkGray),
+                };
+                Row::new(vec![Cell::from(artist.clone())]).style(style.bg(if index % 2 == 0 {
+                    ROW_COLOR
+                } else {
+                    ROW_ALTERNATE_COLOR
+                }))
+            })
+            .collect();
+        let similar = Table::new(rows, &[Constraint::Fill(1)])
+            .highlight_symbol("=>")
+            .row_highlight_style(Style::default().fg(highlight_color()))
+            .block(block(String::from(
+                "Similar - enter opens it in the library",
+            )));
+
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(4), Constraint::Length(8)])
+            .split(area);
+        frame.render_widget(Clear, area);
+        frame.render_widget(
+            Paragraph::new(format!("{}\n\n{}", self.lookup.artist, bio))
+                .wrap(Wrap { trim: false })
+                .block(block(title)),
+            layout[0],
+        );
+        frame.render_stateful_widget(similar, layout[1], &mut self.state);
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::musictrack::Badge;
+use log::warn;
+use ratatui::{
+    buffer::Buffer,
+    prelude::Rect,
+    style::{Color, Style},
+    text::{Line, Span},
+    widgets::Widget,
+};
+
+fn default_color(badge: Badge) -> Color {
+    match badge {
+        Badge::Flac => Color::Rgb(70, 130, 180),
+        Badge::Mp3 => Color::Rgb(120, 120, 120),
+        Badge::Ogg => Color::Rgb(150, 110, 180),
+        Badge::M4a => Color::Rgb(200, 90, 90),
+        Badge::Wav | Badge::Aiff => Color::Rgb(80, 150, 110),
+        Badge::Dsd => Color::Rgb(180, 60, 140),
+        Badge::HiRes => Color::Rgb(255, 191, 0),
+    }
+}
+
+/// Background of each badge, the defaults overridden by the `[badges]` colors of the config.
+#[derive(Clone)]
+pub struct BadgeColors {
+    colors: HashMap<Badge, Color>,
+}
+
+impl BadgeColors {
+    /// Unknown badges and invalid colors are reported and keep their default.
+    pub fn new(config: &HashMap<String, String>) -> Self {
+        let mut colors: HashMap<Badge, Color> = Badge::ALL
+            .iter()
+            .map(|badge| (*badge, default_color(*badge)))
+            .collect();
+        for (label, color) in config {
+            let badge = Badge::ALL
+                .into_iter()
+                .find(|badge| badge.label().eq_ignore_ascii_case(label));
+            match (badge, Color::from_str(color)) {
+                (Some(badge), Ok(color)) => {
+                    colors.insert(badge, color);
+                }
+                (None, _) => warn!("Unknown badge {}", label),
+                (_, Err(_)) => warn!("Invalid color for the {} badge: {}", label, color),
+            }
+        }
+        Self { colors }
+    }
+}
+
+/// Format badges of a track, dark text on their color.
+pub struct Badges<'a> {
+    badges: Vec<Badge>,
+    colors: &'a BadgeColors,
+}
+
+impl<'a> Badges<'a> {
+    pub fn new(badges: Vec<Badge>, colors: &'a BadgeColors) -> Self {
+        Self { badges, colors }
+    }
+
+    pub fn line(self) -> Line<'static> {
+        let mut spans = Vec::new();
+        for badge in self.badges {
+            if !spans.is_empty() {
+                spans.push(Span::raw(" "));
+            }
+            let color = self.colors.colors.get(&badge).copied();
+            spans.push(Span::styled(
+                format!(" {} ", badge.label()),
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(color.unwrap_or(default_color(badge))),
+            ));
+        }
+        Line::from(spans)
+    }
+}
+
+impl Widget for Badges<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        self.line().render(area, buf);
+    }
+}
@@ -0,0 +1,47 @@
+use crate::ui::highlight_color;
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget},
+};
+
+pub struct DebugOverlay {
+    decode_cpu: f64,
+    render_cpu: f64,
+    pollmode: bool,
+}
+
+impl DebugOverlay {
+    pub fn new(decode_cpu: f64, render_cpu: f64, pollmode: bool) -> Self {
+        Self {
+            decode_cpu,
+            render_cpu,
+            pollmode,
+        }
+    }
+}
+
+impl Widget for DebugOverlay {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        Paragraph::new(vec![
+            Line::from(format!("Decode CPU {:>6.1}%", self.decode_cpu * 100.0)),
+            Line::from(format!("Render CPU {:>6.1}%", self.render_cpu * 100.0)),
+            Line::from(format!(
+                "Render mode {:>6}",
+                if self.pollmode { "poll" } else { "event" }
+            )),
+        ])
+        .block(
+            Block::default()
+                .title("Debug")
+                .title_alignment(Alignment::Left)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(highlight_color())),
+        )
+        .render(area, buf);
+    }
+}
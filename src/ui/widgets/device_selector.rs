@@ -1,15 +1,53 @@
 use crate::{
-    audio::{Device, DeviceTrait, Host, HostTrait},
-    ui::{HIGHLIGHT_COLOR, ROW_ALTERNATE_COLOR, ROW_COLOR},
+    audio::{probe::CapabilityProbes, Device, DeviceTrait, FormFactor, Host, HostTrait},
+    ui::{highlight_color, ROW_ALTERNATE_COLOR, ROW_COLOR},
 };
 use anyhow::{anyhow, Result};
 use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use log::warn;
 use ratatui::{
-    prelude::{Alignment, Constraint, Rect},
+    prelude::{Alignment, Constraint, Direction, Layout, Rect},
     style::Style,
-    widgets::{Block, Borders, Cell, Clear, Row, Table, TableState},
+    widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Table, TableState},
     Frame,
 };
+use std::time::{Duration, Instant};
+
+const SPINNER: [&str; 8] = ["⣾", "⣽", "⣻", "⢿", "⡿", "⣟", "⣯", "⣷"];
+/// How often the list is checked against the devices of the host while shown.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+fn icon(form_factor: FormFactor) -> &'static str {
+    match form_factor {
+        FormFactor::Speakers => "󰕾",
+        FormFactor::Headphones => "󰋋",
+        FormFactor::Usb => "󰕓",
+        FormFactor::Spdif => "󱎔",
+        FormFactor::Hdmi => "󰔂",
+        FormFactor::LineLevel => "󰚥",
+        FormFactor::Unknown => " ",
+    }
+}
+
+fn group(form_factor: FormFactor) -> &'static str {
+    match form_factor {
+        FormFactor::Speakers => "Speakers",
+        FormFactor::Headphones => "Headphones",
+        FormFactor::Usb => "USB",
+        FormFactor::Spdif => "S/PDIF",
+        FormFactor::Hdmi => "HDMI",
+        FormFactor::LineLevel => "Line out",
+        FormFactor::Unknown => "Other",
+    }
+}
+
+/// A device as listed, read from the system once per refresh.
+struct Listed {
+    /// Index in the list of the host
+    device: usize,
+    form_factor: FormFactor,
+    friendly_name: String,
+}
 
 pub struct DeviceSelector {
     state: TableState,
@@ -17,31 +55,104 @@ pub struct DeviceSelector {
     selected: Option<String>,
     default: Device,
     devices: Vec<Device>,
+    /// Devices grouped by form factor, in the order shown
+    listed: Vec<Listed>,
+    /// Capabilities of the highlighted device, probed in the background
+    probes: CapabilityProbes,
+    /// Frames drawn, turning the spinner while probing
+    ticks: usize,
+    /// When the list was last checked against the host
+    refreshed: Instant,
+    /// Tracks play through the system mixer rather than in exclusive mode
+    shared: bool,
 }
 
 impl DeviceSelector {
-    pub fn new(host: Host) -> Result<DeviceSelector> {
+    /// `selected` is the name of the device playing, the default one for `None`.
+    pub fn new(host: Host, selected: Option<String>, shared: bool) -> Result<DeviceSelector> {
         let mut state = TableState::default();
         state.select(Some(0));
 
         Ok(DeviceSelector {
             state,
             host,
-            selected: None,
+            selected,
             default: Device::None,
             devices: Vec::new(),
+            listed: Vec::new(),
+            probes: CapabilityProbes::default(),
+            ticks: 0,
+            refreshed: Instant::now(),
+            shared,
         })
     }
 
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
     pub fn refresh_device_list(&mut self) -> Result<()> {
-        self.devices = self
+        let devices = self
             .host
             .get_devices()
             .map_err(|err| anyhow!(err.to_string()))?;
+        self.list(devices)
+    }
+
+    /// Lists the devices again once the host has others, plugged or unplugged while shown.
+    /// The highlighted device stays so when still there.
+    fn follow_changes(&mut self) {
+        if self.refreshed.elapsed() < REFRESH_INTERVAL {
+            return;
+        }
+        self.refreshed = Instant::now();
+        let Ok(devices) = self.host.get_devices() else {
+            return;
+        };
+        let ids = |devices: &[Device]| -> Vec<Option<String>> {
+            devices.iter().map(|device| device.id().ok()).collect()
+        };
+        if ids(&devices) == ids(&self.devices) {
+            return;
+        }
+        let highlighted = self.highlighted().and_then(|device| device.id().ok());
+        if let Err(err) = self.list(devices) {
+            warn!("Cannot list the output devices: {}", err);
+            return;
+        }
+        let row = highlighted.and_then(|highlighted| {
+            self.listed.iter().position(|listed| {
+                self.devices[listed.device]
+                    .id()
+                    .is_ok_and(|id| id == highlighted)
+            })
+        });
+        if let Some(row) = row {
+            self.state.select(Some(row));
+        }
+    }
+
+    fn list(&mut self, devices: Vec<Device>) -> Result<()> {
+        self.devices = devices;
         self.default = self
             .host
             .get_default_device()
             .map_err(|err| anyhow!(err.to_string()))?;
+        self.listed = self
+            .devices
+            .iter()
+            .enumerate()
+            .map(|(index, device)| Listed {
+                device: index,
+                form_factor: device.form_factor().unwrap_or_default(),
+                friendly_name: device
+                    .friendly_name()
+                    .or_else(|_| device.name())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        // Stable, the host order is kept within a group
+        self.listed.sort_by_key(|listed| listed.form_factor);
         self.state.select(Some(0));
 
         if let Some(device) = self.selected.as_ref() {
@@ -53,12 +164,26 @@ impl DeviceSelector {
         Ok(())
     }
 
+    /// Index of the selected device in the list of the host, `None` for the default one.
+    pub fn selected_id(&self) -> Option<u32> {
+        let selected = self.selected.as_ref()?;
+        self.devices
+            .iter()
+            .position(|device| device.name().is_ok_and(|name| &name == selected))
+            .map(|index| index as u32)
+    }
+
+    /// Device of the highlighted row.
+    fn highlighted(&self) -> Option<&Device> {
+        let listed = self.listed.get(self.state.selected()?)?;
+        self.devices.get(listed.device)
+    }
+
     pub fn set_selected_device(&mut self) -> Result<()> {
         self.selected = match self.state.selected() {
-            Some(i) => Some(if i < self.devices.len() {
-                self.devices[i].name()?
-            } else {
-                self.default.name()?
+            Some(_) => Some(match self.highlighted() {
+                Some(device) => device.name()?,
+                None => self.default.name()?,
             }),
             None => None,
         };
@@ -93,19 +218,25 @@ impl DeviceSelector {
         self.state.select(Some(i));
     }
 
-    pub fn event_handler(&mut self, key: KeyEvent) -> Result<()> {
+    /// Returns whether a device was chosen.
+    pub fn event_handler(&mut self, key: KeyEvent) -> Result<bool> {
         if key.kind == KeyEventKind::Press {
             match key.code {
                 KeyCode::Up => self.previous(),
                 KeyCode::Down => self.next(),
-                KeyCode::Enter => self.set_selected_device()?,
+                KeyCode::Enter => {
+                    self.set_selected_device()?;
+                    return Ok(true);
+                }
+                KeyCode::Char('s') => self.shared = !self.shared,
                 _ => (),
             }
         }
-        Ok(())
+        Ok(false)
     }
 
     pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        self.follow_changes();
         let default = &self.default.name()?.clone();
         let selected_device_name = if let Some(device) = self.selected.as_ref() {
             device
@@ -114,11 +245,16 @@ impl DeviceSelector {
         };
 
         let mut items = Vec::new();
-        for device in &self.devices {
+        for (index, listed) in self.listed.iter().enumerate() {
+            let device = &self.devices[listed.device];
             let is_selected = &device.name()? == selected_device_name;
+            // The kind of endpoint heads its group
+            let first = index == 0 || self.listed[index - 1].form_factor != listed.form_factor;
             let row = Row::new(vec![
                 Cell::from(if is_selected { "󰓃" } else { "  " }),
-                Cell::from(device.name()?),
+                Cell::from(icon(listed.form_factor)),
+                Cell::from(listed.friendly_name.clone()),
+                Cell::from(if first { group(listed.form_factor) } else { "" }),
             ])
             .height(1)
             .style(Style::default().bg(if items.len() % 2 == 0 {
@@ -129,20 +265,85 @@ impl DeviceSelector {
             items.push(row);
         }
 
-        let table = Table::new(items, &[Constraint::Length(1), Constraint::Percentage(100)])
-            .highlight_symbol("=>")
-            .row_highlight_style(Style::default().fg(HIGHLIGHT_COLOR))
-            .block(
-                Block::default()
-                    .title("Select Output Device")
-                    .title_alignment(Alignment::Center)
-                    .borders(Borders::ALL)
-                    .border_type(ratatui::widgets::BorderType::Rounded)
-                    .border_style(Style::default().fg(HIGHLIGHT_COLOR)),
-            );
+        let table = Table::new(
+            items,
+            &[
+                Constraint::Length(1),
+                Constraint::Length(1),
+                Constraint::Percentage(100),
+                Constraint::Length(10),
+            ],
+        )
+        .highlight_symbol("=>")
+        .row_highlight_style(Style::default().fg(highlight_color()))
+        .block(
+            Block::default()
+                .title(format!(
+                    "Select Output Device - {} mode, s to change",
+                    if self.shared { "shared" } else { "exclusive" }
+                ))
+                .title_alignment(Alignment::Center)
+                .borders(Borders::ALL)
+                .border_type(ratatui::widgets::BorderType::Rounded)
+                .border_style(Style::default().fg(highlight_color())),
+        );
 
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(3), Constraint::Length(4)])
+            .split(area);
         frame.render_widget(Clear, area);
-        frame.render_stateful_widget(table, area, &mut self.state);
+        frame.render_stateful_widget(table, layout[0], &mut self.state);
+        frame.render_widget(self.capabilities()?, layout[1]);
         Ok(())
     }
+
+    /// Formats of the highlighted device, filled in as the probe finds them.
+    fn capabilities(&mut self) -> Result<Paragraph<'static>> {
+        self.ticks = self.ticks.wrapping_add(1);
+        let device = self
+            .state
+            .selected()
+            .and_then(|index| self.listed.get(index))
+            .and_then(|listed| self.devices.get(listed.device));
+        let (rates, bits) = match device {
+            Some(device) => {
+                let probed = self.probes.get(self.host, device)?;
+                let rates = probed
+                    .capabilities
+                    .sample_rates
+                    .iter()
+                    .map(|rate| format!("{}", *rate as usize as f32 / 1000.0))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                let bits = probed
+                    .capabilities
+                    .bits_per_samples
+                    .iter()
+                    .map(|bits| format!("{}", *bits as usize))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+                let spinner = match probed.done {
+                    true => "",
+                    false => SPINNER[self.ticks % SPINNER.len()],
+                };
+                (
+                    format!("{} {}KHz", spinner, rates),
+                    format!("{} {}bits", spinner, bits),
+                )
+            }
+            None => (String::new(), String::new()),
+        };
+        let lines = vec![
+            rates.trim().to_string().into(),
+            bits.trim().to_string().into(),
+        ];
+        Ok(Paragraph::new(lines).block(
+            Block::default()
+                .title("Capabilities")
+                .borders(Borders::ALL)
+                .border_type(ratatui::widgets::BorderType::Rounded)
+                .border_style(Style::default().fg(highlight_color())),
+        ))
+    }
 }
@@ -0,0 +1,50 @@
+use crate::ui::{highlight_color, ROW_ALTERNATE_COLOR, ROW_COLOR};
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Constraint, Rect},
+    style::Style,
+    widgets::{Block, BorderType, Borders, Cell, Clear, Row, Table, Widget},
+};
+
+/// Tracks played this session, the current one first and highlighted.
+pub struct HistoryPopup<'a> {
+    titles: Vec<&'a str>,
+}
+
+impl<'a> HistoryPopup<'a> {
+    pub fn new(titles: Vec<&'a str>) -> Self {
+        Self { titles }
+    }
+}
+
+impl Widget for HistoryPopup<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        let rows = self.titles.iter().enumerate().map(|(index, title)| {
+            let style = Style::default().bg(if index % 2 == 0 {
+                ROW_COLOR
+            } else {
+                ROW_ALTERNATE_COLOR
+            });
+            Row::new(vec![
+                Cell::from(format!("{}", index + 1)),
+                Cell::from(title.to_string()),
+            ])
+            .style(if index == 0 {
+                style.fg(highlight_color())
+            } else {
+                style
+            })
+        });
+        Table::new(rows, &[Constraint::Length(4), Constraint::Fill(1)])
+            .block(
+                Block::default()
+                    .title(format!("History - {}", self.titles.len()))
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(highlight_color())),
+            )
+            .render(area, buf);
+    }
+}
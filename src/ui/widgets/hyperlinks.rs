@@ -0,0 +1,128 @@
+use std::path::{self, Path};
+use std::sync::OnceLock;
+
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use ratatui::{buffer::Buffer, prelude::Rect, text::Span, widgets::Widget};
+
+use crate::config::HyperlinkMode;
+use crate::musictrack::MusicTrack;
+
+/// Characters of a path escaped in a `file://` URL, non-ASCII ones always are.
+const PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Terminals known to handle OSC 8, read once from the environment. Others could print the
+/// escape codes as text.
+fn detected() -> bool {
+    static DETECTED: OnceLock<bool> = OnceLock::new();
+    *DETECTED.get_or_init(|| {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        let term = var("TERM");
+        // Multiplexers drop them unless set up to pass them through
+        if std::env::var_os("TMUX").is_some() || term.starts_with("screen") {
+            return false;
+        }
+        [
+            "WT_SESSION",
+            "KITTY_WINDOW_ID",
+            "WEZTERM_EXECUTABLE",
+            "KONSOLE_VERSION",
+        ]
+        .iter()
+        .any(|name| std::env::var_os(name).is_some())
+            || matches!(
+                var("TERM_PROGRAM").as_str(),
+                "iTerm.app" | "WezTerm" | "vscode" | "ghostty" | "Hyper" | "rio"
+            )
+            || var("VTE_VERSION")
+                .parse::<u32>()
+                .is_ok_and(|version| version >= 5000)
+            || matches!(
+                term.as_str(),
+                "xterm-kitty" | "xterm-ghostty" | "foot" | "foot-extra" | "alacritty" | "wezterm"
+            )
+    })
+}
+
+/// Whether rows are linked under `mode` in this terminal.
+pub fn hyperlinks_enabled(mode: HyperlinkMode) -> bool {
+    match mode {
+        HyperlinkMode::Auto => detected(),
+        HyperlinkMode::Always => true,
+        HyperlinkMode::Never => false,
+    }
+}
+
+/// Where ctrl+click on the row of `song` leads: the folder of the file, or the address of a
+/// radio. `None` when it cannot be told.
+pub fn track_url(song: &MusicTrack) -> Option<String> {
+    if song.is_stream() {
+        return (!song.path.chars().any(char::is_control)).then(|| song.path.clone());
+    }
+    let folder = path::absolute(Path::new(&song.path))
+        .ok()?
+        .parent()?
+        .to_path_buf();
+    let folder = folder.to_str()?.replace('\\', "/");
+    // Drive letters come after the root slash
+    let folder = match folder.starts_with('/') {
+        true => folder,
+        false => format!("/{}", folder),
+    };
+    Some(format!("file://{}", utf8_percent_encode(&folder, PATH)))
+}
+
+/// Columns taken by the cell at `x`, `y` once printed.
+fn width(buf: &Buffer, x: u16, y: u16) -> usize {
+    Span::raw(buf[(x, y)].symbol()).width()
+}
+
+/// Turns rows already drawn into OSC 8 hyperlinks, one URL per row of the area from the top.
+/// The buffer knows nothing of escape codes: each link is cut into cells two columns wide,
+/// the second column left to the terminal. Columns which cannot be paired stay plain text.
+pub struct Hyperlinks {
+    urls: Vec<Option<String>>,
+}
+
+impl Hyperlinks {
+    pub fn new(urls: Vec<Option<String>>) -> Self {
+        Self { urls }
+    }
+}
+
+impl Widget for Hyperlinks {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for (y, url) in (area.top()..area.bottom()).zip(self.urls) {
+            let Some(url) = url else {
+                continue;
+            };
+            let mut x = area.left();
+            while x < area.right() {
+                let text = match width(buf, x, y) {
+                    2 if x + 1 < area.right() => buf[(x, y)].symbol().to_string(),
+                    1 if x + 1 < area.right()
+                        && width(buf, x + 1, y) == 1
+                        && buf[(x, y)].style() == buf[(x + 1, y)].style() =>
+                    {
+                        format!("{}{}", buf[(x, y)].symbol(), buf[(x + 1, y)].symbol())
+                    }
+                    _ => {
+                        x += 1;
+                        continue;
+                    }
+                };
+                buf[(x, y)].set_symbol(&format!("\x1b]8;;{}\x07{}\x1b]8;;\x07", url, text));
+                x += 2;
+            }
+        }
+    }
+}
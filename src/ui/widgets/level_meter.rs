@@ -0,0 +1,67 @@
+use crate::ui::{highlight_color, ROW_COLOR};
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Constraint, Direction, Layout, Rect},
+    style::{Color, Style},
+    symbols,
+    widgets::{LineGauge, Widget},
+};
+
+const FLOOR_DB: f32 = -60.0;
+
+fn decibels(level: f32) -> f32 {
+    if level > 0.0 {
+        (20.0 * level.log10()).max(FLOOR_DB)
+    } else {
+        FLOOR_DB
+    }
+}
+
+/// One gauge per channel showing the peak level, red once clipping.
+pub struct LevelMeter<'a> {
+    levels: &'a [f32],
+    rms: Option<&'a [f32]>,
+}
+
+impl<'a> LevelMeter<'a> {
+    pub fn new(levels: &'a [f32]) -> Self {
+        Self { levels, rms: None }
+    }
+
+    /// Adds the RMS level of each channel to the labels.
+    pub fn rms(mut self, rms: &'a [f32]) -> Self {
+        self.rms = Some(rms);
+        self
+    }
+}
+
+impl Widget for LevelMeter<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(self.levels.iter().map(|_| Constraint::Length(1)))
+            .split(area);
+        for (index, (level, row)) in self.levels.iter().zip(rows.iter()).enumerate() {
+            let db = decibels(*level);
+            let label = match (self.levels.len(), index) {
+                (2, 0) => "L".to_string(),
+                (2, 1) => "R".to_string(),
+                (_, index) => format!("{}", index + 1),
+            };
+            LineGauge::default()
+                .filled_style(Style::default().fg(if *level >= 1.0 {
+                    Color::Red
+                } else {
+                    highlight_color()
+                }))
+                .unfilled_style(Style::default().fg(ROW_COLOR))
+                .line_set(symbols::line::THICK)
+                .label(match self.rms.and_then(|rms| rms.get(index)) {
+                    Some(rms) => format!("{} {:>6.1}dB rms {:>6.1}dB", label, db, decibels(*rms)),
+                    None => format!("{} {:>6.1}dB", label, db),
+                })
+                .ratio(((db - FLOOR_DB) / -FLOOR_DB) as f64)
+                .render(*row, buf);
+        }
+    }
+}
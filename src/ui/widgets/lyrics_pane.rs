@@ -0,0 +1,65 @@
+use crate::ui::highlight_color;
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Rect},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget},
+};
+
+/// Lyrics of the playing track, the current line highlighted and kept in the middle.
+pub struct LyricsPane<'a> {
+    lines: Vec<&'a str>,
+    current: Option<usize>,
+}
+
+impl<'a> LyricsPane<'a> {
+    pub fn new(lines: Vec<&'a str>, current: Option<usize>) -> Self {
+        Self { lines, current }
+    }
+}
+
+impl Widget for LyricsPane<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        let block = Block::default()
+            .title("Lyrics")
+            .title_alignment(Alignment::Left)
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(highlight_color()));
+        if self.lines.is_empty() {
+            Paragraph::new("No lyrics")
+                .alignment(Alignment::Center)
+                .block(block)
+                .render(area, buf);
+            return;
+        }
+        let height = area.height.saturating_sub(2) as usize;
+        let scroll = self
+            .current
+            .map_or(0, |current| current.saturating_sub(height / 2));
+        let lines: Vec<Line> = self
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                if Some(index) == self.current {
+                    Line::styled(
+                        *line,
+                        Style::default()
+                            .fg(highlight_color())
+                            .add_modifier(Modifier::BOLD),
+                    )
+                } else {
+                    Line::raw(*line)
+                }
+            })
+            .collect();
+        Paragraph::new(lines)
+            .alignment(Alignment::Center)
+            .scroll((scroll as u16, 0))
+            .block(block)
+            .render(area, buf);
+    }
+}
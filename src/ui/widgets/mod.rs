@@ -1,2 +1,36 @@
+mod alarm_settings;
+mod album_info_popup;
+mod artist_pane;
+mod badges;
+mod debug_overlay;
 mod device_selector;
+mod history_popup;
+mod hyperlinks;
+mod level_meter;
+mod lyrics_pane;
+mod notice_popup;
+mod now_playing;
+mod path_prompt;
+mod queue_pane;
+mod search_prompt;
+mod spectrum;
+mod tasks_popup;
+mod track_info_popup;
+pub(crate) use alarm_settings::AlarmSettingsPane;
+pub(crate) use album_info_popup::AlbumInfoPopup;
+pub(crate) use artist_pane::ArtistPane;
+pub(crate) use badges::{BadgeColors, Badges};
+pub(crate) use debug_overlay::DebugOverlay;
 pub(crate) use device_selector::DeviceSelector;
+pub(crate) use history_popup::HistoryPopup;
+pub(crate) use hyperlinks::{hyperlinks_enabled, track_url, Hyperlinks};
+pub(crate) use level_meter::LevelMeter;
+pub(crate) use lyrics_pane::LyricsPane;
+pub(crate) use notice_popup::NoticePopup;
+pub(crate) use now_playing::NowPlaying;
+pub(crate) use path_prompt::{PathPrompt, PromptAction};
+pub(crate) use queue_pane::QueuePane;
+pub(crate) use search_prompt::{SearchAction, SearchPrompt};
+pub(crate) use spectrum::{Spectrum, SpectrumAnalyzer};
+pub(crate) use tasks_popup::TasksPopup;
+pub(crate) use track_info_popup::TrackInfoPopup;
@@ -0,0 +1,34 @@
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Rect},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget},
+};
+
+/// Failure of a background task, such as the streaming of a track.
+pub struct NoticePopup<'a> {
+    notice: &'a str,
+}
+
+impl<'a> NoticePopup<'a> {
+    pub fn new(notice: &'a str) -> Self {
+        Self { notice }
+    }
+}
+
+impl Widget for NoticePopup<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        Paragraph::new(Line::from(self.notice))
+            .block(
+                Block::default()
+                    .title("Error")
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(Color::Red)),
+            )
+            .render(area, buf);
+    }
+}
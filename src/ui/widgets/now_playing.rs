@@ -0,0 +1,104 @@
+use crate::player::PlayerSnapshot;
+use ratatui::{
+    style::{Color, Style},
+    text::{Line, Span},
+};
+
+use super::{BadgeColors, Badges};
+
+/// Blank between the end of a scrolling title and its start again.
+const MARQUEE_GAP: &str = "   ";
+
+/// Columns taken by `text` once printed.
+fn width(text: &str) -> usize {
+    Span::raw(text).width()
+}
+
+/// `width` columns of `text` read in a loop from its character at `offset`.
+fn marquee(text: &str, width: usize, offset: usize) -> String {
+    let looped: Vec<char> = text.chars().chain(MARQUEE_GAP.chars()).collect();
+    let mut shown = String::new();
+    let mut used = 0;
+    for c in looped.iter().cycle().skip(offset % looped.len()) {
+        used += self::width(c.encode_utf8(&mut [0; 4]));
+        if used > width {
+            break;
+        }
+        shown.push(*c);
+    }
+    shown
+}
+
+/// Playing track as drawn along the bottom border of the playlist: title, badges and the
+/// format the device plays it in when it differs from the file, with the resampler used.
+pub struct NowPlaying<'a> {
+    snapshot: &'a PlayerSnapshot,
+    colors: &'a BadgeColors,
+    /// Columns available, with how far the title scrolled when it does not fit
+    scroll: Option<(usize, usize)>,
+}
+
+impl<'a> NowPlaying<'a> {
+    pub fn new(snapshot: &'a PlayerSnapshot, colors: &'a BadgeColors) -> Self {
+        Self {
+            snapshot,
+            colors,
+            scroll: None,
+        }
+    }
+
+    /// Scrolls the title by `offset` characters when the line is wider than `width`, the
+    /// badges and format stay in place.
+    pub fn scroll(mut self, width: usize, offset: usize) -> Self {
+        self.scroll = Some((width, offset));
+        self
+    }
+
+    pub fn line(self) -> Line<'static> {
+        let Some((_, song)) = &self.snapshot.track else {
+            return Line::default();
+        };
+        let mut line = Badges::new(song.badges(), self.colors).line();
+        let title = format!(" {} - {} ", song.display_title(), song.artist);
+        line.spans.insert(0, title.into());
+        if let Some(output) = self.snapshot.output {
+            // DoP reaches the device as 24 bits PCM, it is not converted
+            if song.dsd_rate.is_none()
+                && (output.samplerate != song.sample
+                    || output.bits_per_sample != song.bits_per_sample)
+            {
+                let mut converted = format!(
+                    " → {}KHz {}bits",
+                    output.samplerate as usize as f32 / 1000.0,
+                    output.bits_per_sample as usize
+                );
+                if let Some(resampler) = self.snapshot.resampler {
+                    converted.push_str(&format!(" ({})", resampler));
+                }
+                line.spans.push(Span::styled(
+                    converted,
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            // Neither bit perfect nor at the rate of the track
+            if output.shared_fallback {
+                line.spans.push(" ".into());
+                line.spans.push(Span::styled(
+                    " shared mode ",
+                    Style::default().fg(Color::Black).bg(Color::Yellow),
+                ));
+            }
+        }
+        line.spans.push(" ".into());
+        if let Some((available, offset)) = self.scroll {
+            let title = format!("{} - {}", song.display_title(), song.artist);
+            // The title span keeps its padding, the rest of the line its width
+            let others = line.width() - line.spans[0].width();
+            let room = available.saturating_sub(others + 2);
+            if line.width() > available && room > 0 {
+                line.spans[0] = format!(" {} ", marquee(&title, room, offset)).into();
+            }
+        }
+        line
+    }
+}
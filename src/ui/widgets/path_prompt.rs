@@ -0,0 +1,68 @@
+use crate::ui::highlight_color;
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind};
+use ratatui::{
+    prelude::{Alignment, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph},
+    Frame,
+};
+
+pub enum PromptAction {
+    /// File or directory typed, quotes left by drag and drop removed
+    Insert(String),
+    Cancel,
+}
+
+/// Path of a file or directory to insert in the playlist, typed or dropped on the terminal.
+#[derive(Default)]
+pub struct PathPrompt {
+    input: String,
+}
+
+impl PathPrompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn event_handler(&mut self, key: KeyEvent) -> Option<PromptAction> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        match key.code {
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Esc => return Some(PromptAction::Cancel),
+            KeyCode::Enter => {
+                let path = self.input.trim().trim_matches(['"', '\'']);
+                return Some(match path.is_empty() {
+                    true => PromptAction::Cancel,
+                    false => PromptAction::Insert(path.to_string()),
+                });
+            }
+            _ => (),
+        }
+        None
+    }
+
+    pub(crate) fn render(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        // The end of long paths stays in view
+        let width = area.width.saturating_sub(3) as usize;
+        let shown = self.input.chars().count().saturating_sub(width);
+        let input: String = self.input.chars().skip(shown).collect();
+        let prompt = Paragraph::new(Line::from(format!("{}_", input))).block(
+            Block::default()
+                .title("Insert after the selection - enter to add, esc to cancel")
+                .title_alignment(Alignment::Left)
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(highlight_color())),
+        );
+        frame.render_widget(Clear, area);
+        frame.render_widget(prompt, area);
+        Ok(())
+    }
+}
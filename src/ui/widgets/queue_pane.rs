@@ -0,0 +1,53 @@
+use crate::ui::{highlight_color, ROW_ALTERNATE_COLOR, ROW_COLOR};
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Constraint, Rect},
+    style::Style,
+    widgets::{Block, BorderType, Borders, Cell, Row, Table, Widget},
+};
+
+/// Prioritized tracks are highlighted.
+pub struct QueuePane<'a> {
+    titles: Vec<(&'a str, bool)>,
+}
+
+impl<'a> QueuePane<'a> {
+    pub fn new(titles: Vec<(&'a str, bool)>) -> Self {
+        Self { titles }
+    }
+}
+
+impl Widget for QueuePane<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let rows = self
+            .titles
+            .iter()
+            .enumerate()
+            .map(|(index, (title, priority))| {
+                let style = Style::default().bg(if index % 2 == 0 {
+                    ROW_COLOR
+                } else {
+                    ROW_ALTERNATE_COLOR
+                });
+                Row::new(vec![
+                    Cell::from(format!("{}", index + 1)),
+                    Cell::from(title.to_string()),
+                ])
+                .style(if *priority {
+                    style.fg(highlight_color())
+                } else {
+                    style
+                })
+            });
+        Table::new(rows, &[Constraint::Length(3), Constraint::Fill(1)])
+            .block(
+                Block::default()
+                    .title(format!("Queue - {}", self.titles.len()))
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(highlight_color())),
+            )
+            .render(area, buf);
+    }
+}
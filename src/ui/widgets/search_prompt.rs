@@ -0,0 +1,88 @@
+use crate::ui::highlight_color;
+use anyhow::Result;
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::{
+    prelude::{Alignment, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph},
+    Frame,
+};
+
+pub enum SearchAction {
+    /// Query changed, searched again as typed
+    Query(String),
+    NextMatch,
+    PreviousMatch,
+    /// Keeps the match selected
+    Done,
+    Cancel,
+}
+
+/// Query of the fuzzy search in the playlist.
+#[derive(Default)]
+pub struct SearchPrompt {
+    input: String,
+}
+
+impl SearchPrompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn event_handler(&mut self, key: KeyEvent) -> Option<SearchAction> {
+        if key.kind != KeyEventKind::Press {
+            return None;
+        }
+        match key.code {
+            KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(SearchAction::NextMatch)
+            }
+            KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(SearchAction::PreviousMatch)
+            }
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Down | KeyCode::Tab => return Some(SearchAction::NextMatch),
+            KeyCode::Up | KeyCode::BackTab => return Some(SearchAction::PreviousMatch),
+            KeyCode::Esc => return Some(SearchAction::Cancel),
+            KeyCode::Enter => return Some(SearchAction::Done),
+            _ => return None,
+        }
+        Some(SearchAction::Query(self.input.clone()))
+    }
+
+    /// `status` is the rank of the match selected and how many there are.
+    pub(crate) fn render(
+        &mut self,
+        frame: &mut Frame,
+        area: Rect,
+        status: Option<(usize, usize)>,
+    ) -> Result<()> {
+        // The end of long queries stays in view
+        let width = area.width.saturating_sub(3) as usize;
+        let shown = self.input.chars().count().saturating_sub(width);
+        let input: String = self.input.chars().skip(shown).collect();
+        let title = match status {
+            Some((_, 0)) if !self.input.trim().is_empty() => "Search - no match".to_string(),
+            Some((current, count)) if count > 0 => format!("Search - {}/{}", current, count),
+            _ => "Search".to_string(),
+        };
+        let prompt = Paragraph::new(Line::from(format!("{}_", input))).block(
+            Block::default()
+                .title(title)
+                .title_alignment(Alignment::Left)
+                .title_bottom(Line::from(
+                    "up/down through matches - enter to keep, esc to cancel",
+                ))
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(highlight_color())),
+        );
+        frame.render_widget(Clear, area);
+        frame.render_widget(prompt, area);
+        Ok(())
+    }
+}
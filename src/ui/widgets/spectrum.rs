@@ -0,0 +1,111 @@
+use crate::ui::highlight_color;
+use ratatui::{buffer::Buffer, prelude::Rect, style::Style, symbols::bar, widgets::Widget};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::sync::Arc;
+
+const FFT_LENGTH: usize = 2048;
+const LOWEST_FREQUENCY: f32 = 30.0;
+const HIGHEST_FREQUENCY: f32 = 16000.0;
+const FLOOR_DB: f32 = -70.0;
+/// Height lost by a bar on every update, peaks fall slowly instead of flickering
+const FALL: f32 = 0.04;
+
+/// Turns the tapped samples into bar heights between 0 and 1, on a logarithmic frequency
+/// scale.
+pub struct SpectrumAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    bars: Vec<f32>,
+}
+
+impl Default for SpectrumAnalyzer {
+    fn default() -> Self {
+        Self {
+            fft: FftPlanner::new().plan_fft_forward(FFT_LENGTH),
+            window: (0..FFT_LENGTH)
+                .map(|i| {
+                    0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / FFT_LENGTH as f32).cos()
+                })
+                .collect(),
+            bars: Vec::new(),
+        }
+    }
+}
+
+impl SpectrumAnalyzer {
+    /// Analyzes the most recent samples, missing ones being silence.
+    pub fn update(&mut self, samples: &[f32], sample_rate: u32, count: usize) -> &[f32] {
+        self.bars.resize(count, 0.0);
+        if sample_rate == 0 || count == 0 {
+            return &self.bars;
+        }
+        let start = samples.len().saturating_sub(FFT_LENGTH);
+        let mut spectrum: Vec<Complex<f32>> = samples[start..]
+            .iter()
+            .zip(&self.window)
+            .map(|(sample, window)| Complex::new(sample * window, 0.0))
+            .collect();
+        spectrum.resize(FFT_LENGTH, Complex::default());
+        self.fft.process(&mut spectrum);
+        // A full scale sine reaches 0dB through the Hann window
+        let scale = 4.0 / FFT_LENGTH as f32;
+        let bin_width = sample_rate as f32 / FFT_LENGTH as f32;
+        let highest = HIGHEST_FREQUENCY.min(sample_rate as f32 / 2.0);
+        let ratio = highest / LOWEST_FREQUENCY;
+        let bin = |bar: usize| {
+            let frequency = LOWEST_FREQUENCY * ratio.powf(bar as f32 / count as f32);
+            ((frequency / bin_width) as usize).min(FFT_LENGTH / 2)
+        };
+        for (index, height) in self.bars.iter_mut().enumerate() {
+            let (low, high) = (bin(index), bin(index + 1));
+            let magnitude = spectrum[low..high.max(low + 1)]
+                .iter()
+                .fold(0.0f32, |peak, bin| peak.max(bin.norm() * scale));
+            let db = if magnitude > 0.0 {
+                20.0 * magnitude.log10()
+            } else {
+                FLOOR_DB
+            };
+            let level = ((db - FLOOR_DB) / -FLOOR_DB).clamp(0.0, 1.0);
+            *height = level.max(*height - FALL);
+        }
+        &self.bars
+    }
+}
+
+/// One column per bar, drawn with eighth blocks.
+pub struct Spectrum<'a> {
+    bars: &'a [f32],
+}
+
+impl<'a> Spectrum<'a> {
+    pub fn new(bars: &'a [f32]) -> Self {
+        Self { bars }
+    }
+}
+
+impl Widget for Spectrum<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let symbols = [
+            bar::NINE_LEVELS.empty,
+            bar::NINE_LEVELS.one_eighth,
+            bar::NINE_LEVELS.one_quarter,
+            bar::NINE_LEVELS.three_eighths,
+            bar::NINE_LEVELS.half,
+            bar::NINE_LEVELS.five_eighths,
+            bar::NINE_LEVELS.three_quarters,
+            bar::NINE_LEVELS.seven_eighths,
+            bar::NINE_LEVELS.full,
+        ];
+        for (column, height) in self.bars.iter().take(area.width as usize).enumerate() {
+            let mut eighths = (height * area.height as f32 * 8.0).round() as usize;
+            for row in (0..area.height).rev() {
+                let symbol = symbols[eighths.min(8)];
+                eighths = eighths.saturating_sub(8);
+                buf[(area.x + column as u16, area.y + row)]
+                    .set_symbol(symbol)
+                    .set_style(Style::default().fg(highlight_color()));
+            }
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::tasks::TasksSnapshot;
+use crate::ui::highlight_color;
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Rect},
+    style::{Color, Style},
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget},
+};
+
+/// Background tasks, running first then queued then recently finished.
+pub struct TasksPopup<'a> {
+    snapshot: &'a TasksSnapshot,
+}
+
+impl<'a> TasksPopup<'a> {
+    pub fn new(snapshot: &'a TasksSnapshot) -> Self {
+        Self { snapshot }
+    }
+}
+
+impl Widget for TasksPopup<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        let mut lines: Vec<Line> = Vec::new();
+        for name in &self.snapshot.running {
+            lines.push(Line::styled(
+                format!("running  {}", name),
+                Style::default().fg(highlight_color()),
+            ));
+        }
+        for name in &self.snapshot.queued {
+            lines.push(Line::from(format!("queued   {}", name)));
+        }
+        let hidden = self.snapshot.queued_count - self.snapshot.queued.len();
+        if hidden > 0 {
+            lines.push(Line::from(format!("         {} more queued", hidden)));
+        }
+        for (name, succeeded) in &self.snapshot.finished {
+            lines.push(if *succeeded {
+                Line::styled(
+                    format!("done     {}", name),
+                    Style::default().fg(Color::DarkGray),
+                )
+            } else {
+                Line::styled(
+                    format!("failed   {}", name),
+                    Style::default().fg(Color::Red),
+                )
+            });
+        }
+        if lines.is_empty() {
+            lines.push(Line::from("No tasks"));
+        }
+        Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .title(format!(
+                        "Tasks - {} running, {} queued",
+                        self.snapshot.running.len(),
+                        self.snapshot.queued_count
+                    ))
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(highlight_color())),
+            )
+            .render(area, buf);
+    }
+}
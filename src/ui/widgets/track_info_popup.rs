@@ -0,0 +1,55 @@
+use crate::musictrack::MusicTrack;
+use crate::ui::{highlight_color, WARNING_COLOR, WARNING_ICON};
+use ratatui::{
+    buffer::Buffer,
+    prelude::{Alignment, Rect},
+    style::Style,
+    text::Line,
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget, Wrap},
+};
+
+/// Tags and format of the selected track, with how the output device will play it.
+pub struct TrackInfoPopup<'a> {
+    song: &'a MusicTrack,
+    warning: Option<String>,
+}
+
+impl<'a> TrackInfoPopup<'a> {
+    pub fn new(song: &'a MusicTrack, warning: Option<String>) -> Self {
+        Self { song, warning }
+    }
+}
+
+impl Widget for TrackInfoPopup<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Clear.render(area, buf);
+        let mut lines = vec![
+            Line::from(self.song.display_title()),
+            Line::from(format!("{} - {}", self.song.artist, self.song.album)),
+            Line::from(format!(
+                "{} - {} channels - {}",
+                self.song.info(),
+                self.song.channels,
+                self.song.formated_duration()
+            )),
+            Line::from(self.song.path.as_str()),
+        ];
+        if let Some(warning) = self.warning {
+            lines.push(Line::styled(
+                format!("{} {}", WARNING_ICON, warning),
+                Style::default().fg(WARNING_COLOR),
+            ));
+        }
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(
+                Block::default()
+                    .title("Track info")
+                    .title_alignment(Alignment::Left)
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(highlight_color())),
+            )
+            .render(area, buf);
+    }
+}
@@ -0,0 +1,126 @@
+use anyhow::{anyhow, Result};
+use log::warn;
+use notify::event::{AccessKind, AccessMode, MetadataKind, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+
+/// A file or directory appearing under, changing in or disappearing from the watched directory.
+#[derive(PartialEq)]
+pub enum Change {
+    Added(PathBuf),
+    /// Rewritten in place, e.g. by a tag editor, also reported while a file is being written
+    Modified(PathBuf),
+    Removed(PathBuf),
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Added(path) | Self::Modified(path) | Self::Removed(path) => path,
+        }
+    }
+}
+
+fn changes(event: Event) -> Vec<Change> {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            event.paths.into_iter().map(Change::Added).collect()
+        }
+        // Windows does not tell what was modified
+        EventKind::Modify(ModifyKind::Data(_))
+        | EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime))
+        | EventKind::Modify(ModifyKind::Any)
+        | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+            event.paths.into_iter().map(Change::Modified).collect()
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            event.paths.into_iter().map(Change::Removed).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            let mut paths = event.paths.into_iter();
+            paths
+                .next()
+                .map(Change::Removed)
+                .into_iter()
+                .chain(paths.next().map(Change::Added))
+                .collect()
+        }
+        // Some platforms do not tell which side of a rename the path is
+        EventKind::Modify(ModifyKind::Name(_)) => event
+            .paths
+            .into_iter()
+            .map(|path| {
+                if path.exists() {
+                    Change::Added(path)
+                } else {
+                    Change::Removed(path)
+                }
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Recursively watches a directory, changes are polled from the UI loop.
+pub struct DirWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<Change>,
+}
+
+impl DirWatcher {
+    pub fn new(dir: &Path) -> Result<Self> {
+        Self::watch(dir, RecursiveMode::Recursive)
+    }
+
+    fn watch(dir: &Path, mode: RecursiveMode) -> Result<Self> {
+        let (tx, events) = channel();
+        let watched = dir.to_path_buf();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) => {
+                    for change in changes(event) {
+                        let _ = tx.send(change);
+                    }
+                }
+                Err(err) => warn!("Watcher error on {}: {}", watched.display(), err),
+            })?;
+        watcher.watch(dir, mode)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    pub fn changes(&self) -> impl Iterator<Item = Change> + '_ {
+        self.events.try_iter()
+    }
+}
+
+/// Watches a single file through its directory, editors often save by replacing the file.
+pub struct FileWatcher {
+    watcher: DirWatcher,
+    path: PathBuf,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<Self> {
+        let dir = path
+            .parent()
+            .ok_or(anyhow!("{} has no directory", path.display()))?;
+        Ok(Self {
+            watcher: DirWatcher::watch(dir, RecursiveMode::NonRecursive)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Whether the file was written, replaced or removed since the last call.
+    pub fn changed(&self) -> bool {
+        // Every event is drained, the ones of the other files of the directory included
+        let mut changed = false;
+        for change in self.watcher.changes() {
+            changed |= change.path() == self.path;
+        }
+        changed
+    }
+}
@@ -0,0 +1,362 @@
+#!/usr/bin/env python3
+"""Writes the tiny fixtures probed by the MusicTrack tests.
+
+Every file is built by hand so no encoder is needed, the audio is silence:
+FLAC frames use constant subframes, MP3 frames have empty side information
+and Vorbis packets mark every channel as unused.
+
+Run from anywhere, the files are written next to this script.
+"""
+
+import os
+import struct
+import zlib
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+
+def write(name, data):
+    with open(os.path.join(HERE, name), "wb") as f:
+        f.write(data)
+
+
+class MsbWriter:
+    def __init__(self):
+        self.bits = []
+
+    def put(self, value, count):
+        for shift in range(count - 1, -1, -1):
+            self.bits.append((value >> shift) & 1)
+
+    def align(self):
+        while len(self.bits) % 8:
+            self.bits.append(0)
+
+    def bytes(self):
+        self.align()
+        return bytes(
+            int("".join(map(str, self.bits[i : i + 8])), 2)
+            for i in range(0, len(self.bits), 8)
+        )
+
+
+class LsbWriter:
+    def __init__(self):
+        self.bits = []
+
+    def put(self, value, count):
+        for shift in range(count):
+            self.bits.append((value >> shift) & 1)
+
+    def bytes(self):
+        while len(self.bits) % 8:
+            self.bits.append(0)
+        return bytes(
+            sum(bit << i for i, bit in enumerate(self.bits[n : n + 8]))
+            for n in range(0, len(self.bits), 8)
+        )
+
+
+# WAV
+
+
+def wav(samplerate, channels, bits, seconds, info=None, extensible=False):
+    frames = int(samplerate * seconds)
+    block_align = channels * bits // 8
+    data = bytes(frames * block_align)
+    if extensible:
+        mask = (1 << channels) - 1
+        fmt = struct.pack(
+            "<HHIIHHHHI16s",
+            0xFFFE,
+            channels,
+            samplerate,
+            samplerate * block_align,
+            block_align,
+            bits,
+            22,
+            bits,
+            mask,
+            b"\x01\x00\x00\x00\x00\x00\x10\x00\x80\x00\x00\xaa\x00\x38\x9b\x71",
+        )
+    else:
+        fmt = struct.pack(
+            "<HHIIHH", 1, channels, samplerate, samplerate * block_align, block_align, bits
+        )
+    chunks = chunk(b"fmt ", fmt)
+    if info:
+        entries = b"".join(chunk(key, value.encode() + b"\0") for key, value in info)
+        chunks += chunk(b"LIST", b"INFO" + entries)
+    chunks += chunk(b"data", data)
+    return b"RIFF" + struct.pack("<I", 4 + len(chunks)) + b"WAVE" + chunks
+
+
+def chunk(fourcc, payload):
+    pad = b"\0" if len(payload) % 2 else b""
+    return fourcc + struct.pack("<I", len(payload)) + payload + pad
+
+
+# FLAC
+
+FLAC_RATE_CODES = {88200: 1, 176400: 2, 192000: 3, 22050: 6, 44100: 9, 48000: 10, 96000: 11}
+FLAC_BITS_CODES = {8: 1, 16: 4, 24: 6}
+
+
+def crc8(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x07) & 0xFF if crc & 0x80 else (crc << 1) & 0xFF
+    return crc
+
+
+def crc16(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte << 8
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x8005) & 0xFFFF if crc & 0x8000 else (crc << 1) & 0xFFFF
+    return crc
+
+
+def utf8_number(value):
+    if value < 0x80:
+        return bytes([value])
+    if value < 0x800:
+        return bytes([0xC0 | value >> 6, 0x80 | value & 0x3F])
+    return bytes([0xE0 | value >> 12, 0x80 | (value >> 6) & 0x3F, 0x80 | value & 0x3F])
+
+
+def flac_frame(number, block_size, samplerate, channels, bits):
+    header = MsbWriter()
+    header.put(0b11111111111110, 14)
+    header.put(0, 1)
+    header.put(0, 1)
+    header.put(0b0111, 4)
+    header.put(FLAC_RATE_CODES[samplerate], 4)
+    header.put(channels - 1, 4)
+    header.put(FLAC_BITS_CODES[bits], 3)
+    header.put(0, 1)
+    data = header.bytes() + utf8_number(number) + struct.pack(">H", block_size - 1)
+    data += bytes([crc8(data)])
+    body = MsbWriter()
+    for _ in range(channels):
+        body.put(0, 1)
+        body.put(0, 6)
+        body.put(0, 1)
+        body.put(0, bits)
+    data += body.bytes()
+    return data + struct.pack(">H", crc16(data))
+
+
+def vorbis_comments(tags):
+    vendor = b"rhap fixtures"
+    data = struct.pack("<I", len(vendor)) + vendor + struct.pack("<I", len(tags))
+    for key, value in tags:
+        entry = f"{key}={value}".encode()
+        data += struct.pack("<I", len(entry)) + entry
+    return data
+
+
+def flac(samplerate, channels, bits, seconds, tags):
+    block_size = 4096
+    total = int(samplerate * seconds)
+    info = MsbWriter()
+    info.put(block_size, 16)
+    info.put(block_size, 16)
+    info.put(0, 24)
+    info.put(0, 24)
+    info.put(samplerate, 20)
+    info.put(channels - 1, 3)
+    info.put(bits - 1, 5)
+    info.put(total, 36)
+    streaminfo = info.bytes() + bytes(16)
+    comments = vorbis_comments(tags)
+    data = b"fLaC"
+    data += bytes([0]) + len(streaminfo).to_bytes(3, "big") + streaminfo
+    data += bytes([0x80 | 4]) + len(comments).to_bytes(3, "big") + comments
+    number = 0
+    while total > 0:
+        size = min(block_size, total)
+        data += flac_frame(number, size, samplerate, channels, bits)
+        total -= size
+        number += 1
+    return data
+
+
+# MP3
+
+
+def id3v23(tags):
+    frames = b""
+    for frame_id, value in tags:
+        payload = b"\0" + value.encode("latin-1")
+        frames += frame_id + struct.pack(">I", len(payload)) + b"\0\0" + payload
+    size = len(frames)
+    syncsafe = bytes([(size >> 21) & 0x7F, (size >> 14) & 0x7F, (size >> 7) & 0x7F, size & 0x7F])
+    return b"ID3\x03\x00\x00" + syncsafe + frames
+
+
+def mp3(seconds, tags):
+    # MPEG-1 layer III, 128kbps, 44.1kHz, mono, 1152 samples per frame
+    frame = b"\xff\xfb\x90\xc0" + bytes(417 - 4)
+    count = round(44100 * seconds / 1152)
+    return id3v23(tags) + frame * count
+
+
+# Ogg Vorbis
+
+
+def ogg_crc(data):
+    crc = 0
+    for byte in data:
+        crc ^= byte << 24
+        for _ in range(8):
+            crc = ((crc << 1) ^ 0x04C11DB7) & 0xFFFFFFFF if crc & 0x80000000 else (crc << 1) & 0xFFFFFFFF
+    return crc
+
+
+def ogg_page(packets, granule, sequence, flags, serial=0x72686170):
+    segments = b""
+    for packet in packets:
+        segments += b"\xff" * (len(packet) // 255) + bytes([len(packet) % 255])
+    header = struct.pack("<4sBBqIII", b"OggS", 0, flags, granule, serial, sequence, 0)
+    page = header + bytes([len(segments)]) + segments + b"".join(packets)
+    crc = ogg_crc(page)
+    return page[:22] + struct.pack("<I", crc) + page[26:]
+
+
+def vorbis_setup(channels):
+    bits = LsbWriter()
+    # One codebook: two entries of one bit, no lookup
+    bits.put(0, 8)
+    bits.put(0x564342, 24)
+    bits.put(1, 16)
+    bits.put(2, 24)
+    bits.put(0, 1)
+    bits.put(0, 1)
+    bits.put(0, 5)
+    bits.put(0, 5)
+    bits.put(0, 4)
+    # One placeholder time transform
+    bits.put(0, 6)
+    bits.put(0, 16)
+    # One floor 1 without partitions
+    bits.put(0, 6)
+    bits.put(1, 16)
+    bits.put(0, 5)
+    bits.put(0, 2)
+    bits.put(7, 4)
+    # One residue 0 covering nothing
+    bits.put(0, 6)
+    bits.put(0, 16)
+    bits.put(0, 24)
+    bits.put(0, 24)
+    bits.put(0, 24)
+    bits.put(0, 6)
+    bits.put(0, 8)
+    bits.put(0, 3)
+    bits.put(0, 1)
+    # One mapping with a single submap
+    bits.put(0, 6)
+    bits.put(0, 16)
+    bits.put(0, 1)
+    bits.put(0, 1)
+    bits.put(0, 2)
+    bits.put(0, 8)
+    bits.put(0, 8)
+    bits.put(0, 8)
+    # One short block mode
+    bits.put(0, 6)
+    bits.put(0, 1)
+    bits.put(0, 16)
+    bits.put(0, 16)
+    bits.put(0, 8)
+    bits.put(1, 1)
+    return b"\x05vorbis" + bits.bytes()
+
+
+def ogg_vorbis(samplerate, channels, seconds, tags, serial=0x72686170):
+    # Blocks of 256 samples, each packet after the first one adds 128 samples
+    ident = b"\x01vorbis" + struct.pack("<IBIiiiBB", 0, channels, samplerate, 0, 0, 0, 0x88, 1)
+    comment = b"\x03vorbis" + vorbis_comments(tags) + b"\x01"
+    setup = vorbis_setup(channels)
+    audio = LsbWriter()
+    audio.put(0, 1)
+    for _ in range(channels):
+        audio.put(0, 1)
+    packet = audio.bytes()
+    count = int(samplerate * seconds) // 128 + 1
+    data = ogg_page([ident], 0, 0, 0x02, serial)
+    data += ogg_page([comment, setup], 0, 1, 0, serial)
+    sequence = 2
+    granule = 0
+    first = True
+    while count > 0:
+        size = min(count, 200)
+        count -= size
+        granule += (size - 1 if first else size) * 128
+        first = False
+        data += ogg_page([packet] * size, granule, sequence, 0x04 if count == 0 else 0, serial)
+        sequence += 1
+    return data
+
+
+def main():
+    write(
+        "tagged.flac",
+        flac(
+            44100,
+            2,
+            16,
+            61,
+            [
+                ("TITLE", "Flac Title"),
+                ("ARTIST", "Flac Artist"),
+                ("ALBUM", "Flac Album"),
+                ("REPLAYGAIN_TRACK_GAIN", "-7.00 dB"),
+            ],
+        ),
+    )
+    write("odd_rate.flac", flac(22050, 2, 16, 1, [("TITLE", "Odd Rate")]))
+    write(
+        "tagged.mp3",
+        mp3(1, [(b"TIT2", "Mp3 Title"), (b"TPE1", "Mp3 Artist"), (b"TALB", "Mp3 Album")]),
+    )
+    write(
+        "tagged.ogg",
+        ogg_vorbis(
+            48000,
+            2,
+            2,
+            [
+                ("TITLE", "Ogg Title"),
+                ("ALBUMARTIST", "Ogg Album Artist"),
+                ("ALBUM", "Ogg Album"),
+                ("R128_TRACK_GAIN", "-1280"),
+            ],
+        ),
+    )
+    write(
+        "tagged.wav",
+        wav(
+            96000,
+            2,
+            24,
+            0.1,
+            [(b"INAM", "Wav Title"), (b"IART", "Wav Artist"), (b"IPRD", "Wav Album")],
+        ),
+    )
+    write("untagged.wav", wav(44100, 2, 16, 0.1))
+    write("multichannel.wav", wav(48000, 6, 24, 0.05, extensible=True))
+    # Two songs chained the way internet radios send them, one logical stream each
+    write(
+        "chained.ogg",
+        ogg_vorbis(44100, 2, 1, [("TITLE", "First Song")], serial=1)
+        + ogg_vorbis(44100, 2, 1, [("TITLE", "Second Song")], serial=2),
+    )
+
+
+if __name__ == "__main__":
+    main()